@@ -0,0 +1,93 @@
+//! A small Adler-32 implementation, used where the zlib trailer has to be
+//! computed outside of the compressor, for instance when independently
+//! compressed pieces of a stream are stitched together.
+
+const BASE: u32 = 65521;
+// The largest n such that 255n(n+1)/2 + (n+1)(BASE-1) fits in a u32.
+const NMAX: usize = 5552;
+
+/// A running Adler-32 checksum along with the number of bytes it covers.
+#[derive(Debug, Clone)]
+pub(crate) struct Adler32 {
+    a: u32,
+    b: u32,
+    amt: u64,
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Adler32 {
+    pub(crate) fn new() -> Adler32 {
+        Adler32 { a: 1, b: 0, amt: 0 }
+    }
+
+    /// Returns the current adler32 checksum.
+    pub(crate) fn sum(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+
+    /// Update the checksum with the bytes in `data`.
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(NMAX) {
+            for &byte in chunk {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= BASE;
+            self.b %= BASE;
+        }
+        self.amt += data.len() as u64;
+    }
+
+    /// Combine the checksum with the checksum for the subsequent block of
+    /// bytes, as zlib's `adler32_combine` does.
+    pub(crate) fn combine(&mut self, other: &Adler32) {
+        let base = u64::from(BASE);
+        let rem = other.amt % base;
+        let (a1, b1) = (u64::from(self.a), u64::from(self.b));
+        let (a2, b2) = (u64::from(other.a), u64::from(other.b));
+
+        let a = (a1 + a2 + base - 1) % base;
+        let b = (rem * a1 + b1 + b2 + base - rem) % base;
+
+        self.a = a as u32;
+        self.b = b as u32;
+        self.amt += other.amt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Adler32;
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn known_value() {
+        let mut adler = Adler32::new();
+        adler.update(b"Wikipedia");
+        assert_eq!(adler.sum(), 0x11e60398);
+    }
+
+    #[test]
+    fn combine_matches_sequential() {
+        let mut rng = thread_rng();
+        for _ in 0..20 {
+            let data: Vec<u8> = (0..rng.gen_range(0..20_000)).map(|_| rng.gen()).collect();
+            let split = rng.gen_range(0..=data.len());
+
+            let mut whole = Adler32::new();
+            whole.update(&data);
+
+            let (mut first, mut second) = (Adler32::new(), Adler32::new());
+            first.update(&data[..split]);
+            second.update(&data[split..]);
+            first.combine(&second);
+
+            assert_eq!(first.sum(), whole.sum());
+        }
+    }
+}
@@ -0,0 +1,713 @@
+use std::cmp;
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use super::zio::{poll_read_limited, LimitedDecompress};
+use super::{async_read, project, AsyncBufSource, ReadState};
+use crate::gz::{check_trailer, GzBuilder, GzHeader, GzHeaderParser};
+use crate::limits::Limiter;
+use crate::zio::read_step;
+use crate::{Compress, Compression, Crc, Decompress, Error, Limits};
+
+/// An asynchronous DEFLATE encoder, or compressor.
+///
+/// This structure implements an asynchronous `Read` interface. When read from,
+/// it reads uncompressed data from the underlying buffered reader and provides
+/// the compressed data.
+#[derive(Debug)]
+pub struct DeflateEncoder<R> {
+    obj: R,
+    state: Compress,
+}
+
+impl<R> DeflateEncoder<R> {
+    /// Creates a new encoder which will read uncompressed data from the given
+    /// stream and emit the compressed stream.
+    pub fn new(r: R, level: Compression) -> DeflateEncoder<R> {
+        DeflateEncoder::new_with_compress(r, Compress::new(level, false))
+    }
+
+    /// Creates a new encoder with the given `compression` settings which will
+    /// read uncompressed data from the given stream `r` and emit the compressed stream.
+    pub fn new_with_compress(r: R, compression: Compress) -> DeflateEncoder<R> {
+        DeflateEncoder {
+            obj: r,
+            state: compression,
+        }
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        project!(self).0
+    }
+
+    /// Consumes this encoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+
+    /// Returns the number of bytes that have been read into this compressor.
+    pub fn total_in(&self) -> u64 {
+        self.state.total_in()
+    }
+
+    /// Returns the number of bytes that the compressor has produced.
+    pub fn total_out(&self) -> u64 {
+        self.state.total_out()
+    }
+}
+
+async_read!(DeflateEncoder);
+
+/// An asynchronous DEFLATE decoder, or decompressor.
+///
+/// This structure implements an asynchronous `Read` interface. When read from,
+/// it reads compressed data from the underlying buffered reader and provides
+/// the uncompressed data.
+#[derive(Debug)]
+pub struct DeflateDecoder<R> {
+    obj: R,
+    state: LimitedDecompress,
+}
+
+impl<R> DeflateDecoder<R> {
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream.
+    pub fn new(r: R) -> DeflateDecoder<R> {
+        DeflateDecoder::new_with_limits(r, Limits::new())
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, using the given `decompression` settings.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> DeflateDecoder<R> {
+        DeflateDecoder {
+            obj: r,
+            state: LimitedDecompress::new(decompression, Limits::new()),
+        }
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, failing once any of the given `limits` is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> DeflateDecoder<R> {
+        DeflateDecoder {
+            obj: r,
+            state: LimitedDecompress::new(Decompress::new(false), limits),
+        }
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        project!(self).0
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+
+    /// Returns the number of bytes that the decompressor has consumed.
+    pub fn total_in(&self) -> u64 {
+        self.state.data.total_in()
+    }
+
+    /// Returns the number of bytes that the decompressor has produced.
+    pub fn total_out(&self) -> u64 {
+        self.state.data.total_out()
+    }
+}
+
+async_read!(DeflateDecoder);
+
+/// An asynchronous ZLIB encoder, or compressor.
+///
+/// This structure implements an asynchronous `Read` interface. When read from,
+/// it reads uncompressed data from the underlying buffered reader and provides
+/// the compressed data.
+#[derive(Debug)]
+pub struct ZlibEncoder<R> {
+    obj: R,
+    state: Compress,
+}
+
+impl<R> ZlibEncoder<R> {
+    /// Creates a new encoder which will read uncompressed data from the given
+    /// stream and emit the compressed stream.
+    pub fn new(r: R, level: Compression) -> ZlibEncoder<R> {
+        ZlibEncoder::new_with_compress(r, Compress::new(level, true))
+    }
+
+    /// Creates a new encoder with the given `compression` settings which will
+    /// read uncompressed data from the given stream `r` and emit the compressed stream.
+    pub fn new_with_compress(r: R, compression: Compress) -> ZlibEncoder<R> {
+        ZlibEncoder {
+            obj: r,
+            state: compression,
+        }
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        project!(self).0
+    }
+
+    /// Consumes this encoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+
+    /// Returns the number of bytes that have been read into this compressor.
+    pub fn total_in(&self) -> u64 {
+        self.state.total_in()
+    }
+
+    /// Returns the number of bytes that the compressor has produced.
+    pub fn total_out(&self) -> u64 {
+        self.state.total_out()
+    }
+}
+
+async_read!(ZlibEncoder);
+
+/// An asynchronous ZLIB decoder, or decompressor.
+///
+/// This structure implements an asynchronous `Read` interface. When read from,
+/// it reads compressed data from the underlying buffered reader and provides
+/// the uncompressed data.
+#[derive(Debug)]
+pub struct ZlibDecoder<R> {
+    obj: R,
+    state: LimitedDecompress,
+}
+
+impl<R> ZlibDecoder<R> {
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream.
+    pub fn new(r: R) -> ZlibDecoder<R> {
+        ZlibDecoder::new_with_decompress(r, Decompress::new(true))
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, using the given `decompression` settings.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> ZlibDecoder<R> {
+        ZlibDecoder {
+            obj: r,
+            state: LimitedDecompress::new(decompression, Limits::new()),
+        }
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, failing once any of the given `limits` is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> ZlibDecoder<R> {
+        ZlibDecoder {
+            obj: r,
+            state: LimitedDecompress::new(Decompress::new(true), limits),
+        }
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        project!(self).0
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+
+    /// Returns the number of bytes that the decompressor has consumed.
+    pub fn total_in(&self) -> u64 {
+        self.state.data.total_in()
+    }
+
+    /// Returns the number of bytes that the decompressor has produced.
+    pub fn total_out(&self) -> u64 {
+        self.state.data.total_out()
+    }
+}
+
+async_read!(ZlibDecoder);
+
+/// An asynchronous gzip encoder.
+///
+/// This structure implements an asynchronous `Read` interface. When read from,
+/// it reads uncompressed data from the underlying buffered reader and provides
+/// the compressed data.
+#[derive(Debug)]
+pub struct GzEncoder<R> {
+    obj: R,
+    state: GzEncoderState,
+}
+
+#[derive(Debug)]
+struct GzEncoderState {
+    data: Compress,
+    crc: Crc,
+    header: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+pub(crate) fn gz_encoder<R>(header: Vec<u8>, r: R, data: Compress) -> GzEncoder<R> {
+    GzEncoder {
+        obj: r,
+        state: GzEncoderState {
+            data,
+            crc: Crc::new(),
+            header,
+            pos: 0,
+            eof: false,
+        },
+    }
+}
+
+impl<R> GzEncoder<R> {
+    /// Creates a new encoder which will use the given compression level.
+    ///
+    /// The encoder is not configured specially for the emitted header. For
+    /// header configuration, see the `GzBuilder` type.
+    pub fn new(r: R, level: Compression) -> GzEncoder<R> {
+        GzBuilder::new().async_buf_read(r, level)
+    }
+
+    /// Creates a new encoder with the given `compression` settings, the data
+    /// read from the stream `r` being compressed and available through the
+    /// returned reader.
+    ///
+    /// The header emitted doesn't tell how hard the data was compressed.
+    pub fn new_with_compress(r: R, compression: Compress) -> GzEncoder<R> {
+        let header = GzBuilder::new().into_header(Compression::default());
+        gz_encoder(header, r, compression)
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        project!(self).0
+    }
+
+    /// Returns the underlying stream, consuming this encoder
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+}
+
+fn copy(into: &mut [u8], from: &[u8], pos: &mut usize) -> usize {
+    let min = cmp::min(into.len(), from.len() - *pos);
+    into[..min].copy_from_slice(&from[*pos..*pos + min]);
+    *pos += min;
+    min
+}
+
+impl ReadState for GzEncoderState {
+    fn poll_read<A: AsyncBufSource>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+        dst: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.eof {
+            let footer = trailer(&self.crc);
+            return Poll::Ready(Ok(copy(dst, &footer, &mut self.pos)));
+        } else if self.pos < self.header.len() {
+            return Poll::Ready(Ok(copy(dst, &self.header, &mut self.pos)));
+        }
+
+        loop {
+            let (consumed, ret) = {
+                let input = ready!(obj.poll_fill_buf(cx))?;
+                let (consumed, ret) = read_step(input, &mut self.data, dst, false);
+                self.crc.update(&input[..consumed]);
+                (consumed, ret)
+            };
+            obj.consume(consumed);
+
+            match ret {
+                Some(Ok(0)) if !dst.is_empty() => {
+                    self.eof = true;
+                    self.pos = 0;
+                    return self.poll_read(obj, cx, dst);
+                }
+                Some(ret) => return Poll::Ready(ret),
+                None => {}
+            }
+        }
+    }
+}
+
+pub(crate) fn trailer(crc: &Crc) -> [u8; 8] {
+    let (sum, amt) = (crc.sum(), crc.amount());
+    let mut buf = [0; 8];
+    buf[..4].copy_from_slice(&sum.to_le_bytes());
+    buf[4..].copy_from_slice(&amt.to_le_bytes());
+    buf
+}
+
+async_read!(GzEncoder);
+
+/// An asynchronous decoder for a single member of a gzip file.
+///
+/// This structure implements an asynchronous `Read` interface. When read from,
+/// it reads compressed data from the underlying buffered reader and provides
+/// the uncompressed data.
+///
+/// Unlike the blocking [`GzDecoder`](crate::bufread::GzDecoder) the header is
+/// only parsed once the decoder is first read from, so [`header`] returns
+/// `None` until then.
+///
+/// After reading a single member of the gzip data this reader will return
+/// `Ok(0)` even if there are more bytes available in the underlying reader.
+///
+/// [`header`]: GzDecoder::header
+#[derive(Debug)]
+pub struct GzDecoder<R> {
+    obj: R,
+    state: GzDecoderState,
+}
+
+#[derive(Debug)]
+enum GzState {
+    Header(GzHeaderParser),
+    Body(GzHeader),
+    Finished(GzHeader, usize, [u8; 8]),
+    End(Option<GzHeader>),
+}
+
+#[derive(Debug)]
+pub(crate) struct GzDecoderState {
+    inner: GzState,
+    data: Decompress,
+    limiter: Limiter,
+    crc: Crc,
+    multi: bool,
+    // Offsets of the current member and of its deflate data in the input.
+    member_start: u64,
+    data_start: u64,
+}
+
+impl GzDecoderState {
+    fn new(multi: bool, data: Decompress, limits: Limits) -> GzDecoderState {
+        GzDecoderState {
+            inner: GzState::Header(GzHeaderParser::new()),
+            data,
+            limiter: Limiter::new(limits),
+            crc: Crc::new(),
+            multi,
+            member_start: 0,
+            data_start: 0,
+        }
+    }
+
+    fn header(&self) -> Option<&GzHeader> {
+        match &self.inner {
+            GzState::Body(header) | GzState::Finished(header, _, _) => Some(header),
+            GzState::End(header) => header.as_ref(),
+            GzState::Header(_) => None,
+        }
+    }
+}
+
+impl ReadState for GzDecoderState {
+    fn poll_read<A: AsyncBufSource>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+        dst: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match &mut self.inner {
+                GzState::Header(parser) => {
+                    let (consumed, ret) = {
+                        let input = ready!(obj.poll_fill_buf(cx))?;
+                        if input.is_empty() {
+                            let offset = self.member_start + parser.len();
+                            self.inner = GzState::End(None);
+                            return Poll::Ready(Err(Error::Truncated { offset }.into()));
+                        }
+                        let mut rest = input;
+                        let ret = parser.parse(&mut rest);
+                        (input.len() - rest.len(), ret)
+                    };
+                    obj.consume(consumed);
+                    match ret {
+                        Ok(()) => {
+                            self.data_start = self.member_start + parser.len();
+                            self.inner = GzState::Body(GzHeader::from(mem::take(parser)));
+                        }
+                        // The parser ran out of buffered data, ask for more.
+                        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+                        Err(e) => {
+                            self.inner = GzState::End(None);
+                            return Poll::Ready(Err(Error::rebase(e, self.member_start)));
+                        }
+                    }
+                }
+                GzState::Body(header) => {
+                    if dst.is_empty() {
+                        return Poll::Ready(Ok(0));
+                    }
+                    let data_start = self.data_start;
+                    let ret = ready!(poll_read_limited(
+                        obj,
+                        &mut self.data,
+                        cx,
+                        dst,
+                        &mut self.limiter
+                    ));
+                    match ret.map_err(|e| Error::rebase(e, data_start))? {
+                        0 => {
+                            self.inner = GzState::Finished(mem::take(header), 0, [0; 8]);
+                        }
+                        n => {
+                            self.crc.update(&dst[..n]);
+                            return Poll::Ready(Ok(n));
+                        }
+                    }
+                }
+                GzState::Finished(header, pos, buf) => {
+                    let trailer_start = self.data_start + self.data.total_in();
+                    if *pos < buf.len() {
+                        let input = ready!(obj.poll_fill_buf(cx))?;
+                        if input.is_empty() {
+                            let offset = trailer_start + *pos as u64;
+                            return Poll::Ready(Err(Error::Truncated { offset }.into()));
+                        }
+                        let n = copy(&mut buf[*pos..], input, &mut 0);
+                        obj.consume(n);
+                        *pos += n;
+                    } else if let Err(e) = check_trailer(buf, &self.crc, trailer_start) {
+                        self.inner = GzState::End(Some(mem::take(header)));
+                        return Poll::Ready(Err(e));
+                    } else if self.multi {
+                        let is_eof = ready!(obj.poll_fill_buf(cx))?.is_empty();
+                        if is_eof {
+                            self.inner = GzState::End(Some(mem::take(header)));
+                        } else if let Err(limit) = self.limiter.next_member() {
+                            self.inner = GzState::End(Some(mem::take(header)));
+                            let offset = trailer_start + 8;
+                            return Poll::Ready(Err(Error::LimitExceeded { limit, offset }.into()));
+                        } else {
+                            self.data.reset(false);
+                            self.crc.reset();
+                            self.member_start = trailer_start + 8;
+                            self.inner = GzState::Header(GzHeaderParser::new());
+                        }
+                    } else {
+                        self.inner = GzState::End(Some(mem::take(header)));
+                    }
+                }
+                GzState::End(_) => return Poll::Ready(Ok(0)),
+            }
+        }
+    }
+}
+
+impl<R> GzDecoder<R> {
+    /// Creates a new decoder from the given reader.
+    pub fn new(r: R) -> GzDecoder<R> {
+        GzDecoder::new_with_limits(r, Limits::new())
+    }
+
+    /// Creates a new decoder from the given reader, failing once any of the
+    /// given `limits` is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> GzDecoder<R> {
+        GzDecoder {
+            obj: r,
+            state: GzDecoderState::new(false, Decompress::new(false), limits),
+        }
+    }
+
+    /// Creates a new decoder from the given reader, inflating the data with
+    /// the given `decompression` settings, which must expect raw deflate data.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> GzDecoder<R> {
+        GzDecoder {
+            obj: r,
+            state: GzDecoderState::new(false, decompression, Limits::new()),
+        }
+    }
+
+    /// Returns the header associated with this stream, if it was valid.
+    pub fn header(&self) -> Option<&GzHeader> {
+        self.state.header()
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying stream.
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying stream.
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        project!(self).0
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+}
+
+async_read!(GzDecoder);
+
+/// An asynchronous gzip decoder that decodes all members of a gzip file.
+///
+/// This structure implements an asynchronous `Read` interface. When read from,
+/// it reads compressed data from the underlying buffered reader and provides
+/// the uncompressed data of every member one after another, returning `Ok(0)`
+/// once the underlying reader does.
+#[derive(Debug)]
+pub struct MultiGzDecoder<R> {
+    obj: R,
+    state: GzDecoderState,
+}
+
+impl<R> MultiGzDecoder<R> {
+    /// Creates a new decoder from the given reader. If the gzip stream
+    /// contains multiple members all will be decoded.
+    pub fn new(r: R) -> MultiGzDecoder<R> {
+        MultiGzDecoder::new_with_limits(r, Limits::new())
+    }
+
+    /// Creates a new decoder from the given reader, failing once any of the
+    /// given `limits` is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> MultiGzDecoder<R> {
+        MultiGzDecoder {
+            obj: r,
+            state: GzDecoderState::new(true, Decompress::new(false), limits),
+        }
+    }
+
+    /// Creates a new decoder from the given reader, inflating the members
+    /// with the given `decompression` settings, which must expect raw deflate
+    /// data.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> MultiGzDecoder<R> {
+        MultiGzDecoder {
+            obj: r,
+            state: GzDecoderState::new(true, decompression, Limits::new()),
+        }
+    }
+
+    /// Returns the current header associated with this stream, if it's valid.
+    pub fn header(&self) -> Option<&GzHeader> {
+        self.state.header()
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying stream.
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying stream.
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        project!(self).0
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+}
+
+async_read!(MultiGzDecoder);
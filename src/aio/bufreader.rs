@@ -0,0 +1,147 @@
+use std::cmp;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use super::AsyncSource;
+
+/// The asynchronous equivalent of `crate::bufreader::BufReader`, used to turn
+/// the `read` flavor of the streams into the `bufread` one.
+pub struct BufReader<R> {
+    obj: R,
+    state: Buffer,
+}
+
+struct Buffer {
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+}
+
+impl<R> std::fmt::Debug for BufReader<R>
+where
+    R: std::fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        fmt.debug_struct("BufReader")
+            .field("reader", &self.obj)
+            .field(
+                "buffer",
+                &format_args!(
+                    "{}/{}",
+                    self.state.cap - self.state.pos,
+                    self.state.buf.len()
+                ),
+            )
+            .finish()
+    }
+}
+
+impl<R> BufReader<R> {
+    pub fn new(inner: R) -> BufReader<R> {
+        BufReader::with_buf(vec![0; 32 * 1024], inner)
+    }
+
+    pub fn with_buf(buf: Vec<u8>, inner: R) -> BufReader<R> {
+        BufReader {
+            obj: inner,
+            state: Buffer {
+                buf: buf.into_boxed_slice(),
+                pos: 0,
+                cap: 0,
+            },
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        super::project!(self).0
+    }
+
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+}
+
+impl Buffer {
+    fn poll_fill_buf<A: AsyncSource>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<&[u8]>> {
+        // If we've reached the end of our internal buffer then we need to fetch
+        // some more data from the underlying reader.
+        if self.pos == self.cap {
+            self.cap = ready!(obj.poll_read(cx, &mut self.buf))?;
+            self.pos = 0;
+        }
+        Poll::Ready(Ok(&self.buf[self.pos..self.cap]))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: ::tokio::io::AsyncRead> ::tokio::io::AsyncBufRead for BufReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let (obj, state) = super::project!(self);
+        state.poll_fill_buf(&mut super::Tokio(obj), cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        super::project!(self).1.consume(amt)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: ::tokio::io::AsyncRead> ::tokio::io::AsyncRead for BufReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ::tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let (obj, state) = super::project!(self);
+        let rem = ready!(state.poll_fill_buf(&mut super::Tokio(obj), cx))?;
+        let amt = cmp::min(rem.len(), buf.remaining());
+        buf.put_slice(&rem[..amt]);
+        state.consume(amt);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<R: ::futures_io::AsyncRead> ::futures_io::AsyncBufRead for BufReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let (obj, state) = super::project!(self);
+        state.poll_fill_buf(&mut super::Futures(obj), cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        super::project!(self).1.consume(amt)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<R: ::futures_io::AsyncRead> ::futures_io::AsyncRead for BufReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let (obj, state) = super::project!(self);
+        let rem = ready!(state.poll_fill_buf(&mut super::Futures(obj), cx))?;
+        let amt = cmp::min(rem.len(), buf.len());
+        buf[..amt].copy_from_slice(&rem[..amt]);
+        state.consume(amt);
+        Poll::Ready(Ok(amt))
+    }
+}
@@ -0,0 +1,289 @@
+//! Asynchronous counterparts of the `read`, `bufread` and `write` modules.
+//!
+//! The types in here are shared between the `tokio` and `futures-io` flavors:
+//! each of them implements the asynchronous I/O traits of whichever runtime
+//! features are enabled. They are driven by the same `Compress`/`Decompress`
+//! state machines as the blocking types, the runtime specific bits are
+//! confined to the small adaptors at the bottom of this file.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+pub mod bufread;
+pub mod read;
+pub mod write;
+
+mod bufreader;
+mod zio;
+
+pub(crate) use self::bufreader::BufReader;
+
+/// A buffered asynchronous source of bytes, the equivalent of `BufRead`.
+pub(crate) trait AsyncBufSource {
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>>;
+    fn consume(&mut self, amt: usize);
+}
+
+/// An asynchronous source of bytes, the equivalent of `Read`.
+pub(crate) trait AsyncSource {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>>;
+}
+
+/// An asynchronous sink of bytes, the equivalent of `Write`.
+pub(crate) trait AsyncSink {
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>>;
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+    fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+/// The state of a reading stream, everything but the underlying reader.
+pub(crate) trait ReadState {
+    fn poll_read<A: AsyncBufSource>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+        dst: &mut [u8],
+    ) -> Poll<io::Result<usize>>;
+}
+
+/// The state of a writing stream, everything but the underlying writer.
+pub(crate) trait WriteState {
+    fn poll_write<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>;
+    fn poll_flush<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>>;
+    fn poll_shutdown<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>>;
+}
+
+/// Splits a pinned stream into its pinned underlying I/O object and its
+/// unpinned state.
+///
+/// The I/O object is structurally pinned: it is never moved out of a pinned
+/// stream, and no other field is ever pinned.
+macro_rules! project {
+    ($this:expr) => {{
+        // SAFETY: see the macro documentation above.
+        let this = unsafe { $this.get_unchecked_mut() };
+        (
+            unsafe { Pin::new_unchecked(&mut this.obj) },
+            &mut this.state,
+        )
+    }};
+}
+
+/// Implements the runtime `AsyncRead` traits for a stream with an `obj`
+/// implementing the runtime's `AsyncBufRead` and a `state` implementing
+/// `ReadState`.
+macro_rules! async_read {
+    ($name:ident) => {
+        #[cfg(feature = "tokio")]
+        impl<R: ::tokio::io::AsyncBufRead> ::tokio::io::AsyncRead for $name<R> {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut ::tokio::io::ReadBuf<'_>,
+            ) -> Poll<io::Result<()>> {
+                let (obj, state) = project!(self);
+                let dst = buf.initialize_unfilled();
+                let n = ready!(state.poll_read(&mut $crate::aio::Tokio(obj), cx, dst))?;
+                buf.advance(n);
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        #[cfg(feature = "futures-io")]
+        impl<R: ::futures_io::AsyncBufRead> ::futures_io::AsyncRead for $name<R> {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                let (obj, state) = project!(self);
+                state.poll_read(&mut $crate::aio::Futures(obj), cx, buf)
+            }
+        }
+    };
+}
+
+/// Implements the runtime `AsyncRead` traits for a stream wrapping the
+/// `bufread` flavor of itself in an `inner` field.
+macro_rules! async_read_buffered {
+    ($name:ident) => {
+        impl<R> $name<R> {
+            fn project_inner(self: Pin<&mut Self>) -> Pin<&mut bufread::$name<BufReader<R>>> {
+                // SAFETY: `inner` is structurally pinned and is the only field.
+                unsafe { self.map_unchecked_mut(|this| &mut this.inner) }
+            }
+        }
+
+        #[cfg(feature = "tokio")]
+        impl<R: ::tokio::io::AsyncRead> ::tokio::io::AsyncRead for $name<R> {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut ::tokio::io::ReadBuf<'_>,
+            ) -> Poll<io::Result<()>> {
+                ::tokio::io::AsyncRead::poll_read(self.project_inner(), cx, buf)
+            }
+        }
+
+        #[cfg(feature = "futures-io")]
+        impl<R: ::futures_io::AsyncRead> ::futures_io::AsyncRead for $name<R> {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                ::futures_io::AsyncRead::poll_read(self.project_inner(), cx, buf)
+            }
+        }
+    };
+}
+
+/// Implements the runtime `AsyncWrite` traits for a stream with an `obj`
+/// implementing the runtime's `AsyncWrite` and a `state` implementing
+/// `WriteState`.
+macro_rules! async_write {
+    ($name:ident) => {
+        #[cfg(feature = "tokio")]
+        impl<W: ::tokio::io::AsyncWrite> ::tokio::io::AsyncWrite for $name<W> {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                let (obj, state) = project!(self);
+                state.poll_write(&mut $crate::aio::Tokio(obj), cx, buf)
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                let (obj, state) = project!(self);
+                state.poll_flush(&mut $crate::aio::Tokio(obj), cx)
+            }
+
+            fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                let (obj, state) = project!(self);
+                state.poll_shutdown(&mut $crate::aio::Tokio(obj), cx)
+            }
+        }
+
+        #[cfg(feature = "futures-io")]
+        impl<W: ::futures_io::AsyncWrite> ::futures_io::AsyncWrite for $name<W> {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                let (obj, state) = project!(self);
+                state.poll_write(&mut $crate::aio::Futures(obj), cx, buf)
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                let (obj, state) = project!(self);
+                state.poll_flush(&mut $crate::aio::Futures(obj), cx)
+            }
+
+            fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                let (obj, state) = project!(self);
+                state.poll_shutdown(&mut $crate::aio::Futures(obj), cx)
+            }
+        }
+    };
+}
+
+pub(crate) use {async_read, async_read_buffered, async_write, project};
+
+/// Adaptor from the `tokio` I/O traits to the ones used in this module.
+#[cfg(feature = "tokio")]
+pub(crate) struct Tokio<'a, T>(pub(crate) Pin<&'a mut T>);
+
+#[cfg(feature = "tokio")]
+impl<T: ::tokio::io::AsyncBufRead> AsyncBufSource for Tokio<'_, T> {
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        ::tokio::io::AsyncBufRead::poll_fill_buf(self.0.as_mut(), cx)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        ::tokio::io::AsyncBufRead::consume(self.0.as_mut(), amt)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: ::tokio::io::AsyncRead> AsyncSource for Tokio<'_, T> {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut buf = ::tokio::io::ReadBuf::new(buf);
+        std::task::ready!(::tokio::io::AsyncRead::poll_read(
+            self.0.as_mut(),
+            cx,
+            &mut buf
+        ))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: ::tokio::io::AsyncWrite> AsyncSink for Tokio<'_, T> {
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ::tokio::io::AsyncWrite::poll_write(self.0.as_mut(), cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ::tokio::io::AsyncWrite::poll_flush(self.0.as_mut(), cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ::tokio::io::AsyncWrite::poll_shutdown(self.0.as_mut(), cx)
+    }
+}
+
+/// Adaptor from the `futures-io` I/O traits to the ones used in this module.
+#[cfg(feature = "futures-io")]
+pub(crate) struct Futures<'a, T>(pub(crate) Pin<&'a mut T>);
+
+#[cfg(feature = "futures-io")]
+impl<T: ::futures_io::AsyncBufRead> AsyncBufSource for Futures<'_, T> {
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        ::futures_io::AsyncBufRead::poll_fill_buf(self.0.as_mut(), cx)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        ::futures_io::AsyncBufRead::consume(self.0.as_mut(), amt)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<T: ::futures_io::AsyncRead> AsyncSource for Futures<'_, T> {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        ::futures_io::AsyncRead::poll_read(self.0.as_mut(), cx, buf)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<T: ::futures_io::AsyncWrite> AsyncSink for Futures<'_, T> {
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ::futures_io::AsyncWrite::poll_write(self.0.as_mut(), cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ::futures_io::AsyncWrite::poll_flush(self.0.as_mut(), cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ::futures_io::AsyncWrite::poll_close(self.0.as_mut(), cx)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,420 @@
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use rand::{thread_rng, Rng};
+
+use crate::{Compress, Compression, Decompress, LimitKind, Limits};
+
+fn random_data() -> Vec<u8> {
+    let mut rng = thread_rng();
+    let mut data = Vec::new();
+    for _ in 0..rng.gen_range(1..40) {
+        let byte = rng.gen::<u8>();
+        data.resize(data.len() + rng.gen_range(1..2000), byte);
+        data.extend((0..rng.gen_range(1..200)).map(|_| rng.gen::<u8>()));
+    }
+    data
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut e = crate::write::GzEncoder::new(Vec::new(), Compression::default());
+    e.write_all(data).unwrap();
+    e.finish().unwrap()
+}
+
+fn limit_kind(err: io::Error) -> crate::LimitKind {
+    match err.get_ref().and_then(|e| e.downcast_ref::<crate::Error>()) {
+        Some(crate::Error::LimitExceeded { limit, .. }) => limit.kind(),
+        _ => panic!("unexpected error: {}", err),
+    }
+}
+
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    let mut d = crate::read::MultiGzDecoder::new(data);
+    let mut out = Vec::new();
+    d.read_to_end(&mut out).unwrap();
+    out
+}
+
+/// An I/O object which returns `Pending` before every operation and then
+/// only transfers a few bytes at a time, to exercise resumption everywhere.
+struct Trickle<T> {
+    inner: T,
+    pending: bool,
+}
+
+impl<T> Trickle<T> {
+    fn new(inner: T) -> Trickle<T> {
+        Trickle {
+            inner,
+            pending: true,
+        }
+    }
+
+    fn poll_op<R>(&mut self, cx: &mut Context<'_>, op: impl FnOnce(&mut T) -> R) -> Poll<R> {
+        self.pending = !self.pending;
+        if !self.pending {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        Poll::Ready(op(&mut self.inner))
+    }
+}
+
+impl<T: Read + Unpin> futures::io::AsyncRead for Trickle<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let len = buf.len().min(7);
+        self.poll_op(cx, |inner| inner.read(&mut buf[..len]))
+    }
+}
+
+impl<T: Read + Unpin> tokio::io::AsyncRead for Trickle<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let len = buf.remaining().min(7);
+        let n = std::task::ready!(self.poll_op(cx, |inner| {
+            inner.read(&mut buf.initialize_unfilled()[..len])
+        }))?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: Write + Unpin> futures::io::AsyncWrite for Trickle<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let len = buf.len().min(7);
+        self.poll_op(cx, |inner| inner.write(&buf[..len]))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_op(cx, |inner| inner.flush())
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_op(cx, |inner| inner.flush())
+    }
+}
+
+impl<T: Write + Unpin> tokio::io::AsyncWrite for Trickle<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let len = buf.len().min(7);
+        self.poll_op(cx, |inner| inner.write(&buf[..len]))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_op(cx, |inner| inner.flush())
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_op(cx, |inner| inner.flush())
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod tokio_flavor {
+    use super::*;
+    use crate::tokio::{bufread, read, write};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn gz_roundtrip_read() {
+        let data = random_data();
+        let compressed = block_on(async {
+            let mut e = read::GzEncoder::new(Trickle::new(&data[..]), Compression::default());
+            let mut out = Vec::new();
+            e.read_to_end(&mut out).await.unwrap();
+            out
+        });
+        assert_eq!(gunzip(&compressed), data);
+
+        let decompressed = block_on(async {
+            let mut d = read::GzDecoder::new(Trickle::new(&compressed[..]));
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).await.unwrap();
+            assert!(d.header().is_some());
+            out
+        });
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn gz_roundtrip_write() {
+        let data = random_data();
+        let compressed = block_on(async {
+            let mut e = write::GzEncoder::new(Trickle::new(Vec::new()), Compression::fast());
+            e.write_all(&data).await.unwrap();
+            e.shutdown().await.unwrap();
+            e.into_inner().inner
+        });
+        assert_eq!(gunzip(&compressed), data);
+
+        let decompressed = block_on(async {
+            let mut d = write::GzDecoder::new(Trickle::new(Vec::new()));
+            d.write_all(&compressed).await.unwrap();
+            d.shutdown().await.unwrap();
+            assert!(d.header().is_some());
+            d.into_inner().inner
+        });
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn gz_flush_is_decodable() {
+        let compressed = block_on(async {
+            let mut e = write::GzEncoder::new(Vec::new(), Compression::default());
+            e.write_all(b"hello ").await.unwrap();
+            e.flush().await.unwrap();
+            e.into_inner()
+        });
+        let mut d = crate::write::GzDecoder::new(Vec::new());
+        d.write_all(&compressed).unwrap();
+        d.flush().unwrap();
+        assert_eq!(d.get_ref(), b"hello ");
+    }
+
+    #[test]
+    fn gz_bufread_leaves_trailing_data() {
+        let data = random_data();
+        let mut input = gzip(&data);
+        input.extend_from_slice(b"trailing");
+        block_on(async {
+            let mut d = bufread::GzDecoder::new(BufReader::new(Trickle::new(&input[..])));
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).await.unwrap();
+            assert_eq!(out, data);
+            let mut rest = Vec::new();
+            d.into_inner().read_to_end(&mut rest).await.unwrap();
+            assert_eq!(rest, b"trailing");
+        });
+    }
+
+    #[test]
+    fn multi_gz() {
+        let (a, b) = (random_data(), random_data());
+        let mut input = gzip(&a);
+        input.extend(gzip(&b));
+        let expected = [&a[..], &b[..]].concat();
+
+        let out = block_on(async {
+            let mut d = read::MultiGzDecoder::new(Trickle::new(&input[..]));
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).await.unwrap();
+            out
+        });
+        assert_eq!(out, expected);
+
+        let out = block_on(async {
+            let mut d = write::MultiGzDecoder::new(Vec::new());
+            d.write_all(&input).await.unwrap();
+            d.shutdown().await.unwrap();
+            d.into_inner()
+        });
+        assert_eq!(out, expected);
+
+        let out = block_on(async {
+            let mut d = read::GzDecoder::new(&input[..]);
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).await.unwrap();
+            out
+        });
+        assert_eq!(out, a);
+    }
+
+    #[test]
+    fn gz_corrupt_trailer() {
+        let mut input = gzip(b"hello world");
+        let len = input.len();
+        input[len - 8] ^= 0xff;
+
+        block_on(async {
+            let mut d = read::GzDecoder::new(&input[..]);
+            let mut out = Vec::new();
+            let err = d.read_to_end(&mut out).await.unwrap_err();
+            assert_crc_mismatch(err, len - 8);
+
+            let mut d = write::GzDecoder::new(Vec::new());
+            d.write_all(&input).await.unwrap();
+            assert_crc_mismatch(d.shutdown().await.unwrap_err(), len - 8);
+        });
+
+        fn assert_crc_mismatch(err: io::Error, at: usize) {
+            match err.get_ref().and_then(|e| e.downcast_ref::<crate::Error>()) {
+                Some(crate::Error::DataCrcMismatch { offset }) => assert_eq!(*offset, at as u64),
+                _ => panic!("unexpected error: {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn zlib_and_deflate_roundtrip() {
+        let data = random_data();
+        block_on(async {
+            let mut e = write::ZlibEncoder::new(Vec::new(), Compression::default());
+            e.write_all(&data).await.unwrap();
+            e.shutdown().await.unwrap();
+            let mut d = read::ZlibDecoder::new(Trickle::new(&e.get_ref()[..]));
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).await.unwrap();
+            assert_eq!(out, data);
+
+            let mut e = read::DeflateEncoder::new(Trickle::new(&data[..]), Compression::best());
+            let mut compressed = Vec::new();
+            e.read_to_end(&mut compressed).await.unwrap();
+            let mut d = write::DeflateDecoder::new(Trickle::new(Vec::new()));
+            d.write_all(&compressed).await.unwrap();
+            d.shutdown().await.unwrap();
+            assert_eq!(d.into_inner().inner, data);
+        });
+    }
+
+    #[test]
+    fn gz_and_deflate_with_settings() {
+        let data = random_data();
+        block_on(async {
+            let c = Compress::new(Compression::best(), false);
+            let mut e = write::GzEncoder::new_with_compress(Vec::new(), c);
+            e.write_all(&data).await.unwrap();
+            e.shutdown().await.unwrap();
+            assert_eq!(gunzip(e.get_ref()), data);
+            let d = Decompress::new(false);
+            let mut d = read::GzDecoder::new_with_decompress(Trickle::new(&e.get_ref()[..]), d);
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).await.unwrap();
+            assert_eq!(out, data);
+
+            let c = Compress::new(Compression::fast(), false);
+            let mut e = bufread::DeflateEncoder::new_with_compress(&data[..], c);
+            let mut compressed = Vec::new();
+            e.read_to_end(&mut compressed).await.unwrap();
+            let d = Decompress::new(false);
+            let mut d = write::DeflateDecoder::new_with_decompress(Vec::new(), d);
+            d.write_all(&compressed).await.unwrap();
+            d.shutdown().await.unwrap();
+            assert_eq!(d.into_inner(), data);
+        });
+    }
+
+    #[test]
+    fn limits() {
+        let compressed = gzip(&[7; 100_000]);
+        let mut input = compressed.clone();
+        input.extend(&compressed);
+
+        block_on(async {
+            let limits = Limits::new().max_output(99_999);
+            let mut d = read::GzDecoder::new_with_limits(Trickle::new(&input[..]), limits);
+            let mut out = Vec::new();
+            let err = d.read_to_end(&mut out).await.unwrap_err();
+            assert_eq!(limit_kind(err), LimitKind::Output);
+            assert!(out.len() <= 99_999);
+
+            let limits = Limits::new().max_members(1);
+            let mut d = read::MultiGzDecoder::new_with_limits(&input[..], limits);
+            let mut out = Vec::new();
+            let err = d.read_to_end(&mut out).await.unwrap_err();
+            assert_eq!(limit_kind(err), LimitKind::Members);
+            assert_eq!(out.len(), 100_000);
+
+            let limits = Limits::new().max_output(1000);
+            let mut d = write::MultiGzDecoder::new_with_limits(Trickle::new(Vec::new()), limits);
+            let err = d.write_all(&input).await.unwrap_err();
+            assert_eq!(limit_kind(err), LimitKind::Output);
+            assert!(d.shutdown().await.is_err());
+            assert_eq!(d.into_inner().inner.len(), 1000);
+
+            let limits = Limits::new().max_members(1);
+            let mut d = write::MultiGzDecoder::new_with_limits(Vec::new(), limits);
+            let err = d.write_all(&input).await.unwrap_err();
+            assert_eq!(limit_kind(err), LimitKind::Members);
+
+            let mut e = write::ZlibEncoder::new(Vec::new(), Compression::default());
+            e.write_all(&[7; 100_000]).await.unwrap();
+            e.shutdown().await.unwrap();
+            let limits = Limits::new().max_ratio(100);
+            let mut d = bufread::ZlibDecoder::new_with_limits(&e.get_ref()[..], limits);
+            let err = d.read_to_end(&mut Vec::new()).await.unwrap_err();
+            assert_eq!(limit_kind(err), LimitKind::Ratio);
+        });
+    }
+}
+
+#[cfg(feature = "futures-io")]
+mod futures_flavor {
+    use super::*;
+    use crate::futures::{bufread, read, write};
+    use futures::executor::block_on;
+    use futures::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+    #[test]
+    fn gz_roundtrip() {
+        let data = random_data();
+        let compressed = block_on(async {
+            let mut e = write::GzEncoder::new(Trickle::new(Vec::new()), Compression::default());
+            e.write_all(&data).await.unwrap();
+            e.close().await.unwrap();
+            e.into_inner().inner
+        });
+        assert_eq!(gunzip(&compressed), data);
+
+        let decompressed = block_on(async {
+            let mut d = read::GzDecoder::new(Trickle::new(&compressed[..]));
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).await.unwrap();
+            out
+        });
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn multi_gz_bufread() {
+        let (a, b) = (random_data(), random_data());
+        let mut input = gzip(&a);
+        input.extend(gzip(&b));
+
+        let out = block_on(async {
+            let r = BufReader::new(Trickle::new(&input[..]));
+            let mut d = bufread::MultiGzDecoder::new(r);
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).await.unwrap();
+            out
+        });
+        assert_eq!(out, [&a[..], &b[..]].concat());
+    }
+
+    #[test]
+    fn zlib_roundtrip() {
+        let data = random_data();
+        block_on(async {
+            let mut e = read::ZlibEncoder::new(Trickle::new(&data[..]), Compression::fast());
+            let mut compressed = Vec::new();
+            e.read_to_end(&mut compressed).await.unwrap();
+            let mut d = write::ZlibDecoder::new(Vec::new());
+            d.write_all(&compressed).await.unwrap();
+            d.close().await.unwrap();
+            assert_eq!(d.into_inner(), data);
+        });
+    }
+}
@@ -0,0 +1,757 @@
+use std::cmp;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use super::zio::Writer;
+use super::{async_write, project, AsyncSink, WriteState};
+use crate::gz::{check_trailer, GzBuilder, GzHeader, GzHeaderParser};
+use crate::{Compress, Compression, Crc, Decompress, Error, Limits, Status};
+
+/// An asynchronous DEFLATE encoder, or compressor.
+///
+/// This structure implements an asynchronous `Write` interface and takes a
+/// stream of uncompressed data, writing the compressed data to the wrapped
+/// writer. The stream is only complete once it has been shut down.
+#[derive(Debug)]
+pub struct DeflateEncoder<W> {
+    obj: W,
+    state: Writer<Compress>,
+}
+
+impl<W> DeflateEncoder<W> {
+    /// Creates a new encoder which will write compressed data to the stream
+    /// given at the given compression level.
+    pub fn new(w: W, level: Compression) -> DeflateEncoder<W> {
+        DeflateEncoder::new_with_compress(w, Compress::new(level, false))
+    }
+
+    /// Creates a new encoder which will write compressed data to the stream
+    /// `w` with the given `compression` settings.
+    pub fn new_with_compress(w: W, compression: Compress) -> DeflateEncoder<W> {
+        DeflateEncoder {
+            obj: w,
+            state: Writer::new(compression),
+        }
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        project!(self).0
+    }
+
+    /// Consumes this encoder, returning the underlying writer.
+    ///
+    /// Note that compressed data which has not been written out yet is lost,
+    /// the stream should be shut down before calling this function.
+    pub fn into_inner(self) -> W {
+        self.obj
+    }
+
+    /// Returns the number of bytes that have been written to this compressor.
+    pub fn total_in(&self) -> u64 {
+        self.state.data.total_in()
+    }
+
+    /// Returns the number of bytes that the compressor has produced.
+    ///
+    /// Note that not all bytes may have been written yet, some may still be
+    /// buffered.
+    pub fn total_out(&self) -> u64 {
+        self.state.data.total_out()
+    }
+}
+
+async_write!(DeflateEncoder);
+
+/// An asynchronous DEFLATE decoder, or decompressor.
+///
+/// This structure implements an asynchronous `Write` interface and takes a
+/// stream of compressed data, writing the decompressed data to the wrapped
+/// writer.
+#[derive(Debug)]
+pub struct DeflateDecoder<W> {
+    obj: W,
+    state: Writer<Decompress>,
+}
+
+impl<W> DeflateDecoder<W> {
+    /// Creates a new decoder which will write uncompressed data to the stream.
+    pub fn new(w: W) -> DeflateDecoder<W> {
+        DeflateDecoder::new_with_decompress(w, Decompress::new(false))
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream
+    /// `w` using the given `decompression` settings.
+    pub fn new_with_decompress(w: W, decompression: Decompress) -> DeflateDecoder<W> {
+        DeflateDecoder {
+            obj: w,
+            state: Writer::new(decompression),
+        }
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// failing once any of the given `limits` is exceeded.
+    ///
+    /// Output beyond the limits is never written to the stream.
+    pub fn new_with_limits(w: W, limits: Limits) -> DeflateDecoder<W> {
+        DeflateDecoder {
+            obj: w,
+            state: Writer::new_with_limits(Decompress::new(false), limits),
+        }
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        project!(self).0
+    }
+
+    /// Consumes this decoder, returning the underlying writer.
+    ///
+    /// Note that decompressed data which has not been written out yet is
+    /// lost, the stream should be shut down before calling this function.
+    pub fn into_inner(self) -> W {
+        self.obj
+    }
+
+    /// Returns the number of bytes that the decompressor has consumed for
+    /// decompression.
+    pub fn total_in(&self) -> u64 {
+        self.state.data.total_in()
+    }
+
+    /// Returns the number of bytes that the decompressor has written to its
+    /// output stream.
+    pub fn total_out(&self) -> u64 {
+        self.state.data.total_out()
+    }
+}
+
+async_write!(DeflateDecoder);
+
+/// An asynchronous ZLIB encoder, or compressor.
+///
+/// This structure implements an asynchronous `Write` interface and takes a
+/// stream of uncompressed data, writing the compressed data to the wrapped
+/// writer. The stream is only complete once it has been shut down.
+#[derive(Debug)]
+pub struct ZlibEncoder<W> {
+    obj: W,
+    state: Writer<Compress>,
+}
+
+impl<W> ZlibEncoder<W> {
+    /// Creates a new encoder which will write compressed data to the stream
+    /// given at the given compression level.
+    pub fn new(w: W, level: Compression) -> ZlibEncoder<W> {
+        ZlibEncoder::new_with_compress(w, Compress::new(level, true))
+    }
+
+    /// Creates a new encoder which will use the specified compressor.
+    pub fn new_with_compress(w: W, compression: Compress) -> ZlibEncoder<W> {
+        ZlibEncoder {
+            obj: w,
+            state: Writer::new(compression),
+        }
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        project!(self).0
+    }
+
+    /// Consumes this encoder, returning the underlying writer.
+    ///
+    /// Note that compressed data which has not been written out yet is lost,
+    /// the stream should be shut down before calling this function.
+    pub fn into_inner(self) -> W {
+        self.obj
+    }
+
+    /// Returns the number of bytes that have been written to this compressor.
+    pub fn total_in(&self) -> u64 {
+        self.state.data.total_in()
+    }
+
+    /// Returns the number of bytes that the compressor has produced.
+    ///
+    /// Note that not all bytes may have been written yet, some may still be
+    /// buffered.
+    pub fn total_out(&self) -> u64 {
+        self.state.data.total_out()
+    }
+}
+
+async_write!(ZlibEncoder);
+
+/// An asynchronous ZLIB decoder, or decompressor.
+///
+/// This structure implements an asynchronous `Write` interface and takes a
+/// stream of compressed data, writing the decompressed data to the wrapped
+/// writer.
+#[derive(Debug)]
+pub struct ZlibDecoder<W> {
+    obj: W,
+    state: Writer<Decompress>,
+}
+
+impl<W> ZlibDecoder<W> {
+    /// Creates a new decoder which will write uncompressed data to the stream.
+    pub fn new(w: W) -> ZlibDecoder<W> {
+        ZlibDecoder::new_with_decompress(w, Decompress::new(true))
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream
+    /// `w` using the given `decompression` settings.
+    pub fn new_with_decompress(w: W, decompression: Decompress) -> ZlibDecoder<W> {
+        ZlibDecoder {
+            obj: w,
+            state: Writer::new(decompression),
+        }
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// failing once any of the given `limits` is exceeded.
+    ///
+    /// Output beyond the limits is never written to the stream.
+    pub fn new_with_limits(w: W, limits: Limits) -> ZlibDecoder<W> {
+        ZlibDecoder {
+            obj: w,
+            state: Writer::new_with_limits(Decompress::new(true), limits),
+        }
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        project!(self).0
+    }
+
+    /// Consumes this decoder, returning the underlying writer.
+    ///
+    /// Note that decompressed data which has not been written out yet is
+    /// lost, the stream should be shut down before calling this function.
+    pub fn into_inner(self) -> W {
+        self.obj
+    }
+
+    /// Returns the number of bytes that the decompressor has consumed for
+    /// decompression.
+    pub fn total_in(&self) -> u64 {
+        self.state.data.total_in()
+    }
+
+    /// Returns the number of bytes that the decompressor has written to its
+    /// output stream.
+    pub fn total_out(&self) -> u64 {
+        self.state.data.total_out()
+    }
+}
+
+async_write!(ZlibDecoder);
+
+/// An asynchronous gzip encoder.
+///
+/// This structure implements an asynchronous `Write` interface and takes a
+/// stream of uncompressed data, writing the compressed data to the wrapped
+/// writer. Shutting the encoder down writes out the gzip trailer before
+/// shutting down the underlying writer.
+#[derive(Debug)]
+pub struct GzEncoder<W> {
+    obj: W,
+    state: GzEncoderState,
+}
+
+#[derive(Debug)]
+struct GzEncoderState {
+    inner: Writer<Compress>,
+    crc: Crc,
+    crc_bytes_written: usize,
+    header: Vec<u8>,
+}
+
+pub(crate) fn gz_encoder<W>(header: Vec<u8>, w: W, data: Compress) -> GzEncoder<W> {
+    GzEncoder {
+        obj: w,
+        state: GzEncoderState {
+            inner: Writer::new(data),
+            crc: Crc::new(),
+            crc_bytes_written: 0,
+            header,
+        },
+    }
+}
+
+impl<W> GzEncoder<W> {
+    /// Creates a new encoder which will use the given compression level.
+    ///
+    /// The encoder is not configured specially for the emitted header. For
+    /// header configuration, see the `GzBuilder` type.
+    pub fn new(w: W, level: Compression) -> GzEncoder<W> {
+        GzBuilder::new().async_write(w, level)
+    }
+
+    /// Creates a new encoder which will write the data compressed with the
+    /// given `compression` settings to the stream `w`.
+    ///
+    /// The header emitted doesn't tell how hard the data was compressed.
+    pub fn new_with_compress(w: W, compression: Compress) -> GzEncoder<W> {
+        let header = GzBuilder::new().into_header(Compression::default());
+        gz_encoder(header, w, compression)
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutation of the writer may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Note that mutation of the writer may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        project!(self).0
+    }
+
+    /// Consumes this encoder, returning the underlying writer.
+    ///
+    /// Note that compressed data which has not been written out yet is lost,
+    /// the stream should be shut down before calling this function.
+    pub fn into_inner(self) -> W {
+        self.obj
+    }
+}
+
+impl GzEncoderState {
+    fn poll_write_header<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        while !self.header.is_empty() {
+            let n = ready!(obj.poll_write(cx, &self.header))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.header.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl WriteState for GzEncoderState {
+    fn poll_write<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        assert_eq!(self.crc_bytes_written, 0);
+        ready!(self.poll_write_header(obj, cx))?;
+        let n = ready!(self.inner.poll_write(obj, cx, buf))?;
+        self.crc.update(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        assert_eq!(self.crc_bytes_written, 0);
+        ready!(self.poll_write_header(obj, cx))?;
+        self.inner.poll_flush(obj, cx)
+    }
+
+    fn poll_shutdown<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        ready!(self.poll_write_header(obj, cx))?;
+        ready!(self.inner.poll_finish(obj, cx))?;
+
+        let trailer = super::bufread::trailer(&self.crc);
+        while self.crc_bytes_written < trailer.len() {
+            let n = ready!(obj.poll_write(cx, &trailer[self.crc_bytes_written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.crc_bytes_written += n;
+        }
+        obj.poll_shutdown(cx)
+    }
+}
+
+async_write!(GzEncoder);
+
+/// An asynchronous decoder for a single member of a gzip file.
+///
+/// This structure implements an asynchronous `Write` interface, receiving
+/// compressed data and writing uncompressed data to the underlying writer.
+/// The checksum of the member is verified when the decoder is shut down.
+#[derive(Debug)]
+pub struct GzDecoder<W> {
+    obj: W,
+    state: GzDecoderState,
+}
+
+const CRC_BYTES_LEN: usize = 8;
+
+#[derive(Debug)]
+struct GzDecoderState {
+    inner: Writer<Decompress>,
+    crc: Crc,
+    crc_bytes: Vec<u8>,
+    header_parser: GzHeaderParser,
+    multi: bool,
+    // Offset of the current member in the input.
+    member_start: u64,
+}
+
+/// Computes the checksum of everything written to the wrapped sink, the
+/// asynchronous equivalent of `CrcWriter`.
+struct CrcSink<'a, A> {
+    obj: &'a mut A,
+    crc: &'a mut Crc,
+}
+
+impl<A: AsyncSink> AsyncSink for CrcSink<'_, A> {
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let n = ready!(self.obj.poll_write(cx, buf))?;
+        self.crc.update(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.obj.poll_flush(cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.obj.poll_shutdown(cx)
+    }
+}
+
+impl GzDecoderState {
+    fn new(multi: bool, data: Decompress, limits: Limits) -> GzDecoderState {
+        GzDecoderState {
+            inner: Writer::new_with_limits(data, limits),
+            crc: Crc::new(),
+            crc_bytes: Vec::with_capacity(CRC_BYTES_LEN),
+            header_parser: GzHeaderParser::new(),
+            multi,
+            member_start: 0,
+        }
+    }
+
+    fn poll_finish_and_check_crc<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        let data_start = self.data_start();
+        let mut sink = CrcSink {
+            obj,
+            crc: &mut self.crc,
+        };
+        let ret = ready!(self.inner.poll_finish(&mut sink, cx));
+        ret.map_err(|err| Error::rebase(err, data_start))?;
+
+        let trailer_start = data_start + self.inner.data.total_in();
+        if self.header_parser.header().is_none() {
+            return Poll::Ready(Err(Error::Truncated { offset: data_start }.into()));
+        }
+        if self.crc_bytes.len() != CRC_BYTES_LEN {
+            let offset = trailer_start + self.crc_bytes.len() as u64;
+            return Poll::Ready(Err(Error::Truncated { offset }.into()));
+        }
+        Poll::Ready(check_trailer(&self.crc_bytes, &self.crc, trailer_start))
+    }
+
+    fn data_start(&self) -> u64 {
+        self.member_start + self.header_parser.len()
+    }
+}
+
+impl WriteState for GzDecoderState {
+    fn poll_write<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+        mut buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.multi && !buf.is_empty() && self.crc_bytes.len() == CRC_BYTES_LEN {
+            // The current member is complete, verify it and start over with
+            // the next one.
+            ready!(self.poll_finish_and_check_crc(obj, cx))?;
+            self.member_start = self.data_start() + self.inner.data.total_in() + 8;
+            if let Err(limit) = self.inner.limiter.next_member() {
+                let offset = self.member_start;
+                return Poll::Ready(Err(Error::LimitExceeded { limit, offset }.into()));
+            }
+            self.inner.data.reset(false);
+            self.crc.reset();
+            self.crc_bytes.clear();
+            self.header_parser = GzHeaderParser::new();
+        }
+
+        let buflen = buf.len();
+        if self.header_parser.header().is_none() {
+            return Poll::Ready(match self.header_parser.parse(&mut buf) {
+                // all data read but header still not complete
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(buflen),
+                Err(e) => Err(Error::rebase(e, self.member_start)),
+                // buf now contains the unread part of the original buf
+                Ok(()) => Ok(buflen - buf.len()),
+            });
+        }
+
+        let data_start = self.data_start();
+        let mut sink = CrcSink {
+            obj,
+            crc: &mut self.crc,
+        };
+        let ret = ready!(self.inner.poll_write_with_status(&mut sink, cx, buf));
+        let (n, status) = ret.map_err(|err| Error::rebase(err, data_start))?;
+
+        if status == Status::StreamEnd && n < buf.len() && self.crc_bytes.len() < CRC_BYTES_LEN {
+            let remaining = buf.len() - n;
+            let crc_bytes = cmp::min(remaining, CRC_BYTES_LEN - self.crc_bytes.len());
+            self.crc_bytes.extend(&buf[n..n + crc_bytes]);
+            return Poll::Ready(Ok(n + crc_bytes));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut sink = CrcSink {
+            obj,
+            crc: &mut self.crc,
+        };
+        self.inner.poll_flush(&mut sink, cx)
+    }
+
+    fn poll_shutdown<A: AsyncSink>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        ready!(self.poll_finish_and_check_crc(obj, cx))?;
+        obj.poll_shutdown(cx)
+    }
+}
+
+impl<W> GzDecoder<W> {
+    /// Creates a new decoder which will write uncompressed data to the stream.
+    pub fn new(w: W) -> GzDecoder<W> {
+        GzDecoder::new_with_limits(w, Limits::new())
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// failing once any of the given `limits` is exceeded.
+    ///
+    /// Output beyond the limits is never written to the stream.
+    pub fn new_with_limits(w: W, limits: Limits) -> GzDecoder<W> {
+        GzDecoder {
+            obj: w,
+            state: GzDecoderState::new(false, Decompress::new(false), limits),
+        }
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream
+    /// `w`, inflating the data with the given `decompression` settings,
+    /// which must expect raw deflate data.
+    pub fn new_with_decompress(w: W, decompression: Decompress) -> GzDecoder<W> {
+        GzDecoder {
+            obj: w,
+            state: GzDecoderState::new(false, decompression, Limits::new()),
+        }
+    }
+
+    /// Returns the header associated with this stream.
+    pub fn header(&self) -> Option<&GzHeader> {
+        self.state.header_parser.header()
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        project!(self).0
+    }
+
+    /// Consumes this decoder, returning the underlying writer.
+    ///
+    /// Note that decompressed data which has not been written out yet is
+    /// lost, the stream should be shut down before calling this function.
+    pub fn into_inner(self) -> W {
+        self.obj
+    }
+}
+
+async_write!(GzDecoder);
+
+/// An asynchronous gzip decoder that decodes all members of a gzip file.
+///
+/// This structure implements an asynchronous `Write` interface, receiving
+/// compressed data and writing the uncompressed data of every member to the
+/// underlying writer one after another.
+#[derive(Debug)]
+pub struct MultiGzDecoder<W> {
+    obj: W,
+    state: GzDecoderState,
+}
+
+impl<W> MultiGzDecoder<W> {
+    /// Creates a new decoder which will write uncompressed data to the stream.
+    /// If the gzip stream contains multiple members all will be decoded.
+    pub fn new(w: W) -> MultiGzDecoder<W> {
+        MultiGzDecoder::new_with_limits(w, Limits::new())
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// failing once any of the given `limits` is exceeded.
+    ///
+    /// Output beyond the limits is never written to the stream.
+    pub fn new_with_limits(w: W, limits: Limits) -> MultiGzDecoder<W> {
+        MultiGzDecoder {
+            obj: w,
+            state: GzDecoderState::new(true, Decompress::new(false), limits),
+        }
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream
+    /// `w`, inflating the members with the given `decompression` settings,
+    /// which must expect raw deflate data.
+    pub fn new_with_decompress(w: W, decompression: Decompress) -> MultiGzDecoder<W> {
+        MultiGzDecoder {
+            obj: w,
+            state: GzDecoderState::new(true, decompression, Limits::new()),
+        }
+    }
+
+    /// Returns the header associated with the current member.
+    pub fn header(&self) -> Option<&GzHeader> {
+        self.state.header_parser.header()
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        project!(self).0
+    }
+
+    /// Consumes this decoder, returning the underlying writer.
+    ///
+    /// Note that decompressed data which has not been written out yet is
+    /// lost, the stream should be shut down before calling this function.
+    pub fn into_inner(self) -> W {
+        self.obj
+    }
+}
+
+async_write!(MultiGzDecoder);
@@ -0,0 +1,266 @@
+use std::cmp;
+use std::io;
+use std::task::{ready, Context, Poll};
+
+use super::{AsyncBufSource, AsyncSink, ReadState, WriteState};
+use crate::limits::Limiter;
+use crate::zio::{exceeded, read_step, Flush, Ops};
+use crate::{Compress, Decompress, Error, Limits, Status};
+
+pub fn poll_read<R, D>(
+    obj: &mut R,
+    data: &mut D,
+    cx: &mut Context<'_>,
+    dst: &mut [u8],
+) -> Poll<io::Result<usize>>
+where
+    R: AsyncBufSource,
+    D: Ops,
+{
+    poll_read_limited(obj, data, cx, dst, &mut Limiter::new(Limits::new()))
+}
+
+// The asynchronous equivalent of `crate::zio::read_limited`.
+pub fn poll_read_limited<R, D>(
+    obj: &mut R,
+    data: &mut D,
+    cx: &mut Context<'_>,
+    dst: &mut [u8],
+    limiter: &mut Limiter,
+) -> Poll<io::Result<usize>>
+where
+    R: AsyncBufSource,
+    D: Ops,
+{
+    limiter
+        .check()
+        .map_err(|limit| exceeded(limit, data.total_in()))?;
+    let len = limiter.clamp(dst.len());
+    let dst = &mut dst[..len];
+    loop {
+        let (consumed, ret) = {
+            let input = ready!(obj.poll_fill_buf(cx))?;
+            read_step(input, data, dst, false)
+        };
+        obj.consume(consumed);
+
+        let read = match ret {
+            Some(Ok(read)) => read,
+            _ => 0,
+        };
+        limiter
+            .account(consumed as u64, read as u64)
+            .map_err(|limit| exceeded(limit, data.total_in()))?;
+
+        if let Some(ret) = ret {
+            return Poll::Ready(ret);
+        }
+    }
+}
+
+impl ReadState for Compress {
+    fn poll_read<A: AsyncBufSource>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+        dst: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        poll_read(obj, self, cx, dst)
+    }
+}
+
+/// A `Decompress` along with the state of its limits.
+#[derive(Debug)]
+pub struct LimitedDecompress {
+    pub data: Decompress,
+    pub limiter: Limiter,
+}
+
+impl LimitedDecompress {
+    pub fn new(data: Decompress, limits: Limits) -> LimitedDecompress {
+        LimitedDecompress {
+            data,
+            limiter: Limiter::new(limits),
+        }
+    }
+}
+
+impl ReadState for LimitedDecompress {
+    fn poll_read<A: AsyncBufSource>(
+        &mut self,
+        obj: &mut A,
+        cx: &mut Context<'_>,
+        dst: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        poll_read_limited(obj, &mut self.data, cx, dst, &mut self.limiter)
+    }
+}
+
+/// The asynchronous equivalent of `crate::zio::Writer`, without the writer
+/// itself which is handed to every call instead.
+#[derive(Debug)]
+pub struct Writer<D: Ops> {
+    pub data: D,
+    pub limiter: Limiter,
+    buf: Vec<u8>,
+    // Whether a sync flush has been requested from `data` but its output not
+    // yet fully written out, so that a pending `poll_flush` doesn't emit a
+    // second one when it is resumed.
+    flushing: bool,
+}
+
+impl<D: Ops> Writer<D> {
+    pub fn new(d: D) -> Writer<D> {
+        Writer::new_with_limits(d, Limits::new())
+    }
+
+    pub fn new_with_limits(d: D, limits: Limits) -> Writer<D> {
+        Writer {
+            data: d,
+            limiter: Limiter::new(limits),
+            buf: Vec::with_capacity(32 * 1024),
+            flushing: false,
+        }
+    }
+
+    pub fn poll_finish<W: AsyncSink>(
+        &mut self,
+        obj: &mut W,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.poll_dump(obj, cx))?;
+            self.check()?;
+
+            let before_in = self.data.total_in();
+            let before = self.data.total_out();
+            self.data.run_vec(&[], &mut self.buf, D::Flush::finish())?;
+            self.limit(before_in, before);
+            if before == self.data.total_out() && self.limiter.check().is_ok() {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    // Returns total written bytes and status of underlying codec
+    pub fn poll_write_with_status<W: AsyncSink>(
+        &mut self,
+        obj: &mut W,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<(usize, Status)>> {
+        // See `crate::zio::Writer::write_with_status` for why this loops.
+        loop {
+            ready!(self.poll_dump(obj, cx))?;
+            self.check()?;
+
+            let before_in = self.data.total_in();
+            let before_out = self.data.total_out();
+            let ret = self.data.run_vec(buf, &mut self.buf, D::Flush::none());
+            self.limit(before_in, before_out);
+            let written = (self.data.total_in() - before_in) as usize;
+            let is_stream_end = matches!(ret, Ok(Status::StreamEnd));
+
+            // Past the limits, the output within them is written out before
+            // failing at the top of the loop.
+            if self.limiter.check().is_err()
+                || !buf.is_empty() && written == 0 && ret.is_ok() && !is_stream_end
+            {
+                continue;
+            }
+            return Poll::Ready(match ret {
+                Ok(st) => Ok((written, st)),
+                Err(err) => Err(Error::decompress(err, self.data.total_in()).into()),
+            });
+        }
+    }
+
+    pub fn poll_dump<W: AsyncSink>(
+        &mut self,
+        obj: &mut W,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        while !self.buf.is_empty() {
+            let n = ready!(obj.poll_write(cx, &self.buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.buf.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    // Accounts for what the codec did since `before_in` and `before_out`,
+    // dropping any output past the limits so that it never reaches the
+    // writer.
+    fn limit(&mut self, before_in: u64, before_out: u64) {
+        let consumed = self.data.total_in() - before_in;
+        let produced = self.data.total_out() - before_out;
+        if self.limiter.account(consumed, produced).is_err() {
+            let excess = cmp::min(self.limiter.excess(), produced) as usize;
+            self.buf.truncate(self.buf.len() - excess);
+        }
+    }
+
+    fn check(&self) -> io::Result<()> {
+        self.limiter
+            .check()
+            .map_err(|limit| exceeded(limit, self.data.total_in()))
+    }
+}
+
+impl<D: Ops> WriteState for Writer<D> {
+    fn poll_write<W: AsyncSink>(
+        &mut self,
+        obj: &mut W,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let (n, _) = ready!(self.poll_write_with_status(obj, cx, buf))?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush<W: AsyncSink>(
+        &mut self,
+        obj: &mut W,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        if !self.flushing {
+            self.check()?;
+            let before_in = self.data.total_in();
+            let before_out = self.data.total_out();
+            self.data.run_vec(&[], &mut self.buf, D::Flush::sync())?;
+            self.limit(before_in, before_out);
+            self.flushing = true;
+        }
+
+        // See `crate::zio::Writer::flush` for why this loops.
+        loop {
+            ready!(self.poll_dump(obj, cx))?;
+            if let Err(err) = self.check() {
+                self.flushing = false;
+                return Poll::Ready(Err(err));
+            }
+            let before_in = self.data.total_in();
+            let before = self.data.total_out();
+            self.data.run_vec(&[], &mut self.buf, D::Flush::none())?;
+            self.limit(before_in, before);
+            if before == self.data.total_out() && self.limiter.check().is_ok() {
+                break;
+            }
+        }
+
+        ready!(obj.poll_flush(cx))?;
+        self.flushing = false;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown<W: AsyncSink>(
+        &mut self,
+        obj: &mut W,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        ready!(self.poll_finish(obj, cx))?;
+        obj.poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,219 @@
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+
+use super::{detect, Format, Peeked};
+use crate::bufread::{DeflateDecoder, GzDecoder, MultiGzDecoder, ZlibDecoder};
+use crate::{DecoderOptions, GzHeader};
+
+/// A decoder detecting whether its input is gzip, zlib or raw deflate data,
+/// and decompressing it accordingly.
+///
+/// This structure implements a [`Read`] interface. When read from, it reads
+/// data from the underlying [`BufRead`] and provides the uncompressed data.
+/// Input that isn't in any of these formats is passed through unchanged, like
+/// `zcat -f` does.
+///
+/// The format is detected on the first read. Gzip data is recognized by its
+/// magic bytes, and all its members are decoded unless
+/// [`multi_member`](Self::multi_member) says otherwise. Zlib and raw deflate
+/// data have no magic bytes to speak of, so the input is test-decoded instead:
+/// it is only taken as zlib or deflate data if its first 4 KiB decode without
+/// error, or if a complete stream ends within them. Anything following a zlib
+/// or deflate stream is left unread.
+///
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+/// [`BufRead`]: https://doc.rust-lang.org/std/io/trait.BufRead.html
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::bufread::AutoDecoder;
+/// use flate2::write::ZlibEncoder;
+/// use flate2::{Compression, Format};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+/// e.write_all(b"Hello World")?;
+/// let bytes = e.finish()?;
+///
+/// let mut d = AutoDecoder::new(&bytes[..]);
+/// let mut s = String::new();
+/// d.read_to_string(&mut s)?;
+/// assert_eq!(s, "Hello World");
+/// assert_eq!(d.format(), Some(Format::Zlib));
+///
+/// let mut d = AutoDecoder::new(&b"Hello World"[..]);
+/// let mut s = String::new();
+/// d.read_to_string(&mut s)?;
+/// assert_eq!(s, "Hello World");
+/// assert_eq!(d.format(), Some(Format::Uncompressed));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AutoDecoder<R> {
+    inner: Inner<R>,
+    options: DecoderOptions,
+    multi: bool,
+}
+
+#[derive(Debug)]
+enum Inner<R> {
+    Undetected(Peeked<R>),
+    Gzip(GzDecoder<Peeked<R>>),
+    MultiGzip(MultiGzDecoder<Peeked<R>>),
+    Zlib(ZlibDecoder<Peeked<R>>),
+    Deflate(DeflateDecoder<Peeked<R>>),
+    Uncompressed(Peeked<R>),
+    // Only seen if creating the decoder for the detected format panicked.
+    Empty,
+}
+
+impl<R: BufRead> AutoDecoder<R> {
+    /// Creates a new decoder from the given reader.
+    pub fn new(r: R) -> AutoDecoder<R> {
+        AutoDecoder::new_with_options(r, DecoderOptions::new())
+    }
+
+    /// Creates a new decoder from the given reader, configured by `options`
+    /// whatever the detected format.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> AutoDecoder<R> {
+        AutoDecoder {
+            inner: Inner::Undetected(Peeked::new(r)),
+            options,
+            multi: true,
+        }
+    }
+
+    fn detect(&mut self) -> io::Result<()> {
+        let format = match self.inner {
+            Inner::Undetected(ref mut r) => detect(r, &self.options)?,
+            _ => return Ok(()),
+        };
+        let r = match mem::replace(&mut self.inner, Inner::Empty) {
+            Inner::Undetected(r) => r,
+            _ => unreachable!(),
+        };
+        let options = self.options;
+        self.inner = match format {
+            Format::Gzip if self.multi => {
+                Inner::MultiGzip(MultiGzDecoder::new_with_options(r, options))
+            }
+            Format::Gzip => Inner::Gzip(GzDecoder::new_with_options(r, options)),
+            Format::Zlib => Inner::Zlib(ZlibDecoder::new_with_options(r, options)),
+            Format::Deflate => Inner::Deflate(DeflateDecoder::new_with_options(r, options)),
+            Format::Uncompressed => Inner::Uncompressed(r),
+        };
+        Ok(())
+    }
+}
+
+impl<R> AutoDecoder<R> {
+    /// Configures whether all the members of a gzip stream are decoded, which
+    /// they are by default, or only the first one.
+    ///
+    /// This has no effect once the format has been detected.
+    pub fn multi_member(mut self, multi: bool) -> AutoDecoder<R> {
+        self.multi = multi;
+        self
+    }
+
+    /// Returns the format of the input, or `None` if it hasn't been detected
+    /// yet because nothing was read.
+    pub fn format(&self) -> Option<Format> {
+        match self.inner {
+            Inner::Undetected(_) | Inner::Empty => None,
+            Inner::Gzip(_) | Inner::MultiGzip(_) => Some(Format::Gzip),
+            Inner::Zlib(_) => Some(Format::Zlib),
+            Inner::Deflate(_) => Some(Format::Deflate),
+            Inner::Uncompressed(_) => Some(Format::Uncompressed),
+        }
+    }
+
+    /// Returns the header of the current gzip member, if the input is gzip
+    /// data and the header is valid.
+    pub fn header(&self) -> Option<&GzHeader> {
+        match self.inner {
+            Inner::Gzip(ref d) => d.header(),
+            Inner::MultiGzip(ref d) => d.header(),
+            _ => None,
+        }
+    }
+
+    fn peeked(&self) -> &Peeked<R> {
+        match self.inner {
+            Inner::Undetected(ref r) | Inner::Uncompressed(ref r) => r,
+            Inner::Gzip(ref d) => d.get_ref(),
+            Inner::MultiGzip(ref d) => d.get_ref(),
+            Inner::Zlib(ref d) => d.get_ref(),
+            Inner::Deflate(ref d) => d.get_ref(),
+            Inner::Empty => panic!("decoder used after a panic"),
+        }
+    }
+
+    fn peeked_mut(&mut self) -> &mut Peeked<R> {
+        match self.inner {
+            Inner::Undetected(ref mut r) | Inner::Uncompressed(ref mut r) => r,
+            Inner::Gzip(ref mut d) => d.get_mut(),
+            Inner::MultiGzip(ref mut d) => d.get_mut(),
+            Inner::Zlib(ref mut d) => d.get_mut(),
+            Inner::Deflate(ref mut d) => d.get_mut(),
+            Inner::Empty => panic!("decoder used after a panic"),
+        }
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.peeked().get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying stream.
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.peeked_mut().get_mut()
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    ///
+    /// Note that the start of the stream may have been taken out of the
+    /// underlying reader to detect the format, when it didn't buffer enough of
+    /// it, and that it is lost if it wasn't read from this decoder yet.
+    pub fn into_inner(self) -> R {
+        match self.inner {
+            Inner::Undetected(r) | Inner::Uncompressed(r) => r.into_inner(),
+            Inner::Gzip(d) => d.into_inner().into_inner(),
+            Inner::MultiGzip(d) => d.into_inner().into_inner(),
+            Inner::Zlib(d) => d.into_inner().into_inner(),
+            Inner::Deflate(d) => d.into_inner().into_inner(),
+            Inner::Empty => panic!("decoder used after a panic"),
+        }
+    }
+}
+
+impl<R: BufRead> Read for AutoDecoder<R> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        self.detect()?;
+        match self.inner {
+            Inner::Gzip(ref mut d) => d.read(into),
+            Inner::MultiGzip(ref mut d) => d.read(into),
+            Inner::Zlib(ref mut d) => d.read(into),
+            Inner::Deflate(ref mut d) => d.read(into),
+            Inner::Uncompressed(ref mut r) => r.read(into),
+            Inner::Undetected(_) | Inner::Empty => unreachable!(),
+        }
+    }
+}
+
+impl<R: BufRead + Write> Write for AutoDecoder<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
@@ -0,0 +1,285 @@
+//! Decoders detecting the format of their input.
+
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+
+use crate::{DecoderOptions, FlushDecompress, Status};
+
+pub mod bufread;
+pub mod read;
+
+// How much of the input is inflated when checking whether it's deflate data.
+const SNIFF_LEN: usize = 4 * 1024;
+
+/// The format of a stream, as detected by an `AutoDecoder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// A gzip stream, starting with the gzip magic bytes.
+    Gzip,
+    /// A zlib stream, starting with a valid zlib header.
+    Zlib,
+    /// A raw deflate stream.
+    Deflate,
+    /// Anything else, which is passed through unchanged.
+    Uncompressed,
+}
+
+// Detects the format of the stream, which must not have been read from yet.
+//
+// Gzip is recognized by its magic bytes alone. Zlib and raw deflate data must
+// inflate without error, either up to the end of a complete stream or for the
+// whole of the first `SNIFF_LEN` bytes, which is unlikely for anything else.
+// Whatever follows a complete stream is left to the decoder of the format,
+// which handles it as `options` say. The data is inflated with the backend of
+// `options`.
+pub(crate) fn detect<R: BufRead>(
+    r: &mut Peeked<R>,
+    options: &DecoderOptions,
+) -> io::Result<Format> {
+    if r.peek(2)?.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Format::Gzip);
+    }
+    let buf = r.peek(SNIFF_LEN)?;
+    Ok(
+        if buf.len() >= 2
+            && crate::zlib::is_header([buf[0], buf[1]])
+            && inflates(buf, true, options)
+        {
+            Format::Zlib
+        } else if inflates(buf, false, options) {
+            Format::Deflate
+        } else {
+            Format::Uncompressed
+        },
+    )
+}
+
+fn inflates(input: &[u8], zlib_header: bool, options: &DecoderOptions) -> bool {
+    let input = &input[..cmp::min(input.len(), SNIFF_LEN)];
+    let mut data = options.decompress(zlib_header);
+    let mut out = vec![0; 32 * 1024];
+    loop {
+        let (before_in, before_out) = (data.total_in(), data.total_out());
+        let input = &input[before_in as usize..];
+        match data.decompress(input, &mut out, FlushDecompress::None) {
+            Ok(Status::StreamEnd) => return true,
+            Ok(_) if data.total_in() - before_in == input.len() as u64 => {
+                return data.total_in() == SNIFF_LEN as u64
+            }
+            Ok(_) if data.total_in() != before_in || data.total_out() != before_out => {}
+            // Some backends stop making progress on invalid data rather than
+            // failing.
+            Ok(_) | Err(_) => return false,
+        }
+    }
+}
+
+// A reader holding on to the start of the stream when it had to be taken out
+// of the underlying reader to detect the format, because the underlying reader
+// didn't buffer enough of it.
+#[derive(Debug)]
+pub(crate) struct Peeked<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R> Peeked<R> {
+    pub(crate) fn new(inner: R) -> Peeked<R> {
+        Peeked {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: BufRead> Peeked<R> {
+    // Returns the start of the stream, holding at least `len` bytes unless the
+    // stream is shorter than that. Must be called before anything is consumed.
+    pub(crate) fn peek(&mut self, len: usize) -> io::Result<&[u8]> {
+        while self.buf.len() < len {
+            let buf = self.inner.fill_buf()?;
+            if buf.is_empty() || (self.buf.is_empty() && buf.len() >= len) {
+                break;
+            }
+            let n = cmp::min(len - self.buf.len(), buf.len());
+            self.buf.extend_from_slice(&buf[..n]);
+            self.inner.consume(n);
+        }
+        self.fill_buf()
+    }
+}
+
+impl<R: BufRead> Read for Peeked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Peeked<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos < self.buf.len() {
+            Ok(&self.buf[self.pos..])
+        } else {
+            self.inner.fill_buf()
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.pos < self.buf.len() {
+            self.pos = cmp::min(self.pos + amt, self.buf.len());
+        } else {
+            self.inner.consume(amt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::prelude::*;
+
+    use super::{bufread, read, Format};
+    use crate::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use crate::{Compression, DecoderOptions};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    fn decode(input: &[u8]) -> (Vec<u8>, Option<Format>) {
+        let mut d = read::AutoDecoder::new(input);
+        assert_eq!(d.format(), None);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        (out, d.format())
+    }
+
+    #[test]
+    fn formats() {
+        let text = b"Hello World".repeat(100);
+        for data in [&b""[..], b"Hello World", &text] {
+            assert_eq!(decode(&gzip(data)), (data.to_vec(), Some(Format::Gzip)));
+            assert_eq!(decode(&zlib(data)), (data.to_vec(), Some(Format::Zlib)));
+            assert_eq!(
+                decode(&deflate(data)),
+                (data.to_vec(), Some(Format::Deflate))
+            );
+        }
+    }
+
+    #[test]
+    fn passthrough() {
+        let lorem = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do \
+            eiusmod tempor incididunt ut labore et dolore magna aliqua."
+            .repeat(10);
+        let mut random = b"random: ".to_vec();
+        random.extend(crate::random_bytes().take(10_000));
+        for data in [
+            &b""[..],
+            b"a",
+            b"x^",
+            b"hbar",
+            b"Hello World",
+            b"{\"key\": \"value\"}\n",
+            &lorem,
+            &random,
+        ] {
+            let (out, format) = decode(data);
+            assert_eq!(out, data);
+            assert_eq!(format, Some(Format::Uncompressed));
+        }
+    }
+
+    #[test]
+    fn trailing_data() {
+        for (mut data, format) in [
+            (zlib(b"Hello World"), Format::Zlib),
+            (deflate(b"Hello World"), Format::Deflate),
+        ] {
+            data.extend_from_slice(b"\0\0\0\0 and more");
+            let mut d = bufread::AutoDecoder::new(&data[..]);
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).unwrap();
+            assert_eq!(out, b"Hello World");
+            assert_eq!(d.format(), Some(format));
+        }
+    }
+
+    #[test]
+    fn multi_member() {
+        let mut data = gzip(b"Hello ");
+        data.extend(gzip(b"World"));
+
+        let (out, _) = decode(&data);
+        assert_eq!(out, b"Hello World");
+
+        let mut d = read::AutoDecoder::new(&data[..]).multi_member(false);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"Hello ");
+        assert_eq!(d.header().unwrap().operating_system(), 255);
+    }
+
+    #[test]
+    fn options() {
+        let mut data = gzip(b"Hello World");
+        data.resize(512, 0);
+
+        let mut d = read::AutoDecoder::new(&data[..]);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+
+        let options = DecoderOptions::new().ignore_trailing_zeros(true);
+        let mut d = read::AutoDecoder::new_with_options(&data[..], options);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"Hello World");
+    }
+
+    #[test]
+    fn short_reads() {
+        let text = b"Hello World".repeat(100);
+        for (data, format) in [
+            (gzip(&text), Format::Gzip),
+            (zlib(&text), Format::Zlib),
+            (text.clone(), Format::Uncompressed),
+        ] {
+            let r = io::BufReader::with_capacity(1, &data[..]);
+            let mut d = bufread::AutoDecoder::new(r);
+            let mut out = Vec::new();
+            d.read_to_end(&mut out).unwrap();
+            assert_eq!(out, text);
+            assert_eq!(d.format(), Some(format));
+        }
+    }
+}
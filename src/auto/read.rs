@@ -0,0 +1,124 @@
+use std::io;
+use std::io::prelude::*;
+
+use super::{bufread, Format};
+use crate::bufreader::BufReader;
+use crate::{DecoderOptions, GzHeader};
+
+/// A decoder detecting whether its input is gzip, zlib or raw deflate data,
+/// and decompressing it accordingly.
+///
+/// This structure implements a [`Read`] interface. When read from, it reads
+/// data from the underlying [`Read`] and provides the uncompressed data.
+/// Input that isn't in any of these formats is passed through unchanged, like
+/// `zcat -f` does.
+///
+/// See [`bufread::AutoDecoder`](crate::bufread::AutoDecoder) for how the
+/// format is detected.
+///
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::read::AutoDecoder;
+/// use flate2::write::GzEncoder;
+/// use flate2::{Compression, Format};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut e = GzEncoder::new(Vec::new(), Compression::default());
+/// e.write_all(b"Hello World")?;
+/// let bytes = e.finish()?;
+///
+/// let mut d = AutoDecoder::new(&bytes[..]);
+/// let mut s = String::new();
+/// d.read_to_string(&mut s)?;
+/// assert_eq!(s, "Hello World");
+/// assert_eq!(d.format(), Some(Format::Gzip));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AutoDecoder<R> {
+    inner: bufread::AutoDecoder<BufReader<R>>,
+}
+
+impl<R: Read> AutoDecoder<R> {
+    /// Creates a new decoder from the given reader.
+    pub fn new(r: R) -> AutoDecoder<R> {
+        AutoDecoder {
+            inner: bufread::AutoDecoder::new(BufReader::new(r)),
+        }
+    }
+
+    /// Creates a new decoder from the given reader, configured by `options`
+    /// whatever the detected format.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> AutoDecoder<R> {
+        AutoDecoder {
+            inner: bufread::AutoDecoder::new_with_options(BufReader::new(r), options),
+        }
+    }
+}
+
+impl<R> AutoDecoder<R> {
+    /// Configures whether all the members of a gzip stream are decoded, which
+    /// they are by default, or only the first one.
+    ///
+    /// This has no effect once the format has been detected.
+    pub fn multi_member(self, multi: bool) -> AutoDecoder<R> {
+        AutoDecoder {
+            inner: self.inner.multi_member(multi),
+        }
+    }
+
+    /// Returns the format of the input, or `None` if it hasn't been detected
+    /// yet because nothing was read.
+    pub fn format(&self) -> Option<Format> {
+        self.inner.format()
+    }
+
+    /// Returns the header of the current gzip member, if the input is gzip
+    /// data and the header is valid.
+    pub fn header(&self) -> Option<&GzHeader> {
+        self.inner.header()
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.inner.get_ref().get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying stream.
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.inner.get_mut().get_mut()
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    ///
+    /// Note that there may be buffered bytes which are not re-acquired as part
+    /// of this transition. It's recommended to only call this function after
+    /// EOF has been reached.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner().into_inner()
+    }
+}
+
+impl<R: Read> Read for AutoDecoder<R> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(into)
+    }
+}
+
+impl<R: Read + Write> Write for AutoDecoder<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
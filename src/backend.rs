@@ -0,0 +1,481 @@
+//! Plugging custom compression engines into the streams of this crate.
+//!
+//! The built-in backend, picked with the features of this crate, is what a
+//! [`Compress`] or a [`Decompress`] uses by default. Anything implementing
+//! [`CompressBackend`] or [`DecompressBackend`] can take its place through
+//! [`Compress::with_backend`] and [`Decompress::with_backend`], and the
+//! resulting objects can then be handed to the `new_with_compress` and
+//! `new_with_decompress` constructors of the [`read`](mod@crate::read),
+//! [`bufread`](mod@crate::bufread) and [`write`](mod@crate::write) types.
+//!
+//! A backend produces or expects the format its user asks for: raw deflate
+//! data for the deflate and gzip types, which deal with the gzip header and
+//! trailer themselves, and zlib data for the zlib types.
+//!
+//! [`Compress`] and [`Decompress`] implement these traits too, so that a
+//! backend can build on the built-in ones.
+//!
+//! # Examples
+//!
+//! Counting how many times the compressor is called:
+//!
+//! ```
+//! use std::io::prelude::*;
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use std::sync::Arc;
+//! use flate2::backend::CompressBackend;
+//! use flate2::write::DeflateEncoder;
+//! use flate2::{Compress, CompressError, Compression, FlushCompress, Status};
+//!
+//! struct Counting {
+//!     inner: Compress,
+//!     calls: Arc<AtomicUsize>,
+//! }
+//!
+//! impl CompressBackend for Counting {
+//!     fn total_in(&self) -> u64 {
+//!         self.inner.total_in()
+//!     }
+//!
+//!     fn total_out(&self) -> u64 {
+//!         self.inner.total_out()
+//!     }
+//!
+//!     fn compress(
+//!         &mut self,
+//!         input: &[u8],
+//!         output: &mut [u8],
+//!         flush: FlushCompress,
+//!     ) -> Result<Status, CompressError> {
+//!         self.calls.fetch_add(1, Ordering::Relaxed);
+//!         self.inner.compress(input, output, flush)
+//!     }
+//!
+//!     fn reset(&mut self) {
+//!         self.inner.reset()
+//!     }
+//! }
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let calls = Arc::new(AtomicUsize::new(0));
+//! let data = Compress::with_backend(Counting {
+//!     inner: Compress::new(Compression::default(), false),
+//!     calls: calls.clone(),
+//! });
+//! let mut e = DeflateEncoder::new_with_compress(Vec::new(), data);
+//! e.write_all(b"Hello World")?;
+//! e.finish()?;
+//! assert!(calls.load(Ordering::Relaxed) > 0);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::mem::{CompressError, DecompressError, FlushCompress, FlushDecompress, Status};
+use crate::{Compress, Compression, Decompress};
+
+/// One of the built-in backends compiled into this crate, all of which can be
+/// used side by side.
+///
+/// Streams created without telling which backend to use get the default one,
+/// which is the zlib library when it is compiled in and can be changed for the
+/// whole process with [`Backend::set_default`].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::write::GzEncoder;
+/// use flate2::{Backend, Compression, EncoderOptions};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let options = EncoderOptions::new().backend(Backend::default());
+/// let mut e = GzEncoder::new_with_options(Vec::new(), Compression::default(), options);
+/// e.write_all(b"Hello World")?;
+/// let compressed = e.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Backend {
+    /// The zlib library picked by the `zlib`, `zlib-default`,
+    /// `zlib-ng-compat`, `zlib-ng`, `cloudflare_zlib` or `zlib-rs` feature.
+    #[cfg(feature = "any_zlib")]
+    Zlib,
+    /// The `miniz_oxide` crate, written in Rust, of the `rust_backend`
+    /// feature.
+    #[cfg(feature = "miniz_oxide")]
+    Rust,
+}
+
+// The backend set with `Backend::set_default`, or `UNSET`.
+static DEFAULT: AtomicU8 = AtomicU8::new(UNSET);
+const UNSET: u8 = 0;
+
+impl Backend {
+    /// Changes the backend used by the streams created from now on without
+    /// telling which backend to use, in the whole process.
+    pub fn set_default(backend: Backend) {
+        DEFAULT.store(backend.id(), Ordering::Relaxed);
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            #[cfg(feature = "any_zlib")]
+            Backend::Zlib => 1,
+            #[cfg(feature = "miniz_oxide")]
+            Backend::Rust => 2,
+        }
+    }
+}
+
+impl Default for Backend {
+    /// Returns the backend used by the streams created without telling which
+    /// backend to use.
+    fn default() -> Backend {
+        match DEFAULT.load(Ordering::Relaxed) {
+            #[cfg(feature = "miniz_oxide")]
+            2 => Backend::Rust,
+            #[cfg(feature = "any_zlib")]
+            _ => Backend::Zlib,
+            #[cfg(not(feature = "any_zlib"))]
+            _ => Backend::Rust,
+        }
+    }
+}
+
+/// A compression engine usable by a [`Compress`].
+///
+/// The methods behave like the ones of the same name of [`Compress`], which
+/// forwards them to the backend. Only the totals tell the caller how much
+/// input was consumed and how much output produced, and compressing with
+/// [`FlushCompress::Finish`] must eventually return [`Status::StreamEnd`],
+/// once all of the output has been produced.
+pub trait CompressBackend: Send + Sync {
+    /// Returns the total number of input bytes consumed so far.
+    fn total_in(&self) -> u64;
+
+    /// Returns the total number of output bytes produced so far.
+    fn total_out(&self) -> u64;
+
+    /// Compresses the input data into the output, as with
+    /// [`Compress::compress`].
+    fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushCompress,
+    ) -> Result<Status, CompressError>;
+
+    /// Starts over with a new stream, the totals back to zero.
+    fn reset(&mut self);
+
+    /// Specifies the compression dictionary to use, returning its Adler-32
+    /// checksum.
+    ///
+    /// Fails by default.
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, CompressError> {
+        let _ = dictionary;
+        Err(CompressError::new("dictionaries are not supported"))
+    }
+
+    /// Changes the compression level of the data following.
+    ///
+    /// Fails by default.
+    fn set_level(&mut self, level: Compression) -> Result<(), CompressError> {
+        let _ = level;
+        Err(CompressError::new("changing the level is not supported"))
+    }
+}
+
+/// A decompression engine usable by a [`Decompress`].
+///
+/// The methods behave like the ones of the same name of [`Decompress`], which
+/// forwards them to the backend. Only the totals tell the caller how much
+/// input was consumed and how much output produced, and the end of the stream
+/// is reported with [`Status::StreamEnd`].
+pub trait DecompressBackend: Send + Sync {
+    /// Returns the total number of input bytes consumed so far.
+    fn total_in(&self) -> u64;
+
+    /// Returns the total number of output bytes produced so far.
+    fn total_out(&self) -> u64;
+
+    /// Decompresses the input data into the output, as with
+    /// [`Decompress::decompress`].
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushDecompress,
+    ) -> Result<Status, DecompressError>;
+
+    /// Starts over with a new stream, the totals back to zero, expecting a
+    /// zlib header or not as told.
+    fn reset(&mut self, zlib_header: bool);
+
+    /// Specifies the decompression dictionary to use, returning its Adler-32
+    /// checksum.
+    ///
+    /// Fails by default.
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, DecompressError> {
+        let _ = dictionary;
+        Err(DecompressError::new("dictionaries are not supported"))
+    }
+}
+
+impl CompressBackend for Compress {
+    fn total_in(&self) -> u64 {
+        Compress::total_in(self)
+    }
+
+    fn total_out(&self) -> u64 {
+        Compress::total_out(self)
+    }
+
+    fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushCompress,
+    ) -> Result<Status, CompressError> {
+        Compress::compress(self, input, output, flush)
+    }
+
+    fn reset(&mut self) {
+        Compress::reset(self)
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, CompressError> {
+        Compress::set_dictionary(self, dictionary)
+    }
+
+    fn set_level(&mut self, level: Compression) -> Result<(), CompressError> {
+        Compress::set_level(self, level)
+    }
+}
+
+impl DecompressBackend for Decompress {
+    fn total_in(&self) -> u64 {
+        Decompress::total_in(self)
+    }
+
+    fn total_out(&self) -> u64 {
+        Decompress::total_out(self)
+    }
+
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushDecompress,
+    ) -> Result<Status, DecompressError> {
+        Decompress::decompress(self, input, output, flush)
+    }
+
+    fn reset(&mut self, zlib_header: bool) {
+        Decompress::reset(self, zlib_header)
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, DecompressError> {
+        Decompress::set_dictionary(self, dictionary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{read, write};
+
+    struct Counting<T> {
+        inner: T,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl CompressBackend for Counting<Compress> {
+        fn total_in(&self) -> u64 {
+            self.inner.total_in()
+        }
+
+        fn total_out(&self) -> u64 {
+            self.inner.total_out()
+        }
+
+        fn compress(
+            &mut self,
+            input: &[u8],
+            output: &mut [u8],
+            flush: FlushCompress,
+        ) -> Result<Status, CompressError> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.inner.compress(input, output, flush)
+        }
+
+        fn reset(&mut self) {
+            self.inner.reset()
+        }
+    }
+
+    impl DecompressBackend for Counting<Decompress> {
+        fn total_in(&self) -> u64 {
+            self.inner.total_in()
+        }
+
+        fn total_out(&self) -> u64 {
+            self.inner.total_out()
+        }
+
+        fn decompress(
+            &mut self,
+            input: &[u8],
+            output: &mut [u8],
+            flush: FlushDecompress,
+        ) -> Result<Status, DecompressError> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.inner.decompress(input, output, flush)
+        }
+
+        fn reset(&mut self, zlib_header: bool) {
+            self.inner.reset(zlib_header)
+        }
+    }
+
+    // Every backend compiled in decodes what any of them encodes.
+    #[test]
+    fn builtin_backends() {
+        let backends = [
+            #[cfg(feature = "any_zlib")]
+            Backend::Zlib,
+            #[cfg(feature = "miniz_oxide")]
+            Backend::Rust,
+        ];
+        let data = b"hello, world! ".repeat(1000);
+        for &encoder in &backends {
+            let options = crate::EncoderOptions::new().backend(encoder);
+            let mut e =
+                write::GzEncoder::new_with_options(Vec::new(), Compression::fast(), options);
+            e.write_all(&data).unwrap();
+            let gz = e.finish().unwrap();
+
+            for &decoder in &backends {
+                let options = crate::DecoderOptions::new().backend(decoder);
+                let mut d = read::GzDecoder::new_with_options(&gz[..], options);
+                let mut out = Vec::new();
+                d.read_to_end(&mut out).unwrap();
+                assert_eq!(out, data, "{:?} to {:?}", encoder, decoder);
+            }
+        }
+    }
+
+    #[test]
+    fn gzip_roundtrip() {
+        let data = b"hello, world! ".repeat(1000);
+        let compressed = Arc::new(AtomicUsize::new(0));
+        let decompressed = Arc::new(AtomicUsize::new(0));
+
+        let mut e = write::GzEncoder::new_with_compress(
+            Vec::new(),
+            Compress::with_backend(Counting {
+                inner: Compress::new(Compression::default(), false),
+                calls: compressed.clone(),
+            }),
+        );
+        e.write_all(&data).unwrap();
+        let gz = e.finish().unwrap();
+
+        let mut d = read::GzDecoder::new_with_decompress(
+            &gz[..],
+            Decompress::with_backend(Counting {
+                inner: Decompress::new(false),
+                calls: decompressed.clone(),
+            }),
+        );
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+        assert!(compressed.load(Ordering::Relaxed) > 0);
+        assert!(decompressed.load(Ordering::Relaxed) > 0);
+    }
+
+    // The multi-stream decoders reset the same backend for each stream.
+    #[test]
+    fn multi_streams() {
+        let mut zlib = Vec::new();
+        let mut deflate = Vec::new();
+        for part in [&b"hello, "[..], b"world!"] {
+            let mut e = write::ZlibEncoder::new(Vec::new(), Compression::default());
+            e.write_all(part).unwrap();
+            zlib.extend(e.finish().unwrap());
+            let mut e = write::DeflateEncoder::new(Vec::new(), Compression::default());
+            e.write_all(part).unwrap();
+            deflate.extend(e.finish().unwrap());
+        }
+        let calls = Arc::new(AtomicUsize::new(0));
+        let decompress = |zlib_header| {
+            Decompress::with_backend(Counting {
+                inner: Decompress::new(zlib_header),
+                calls: calls.clone(),
+            })
+        };
+
+        let mut d = read::MultiZlibDecoder::new_with_decompress(&zlib[..], decompress(true));
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello, world!");
+        assert_eq!(d.take_streams().len(), 2);
+
+        let mut d = write::MultiDeflateDecoder::new_with_decompress(Vec::new(), decompress(false));
+        d.write_all(&deflate).unwrap();
+        d.try_finish().unwrap();
+        assert_eq!(d.take_streams().len(), 2);
+        assert_eq!(d.finish().unwrap(), b"hello, world!");
+        assert!(calls.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut c = Compress::with_backend(Counting {
+            inner: Compress::new(Compression::default(), false),
+            calls: Arc::new(AtomicUsize::new(0)),
+        });
+        assert!(c.set_dictionary(b"hello").is_err());
+        assert!(c.set_level(Compression::best()).is_err());
+
+        let mut d = Decompress::with_backend(Counting {
+            inner: Decompress::new(false),
+            calls: Arc::new(AtomicUsize::new(0)),
+        });
+        assert!(d.set_dictionary(b"hello").is_err());
+    }
+
+    #[test]
+    fn builtin_as_backend() {
+        let mut c = Compress::with_backend(Compress::new(Compression::default(), true));
+        c.set_dictionary(b"hello").unwrap();
+        let mut out = Vec::with_capacity(64);
+        let status = c.compress_vec(b"hello", &mut out, FlushCompress::Finish);
+        assert_eq!(status.unwrap(), Status::StreamEnd);
+
+        let mut d = Decompress::with_backend(Decompress::new(true));
+        let mut buf = Vec::with_capacity(64);
+        let adler = d
+            .decompress_vec(&out, &mut buf, FlushDecompress::Finish)
+            .unwrap_err()
+            .needs_dictionary()
+            .unwrap();
+        assert_eq!(d.set_dictionary(b"hello").unwrap(), adler);
+        let status = d.decompress_vec(
+            &out[d.total_in() as usize..],
+            &mut buf,
+            FlushDecompress::Finish,
+        );
+        assert_eq!(status.unwrap(), Status::StreamEnd);
+        assert_eq!(buf, b"hello");
+    }
+}
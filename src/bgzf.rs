@@ -0,0 +1,647 @@
+//! Support for BGZF, the blocked gzip format used by BAM, tabix-indexed VCF and
+//! many other genomics file formats.
+//!
+//! A BGZF file is a series of gzip members, called blocks, of at most 64 KiB
+//! each. Every block records its compressed size in a `BC` subfield of the
+//! extra field of its header, and the file ends with an empty block acting as
+//! an end-of-file marker. Since BGZF files are valid multi-member gzip files
+//! they can also be read with [`MultiGzDecoder`](crate::read::MultiGzDecoder).
+//!
+//! Positions in a BGZF file are expressed as [`VirtualOffset`]s, combining the
+//! offset of a block in the compressed file with an offset within the
+//! uncompressed data of the block.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::prelude::*;
+//! use std::io::{Cursor, SeekFrom};
+//! use flate2::bgzf::{BgzfDecoder, BgzfEncoder};
+//! use flate2::Compression;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let mut e = BgzfEncoder::new(Vec::new(), Compression::default());
+//! e.write_all(b"Hello ")?;
+//! let offset = e.virtual_position();
+//! e.write_all(b"World")?;
+//! let compressed = e.finish()?;
+//!
+//! let mut d = BgzfDecoder::new(Cursor::new(compressed));
+//! d.seek(SeekFrom::Start(offset.into()))?;
+//! let mut s = String::new();
+//! d.read_to_string(&mut s)?;
+//! assert_eq!(s, "World");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use crate::gz::{bad_header, check_trailer, GzBuilder, GzHeader, GzHeaderParser};
+use crate::limits::Limiter;
+use crate::{
+    Compress, Compression, Crc, Decompress, Error, FlushCompress, FlushDecompress, Limits, Status,
+};
+
+/// The largest amount of uncompressed data stored in a block, chosen so that
+/// the block still fits in 64 KiB when its data doesn't compress.
+pub const MAX_BLOCK_DATA: usize = 0xff00;
+
+/// The largest size of a compressed block.
+pub const MAX_BLOCK_SIZE: usize = 0x10000;
+
+/// The empty block terminating a BGZF file.
+pub const EOF_MARKER: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Returns the total size of a BGZF block from the `BC` subfield of the extra
+/// field of its header, if there is one.
+pub(crate) fn block_size(header: &GzHeader) -> Option<usize> {
+    let mut extra = header.extra()?;
+    while extra.len() >= 4 {
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + len)?;
+        if extra[..2] == *b"BC" && len == 2 {
+            return Some(u16::from_le_bytes([data[0], data[1]]) as usize + 1);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+/// A position in a BGZF file: the offset of a block in the compressed file in
+/// the upper 48 bits, and an offset within the uncompressed data of that block
+/// in the lower 16 bits.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VirtualOffset(u64);
+
+impl VirtualOffset {
+    /// Creates a virtual offset from the compressed offset of a block and an
+    /// offset within its uncompressed data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `compressed` doesn't fit in 48 bits.
+    pub fn new(compressed: u64, uncompressed: u16) -> VirtualOffset {
+        assert!(compressed < 1 << 48, "compressed offset out of range");
+        VirtualOffset(compressed << 16 | u64::from(uncompressed))
+    }
+
+    /// The offset of the block in the compressed file.
+    pub fn compressed(&self) -> u64 {
+        self.0 >> 16
+    }
+
+    /// The offset within the uncompressed data of the block.
+    pub fn uncompressed(&self) -> u16 {
+        self.0 as u16
+    }
+}
+
+impl From<u64> for VirtualOffset {
+    fn from(offset: u64) -> VirtualOffset {
+        VirtualOffset(offset)
+    }
+}
+
+impl From<VirtualOffset> for u64 {
+    fn from(offset: VirtualOffset) -> u64 {
+        offset.0
+    }
+}
+
+/// A BGZF encoder.
+///
+/// This structure exposes a [`Write`] interface that splits the data into
+/// blocks of at most [`MAX_BLOCK_DATA`] bytes, compresses each of them into a
+/// gzip member of its own and writes it to the underlying writer. Finishing
+/// the encoder writes out the last block followed by the end-of-file marker.
+///
+/// Flushing the encoder ends the current block, so that everything written so
+/// far can be read back.
+#[derive(Debug)]
+pub struct BgzfEncoder<W: Write> {
+    obj: Option<W>,
+    level: Compression,
+    data: Vec<u8>,
+    buf: Vec<u8>,
+    // The number of compressed bytes in the blocks written out so far,
+    // including the ones still in `buf`.
+    compressed: u64,
+    finished: bool,
+}
+
+impl<W: Write> BgzfEncoder<W> {
+    /// Creates a new encoder which will write BGZF blocks compressed with the
+    /// given level to `w`.
+    pub fn new(w: W, level: Compression) -> BgzfEncoder<W> {
+        BgzfEncoder {
+            obj: Some(w),
+            level,
+            data: Vec::with_capacity(MAX_BLOCK_DATA),
+            buf: Vec::new(),
+            compressed: 0,
+            finished: false,
+        }
+    }
+
+    /// Returns the virtual offset at which the next byte written will be
+    /// found, suitable for building an index of the file.
+    pub fn virtual_position(&self) -> VirtualOffset {
+        VirtualOffset::new(self.compressed, self.data.len() as u16)
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.obj.as_ref().unwrap()
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.obj.as_mut().unwrap()
+    }
+
+    /// Attempt to finish this output stream, writing out the last block and
+    /// the end-of-file marker.
+    ///
+    /// # Panics
+    ///
+    /// Attempts to write data to this stream may result in a panic after this
+    /// function is called.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.end_block()?;
+            self.buf.extend_from_slice(&EOF_MARKER);
+            self.compressed += EOF_MARKER.len() as u64;
+            self.finished = true;
+        }
+        self.dump()
+    }
+
+    /// Consumes this encoder, finishing the stream and returning the
+    /// underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.obj.take().unwrap())
+    }
+
+    fn dump(&mut self) -> io::Result<()> {
+        while !self.buf.is_empty() {
+            let n = self.obj.as_mut().unwrap().write(&self.buf)?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.buf.drain(..n);
+        }
+        Ok(())
+    }
+
+    // Compresses the pending data into a block, queueing it for output.
+    fn end_block(&mut self) -> io::Result<()> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        let block = compress_block(&self.data, self.level)?;
+        self.compressed += block.len() as u64;
+        self.buf.extend_from_slice(&block);
+        self.data.clear();
+        Ok(())
+    }
+}
+
+// Compresses `data` into a complete BGZF block, falling back to stored
+// deflate blocks when it doesn't fit otherwise.
+fn compress_block(data: &[u8], level: Compression) -> io::Result<Vec<u8>> {
+    let header_len = 18;
+    let mut deflated = Vec::with_capacity(MAX_BLOCK_SIZE - header_len - 8);
+    let mut compress = Compress::new(level, false);
+    let status = compress.compress_vec(data, &mut deflated, FlushCompress::Finish)?;
+    if status != Status::StreamEnd {
+        deflated.clear();
+        let mut compress = Compress::new(Compression::none(), false);
+        deflated.reserve(data.len() + 16);
+        compress.compress_vec(data, &mut deflated, FlushCompress::Finish)?;
+    }
+
+    let bsize = (header_len + deflated.len() + 8 - 1) as u16;
+    let mut extra = b"BC\x02\x00".to_vec();
+    extra.extend_from_slice(&bsize.to_le_bytes());
+    let mut block = GzBuilder::new().extra(extra).into_header(level);
+    debug_assert_eq!(block.len(), header_len);
+    block.extend_from_slice(&deflated);
+
+    let mut crc = Crc::new();
+    crc.update(data);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&crc.amount().to_le_bytes());
+    Ok(block)
+}
+
+impl<W: Write> Write for BgzfEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        assert!(!self.finished, "write after the stream was finished");
+        self.dump()?;
+        if self.data.len() == MAX_BLOCK_DATA {
+            self.end_block()?;
+            self.dump()?;
+        }
+        let n = cmp::min(buf.len(), MAX_BLOCK_DATA - self.data.len());
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.end_block()?;
+        }
+        self.dump()?;
+        self.obj.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for BgzfEncoder<W> {
+    fn drop(&mut self) {
+        if self.obj.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// A BGZF decoder.
+///
+/// This structure exposes a [`Read`] and [`BufRead`] interface handing out
+/// the uncompressed data of the blocks read from the underlying reader, whose
+/// checksums are verified along the way.
+///
+/// When the underlying reader implements [`Seek`] the decoder does as well,
+/// with positions being [`VirtualOffset`]s rather than offsets in the
+/// uncompressed data: `SeekFrom::Start` takes a virtual offset converted to a
+/// `u64`, and the position returned by `seek` is one as well. Seeking
+/// relative to the current position or to the end isn't supported.
+#[derive(Debug)]
+pub struct BgzfDecoder<R> {
+    obj: R,
+    block: Vec<u8>,
+    pos: usize,
+    // The offset of the current block, and of the next one, in the
+    // compressed stream.
+    block_offset: u64,
+    next_offset: u64,
+    compressed: Vec<u8>,
+    limiter: Limiter,
+    // Whether a block has been read yet.
+    started: bool,
+}
+
+impl<R> BgzfDecoder<R> {
+    /// Creates a new decoder reading blocks from the start of `r`.
+    pub fn new(r: R) -> BgzfDecoder<R> {
+        BgzfDecoder::new_with_limits(r, Limits::new())
+    }
+
+    /// Creates a new decoder reading blocks from the start of `r`, failing
+    /// once any of the given `limits` is exceeded.
+    ///
+    /// Every block counts as a gzip member, and everything read counts
+    /// towards the limits, including blocks read again after seeking.
+    pub fn new_with_limits(r: R, limits: Limits) -> BgzfDecoder<R> {
+        BgzfDecoder {
+            obj: r,
+            block: Vec::new(),
+            pos: 0,
+            block_offset: 0,
+            next_offset: 0,
+            compressed: Vec::new(),
+            limiter: Limiter::new(limits),
+            started: false,
+        }
+    }
+
+    /// Returns the virtual offset of the next byte to be read.
+    pub fn virtual_position(&self) -> VirtualOffset {
+        VirtualOffset::new(self.block_offset, self.pos as u16)
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+}
+
+impl<R: Read> BgzfDecoder<R> {
+    // Reads and inflates the block at `next_offset`, returning false at EOF.
+    fn read_block(&mut self) -> io::Result<bool> {
+        // Once a limit is exceeded, the block in which it happened is cut
+        // short, and the error is reported when reading past it.
+        if let Err(limit) = self.limiter.check() {
+            let offset = self.next_offset;
+            return Err(Error::LimitExceeded { limit, offset }.into());
+        }
+        self.block_offset = self.next_offset;
+        self.block.clear();
+        self.pos = 0;
+
+        // The fixed part of the header, then the rest of the block once its
+        // size is known.
+        self.compressed.resize(18, 0);
+        let n = read_full(&mut self.obj, &mut self.compressed)?;
+        if n == 0 {
+            return Ok(false);
+        } else if n < 18 {
+            let offset = self.block_offset + n as u64;
+            return Err(Error::Truncated { offset }.into());
+        }
+        if self.started {
+            if let Err(limit) = self.limiter.next_member() {
+                let offset = self.block_offset;
+                return Err(Error::LimitExceeded { limit, offset }.into());
+            }
+        }
+        self.started = true;
+        let mut parser = GzHeaderParser::new();
+        let mut rest = &self.compressed[..];
+        match parser.parse(&mut rest) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(bad_header()),
+            Err(e) => return Err(Error::rebase(e, self.block_offset)),
+        }
+        let size = match block_size(&parser.into()) {
+            Some(size) if size >= 18 + 8 + rest.len() => size,
+            _ => return Err(bad_header()),
+        };
+        let header_len = 18 - rest.len();
+        self.compressed.resize(size, 0);
+        let n = read_full(&mut self.obj, &mut self.compressed[18..])?;
+        if n < size - 18 {
+            let offset = self.block_offset + 18 + n as u64;
+            return Err(Error::Truncated { offset }.into());
+        }
+        self.next_offset += size as u64;
+
+        let (body, trailer) = self.compressed[header_len..].split_at(size - header_len - 8);
+        let body_offset = self.block_offset + header_len as u64;
+        let trailer_offset = body_offset + body.len() as u64;
+        let isize = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if isize as usize > MAX_BLOCK_SIZE {
+            let offset = trailer_offset + 4;
+            return Err(Error::IsizeMismatch { offset }.into());
+        }
+        self.block.reserve(isize as usize);
+        let mut data = Decompress::new(false);
+        let status = data
+            .decompress_vec(body, &mut self.block, FlushDecompress::Finish)
+            .map_err(|err| Error::decompress(err, body_offset + data.total_in()))?;
+        if status != Status::StreamEnd {
+            let offset = trailer_offset;
+            return Err(Error::Truncated { offset }.into());
+        } else if data.total_in() as usize != body.len() {
+            let offset = body_offset + data.total_in();
+            return Err(Error::Data {
+                error: None,
+                offset,
+            }
+            .into());
+        }
+        let mut crc = Crc::new();
+        crc.update(&self.block);
+        check_trailer(trailer, &crc, trailer_offset)?;
+
+        let produced = self.block.len() as u64;
+        if self.limiter.account(body.len() as u64, produced).is_err() {
+            let excess = cmp::min(self.limiter.excess(), produced) as usize;
+            self.block.truncate(self.block.len() - excess);
+        }
+        Ok(true)
+    }
+}
+
+fn read_full<R: Read>(r: &mut R, mut buf: &mut [u8]) -> io::Result<usize> {
+    let len = buf.len();
+    while !buf.is_empty() {
+        match r.read(buf) {
+            Ok(0) => break,
+            Ok(n) => buf = &mut buf[n..],
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len - buf.len())
+}
+
+impl<R: Read> Read for BgzfDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = cmp::min(available.len(), buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for BgzfDecoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Skip over empty blocks, such as the end-of-file marker.
+        while self.pos == self.block.len() {
+            if !self.read_block()? {
+                break;
+            }
+        }
+        Ok(&self.block[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.block.len());
+    }
+}
+
+impl<R: Read + Seek> Seek for BgzfDecoder<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => VirtualOffset::from(offset),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "BGZF streams can only be seeked to a virtual offset",
+                ))
+            }
+        };
+
+        if offset.compressed() != self.block_offset || self.next_offset == 0 {
+            self.obj.seek(SeekFrom::Start(offset.compressed()))?;
+            self.next_offset = offset.compressed();
+            self.read_block()?;
+        }
+        if usize::from(offset.uncompressed()) > self.block.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "virtual offset past the end of its block",
+            ));
+        }
+        self.pos = offset.uncompressed().into();
+        Ok(offset.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::MultiGzDecoder;
+    use rand::{thread_rng, Rng};
+    use std::io::Cursor;
+
+    #[test]
+    fn roundtrip() {
+        let data = crate::test_data(300_000);
+        let mut e = BgzfEncoder::new(Vec::new(), Compression::default());
+        e.write_all(&data).unwrap();
+        let compressed = e.finish().unwrap();
+        assert!(compressed.ends_with(&EOF_MARKER));
+
+        let mut out = Vec::new();
+        BgzfDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+
+        let mut out = Vec::new();
+        MultiGzDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn incompressible_blocks_fit() {
+        let mut rng = thread_rng();
+        let data: Vec<u8> = (0..200_000).map(|_| rng.gen()).collect();
+        let mut e = BgzfEncoder::new(Vec::new(), Compression::best());
+        e.write_all(&data).unwrap();
+        let compressed = e.finish().unwrap();
+
+        let mut out = Vec::new();
+        BgzfDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn empty() {
+        let compressed = BgzfEncoder::new(Vec::new(), Compression::default())
+            .finish()
+            .unwrap();
+        assert_eq!(compressed, EOF_MARKER);
+        let mut out = Vec::new();
+        BgzfDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn limits() {
+        let data = crate::test_data(300_000);
+        let mut e = BgzfEncoder::new(Vec::new(), Compression::default());
+        e.write_all(&data).unwrap();
+        let compressed = e.finish().unwrap();
+
+        let limits = Limits::new().max_output(100_000);
+        let mut d = BgzfDecoder::new_with_limits(&compressed[..], limits);
+        let mut out = Vec::new();
+        let err = d.read_to_end(&mut out).unwrap_err();
+        assert_eq!(out, data[..100_000]);
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(Error::LimitExceeded { limit, .. }) => assert_eq!(limit.limit(), 100_000),
+            _ => panic!("unexpected error: {}", err),
+        }
+
+        let limits = Limits::new().max_members(2);
+        let mut d = BgzfDecoder::new_with_limits(&compressed[..], limits);
+        let mut out = Vec::new();
+        assert!(d.read_to_end(&mut out).is_err());
+        assert_eq!(out.len(), 2 * MAX_BLOCK_DATA);
+    }
+
+    #[test]
+    fn seek_to_recorded_offsets() {
+        let data = crate::test_data(300_000);
+        let mut e = BgzfEncoder::new(Vec::new(), Compression::fast());
+        let mut offsets = Vec::new();
+        for (i, chunk) in data.chunks(10_000).enumerate() {
+            offsets.push((e.virtual_position(), i * 10_000));
+            e.write_all(chunk).unwrap();
+        }
+        let compressed = e.finish().unwrap();
+
+        let mut d = BgzfDecoder::new(Cursor::new(compressed));
+        for &(offset, pos) in offsets.iter().rev() {
+            d.seek(SeekFrom::Start(offset.into())).unwrap();
+            let mut buf = [0; 100];
+            d.read_exact(&mut buf).unwrap();
+            assert_eq!(buf[..], data[pos..pos + 100]);
+        }
+    }
+
+    #[test]
+    fn reader_position_roundtrips() {
+        let data = crate::test_data(300_000);
+        let mut e = BgzfEncoder::new(Vec::new(), Compression::default());
+        e.write_all(&data).unwrap();
+        let compressed = e.finish().unwrap();
+
+        let mut d = BgzfDecoder::new(Cursor::new(compressed));
+        let mut buf = vec![0; 123_456];
+        d.read_exact(&mut buf).unwrap();
+        let offset = d.virtual_position();
+        d.read_exact(&mut buf[..10]).unwrap();
+        d.seek(SeekFrom::Start(0)).unwrap();
+        d.seek(SeekFrom::Start(offset.into())).unwrap();
+        let mut again = [0; 10];
+        d.read_exact(&mut again).unwrap();
+        assert_eq!(again[..], buf[..10]);
+        assert!(d.seek(SeekFrom::Current(1)).is_err());
+    }
+
+    #[test]
+    fn corrupt_block() {
+        let mut e = BgzfEncoder::new(Vec::new(), Compression::default());
+        e.write_all(b"hello world").unwrap();
+        let mut compressed = e.finish().unwrap();
+        compressed[20] ^= 0xff;
+        let mut out = Vec::new();
+        assert!(BgzfDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .is_err());
+    }
+}
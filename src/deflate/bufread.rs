@@ -2,8 +2,10 @@ use std::io;
 use std::io::prelude::*;
 use std::mem;
 
+use crate::limits::Limiter;
+use crate::multi::MultiDecompress;
 use crate::zio;
-use crate::{Compress, Decompress};
+use crate::{Compress, DecoderOptions, Decompress, EncoderOptions, Limits, StreamRange};
 
 /// A DEFLATE encoder, or compressor.
 ///
@@ -52,6 +54,25 @@ impl<R: BufRead> DeflateEncoder<R> {
             data: Compress::new(level, false),
         }
     }
+
+    /// Creates a new encoder like `new`, which also applies the given
+    /// `options`.
+    pub fn new_with_options(
+        r: R,
+        level: crate::Compression,
+        options: EncoderOptions,
+    ) -> DeflateEncoder<R> {
+        DeflateEncoder::new_with_compress(r, options.compress(level, false))
+    }
+
+    /// Creates a new encoder with the given `compression` settings which will
+    /// read uncompressed data from the given stream `r` and emit the compressed stream.
+    pub fn new_with_compress(r: R, compression: Compress) -> DeflateEncoder<R> {
+        DeflateEncoder {
+            obj: r,
+            data: compression,
+        }
+    }
 }
 
 pub fn reset_encoder_data<R>(zlib: &mut DeflateEncoder<R>) {
@@ -158,19 +179,50 @@ impl<W: BufRead + Write> Write for DeflateEncoder<W> {
 pub struct DeflateDecoder<R> {
     obj: R,
     data: Decompress,
+    limiter: Limiter,
+    report_truncation: bool,
 }
 
 pub fn reset_decoder_data<R>(zlib: &mut DeflateDecoder<R>) {
-    zlib.data = Decompress::new(false);
+    zlib.data.reset(false);
+    zlib.limiter.reset();
 }
 
 impl<R: BufRead> DeflateDecoder<R> {
     /// Creates a new decoder which will decompress data read from the given
     /// stream.
     pub fn new(r: R) -> DeflateDecoder<R> {
+        DeflateDecoder::new_with_limits(r, Limits::new())
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, using the given `decompression` settings.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> DeflateDecoder<R> {
+        DeflateDecoder::new_with_decompress_and_options(r, decompression, DecoderOptions::new())
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, failing once any of the given `limits` is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> DeflateDecoder<R> {
+        DeflateDecoder::new_with_options(r, DecoderOptions::new().limits(limits))
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, as configured by `options`.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> DeflateDecoder<R> {
+        DeflateDecoder::new_with_decompress_and_options(r, options.decompress(false), options)
+    }
+
+    pub(crate) fn new_with_decompress_and_options(
+        r: R,
+        data: Decompress,
+        options: DecoderOptions,
+    ) -> DeflateDecoder<R> {
         DeflateDecoder {
             obj: r,
-            data: Decompress::new(false),
+            data,
+            limiter: Limiter::new(options.limits),
+            report_truncation: options.report_truncation,
         }
     }
 }
@@ -191,9 +243,15 @@ impl<R> DeflateDecoder<R> {
     /// Resets the state of this decoder's data
     ///
     /// This will reset the internal state of this decoder. It will continue
-    /// reading from the same stream.
+    /// reading from the same stream, and keeps counting towards the same
+    /// limits.
     pub fn reset_data(&mut self) {
-        reset_decoder_data(self);
+        self.data.reset(false);
+    }
+
+    /// Returns the limits' state, shared by all the members of a gzip stream.
+    pub(crate) fn limiter(&mut self) -> &mut Limiter {
+        &mut self.limiter
     }
 
     /// Acquires a reference to the underlying stream
@@ -230,7 +288,13 @@ impl<R> DeflateDecoder<R> {
 
 impl<R: BufRead> Read for DeflateDecoder<R> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
-        zio::read(&mut self.obj, &mut self.data, into)
+        zio::read_limited(
+            &mut self.obj,
+            &mut self.data,
+            into,
+            &mut self.limiter,
+            self.report_truncation,
+        )
     }
 }
 
@@ -243,3 +307,162 @@ impl<W: BufRead + Write> Write for DeflateDecoder<W> {
         self.get_mut().flush()
     }
 }
+
+/// A decoder for a concatenation of deflate streams.
+///
+/// This structure implements a [`Read`] interface. When read from, it reads
+/// compressed data from the underlying [`BufRead`] and provides the
+/// uncompressed data of all the deflate streams it holds, one after the other,
+/// until the end of the input. [`take_streams`](Self::take_streams) tells where
+/// each of them lies in the input and in the output.
+///
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+/// [`BufRead`]: https://doc.rust-lang.org/std/io/trait.BufRead.html
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::bufread::MultiDeflateDecoder;
+/// use flate2::write::DeflateEncoder;
+/// use flate2::Compression;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut bytes = Vec::new();
+/// for part in ["Hello ", "World"] {
+///     let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
+///     e.write_all(part.as_bytes())?;
+///     bytes.extend(e.finish()?);
+/// }
+///
+/// let mut d = MultiDeflateDecoder::new(&bytes[..]);
+/// let mut s = String::new();
+/// d.read_to_string(&mut s)?;
+/// assert_eq!(s, "Hello World");
+///
+/// let streams = d.take_streams();
+/// assert_eq!(streams.len(), 2);
+/// assert_eq!(streams[1].uncompressed(), 6..11);
+/// assert_eq!(streams[1].compressed().end, bytes.len() as u64);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MultiDeflateDecoder<R> {
+    obj: R,
+    data: MultiDecompress,
+    limiter: Limiter,
+    report_truncation: bool,
+}
+
+impl<R: BufRead> MultiDeflateDecoder<R> {
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream.
+    pub fn new(r: R) -> MultiDeflateDecoder<R> {
+        MultiDeflateDecoder::new_with_options(r, DecoderOptions::new())
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, as configured by `options`.
+    ///
+    /// The limits of `options` apply to all the streams together.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> MultiDeflateDecoder<R> {
+        MultiDeflateDecoder {
+            obj: r,
+            data: MultiDecompress::new(options.decompress(false), false),
+            limiter: Limiter::new(options.limits),
+            report_truncation: options.report_truncation,
+        }
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, using the given `decompression` settings.
+    ///
+    /// `decompression` is reset for each stream after the first.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> MultiDeflateDecoder<R> {
+        MultiDeflateDecoder {
+            obj: r,
+            data: MultiDecompress::new(decompression, false),
+            limiter: Limiter::new(Limits::new()),
+            report_truncation: false,
+        }
+    }
+}
+
+impl<R> MultiDeflateDecoder<R> {
+    /// Resets the state of this decoder entirely, swapping out the input
+    /// stream for another.
+    ///
+    /// This will reset the internal state of this decoder and replace the
+    /// input stream with the one provided, returning the previous input
+    /// stream. Future data read from this decoder will be the decompressed
+    /// version of `r`'s data.
+    pub fn reset(&mut self, r: R) -> R {
+        self.data.reset();
+        self.limiter.reset();
+        mem::replace(&mut self.obj, r)
+    }
+
+    /// Returns where the streams that were decoded to the end since the
+    /// last call lie in the input and in the output.
+    ///
+    /// The streams are kept track of until this is called.
+    pub fn take_streams(&mut self) -> Vec<StreamRange> {
+        self.data.take_streams()
+    }
+
+    /// Acquires a reference to the underlying stream
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying stream
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+
+    /// Returns the number of bytes that the decompressor has consumed, over
+    /// all the streams.
+    ///
+    /// Note that this will likely be smaller than what the decompressor
+    /// actually read from the underlying stream due to buffering.
+    pub fn total_in(&self) -> u64 {
+        self.data.total_in()
+    }
+
+    /// Returns the number of bytes that the decompressor has produced, over
+    /// all the streams.
+    pub fn total_out(&self) -> u64 {
+        self.data.total_out()
+    }
+}
+
+impl<R: BufRead> Read for MultiDeflateDecoder<R> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        zio::read_limited(
+            &mut self.obj,
+            &mut self.data,
+            into,
+            &mut self.limiter,
+            self.report_truncation,
+        )
+    }
+}
+
+impl<R: BufRead + Write> Write for MultiDeflateDecoder<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
@@ -8,8 +8,8 @@ mod tests {
 
     use rand::{thread_rng, Rng};
 
-    use super::{read, write};
-    use crate::Compression;
+    use super::{bufread, read, write};
+    use crate::{Compression, EncoderOptions, Error, LimitKind, Limits};
 
     #[test]
     fn roundtrip() {
@@ -190,4 +190,145 @@ mod tests {
             v == w.finish().unwrap().finish().unwrap()
         }
     }
+
+    fn limit_kind(err: std::io::Error) -> LimitKind {
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(Error::LimitExceeded { limit, .. }) => limit.kind(),
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn reader_limits() {
+        let mut w = write::DeflateEncoder::new(Vec::new(), Compression::default());
+        w.write_all(&[7; 100_000]).unwrap();
+        let data = w.finish().unwrap();
+
+        let limits = Limits::new().max_output(100_000);
+        let mut r = read::DeflateDecoder::new_with_limits(&data[..], limits);
+        assert_eq!(r.read_to_end(&mut Vec::new()).unwrap(), 100_000);
+
+        let limits = Limits::new().max_output(99_999);
+        let mut r = read::DeflateDecoder::new_with_limits(&data[..], limits);
+        let mut out = Vec::new();
+        let err = r.read_to_end(&mut out).unwrap_err();
+        assert_eq!(limit_kind(err), LimitKind::Output);
+        assert!(out.len() <= 99_999);
+        let err = r.read(&mut [0; 10]).unwrap_err();
+        assert_eq!(limit_kind(err), LimitKind::Output);
+
+        let limits = Limits::new().max_ratio(100);
+        let mut r = read::DeflateDecoder::new_with_limits(&data[..], limits);
+        let err = r.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(limit_kind(err), LimitKind::Ratio);
+    }
+
+    #[test]
+    fn writer_limits() {
+        let mut w = write::DeflateEncoder::new(Vec::new(), Compression::default());
+        w.write_all(&[7; 100_000]).unwrap();
+        let data = w.finish().unwrap();
+
+        let limits = Limits::new().max_output(1000);
+        let mut w = write::DeflateDecoder::new_with_limits(Vec::new(), limits);
+        let err = w.write_all(&data).unwrap_err();
+        assert_eq!(limit_kind(err), LimitKind::Output);
+        assert!(w.try_finish().is_err());
+        assert_eq!(w.get_ref().len(), 1000);
+    }
+
+    #[test]
+    fn multi_stream() {
+        let parts: [&[u8]; 3] = [b"hello ", b"", b"world"];
+        let mut data = Vec::new();
+        let mut ends = Vec::new();
+        for part in &parts {
+            let mut w = write::DeflateEncoder::new(Vec::new(), Compression::default());
+            w.write_all(part).unwrap();
+            data.extend(w.finish().unwrap());
+            ends.push(data.len() as u64);
+        }
+
+        let mut r = bufread::MultiDeflateDecoder::new(&data[..]);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello world");
+        let streams = r.take_streams();
+        let compressed = streams
+            .iter()
+            .map(|s| s.compressed().end)
+            .collect::<Vec<_>>();
+        let uncompressed = streams.iter().map(|s| s.uncompressed()).collect::<Vec<_>>();
+        assert_eq!(compressed, ends);
+        assert_eq!(uncompressed, [0..6, 6..6, 6..11]);
+
+        let mut r = read::MultiDeflateDecoder::new(&data[..]);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello world");
+
+        let mut w = write::MultiDeflateDecoder::new(Vec::new());
+        for chunk in data.chunks(3) {
+            w.write_all(chunk).unwrap();
+        }
+        w.try_finish().unwrap();
+        assert_eq!(w.take_streams(), streams);
+        assert_eq!(w.finish().unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn rsyncable() {
+        let mut rng = thread_rng();
+        let letters = b"abcdefghijklmnopqrstuvwxyz \n";
+        let data: Vec<u8> = (0..500_000)
+            .map(|_| letters[rng.gen_range(0..letters.len())])
+            .collect();
+        let mut edited = data.clone();
+        edited.splice(1000..1010, b"inserted".iter().copied());
+
+        let options = EncoderOptions::new().rsyncable(true);
+        let compress = |data: &[u8]| {
+            let mut e = write::DeflateEncoder::new_with_options(
+                Vec::new(),
+                Compression::default(),
+                options,
+            );
+            for chunk in data.chunks(777) {
+                e.write_all(chunk).unwrap();
+            }
+            e.finish().unwrap()
+        };
+        let (a, b) = (compress(&data), compress(&edited));
+        // Past the first full flush after the edit, the output is the same.
+        let same = a
+            .iter()
+            .rev()
+            .zip(b.iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count();
+        assert!(same > a.len() * 9 / 10, "{} of {}", same, a.len());
+
+        let mut out = Vec::new();
+        read::DeflateDecoder::new(&a[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out == data);
+
+        // Small reads leave full flushes incomplete for lack of output space.
+        let mut r =
+            read::DeflateEncoder::new_with_options(&edited[..], Compression::best(), options);
+        let mut compressed = Vec::new();
+        let mut buf = [0; 3];
+        loop {
+            match r.read(&mut buf).unwrap() {
+                0 => break,
+                n => compressed.extend_from_slice(&buf[..n]),
+            }
+        }
+        out.clear();
+        read::DeflateDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out == edited);
+    }
 }
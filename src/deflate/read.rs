@@ -3,6 +3,7 @@ use std::io::prelude::*;
 
 use super::bufread;
 use crate::bufreader::BufReader;
+use crate::{DecoderOptions, EncoderOptions, Limits, StreamRange};
 
 /// A DEFLATE encoder, or compressor.
 ///
@@ -45,6 +46,26 @@ impl<R: Read> DeflateEncoder<R> {
             inner: bufread::DeflateEncoder::new(BufReader::new(r), level),
         }
     }
+
+    /// Creates a new encoder like `new`, which also applies the given
+    /// `options`.
+    pub fn new_with_options(
+        r: R,
+        level: crate::Compression,
+        options: EncoderOptions,
+    ) -> DeflateEncoder<R> {
+        DeflateEncoder {
+            inner: bufread::DeflateEncoder::new_with_options(BufReader::new(r), level, options),
+        }
+    }
+
+    /// Creates a new encoder with the given `compression` settings which will
+    /// read uncompressed data from the given stream `r` and emit the compressed stream.
+    pub fn new_with_compress(r: R, compression: crate::Compress) -> DeflateEncoder<R> {
+        DeflateEncoder {
+            inner: bufread::DeflateEncoder::new_with_compress(BufReader::new(r), compression),
+        }
+    }
 }
 
 impl<R> DeflateEncoder<R> {
@@ -170,6 +191,30 @@ impl<R: Read> DeflateDecoder<R> {
             inner: bufread::DeflateDecoder::new(BufReader::with_buf(buf, r)),
         }
     }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream `r`, along with `decompression` settings.
+    pub fn new_with_decompress(r: R, decompression: crate::Decompress) -> DeflateDecoder<R> {
+        let r = BufReader::with_buf(vec![0; 32 * 1024], r);
+        DeflateDecoder {
+            inner: bufread::DeflateDecoder::new_with_decompress(r, decompression),
+        }
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, failing once any of the given `limits` is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> DeflateDecoder<R> {
+        DeflateDecoder::new_with_options(r, DecoderOptions::new().limits(limits))
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, as configured by `options`.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> DeflateDecoder<R> {
+        let r = BufReader::with_buf(vec![0; 32 * 1024], r);
+        DeflateDecoder {
+            inner: bufread::DeflateDecoder::new_with_options(r, options),
+        }
+    }
 }
 
 impl<R> DeflateDecoder<R> {
@@ -239,3 +284,152 @@ impl<W: Read + Write> Write for DeflateDecoder<W> {
         self.get_mut().flush()
     }
 }
+
+/// A decoder for a concatenation of deflate streams.
+///
+/// This structure implements a [`Read`] interface. When read from, it reads
+/// compressed data from the underlying [`Read`] and provides the uncompressed
+/// data of all the deflate streams it holds, one after the other, until the end
+/// of the input. [`take_streams`](Self::take_streams) tells where each of them
+/// lies in the input and in the output.
+///
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::read::MultiDeflateDecoder;
+/// use flate2::write::DeflateEncoder;
+/// use flate2::Compression;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut bytes = Vec::new();
+/// for part in ["Hello ", "World"] {
+///     let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
+///     e.write_all(part.as_bytes())?;
+///     bytes.extend(e.finish()?);
+/// }
+///
+/// let mut d = MultiDeflateDecoder::new(&bytes[..]);
+/// let mut s = String::new();
+/// d.read_to_string(&mut s)?;
+/// assert_eq!(s, "Hello World");
+/// assert_eq!(d.take_streams().len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MultiDeflateDecoder<R> {
+    inner: bufread::MultiDeflateDecoder<BufReader<R>>,
+}
+
+impl<R: Read> MultiDeflateDecoder<R> {
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream.
+    pub fn new(r: R) -> MultiDeflateDecoder<R> {
+        MultiDeflateDecoder {
+            inner: bufread::MultiDeflateDecoder::new(BufReader::new(r)),
+        }
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, as configured by `options`.
+    ///
+    /// The limits of `options` apply to all the streams together.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> MultiDeflateDecoder<R> {
+        MultiDeflateDecoder {
+            inner: bufread::MultiDeflateDecoder::new_with_options(BufReader::new(r), options),
+        }
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, using the given `decompression` settings.
+    ///
+    /// `decompression` is reset for each stream after the first.
+    pub fn new_with_decompress(r: R, decompression: crate::Decompress) -> MultiDeflateDecoder<R> {
+        MultiDeflateDecoder {
+            inner: bufread::MultiDeflateDecoder::new_with_decompress(
+                BufReader::new(r),
+                decompression,
+            ),
+        }
+    }
+}
+
+impl<R> MultiDeflateDecoder<R> {
+    /// Resets the state of this decoder entirely, swapping out the input
+    /// stream for another.
+    ///
+    /// This will reset the internal state of this decoder and replace the
+    /// input stream with the one provided, returning the previous input
+    /// stream. Future data read from this decoder will be the decompressed
+    /// version of `r`'s data.
+    ///
+    /// Note that there may be currently buffered data when this function is
+    /// called, and in that case the buffered data is discarded.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.get_mut().reset(r)
+    }
+
+    /// Returns where the streams that were decoded to the end since the
+    /// last call lie in the input and in the output.
+    ///
+    /// The streams are kept track of until this is called.
+    pub fn take_streams(&mut self) -> Vec<StreamRange> {
+        self.inner.take_streams()
+    }
+
+    /// Acquires a reference to the underlying stream
+    pub fn get_ref(&self) -> &R {
+        self.inner.get_ref().get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying stream
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.inner.get_mut().get_mut()
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    ///
+    /// Note that there may be buffered bytes which are not re-acquired as part
+    /// of this transition. It's recommended to only call this function after
+    /// EOF has been reached.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner().into_inner()
+    }
+
+    /// Returns the number of bytes that the decompressor has consumed, over
+    /// all the streams.
+    ///
+    /// Note that this will likely be smaller than what the decompressor
+    /// actually read from the underlying stream due to buffering.
+    pub fn total_in(&self) -> u64 {
+        self.inner.total_in()
+    }
+
+    /// Returns the number of bytes that the decompressor has produced, over
+    /// all the streams.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out()
+    }
+}
+
+impl<R: Read> Read for MultiDeflateDecoder<R> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(into)
+    }
+}
+
+impl<R: Read + Write> Write for MultiDeflateDecoder<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
@@ -0,0 +1,716 @@
+//! Support for dictzip files, gzip files allowing random access.
+//!
+//! A dictzip file is a single gzip member whose data is split into chunks of a
+//! fixed uncompressed length, each compressed independently of the others
+//! thanks to a full flush of the compressor at its end. The extra field of the
+//! header holds an `RA` subfield listing the compressed size of every chunk,
+//! so that any of them can be located and decompressed on its own. Since
+//! dictzip files are valid gzip files, they can also be read with
+//! [`GzDecoder`](crate::read::GzDecoder).
+//!
+//! The chunk table has to be written in the header before the data, so the
+//! encoder needs to seek back into its output once done.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::prelude::*;
+//! use std::io::{Cursor, SeekFrom};
+//! use flate2::dictzip::{DictzipDecoder, DictzipEncoder};
+//! use flate2::Compression;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let mut e = DictzipEncoder::new(Cursor::new(Vec::new()), Compression::default());
+//! e.write_all(b"Hello World")?;
+//! let compressed = e.finish()?.into_inner();
+//!
+//! let mut d = DictzipDecoder::new(Cursor::new(compressed))?;
+//! d.seek(SeekFrom::Start(6))?;
+//! let mut s = String::new();
+//! d.read_to_string(&mut s)?;
+//! assert_eq!(s, "World");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp;
+use std::convert::{TryFrom, TryInto};
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use crate::gz::{GzBuilder, GzHeader, GzHeaderParser};
+use crate::{
+    Compress, Compression, Crc, Decompress, Error, FlushCompress, FlushDecompress, Status,
+};
+
+/// The default uncompressed length of chunks, which is also the largest one
+/// whose compressed size is guaranteed to fit in the chunk table.
+pub const DEFAULT_CHUNK_LEN: usize = 58315;
+
+// The offset of the `RA` subfield in the header, right after XLEN.
+const TABLE_OFFSET: usize = 12;
+// The `RA` subfield header, followed by its version, chunk length and count.
+const TABLE_HEADER_LEN: usize = 10;
+
+/// A builder for dictzip encoders, configuring the gzip header and how the
+/// data is split into chunks.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use std::io::Cursor;
+/// use flate2::dictzip::DictzipBuilder;
+/// use flate2::{Compression, GzBuilder};
+///
+/// let mut e = DictzipBuilder::new()
+///     .gz_header(GzBuilder::new().filename("words.txt"))
+///     .capacity(1024 * 1024)
+///     .write(Cursor::new(Vec::new()), Compression::default());
+/// e.write_all(b"Hello World").unwrap();
+/// let compressed = e.finish().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct DictzipBuilder {
+    header: GzBuilder,
+    chunk_len: usize,
+    capacity: Option<u64>,
+}
+
+impl Default for DictzipBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DictzipBuilder {
+    /// Create a new blank builder, using chunks of [`DEFAULT_CHUNK_LEN`]
+    /// bytes and a chunk table as large as the gzip header allows.
+    pub fn new() -> DictzipBuilder {
+        DictzipBuilder {
+            header: GzBuilder::new(),
+            chunk_len: DEFAULT_CHUNK_LEN,
+            capacity: None,
+        }
+    }
+
+    /// Configure the gzip header. The `RA` subfield is inserted at the start
+    /// of its extra field, before any subfield it already holds.
+    pub fn gz_header(mut self, header: GzBuilder) -> DictzipBuilder {
+        self.header = header;
+        self
+    }
+
+    /// Configure the uncompressed length of the chunks. Shorter chunks make
+    /// random access cheaper at the expense of the compression ratio.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_len` is zero or larger than [`DEFAULT_CHUNK_LEN`].
+    pub fn chunk_len(mut self, chunk_len: usize) -> DictzipBuilder {
+        assert!(
+            chunk_len > 0 && chunk_len <= DEFAULT_CHUNK_LEN,
+            "invalid dictzip chunk length"
+        );
+        self.chunk_len = chunk_len;
+        self
+    }
+
+    /// Configure the largest amount of uncompressed data the file will hold.
+    ///
+    /// Room for the chunk table is reserved in the header before any data is
+    /// written, which by default takes up to 64 KiB. Giving the size of the
+    /// data ahead of time keeps the header as small as possible.
+    pub fn capacity(mut self, capacity: u64) -> DictzipBuilder {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Consume this builder, creating an encoder writing the dictzip file to
+    /// `w`, starting from its current position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the extra field of the gzip header leaves no room for the
+    /// chunk table.
+    pub fn write<W: Write + Seek>(self, w: W, level: Compression) -> DictzipEncoder<W> {
+        let room = (u16::MAX as usize)
+            .checked_sub(self.header.extra_len() + TABLE_HEADER_LEN)
+            .map_or(0, |room| room / 2);
+        let needed = self.capacity.map_or(usize::MAX, |capacity| {
+            let chunks = capacity.div_ceil(self.chunk_len as u64);
+            cmp::max(chunks, 1).try_into().unwrap_or(usize::MAX)
+        });
+        let capacity = cmp::min(room, needed);
+        assert!(capacity > 0, "no room left for the dictzip chunk table");
+
+        let mut table = vec![0; TABLE_HEADER_LEN + 2 * capacity];
+        table[..2].copy_from_slice(b"RA");
+        table[2..4].copy_from_slice(&(6 + 2 * capacity as u16).to_le_bytes());
+        table[4..6].copy_from_slice(&1u16.to_le_bytes());
+        table[6..8].copy_from_slice(&(self.chunk_len as u16).to_le_bytes());
+        let header = self.header.prepend_extra(&table).into_header(level);
+
+        DictzipEncoder {
+            obj: Some(w),
+            header,
+            header_pos: None,
+            capacity,
+            chunk_len: self.chunk_len,
+            sizes: Vec::new(),
+            data: Vec::with_capacity(self.chunk_len),
+            compress: Compress::new(level, false),
+            crc: Crc::new(),
+            buf: Vec::new(),
+            finished: false,
+            patched: false,
+        }
+    }
+}
+
+/// A dictzip encoder.
+///
+/// This structure exposes a [`Write`] interface compressing the data into a
+/// gzip file made of independently compressed chunks, whose sizes are written
+/// to the header once the stream is finished by seeking back to it.
+#[derive(Debug)]
+pub struct DictzipEncoder<W: Write + Seek> {
+    obj: Option<W>,
+    header: Vec<u8>,
+    header_pos: Option<u64>,
+    capacity: usize,
+    chunk_len: usize,
+    sizes: Vec<u16>,
+    data: Vec<u8>,
+    compress: Compress,
+    crc: Crc,
+    buf: Vec<u8>,
+    finished: bool,
+    patched: bool,
+}
+
+impl<W: Write + Seek> DictzipEncoder<W> {
+    /// Creates a new encoder with the defaults of [`DictzipBuilder`].
+    pub fn new(w: W, level: Compression) -> DictzipEncoder<W> {
+        DictzipBuilder::new().write(w, level)
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.obj.as_ref().unwrap()
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt
+    /// this object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.obj.as_mut().unwrap()
+    }
+
+    /// Attempt to finish this output stream, writing out the last chunk and
+    /// the trailer, and filling in the chunk table.
+    ///
+    /// # Panics
+    ///
+    /// Attempts to write data to this stream may result in a panic after this
+    /// function is called.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.start()?;
+            self.end_chunk(FlushCompress::Finish)?;
+            self.buf.extend_from_slice(&self.crc.sum().to_le_bytes());
+            self.buf.extend_from_slice(&self.crc.amount().to_le_bytes());
+            self.finished = true;
+        }
+        self.dump()?;
+        if !self.patched {
+            let table = &mut self.header[TABLE_OFFSET..];
+            table[8..10].copy_from_slice(&(self.sizes.len() as u16).to_le_bytes());
+            for (i, size) in self.sizes.iter().enumerate() {
+                table[10 + 2 * i..12 + 2 * i].copy_from_slice(&size.to_le_bytes());
+            }
+            let obj = self.obj.as_mut().unwrap();
+            let end = obj.stream_position()?;
+            obj.seek(SeekFrom::Start(self.header_pos.unwrap()))?;
+            obj.write_all(&self.header)?;
+            obj.seek(SeekFrom::Start(end))?;
+            self.patched = true;
+        }
+        Ok(())
+    }
+
+    /// Consumes this encoder, finishing the stream and returning the
+    /// underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.obj.take().unwrap())
+    }
+
+    // Queues the header, with an empty chunk table for now.
+    fn start(&mut self) -> io::Result<()> {
+        if self.header_pos.is_none() {
+            self.header_pos = Some(self.obj.as_mut().unwrap().stream_position()?);
+            self.buf.extend_from_slice(&self.header);
+        }
+        Ok(())
+    }
+
+    fn dump(&mut self) -> io::Result<()> {
+        while !self.buf.is_empty() {
+            let n = self.obj.as_mut().unwrap().write(&self.buf)?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.buf.drain(..n);
+        }
+        Ok(())
+    }
+
+    // Compresses the pending data as a chunk, queueing it for output.
+    fn end_chunk(&mut self, flush: FlushCompress) -> io::Result<()> {
+        if self.sizes.len() == self.capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the dictzip chunk table is full",
+            ));
+        }
+        let before = self.compress.total_out();
+        let mut input = &self.data[..];
+        loop {
+            self.buf.reserve(input.len() + 1024);
+            let before_in = self.compress.total_in();
+            let status = self.compress.compress_vec(input, &mut self.buf, flush)?;
+            input = &input[(self.compress.total_in() - before_in) as usize..];
+            let done = match flush {
+                FlushCompress::Finish => status == Status::StreamEnd,
+                _ => input.is_empty() && self.buf.len() < self.buf.capacity(),
+            };
+            if done {
+                break;
+            }
+        }
+        let size = u16::try_from(self.compress.total_out() - before).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "compressed dictzip chunk is too large",
+            )
+        })?;
+        self.sizes.push(size);
+        self.data.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Write for DictzipEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        assert!(!self.finished, "write after the stream was finished");
+        self.start()?;
+        self.dump()?;
+        // Chunks are only completed once there is data following them, since
+        // the last one has to end the deflate stream.
+        if self.data.len() == self.chunk_len && !buf.is_empty() {
+            self.end_chunk(FlushCompress::Full)?;
+            self.dump()?;
+        }
+        let n = cmp::min(buf.len(), self.chunk_len - self.data.len());
+        self.data.extend_from_slice(&buf[..n]);
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.dump()?;
+        self.obj.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for DictzipEncoder<W> {
+    fn drop(&mut self) {
+        if self.obj.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// A dictzip decoder.
+///
+/// This structure exposes a [`Read`] and [`BufRead`] interface over the
+/// uncompressed data of a dictzip file, and implements [`Seek`] in terms of
+/// positions in the uncompressed data by only decompressing the chunk
+/// holding the new position.
+///
+/// The chunk table is checked against the size of the file and the length of
+/// the data recorded in the trailer when the decoder is created, and every
+/// chunk is checked to decompress to exactly the expected length. The
+/// checksum of the data is verified when it is read through from its start.
+#[derive(Debug)]
+pub struct DictzipDecoder<R> {
+    obj: R,
+    header: GzHeader,
+    chunk_len: usize,
+    // The offset of every chunk in the file, followed by the end of the last.
+    offsets: Vec<u64>,
+    len: u64,
+    crc: u32,
+    pos: u64,
+    chunk: Option<usize>,
+    data: Vec<u8>,
+    compressed: Vec<u8>,
+    inflate: Decompress,
+    // The checksum of the chunks read in order from the first one so far.
+    running_crc: Option<(Crc, usize)>,
+}
+
+impl<R: Read + Seek> DictzipDecoder<R> {
+    /// Creates a new decoder for the dictzip file read from `r`, starting at
+    /// its current position, reading its header and chunk table.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error reading from `r` is returned, as well as errors for gzip
+    /// files without a valid chunk table.
+    pub fn new(mut r: R) -> io::Result<DictzipDecoder<R>> {
+        let mut parser = GzHeaderParser::new();
+        parser.parse(&mut r)?;
+        let header = GzHeader::from(parser);
+        let (chunk_len, sizes) = read_table(&header)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a dictzip file"))?;
+
+        let mut offsets = vec![r.stream_position()?];
+        for size in sizes {
+            offsets.push(offsets[offsets.len() - 1] + u64::from(size));
+        }
+        let end = offsets[offsets.len() - 1];
+        if r.seek(SeekFrom::End(0))? != end + 8 {
+            return Err(bad_table());
+        }
+        let mut trailer = [0; 8];
+        r.seek(SeekFrom::Start(end))?;
+        r.read_exact(&mut trailer)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let isize = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+        // The trailer records the length modulo 2^32, which together with the
+        // number of chunks determines it, as only the last may be partial.
+        let chunks = offsets.len() as u64 - 1;
+        let chunk_len = chunk_len as u64;
+        let min = chunks.saturating_sub(1) * chunk_len;
+        let len = min + (u64::from(isize).wrapping_sub(min) & 0xffff_ffff);
+        let len = match len {
+            0 if chunks <= 1 => 0,
+            len if len > min && len <= chunks * chunk_len => len,
+            _ => return Err(bad_table()),
+        };
+
+        Ok(DictzipDecoder {
+            obj: r,
+            header,
+            chunk_len: chunk_len as usize,
+            offsets,
+            len,
+            crc,
+            pos: 0,
+            chunk: None,
+            data: Vec::new(),
+            compressed: Vec::new(),
+            inflate: Decompress::new(false),
+            running_crc: None,
+        })
+    }
+
+    // Decompresses chunk `i`, checking that it matches the table.
+    fn load(&mut self, i: usize) -> io::Result<()> {
+        self.chunk = None;
+        let start = self.offsets[i];
+        let size = (self.offsets[i + 1] - start) as usize;
+        self.obj.seek(SeekFrom::Start(start))?;
+        self.compressed.resize(size, 0);
+        self.obj.read_exact(&mut self.compressed)?;
+
+        let last = i + 2 == self.offsets.len();
+        let expected = cmp::min(
+            self.chunk_len as u64,
+            self.len - (i * self.chunk_len) as u64,
+        );
+        let flush = if last {
+            FlushDecompress::Finish
+        } else {
+            FlushDecompress::Sync
+        };
+        self.data.clear();
+        self.data.reserve(expected as usize + 1);
+        self.inflate.reset(false);
+        let offset = self.offsets[i];
+        let status = self
+            .inflate
+            .decompress_vec(&self.compressed, &mut self.data, flush)
+            .map_err(|err| Error::decompress(err, offset + self.inflate.total_in()))?;
+        if self.inflate.total_in() != size as u64
+            || self.data.len() as u64 != expected
+            || (status == Status::StreamEnd) != last
+        {
+            return Err(bad_table());
+        }
+
+        if i == 0 {
+            self.running_crc = Some((Crc::new(), 0));
+        }
+        match &mut self.running_crc {
+            Some((crc, next)) if *next == i => {
+                crc.update(&self.data);
+                *next += 1;
+                if last && crc.sum() != self.crc {
+                    let offset = self.offsets[i + 1];
+                    return Err(Error::DataCrcMismatch { offset }.into());
+                }
+            }
+            _ => self.running_crc = None,
+        }
+        self.chunk = Some(i);
+        Ok(())
+    }
+}
+
+impl<R> DictzipDecoder<R> {
+    /// Returns the header of the dictzip file.
+    pub fn header(&self) -> &GzHeader {
+        &self.header
+    }
+
+    /// The uncompressed length of the chunks.
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+
+    /// The total length of the uncompressed data.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// Note that mutation of the reader may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+}
+
+// Returns the chunk length and the compressed chunk sizes from the `RA`
+// subfield of the extra field of `header`.
+fn read_table(header: &GzHeader) -> Option<(usize, Vec<u16>)> {
+    let mut extra = header.extra()?;
+    while extra.len() >= 4 {
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + len)?;
+        if extra[..2] == *b"RA" && len >= 6 {
+            let field = |i: usize| u16::from_le_bytes([data[2 * i], data[2 * i + 1]]);
+            let (version, chunk_len, count) = (field(0), field(1), field(2) as usize);
+            if version != 1 || chunk_len == 0 || len < 6 + 2 * count {
+                return None;
+            }
+            return Some((chunk_len.into(), (3..3 + count).map(field).collect()));
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+fn bad_table() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "dictzip chunk table doesn't match the compressed data",
+    )
+}
+
+impl<R: Read + Seek> Read for DictzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = cmp::min(available.len(), buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> BufRead for DictzipDecoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.len {
+            return Ok(&[]);
+        }
+        let i = (self.pos / self.chunk_len as u64) as usize;
+        if self.chunk != Some(i) {
+            self.load(i)?;
+        }
+        let offset = (self.pos - (i * self.chunk_len) as u64) as usize;
+        Ok(&self.data[offset..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+    }
+}
+
+impl<R: Read + Seek> Seek for DictzipDecoder<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::GzDecoder;
+    use rand::{thread_rng, Rng};
+    use std::io::Cursor;
+
+    fn encode(b: DictzipBuilder, data: &[u8]) -> Vec<u8> {
+        let mut e = b.write(Cursor::new(Vec::new()), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = crate::test_data(400_000);
+        let compressed = encode(DictzipBuilder::new(), &data);
+
+        let mut out = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out == data);
+
+        let mut d = DictzipDecoder::new(Cursor::new(&compressed)).unwrap();
+        assert_eq!(d.uncompressed_len(), data.len() as u64);
+        out.clear();
+        d.read_to_end(&mut out).unwrap();
+        assert!(out == data);
+    }
+
+    #[test]
+    fn seek() {
+        let data = crate::test_data(400_000);
+        let b = DictzipBuilder::new().chunk_len(1000);
+        let compressed = encode(b, &data);
+        let mut d = DictzipDecoder::new(Cursor::new(compressed)).unwrap();
+
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let pos = rng.gen_range(0..data.len());
+            let len = cmp::min(rng.gen_range(0..3000), data.len() - pos);
+            d.seek(SeekFrom::Start(pos as u64)).unwrap();
+            let mut buf = vec![0; len];
+            d.read_exact(&mut buf).unwrap();
+            assert!(buf == data[pos..pos + len]);
+        }
+        d.seek(SeekFrom::End(-3)).unwrap();
+        let mut tail = Vec::new();
+        d.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, data[data.len() - 3..]);
+    }
+
+    #[test]
+    fn header_and_capacity() {
+        let data = crate::test_data(400_000);
+        let b = DictzipBuilder::new()
+            .gz_header(GzBuilder::new().filename("data").extra(&b"XY\x01\x00z"[..]))
+            .capacity(data.len() as u64);
+        let compressed = encode(b, &data);
+        let d = DictzipDecoder::new(Cursor::new(&compressed)).unwrap();
+        assert_eq!(d.header().filename(), Some(&b"data"[..]));
+        let extra = d.header().extra().unwrap();
+        assert_eq!(extra.len(), 10 + 2 * 7 + 5);
+        assert!(extra.ends_with(b"XY\x01\x00z"));
+
+        let mut e = DictzipBuilder::new()
+            .capacity(10)
+            .write(Cursor::new(Vec::new()), Compression::default());
+        assert!(e.write_all(&data).is_err());
+    }
+
+    #[test]
+    fn empty() {
+        let compressed = encode(DictzipBuilder::new(), b"");
+        let mut out = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        DictzipDecoder::new(Cursor::new(compressed))
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn invalid_table() {
+        let data = crate::test_data(400_000);
+        let compressed = encode(DictzipBuilder::new().capacity(1 << 20), &data);
+
+        let resize = |c: &mut [u8], i: usize, by: i16| {
+            let at = TABLE_OFFSET + TABLE_HEADER_LEN + 2 * i;
+            let size = u16::from_le_bytes([c[at], c[at + 1]]);
+            c[at..at + 2].copy_from_slice(&size.wrapping_add(by as u16).to_le_bytes());
+        };
+
+        // Moving bytes from one chunk to the next keeps the total size.
+        let mut moved = compressed.clone();
+        resize(&mut moved, 0, -1);
+        resize(&mut moved, 1, 1);
+        let mut d = DictzipDecoder::new(Cursor::new(moved)).unwrap();
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+
+        let mut grown = compressed.clone();
+        resize(&mut grown, 0, 1);
+        assert!(DictzipDecoder::new(Cursor::new(grown)).is_err());
+
+        let mut bad_crc = compressed.clone();
+        let n = bad_crc.len();
+        bad_crc[n - 8] ^= 1;
+        let mut d = DictzipDecoder::new(Cursor::new(bad_crc)).unwrap();
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+
+        let plain = crate::write::GzEncoder::new(Vec::new(), Compression::default())
+            .finish()
+            .unwrap();
+        assert!(DictzipDecoder::new(Cursor::new(plain)).is_err());
+    }
+}
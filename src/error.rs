@@ -0,0 +1,241 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use crate::{DecompressError, LimitExceeded};
+
+/// The ways in which decoding a compressed stream can fail.
+///
+/// The I/O adaptors of this crate report these failures as an [`io::Error`]
+/// wrapping an `Error`, which can be retrieved with [`io::Error::get_ref`] and
+/// `downcast_ref`. Every variant carries the offset in the compressed input,
+/// counted from where the decoder started reading, at which the failure was
+/// detected.
+///
+/// The wrapping [`io::Error`] is of kind [`io::ErrorKind::UnexpectedEof`] for
+/// [`Error::Truncated`], [`io::ErrorKind::Other`] for
+/// [`Error::LimitExceeded`], and [`io::ErrorKind::InvalidInput`] otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::read::GzDecoder;
+///
+/// let mut d = GzDecoder::new(&b"definitely not gzip"[..]);
+/// let err = d.read_to_end(&mut Vec::new()).unwrap_err();
+/// match err.get_ref().and_then(|e| e.downcast_ref::<flate2::Error>()) {
+///     Some(flate2::Error::BadMagic { offset }) => assert_eq!(*offset, 0),
+///     _ => panic!("unexpected error: {}", err),
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The stream doesn't start with the gzip magic bytes.
+    BadMagic {
+        /// The offset of the magic bytes.
+        offset: u64,
+    },
+    /// The gzip header names a compression method other than deflate.
+    UnsupportedMethod {
+        /// The compression method found in the header.
+        method: u8,
+        /// The offset of the compression method.
+        offset: u64,
+    },
+    /// The gzip header has reserved flags set.
+    ReservedFlags {
+        /// The flags found in the header.
+        flags: u8,
+        /// The offset of the flags.
+        offset: u64,
+    },
+    /// The CRC of the gzip header doesn't match its contents.
+    HeaderCrcMismatch {
+        /// The offset of the header CRC.
+        offset: u64,
+    },
+    /// The file name or comment of the gzip header is too long.
+    HeaderFieldTooLong {
+        /// The offset at which the field went over the limit.
+        offset: u64,
+    },
+    /// The CRC-32 of a gzip member, or the Adler-32 of a zlib stream, doesn't
+    /// match the decompressed data.
+    DataCrcMismatch {
+        /// The offset of the checksum.
+        offset: u64,
+    },
+    /// The size recorded in the trailer of a gzip member doesn't match the
+    /// decompressed data.
+    IsizeMismatch {
+        /// The offset of the size.
+        offset: u64,
+    },
+    /// The stream ends before it is complete.
+    Truncated {
+        /// The offset at which more data was expected.
+        offset: u64,
+    },
+    /// The zlib stream needs a preset dictionary to be decompressed.
+    NeedsDictionary {
+        /// The Adler-32 checksum of the dictionary.
+        id: u32,
+        /// The offset of the dictionary checksum.
+        offset: u64,
+    },
+    /// The compressed data is invalid.
+    Data {
+        /// The error reported by the backend, if any.
+        error: Option<DecompressError>,
+        /// The offset reached when the error was detected. The invalid data
+        /// may start a little before it.
+        offset: u64,
+    },
+    /// One of the [`Limits`](crate::Limits) of the decoder was exceeded.
+    LimitExceeded {
+        /// The limit that was exceeded.
+        limit: LimitExceeded,
+        /// The offset reached when the limit was exceeded.
+        offset: u64,
+    },
+}
+
+impl Error {
+    /// Returns the offset in the compressed input at which the failure was
+    /// detected.
+    pub fn offset(&self) -> u64 {
+        match *self {
+            Error::BadMagic { offset }
+            | Error::UnsupportedMethod { offset, .. }
+            | Error::ReservedFlags { offset, .. }
+            | Error::HeaderCrcMismatch { offset }
+            | Error::HeaderFieldTooLong { offset }
+            | Error::DataCrcMismatch { offset }
+            | Error::IsizeMismatch { offset }
+            | Error::Truncated { offset }
+            | Error::NeedsDictionary { offset, .. }
+            | Error::Data { offset, .. }
+            | Error::LimitExceeded { offset, .. } => offset,
+        }
+    }
+
+    fn offset_mut(&mut self) -> &mut u64 {
+        match self {
+            Error::BadMagic { offset }
+            | Error::UnsupportedMethod { offset, .. }
+            | Error::ReservedFlags { offset, .. }
+            | Error::HeaderCrcMismatch { offset }
+            | Error::HeaderFieldTooLong { offset }
+            | Error::DataCrcMismatch { offset }
+            | Error::IsizeMismatch { offset }
+            | Error::Truncated { offset }
+            | Error::NeedsDictionary { offset, .. }
+            | Error::Data { offset, .. }
+            | Error::LimitExceeded { offset, .. } => offset,
+        }
+    }
+
+    /// Classifies an error of the backend found at `offset`.
+    pub(crate) fn decompress(error: DecompressError, offset: u64) -> Error {
+        match error.needs_dictionary() {
+            Some(id) => Error::NeedsDictionary { id, offset },
+            None => Error::Data {
+                error: Some(error),
+                offset,
+            },
+        }
+    }
+
+    /// Adds `base` to the offset of `err` if it holds an `Error`, for errors
+    /// found by a decoder reading only part of the whole input.
+    pub(crate) fn rebase(mut err: io::Error, base: u64) -> io::Error {
+        if let Some(inner) = err.get_mut().and_then(|e| e.downcast_mut::<Error>()) {
+            *inner.offset_mut() += base;
+        }
+        err
+    }
+
+    fn io_kind(&self) -> io::ErrorKind {
+        match self {
+            Error::Truncated { .. } => io::ErrorKind::UnexpectedEof,
+            Error::LimitExceeded { .. } => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadMagic { .. } => f.write_str("invalid gzip header: bad magic bytes"),
+            Error::UnsupportedMethod { method, .. } => write!(
+                f,
+                "invalid gzip header: unsupported compression method {}",
+                method
+            ),
+            Error::ReservedFlags { flags, .. } => {
+                write!(
+                    f,
+                    "invalid gzip header: reserved flags set in {:#04x}",
+                    flags
+                )
+            }
+            Error::HeaderCrcMismatch { .. } => {
+                f.write_str("corrupt gzip header does not have a matching checksum")
+            }
+            Error::HeaderFieldTooLong { .. } => f.write_str("gzip header field too long"),
+            Error::DataCrcMismatch { .. } => {
+                f.write_str("corrupt stream does not have a matching checksum")
+            }
+            Error::IsizeMismatch { .. } => f.write_str("corrupt gzip stream has a mismatched size"),
+            Error::Truncated { .. } => f.write_str("unexpected end of compressed stream"),
+            Error::NeedsDictionary { id, .. } => {
+                write!(f, "stream requires a dictionary with checksum {:#010x}", id)
+            }
+            Error::Data { .. } => f.write_str("corrupt deflate stream"),
+            Error::LimitExceeded { limit, .. } => fmt::Display::fmt(limit, f),
+        }?;
+        write!(f, " at offset {}", self.offset())
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Data {
+                error: Some(error), ..
+            } => Some(error),
+            Error::LimitExceeded { limit, .. } => Some(limit),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        io::Error::new(err.io_kind(), err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebase() {
+        let err = io::Error::from(Error::Truncated { offset: 3 });
+        let err = Error::rebase(err, 10);
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let inner = err.get_ref().unwrap().downcast_ref::<Error>().unwrap();
+        assert_eq!(inner.offset(), 13);
+        assert_eq!(
+            err.to_string(),
+            "unexpected end of compressed stream at offset 13"
+        );
+
+        let err = Error::rebase(io::ErrorKind::UnexpectedEof.into(), 10);
+        assert!(err.get_ref().is_none());
+    }
+}
@@ -0,0 +1,1164 @@
+//! The engine behind `Compression::exhaustive`, which searches for the
+//! smallest deflate encoding of its input much like Zopfli does.
+//!
+//! Input is taken in chunks of up to `CHUNK` bytes. For every position of a
+//! chunk, all the lengths a match can have there are found along with the
+//! closest distance for each of them. A first parse costed with the fixed
+//! Huffman codes is split into blocks where that makes the output smaller, and
+//! each block is then parsed again a number of times, costing literals and
+//! matches with the statistics of the previous parse, keeping whichever parse
+//! comes out smallest once actually encoded. Every block is finally written
+//! with dynamic codes, fixed codes or stored, whichever is the smallest.
+
+use std::fmt;
+
+use crate::adler::Adler32;
+use crate::crc::Crc;
+use crate::ffi::ErrorMessage;
+use crate::mem::{compress_failed, CompressError, FlushCompress, Status};
+use crate::{Compression, GzBuilder};
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// How much input is compressed at once, bounding the memory used.
+const CHUNK: usize = 1 << 20;
+// How many earlier positions with the same hash are tried for a match.
+const MAX_CHAIN: usize = 8192;
+// How many times every block is parsed with updated statistics.
+const ITERATIONS: usize = 15;
+// How many blocks a chunk may be split into.
+const MAX_BLOCKS: usize = 15;
+const HASH_BITS: u32 = 16;
+const NIL: u32 = u32::MAX;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// The order in which the lengths of the code length code are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// What surrounds the deflate data.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Wrapper {
+    Raw,
+    Zlib,
+    Gzip,
+}
+
+/// Where the compressor is in its output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    /// Nothing written yet, not even the header.
+    Header,
+    /// Compressing the body of the stream.
+    Body,
+    /// Flushed, with no input since.
+    Flushed,
+    /// Past the end of the stream.
+    Done,
+}
+
+pub(crate) struct Exhaustive {
+    wrapper: Wrapper,
+    window_bits: u8,
+    /// The input not compressed yet, from `start` on, preceded by as much of
+    /// the input before it as matches can reach.
+    data: Vec<u8>,
+    start: usize,
+    out: Writer,
+    adler: Adler32,
+    crc: Crc,
+    /// The Adler-32 checksum of the preset dictionary, if any.
+    dictionary: Option<u32>,
+    state: State,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl fmt::Debug for Exhaustive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "exhaustive deflate internal state. total_in: {}, total_out: {}",
+            self.total_in, self.total_out,
+        )
+    }
+}
+
+impl Exhaustive {
+    /// Takes the same parameters as the backends, a `window_bits` above 15
+    /// asking for a gzip wrapper.
+    pub(crate) fn new(zlib_header: bool, window_bits: u8) -> Exhaustive {
+        let (wrapper, window_bits) = if window_bits > 15 {
+            (Wrapper::Gzip, window_bits - 16)
+        } else if zlib_header {
+            (Wrapper::Zlib, window_bits)
+        } else {
+            (Wrapper::Raw, window_bits)
+        };
+        Exhaustive {
+            wrapper,
+            window_bits,
+            data: Vec::new(),
+            start: 0,
+            out: Writer::default(),
+            adler: Adler32::new(),
+            crc: Crc::new(),
+            dictionary: None,
+            state: State::Header,
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    pub(crate) fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    pub(crate) fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    pub(crate) fn reset(&mut self) {
+        let window_bits = match self.wrapper {
+            Wrapper::Gzip => self.window_bits + 16,
+            _ => self.window_bits,
+        };
+        *self = Exhaustive::new(self.wrapper == Wrapper::Zlib, window_bits);
+    }
+
+    pub(crate) fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, CompressError> {
+        let started = self.state != State::Header;
+        if self.wrapper == Wrapper::Gzip || self.wrapper == Wrapper::Zlib && started {
+            return compress_failed(ErrorMessage::new("dictionary set too late"));
+        }
+        if self.start < self.data.len() {
+            return compress_failed(ErrorMessage::new("dictionary set with input pending"));
+        }
+        let mut adler = Adler32::new();
+        adler.update(dictionary);
+        self.data.extend_from_slice(dictionary);
+        self.trim();
+        if self.wrapper == Wrapper::Zlib {
+            self.dictionary = Some(adler.sum());
+        }
+        Ok(adler.sum())
+    }
+
+    pub(crate) fn prime(&mut self, bits: u8, value: u16) -> Result<(), CompressError> {
+        if bits > 16 || self.state == State::Done {
+            return compress_failed(ErrorMessage::new("invalid bits to prime"));
+        }
+        self.start_body();
+        let mask = (1u32 << bits) - 1;
+        self.out.put(u32::from(value) & mask, u32::from(bits));
+        Ok(())
+    }
+
+    pub(crate) fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushCompress,
+    ) -> Result<Status, CompressError> {
+        let mut consumed = 0;
+        if self.state != State::Done {
+            self.start_body();
+            consumed = self.accept(input);
+            // A full chunk is only compressed once more input shows it isn't
+            // the last one, and no more than one is per call so that the
+            // output waiting to be taken stays bounded.
+            if consumed < input.len() {
+                self.compress_chunk(false);
+                consumed += self.accept(&input[consumed..]);
+            }
+            if consumed == input.len() {
+                self.flush(flush);
+            }
+        }
+
+        let written = self.out.take(output);
+        self.total_in += consumed as u64;
+        self.total_out += written as u64;
+        Ok(if self.state == State::Done && self.out.is_empty() {
+            Status::StreamEnd
+        } else if consumed == 0 && written == 0 {
+            Status::BufError
+        } else {
+            Status::Ok
+        })
+    }
+
+    fn start_body(&mut self) {
+        if self.state != State::Header {
+            return;
+        }
+        self.state = State::Body;
+        match self.wrapper {
+            Wrapper::Raw => {}
+            Wrapper::Zlib => {
+                let cmf = (self.window_bits - 8) << 4 | 8;
+                // The level hint for the slowest, most compressed output.
+                let mut flg = 3 << 6;
+                if self.dictionary.is_some() {
+                    flg |= 0x20;
+                }
+                flg += (31 - (u16::from(cmf) << 8 | u16::from(flg)) % 31) as u8 % 31;
+                self.out.put_bytes(&[cmf, flg]);
+                if let Some(id) = self.dictionary {
+                    self.out.put_bytes(&id.to_be_bytes());
+                }
+            }
+            Wrapper::Gzip => {
+                let header = GzBuilder::new().into_header(Compression::exhaustive());
+                self.out.put_bytes(&header);
+            }
+        }
+    }
+
+    // Takes as much of `input` as fits in the current chunk.
+    fn accept(&mut self, input: &[u8]) -> usize {
+        let pending = self.data.len() - self.start;
+        let n = input.len().min(CHUNK - pending);
+        if n > 0 {
+            let input = &input[..n];
+            self.data.extend_from_slice(input);
+            self.adler.update(input);
+            self.crc.update(input);
+            if self.state == State::Flushed {
+                self.state = State::Body;
+            }
+        }
+        n
+    }
+
+    fn flush(&mut self, flush: FlushCompress) {
+        match flush {
+            FlushCompress::None => {}
+            FlushCompress::Finish => {
+                self.compress_chunk(true);
+                self.out.align();
+                match self.wrapper {
+                    Wrapper::Raw => {}
+                    Wrapper::Zlib => self.out.put_bytes(&self.adler.sum().to_be_bytes()),
+                    Wrapper::Gzip => {
+                        self.out.put_bytes(&self.crc.sum().to_le_bytes());
+                        self.out.put_bytes(&self.crc.amount().to_le_bytes());
+                    }
+                }
+                self.state = State::Done;
+            }
+            // Flushing again without any input in between would only repeat
+            // the empty block that ended the last flush.
+            _ if self.state == State::Flushed => {}
+            FlushCompress::Partial => {
+                self.compress_chunk(false);
+                self.out.put(1 << 1, 3);
+                self.out.put(0, 7);
+                self.state = State::Flushed;
+            }
+            _ => {
+                self.compress_chunk(false);
+                self.out.put(0, 3);
+                self.out.align();
+                self.out.put_bytes(&[0, 0, 0xff, 0xff]);
+                if flush == FlushCompress::Full {
+                    self.data.clear();
+                    self.start = 0;
+                }
+                self.state = State::Flushed;
+            }
+        }
+    }
+
+    fn compress_chunk(&mut self, last: bool) {
+        if self.start == self.data.len() && !last {
+            return;
+        }
+        let max_dist = 1 << self.window_bits;
+        compress(&self.data, self.start, max_dist, last, &mut self.out);
+        self.start = self.data.len();
+        self.trim();
+    }
+
+    // Drops the input matches can no longer reach.
+    fn trim(&mut self) {
+        let cut = self.data.len().saturating_sub(1 << self.window_bits);
+        self.data.drain(..cut);
+        self.start = self.data.len();
+    }
+}
+
+/// Writes the LSB-first bit stream of deflate, keeping the bytes until they're
+/// taken.
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+    taken: usize,
+    acc: u64,
+    bits: u32,
+}
+
+impl Writer {
+    fn put(&mut self, value: u32, bits: u32) {
+        self.acc |= u64::from(value) << self.bits;
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    // Huffman codes are stored starting from their most significant bit.
+    fn put_code(&mut self, code: u16, bits: u8) {
+        let reversed = u32::from(code).reverse_bits() >> (32 - u32::from(bits));
+        self.put(reversed, u32::from(bits));
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.put(0, 8 - self.bits);
+        }
+    }
+
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        debug_assert_eq!(self.bits, 0);
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.taken == self.out.len()
+    }
+
+    // Moves as many whole bytes as fit to `output`.
+    fn take(&mut self, output: &mut [u8]) -> usize {
+        let n = output.len().min(self.out.len() - self.taken);
+        output[..n].copy_from_slice(&self.out[self.taken..self.taken + n]);
+        self.taken += n;
+        if self.is_empty() {
+            self.out.clear();
+            self.taken = 0;
+        }
+        n
+    }
+}
+
+/// A literal, when `dist` is zero, or a match.
+#[derive(Clone, Copy, Debug)]
+struct Symbol {
+    len: u16,
+    dist: u16,
+}
+
+impl Symbol {
+    fn literal(byte: u8) -> Symbol {
+        Symbol {
+            len: u16::from(byte),
+            dist: 0,
+        }
+    }
+
+    // The number of input bytes this stands for.
+    fn size(&self) -> usize {
+        if self.dist == 0 {
+            1
+        } else {
+            usize::from(self.len)
+        }
+    }
+}
+
+fn length_symbol(len: usize) -> usize {
+    match len {
+        3..=10 => len - 3,
+        MAX_MATCH => 28,
+        _ => {
+            let l = len - 3;
+            let b = (31 - (l as u32).leading_zeros()) as usize;
+            4 * (b - 1) + ((l >> (b - 2)) & 3)
+        }
+    }
+}
+
+fn dist_symbol(dist: usize) -> usize {
+    let d = dist - 1;
+    if d < 4 {
+        d
+    } else {
+        let b = (31 - (d as u32).leading_zeros()) as usize;
+        2 * b + ((d >> (b - 1)) & 1)
+    }
+}
+
+/// The matches found at every position of a chunk.
+struct Matches {
+    /// The end in `runs` of the runs of every position.
+    ends: Vec<u32>,
+    /// Runs of (longest length, distance) with increasing lengths and
+    /// distances, each giving the closest distance for the lengths between
+    /// the one of the previous run, exclusive, and its own.
+    runs: Vec<(u16, u16)>,
+}
+
+impl Matches {
+    // Finds the matches from `start` on, up to `max_dist` back, in `data`.
+    fn find(data: &[u8], start: usize, max_dist: usize) -> Matches {
+        let end = data.len();
+        let hash = |p: usize| {
+            let key = u32::from_le_bytes([data[p], data[p + 1], data[p + 2], 0]);
+            (key.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+        };
+        // Chains of earlier positions with the same hash, most recent first.
+        let mut head = vec![NIL; 1 << HASH_BITS];
+        let mut prev = vec![NIL; end];
+        let insert = |head: &mut [u32], prev: &mut [u32], p: usize| {
+            if p + MIN_MATCH <= end {
+                let h = hash(p);
+                prev[p] = head[h];
+                head[h] = p as u32;
+            }
+        };
+        for p in 0..start {
+            insert(&mut head, &mut prev, p);
+        }
+
+        let mut matches = Matches {
+            ends: Vec::with_capacity(end - start),
+            runs: Vec::new(),
+        };
+        for p in start..end {
+            let limit = (end - p).min(MAX_MATCH);
+            if limit >= MIN_MATCH {
+                let mut best = MIN_MATCH - 1;
+                let mut candidate = head[hash(p)];
+                let mut chain = MAX_CHAIN;
+                while candidate != NIL && chain > 0 {
+                    let c = candidate as usize;
+                    let dist = p - c;
+                    if dist > max_dist {
+                        break;
+                    }
+                    if data[c + best] == data[p + best] {
+                        let len = data[c..c + limit]
+                            .iter()
+                            .zip(&data[p..p + limit])
+                            .take_while(|(a, b)| a == b)
+                            .count();
+                        if len > best {
+                            matches.runs.push((len as u16, dist as u16));
+                            best = len;
+                            if len == limit {
+                                break;
+                            }
+                        }
+                    }
+                    candidate = prev[c];
+                    chain -= 1;
+                }
+            }
+            matches.ends.push(matches.runs.len() as u32);
+            insert(&mut head, &mut prev, p);
+        }
+        matches
+    }
+
+    // The runs at position `i` of the chunk.
+    fn at(&self, i: usize) -> &[(u16, u16)] {
+        let start = if i == 0 { 0 } else { self.ends[i - 1] as usize };
+        &self.runs[start..self.ends[i] as usize]
+    }
+}
+
+/// How often every symbol is used in a block.
+#[derive(Clone)]
+struct Histogram {
+    litlen: [u32; 286],
+    dist: [u32; 30],
+}
+
+impl Histogram {
+    fn new(symbols: &[Symbol]) -> Histogram {
+        let mut h = Histogram {
+            litlen: [0; 286],
+            dist: [0; 30],
+        };
+        for s in symbols {
+            if s.dist == 0 {
+                h.litlen[usize::from(s.len)] += 1;
+            } else {
+                h.litlen[257 + length_symbol(usize::from(s.len))] += 1;
+                h.dist[dist_symbol(usize::from(s.dist))] += 1;
+            }
+        }
+        h.litlen[256] = 1;
+        h
+    }
+
+    // The bits taken by the extra bits of lengths and distances.
+    fn extra_bits(&self) -> u64 {
+        let lengths = self.litlen[257..]
+            .iter()
+            .zip(&LENGTH_EXTRA)
+            .map(|(&n, &e)| u64::from(n) * u64::from(e));
+        let dists = self
+            .dist
+            .iter()
+            .zip(&DIST_EXTRA)
+            .map(|(&n, &e)| u64::from(n) * u64::from(e));
+        lengths.chain(dists).sum()
+    }
+}
+
+/// The lengths of the Huffman codes of a block.
+struct Code {
+    litlen: [u8; 288],
+    dist: [u8; 30],
+}
+
+impl Code {
+    fn fixed() -> Code {
+        let mut litlen = [8; 288];
+        litlen[144..256].iter_mut().for_each(|l| *l = 9);
+        litlen[256..280].iter_mut().for_each(|l| *l = 7);
+        Code {
+            litlen,
+            dist: [5; 30],
+        }
+    }
+
+    fn dynamic(h: &Histogram) -> Code {
+        let mut code = Code {
+            litlen: [0; 288],
+            dist: [0; 30],
+        };
+        huffman_lengths(&h.litlen, 15, &mut code.litlen[..286]);
+        huffman_lengths(&h.dist, 15, &mut code.dist);
+        // Some decoders reject blocks without any distance code.
+        if code.dist.iter().all(|&l| l == 0) {
+            code.dist[0] = 1;
+            code.dist[1] = 1;
+        }
+        code
+    }
+
+    // The bits taken by the symbols of `h`, without the header.
+    fn data_bits(&self, h: &Histogram) -> u64 {
+        let litlen = h.litlen.iter().zip(&self.litlen[..]);
+        let dist = h.dist.iter().zip(&self.dist[..]);
+        let bits: u64 = litlen
+            .chain(dist)
+            .map(|(&n, &l)| u64::from(n) * u64::from(l))
+            .sum();
+        bits + h.extra_bits()
+    }
+
+    // The lengths of the literal/length and distance codes stored in the
+    // header of a dynamic block, with the unused ones at the end left out.
+    fn stored_lengths(&self) -> (Vec<u8>, usize, usize) {
+        let hlit = 257.max(self.litlen.iter().rposition(|&l| l > 0).unwrap_or(0) + 1);
+        let hdist = 1.max(self.dist.iter().rposition(|&l| l > 0).unwrap_or(0) + 1);
+        let mut lengths = self.litlen[..hlit].to_vec();
+        lengths.extend_from_slice(&self.dist[..hdist]);
+        (lengths, hlit, hdist)
+    }
+
+    // Returns the size of the header of a dynamic block using this code,
+    // writing it to `w` if given.
+    fn header(&self, w: Option<&mut Writer>) -> u64 {
+        let (lengths, hlit, hdist) = self.stored_lengths();
+        // Every way of run-length encoding the lengths is tried, as which one
+        // comes out smallest depends on the code length code.
+        let (bits, flags) = (0..8)
+            .map(|flags| {
+                let (tokens, cl, hclen) = run_length_code(&lengths, flags);
+                let tokens = tokens
+                    .iter()
+                    .map(|&(sym, _)| u64::from(cl[usize::from(sym)]) + extra_bits(sym));
+                (14 + 3 * hclen as u64 + tokens.sum::<u64>(), flags)
+            })
+            .min()
+            .unwrap();
+
+        if let Some(w) = w {
+            let (tokens, cl, hclen) = run_length_code(&lengths, flags);
+            w.put(hlit as u32 - 257, 5);
+            w.put(hdist as u32 - 1, 5);
+            w.put(hclen as u32 - 4, 4);
+            for &i in &CODE_LENGTH_ORDER[..hclen] {
+                w.put(u32::from(cl[i]), 3);
+            }
+            let codes = canonical_codes(&cl);
+            for (sym, extra) in tokens {
+                w.put_code(codes[usize::from(sym)], cl[usize::from(sym)]);
+                w.put(u32::from(extra), extra_bits(sym) as u32);
+            }
+        }
+        bits
+    }
+}
+
+// Run-length encodes `lengths` as `run_lengths` does, returning the symbols
+// along with the lengths of the code length code and how many of them are
+// stored.
+fn run_length_code(lengths: &[u8], flags: u8) -> (Vec<(u8, u8)>, [u8; 19], usize) {
+    let tokens = run_lengths(lengths, flags);
+    let mut freqs = [0; 19];
+    for &(sym, _) in &tokens {
+        freqs[usize::from(sym)] += 1;
+    }
+    let mut cl = [0; 19];
+    huffman_lengths(&freqs, 7, &mut cl);
+    let last = CODE_LENGTH_ORDER.iter().rposition(|&i| cl[i] > 0);
+    (tokens, cl, 4.max(last.unwrap_or(0) + 1))
+}
+
+// The number of extra bits following a symbol of the code length code.
+fn extra_bits(sym: u8) -> u64 {
+    match sym {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+// Run-length encodes code lengths into symbols of the code length code and
+// their extra bits, using repeats of the previous length if bit 0 of `flags`
+// is set, short runs of zeros if bit 1 is, and long ones if bit 2 is.
+fn run_lengths(lengths: &[u8], flags: u8) -> Vec<(u8, u8)> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&l| l == value).count();
+        i += run;
+        if value == 0 {
+            while flags & 4 != 0 && run >= 11 {
+                let n = run.min(138);
+                tokens.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            while flags & 2 != 0 && run >= 3 {
+                let n = run.min(10);
+                tokens.push((17, (n - 3) as u8));
+                run -= n;
+            }
+        } else if flags & 1 != 0 && run >= 4 {
+            tokens.push((value, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                tokens.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        for _ in 0..run {
+            tokens.push((value, 0));
+        }
+    }
+    tokens
+}
+
+// Computes the lengths of the optimal prefix code for `freqs` no longer than
+// `limit` bits with the package-merge algorithm. A lone used symbol is given
+// a companion so that the code is complete.
+fn huffman_lengths(freqs: &[u32], limit: u32, lengths: &mut [u8]) {
+    lengths.iter_mut().for_each(|l| *l = 0);
+    let mut leaves: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+    match leaves.len() {
+        0 => return,
+        1 => {
+            lengths[leaves[0]] = 1;
+            lengths[usize::from(leaves[0] == 0)] = 1;
+            return;
+        }
+        _ => {}
+    }
+    leaves.sort_by_key(|&i| freqs[i]);
+
+    // Nodes are either leaves, with a symbol and no right child, or packages
+    // of two nodes.
+    let mut nodes: Vec<(u64, usize, usize)> = leaves
+        .iter()
+        .map(|&sym| (u64::from(freqs[sym]), sym, NIL as usize))
+        .collect();
+    let n = leaves.len();
+    let mut list: Vec<usize> = (0..n).collect();
+    for _ in 1..limit {
+        let mut merged = Vec::with_capacity(2 * n);
+        let mut leaf = 0;
+        for pair in list.chunks_exact(2) {
+            let weight = nodes[pair[0]].0 + nodes[pair[1]].0;
+            while leaf < n && nodes[leaf].0 <= weight {
+                merged.push(leaf);
+                leaf += 1;
+            }
+            nodes.push((weight, pair[0], pair[1]));
+            merged.push(nodes.len() - 1);
+        }
+        merged.extend(leaf..n);
+        list = merged;
+    }
+
+    let mut stack: Vec<usize> = list[..2 * n - 2].to_vec();
+    while let Some(node) = stack.pop() {
+        let (_, left, right) = nodes[node];
+        if right == NIL as usize {
+            lengths[left] += 1;
+        } else {
+            stack.push(left);
+            stack.push(right);
+        }
+    }
+}
+
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut count = [0u16; 16];
+    for &l in lengths {
+        count[usize::from(l)] += 1;
+    }
+    count[0] = 0;
+    let mut next = [0u16; 16];
+    for bits in 1..16 {
+        next[bits] = (next[bits - 1] + count[bits - 1]) << 1;
+    }
+    lengths
+        .iter()
+        .map(|&l| {
+            let code = next[usize::from(l)];
+            next[usize::from(l)] += 1;
+            code
+        })
+        .collect()
+}
+
+/// The cost in bits of every literal, length and distance.
+struct Costs {
+    literal: [f64; 256],
+    length: [f64; MAX_MATCH + 1],
+    dist: [f64; 30],
+}
+
+impl Costs {
+    fn new(litlen: &[f64], dist: &[f64]) -> Costs {
+        let mut costs = Costs {
+            literal: [0.0; 256],
+            length: [0.0; MAX_MATCH + 1],
+            dist: [0.0; 30],
+        };
+        costs.literal.copy_from_slice(&litlen[..256]);
+        for len in MIN_MATCH..=MAX_MATCH {
+            let sym = length_symbol(len);
+            costs.length[len] = litlen[257 + sym] + f64::from(LENGTH_EXTRA[sym]);
+        }
+        for (sym, cost) in costs.dist.iter_mut().enumerate() {
+            *cost = dist[sym] + f64::from(DIST_EXTRA[sym]);
+        }
+        costs
+    }
+
+    fn fixed() -> Costs {
+        let code = Code::fixed();
+        let litlen: Vec<f64> = code.litlen.iter().map(|&l| f64::from(l)).collect();
+        let dist: Vec<f64> = code.dist.iter().map(|&l| f64::from(l)).collect();
+        Costs::new(&litlen, &dist)
+    }
+
+    // The entropy of every symbol in `h`, unused ones costing as much as if
+    // they had been used once.
+    fn from_histogram(h: &Histogram) -> Costs {
+        fn entropy(counts: &[u32]) -> Vec<f64> {
+            let total: u32 = counts.iter().sum();
+            let log_total = f64::from(total.max(1)).log2();
+            counts
+                .iter()
+                .map(|&n| log_total - f64::from(n.max(1)).log2())
+                .collect()
+        }
+        Costs::new(&entropy(&h.litlen), &entropy(&h.dist))
+    }
+}
+
+// Finds the cheapest parse of `bytes[from..to]` given `costs`.
+fn parse(bytes: &[u8], matches: &Matches, from: usize, to: usize, costs: &Costs) -> Vec<Symbol> {
+    let n = to - from;
+    let mut cost = vec![f64::INFINITY; n + 1];
+    // The length and distance of the symbol ending at every position.
+    let mut step = vec![(0u16, 0u16); n + 1];
+    cost[0] = 0.0;
+    for i in 0..n {
+        let here = cost[i];
+        let literal = here + costs.literal[usize::from(bytes[from + i])];
+        if literal < cost[i + 1] {
+            cost[i + 1] = literal;
+            step[i + 1] = (1, 0);
+        }
+        let mut shortest = MIN_MATCH;
+        for &(len, dist) in matches.at(from + i) {
+            let len = usize::from(len).min(n - i);
+            if len < shortest {
+                break;
+            }
+            let base = here + costs.dist[dist_symbol(usize::from(dist))];
+            for l in shortest..=len {
+                let total = base + costs.length[l];
+                if total < cost[i + l] {
+                    cost[i + l] = total;
+                    step[i + l] = (l as u16, dist);
+                }
+            }
+            shortest = len + 1;
+        }
+    }
+
+    let mut symbols = Vec::new();
+    let mut i = n;
+    while i > 0 {
+        let (len, dist) = step[i];
+        if dist == 0 {
+            symbols.push(Symbol::literal(bytes[from + i - 1]));
+            i -= 1;
+        } else {
+            symbols.push(Symbol { len, dist });
+            i -= usize::from(len);
+        }
+    }
+    symbols.reverse();
+    symbols
+}
+
+// The size of a block of `symbols` with dynamic codes.
+fn dynamic_bits(h: &Histogram) -> u64 {
+    let code = Code::dynamic(h);
+    3 + code.header(None) + code.data_bits(h)
+}
+
+// Estimates the size of a block of `symbols` with the better Huffman codes.
+fn block_bits(symbols: &[Symbol]) -> u64 {
+    let h = Histogram::new(symbols);
+    dynamic_bits(&h).min(3 + Code::fixed().data_bits(&h))
+}
+
+// Splits `symbols` in blocks where that makes them smaller, returning the
+// indices where blocks start, apart from the first one.
+fn split(symbols: &[Symbol]) -> Vec<usize> {
+    let mut points = Vec::new();
+    let mut done = Vec::new();
+    while points.len() + 1 < MAX_BLOCKS {
+        let mut bounds = vec![0];
+        bounds.extend(&points);
+        bounds.push(symbols.len());
+        let block = bounds
+            .windows(2)
+            .map(|w| (w[0], w[1]))
+            .filter(|&(a, b)| b - a >= 10 && !done.contains(&(a, b)))
+            .max_by_key(|&(a, b)| b - a);
+        let (a, b) = match block {
+            Some(block) => block,
+            None => break,
+        };
+        let whole = block_bits(&symbols[a..b]);
+        let (m, bits) = find_minimum(a + 1, b, |m| {
+            block_bits(&symbols[a..m]) + block_bits(&symbols[m..b])
+        });
+        if bits < whole {
+            let i = points.partition_point(|&p| p < m);
+            points.insert(i, m);
+        } else {
+            done.push((a, b));
+        }
+    }
+    points
+}
+
+// Finds where `f` is smallest between `start` and `end`, exhaustively over
+// short ranges and narrowing down a grid over longer ones.
+fn find_minimum(start: usize, end: usize, f: impl Fn(usize) -> u64) -> (usize, u64) {
+    const POINTS: usize = 9;
+    if end - start < 256 {
+        return (start..end)
+            .map(|i| (i, f(i)))
+            .min_by_key(|&(_, v)| v)
+            .unwrap();
+    }
+    let (mut lo, mut hi) = (start, end);
+    let mut best = (start, u64::MAX);
+    while hi - lo > POINTS {
+        let p: Vec<usize> = (0..POINTS)
+            .map(|i| lo + (i + 1) * (hi - lo) / (POINTS + 1))
+            .collect();
+        let (i, v) = p
+            .iter()
+            .map(|&x| f(x))
+            .enumerate()
+            .min_by_key(|&(_, v)| v)
+            .unwrap();
+        if v > best.1 {
+            break;
+        }
+        best = (p[i], v);
+        lo = if i == 0 { lo } else { p[i - 1] };
+        hi = if i == POINTS - 1 { hi } else { p[i + 1] };
+    }
+    best
+}
+
+// Parses `bytes[from..to]` again and again, costing symbols with the
+// statistics of the previous parse, and returns the smallest parse found,
+// starting from `initial`.
+fn optimize(
+    bytes: &[u8],
+    matches: &Matches,
+    from: usize,
+    to: usize,
+    initial: &[Symbol],
+) -> Vec<Symbol> {
+    let mut stats = Histogram::new(initial);
+    let mut best_bits = dynamic_bits(&stats);
+    let mut best = initial.to_vec();
+    for _ in 0..ITERATIONS {
+        let symbols = parse(bytes, matches, from, to, &Costs::from_histogram(&stats));
+        stats = Histogram::new(&symbols);
+        let bits = dynamic_bits(&stats);
+        if bits < best_bits {
+            best_bits = bits;
+            best = symbols;
+        }
+    }
+    best
+}
+
+// Compresses `data[start..]`, which `data[..start]` precedes, into blocks,
+// the last one of them final if `last` is set.
+fn compress(data: &[u8], start: usize, max_dist: usize, last: bool, w: &mut Writer) {
+    let bytes = &data[start..];
+    if bytes.is_empty() {
+        w.put(u32::from(last), 1);
+        w.put(1, 2);
+        w.put_code(0, 7);
+        return;
+    }
+    let matches = Matches::find(data, start, max_dist);
+    let initial = parse(bytes, &matches, 0, bytes.len(), &Costs::fixed());
+    let mut bounds = vec![0];
+    bounds.extend(split(&initial));
+    bounds.push(initial.len());
+
+    let mut from = 0;
+    for (i, pair) in bounds.windows(2).enumerate() {
+        let fixed = &initial[pair[0]..pair[1]];
+        let to = from + fixed.iter().map(Symbol::size).sum::<usize>();
+        let best = optimize(bytes, &matches, from, to, fixed);
+        let last = last && i == bounds.len() - 2;
+        write_block(w, &bytes[from..to], &best, fixed, last);
+        from = to;
+    }
+}
+
+// Writes `bytes` as a block, either stored, with the fixed codes and the
+// `fixed` parse, or with dynamic codes and the `best` parse.
+fn write_block(w: &mut Writer, bytes: &[u8], best: &[Symbol], fixed: &[Symbol], last: bool) {
+    let h = Histogram::new(best);
+    let dynamic = Code::dynamic(&h);
+    let dynamic_bits = 3 + dynamic.header(None) + dynamic.data_bits(&h);
+    let fixed_h = Histogram::new(fixed);
+    let fixed_bits = 3 + Code::fixed().data_bits(&fixed_h);
+    let mut stored_bits = u64::from(w.bits);
+    for piece in bytes.chunks(0xffff) {
+        // The header of every piece is followed by padding to a byte.
+        stored_bits = (stored_bits + 10) & !7;
+        stored_bits += 32 + 8 * piece.len() as u64;
+    }
+    let stored_bits = stored_bits - u64::from(w.bits);
+
+    if stored_bits <= dynamic_bits.min(fixed_bits) {
+        let pieces = bytes.chunks(0xffff).count();
+        for (i, piece) in bytes.chunks(0xffff).enumerate() {
+            w.put(u32::from(last && i == pieces - 1), 1);
+            w.put(0, 2);
+            w.align();
+            let len = piece.len() as u16;
+            w.put_bytes(&len.to_le_bytes());
+            w.put_bytes(&(!len).to_le_bytes());
+            w.put_bytes(piece);
+        }
+    } else if fixed_bits <= dynamic_bits {
+        w.put(u32::from(last), 1);
+        w.put(1, 2);
+        write_symbols(w, &Code::fixed(), fixed);
+    } else {
+        w.put(u32::from(last), 1);
+        w.put(2, 2);
+        dynamic.header(Some(w));
+        write_symbols(w, &dynamic, best);
+    }
+}
+
+fn write_symbols(w: &mut Writer, code: &Code, symbols: &[Symbol]) {
+    let litlen = canonical_codes(&code.litlen);
+    let dist = canonical_codes(&code.dist);
+    for s in symbols {
+        if s.dist == 0 {
+            let lit = usize::from(s.len);
+            w.put_code(litlen[lit], code.litlen[lit]);
+            continue;
+        }
+        let (len, d) = (usize::from(s.len), usize::from(s.dist));
+        let sym = length_symbol(len);
+        w.put_code(litlen[257 + sym], code.litlen[257 + sym]);
+        w.put(
+            u32::from(s.len - LENGTH_BASE[sym]),
+            u32::from(LENGTH_EXTRA[sym]),
+        );
+        let sym = dist_symbol(d);
+        w.put_code(dist[sym], code.dist[sym]);
+        w.put(
+            u32::from(s.dist - DIST_BASE[sym]),
+            u32::from(DIST_EXTRA[sym]),
+        );
+    }
+    w.put_code(litlen[256], code.litlen[256]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decompress, FlushDecompress};
+    use rand::{thread_rng, Rng};
+
+    fn decompress(compressed: &[u8], zlib_header: bool) -> Vec<u8> {
+        let mut d = Decompress::new(zlib_header);
+        let mut out = Vec::with_capacity(1 << 20);
+        let status = d
+            .decompress_vec(compressed, &mut out, FlushDecompress::Finish)
+            .unwrap();
+        assert_eq!(status, Status::StreamEnd);
+        assert_eq!(d.total_in(), compressed.len() as u64);
+        out
+    }
+
+    fn compress_all(data: &[u8], zlib_header: bool, out_len: usize) -> Vec<u8> {
+        let mut c = Exhaustive::new(zlib_header, 15);
+        let mut compressed = Vec::new();
+        let mut buf = vec![0; out_len];
+        let mut input = data;
+        loop {
+            let before = c.total_in();
+            let status = c.compress(input, &mut buf, FlushCompress::Finish).unwrap();
+            input = &input[(c.total_in() - before) as usize..];
+            let n = (c.total_out() - compressed.len() as u64) as usize;
+            compressed.extend_from_slice(&buf[..n]);
+            if status == Status::StreamEnd {
+                return compressed;
+            }
+        }
+    }
+
+    fn text(len: usize) -> Vec<u8> {
+        let mut rng = thread_rng();
+        let words = [
+            "the ", "quick ", "brown ", "fox ", "jumps ", "over ", "lazy ", "dog\n",
+        ];
+        let mut data = Vec::new();
+        while data.len() < len {
+            data.extend_from_slice(words[rng.gen_range(0..words.len())].as_bytes());
+        }
+        data.truncate(len);
+        data
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut rng = thread_rng();
+        let random: Vec<u8> = (0..20_000).map(|_| rng.gen()).collect();
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabc".to_vec(),
+            vec![0; 20_000],
+            text(10_000),
+            random,
+        ];
+        for data in &inputs {
+            for &zlib_header in &[false, true] {
+                let compressed = compress_all(data, zlib_header, 1 << 20);
+                assert!(decompress(&compressed, zlib_header) == *data);
+                assert_eq!(compress_all(data, zlib_header, 7), compressed);
+            }
+        }
+    }
+
+    #[test]
+    fn smaller_than_best() {
+        let data = text(10_000);
+        let compressed = compress_all(&data, false, 1 << 20);
+        let mut c = crate::Compress::new(Compression::best(), false);
+        let mut best = Vec::with_capacity(1 << 20);
+        c.compress_vec(&data, &mut best, FlushCompress::Finish)
+            .unwrap();
+        assert!(compressed.len() < best.len());
+    }
+
+    #[test]
+    fn flushes() {
+        let data = text(10_000);
+        let mut c = Exhaustive::new(false, 15);
+        let mut d = Decompress::new(false);
+        let mut decompressed = Vec::new();
+        let flushes = [
+            FlushCompress::Sync,
+            FlushCompress::Full,
+            FlushCompress::Partial,
+        ];
+        for (i, piece) in data.chunks(1000).enumerate() {
+            let mut compressed = vec![0; 4000];
+            let before = c.total_out();
+            c.compress(piece, &mut compressed, flushes[i % 3]).unwrap();
+            compressed.truncate((c.total_out() - before) as usize);
+            let mut out = vec![0; 2000];
+            let before = d.total_out();
+            d.decompress(&compressed, &mut out, FlushDecompress::None)
+                .unwrap();
+            decompressed.extend_from_slice(&out[..(d.total_out() - before) as usize]);
+            assert!(decompressed[..] == data[..decompressed.len()]);
+            assert_eq!(decompressed.len(), 1000 * (i + 1));
+        }
+    }
+
+    #[test]
+    fn code_lengths() {
+        let mut lengths = [0; 6];
+        huffman_lengths(&[1, 1, 2, 4, 8, 16], 15, &mut lengths);
+        assert_eq!(lengths, [5, 5, 4, 3, 2, 1]);
+        huffman_lengths(&[1, 1, 2, 4, 8, 16], 3, &mut lengths);
+        assert_eq!(lengths.iter().map(|&l| 8 >> l).sum::<u32>(), 8);
+        assert_eq!(lengths, [3, 3, 3, 3, 2, 2]);
+        huffman_lengths(&[0, 0, 5, 0], 15, &mut lengths[..4]);
+        assert_eq!(lengths[..4], [1, 0, 1, 0]);
+        for len in MIN_MATCH..=MAX_MATCH {
+            let sym = length_symbol(len);
+            assert!(usize::from(LENGTH_BASE[sym]) <= len);
+            assert!(len - usize::from(LENGTH_BASE[sym]) < 1 << LENGTH_EXTRA[sym] || len == 258);
+        }
+        for dist in 1..=32768 {
+            let sym = dist_symbol(dist);
+            assert!(usize::from(DIST_BASE[sym]) <= dist);
+            assert!(dist - usize::from(DIST_BASE[sym]) < 1 << DIST_EXTRA[sym]);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+//! Finding the ends of deflate blocks, which `miniz_oxide` doesn't report.
+//!
+//! The raw deflate data is scanned ahead of the decompressor, decoding its
+//! Huffman codes without producing any output, so that the decompressor can be
+//! handed the input up to the end of a block and no further.
+
+use std::collections::VecDeque;
+
+/// Where a block ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockEnd {
+    // The number of bits of the stream up to the end of the block.
+    pub(crate) bits: u64,
+    // Whether it is the final block of the stream.
+    pub(crate) last: bool,
+}
+
+/// Scans raw deflate data for the ends of its blocks.
+#[derive(Debug, Default)]
+pub(crate) struct Scanner {
+    // The data not scanned yet, of which the first `pos` bits already were.
+    buf: Vec<u8>,
+    pos: usize,
+    // The offset in the stream of the first byte of `buf`.
+    start: u64,
+    state: State,
+    last: bool,
+    ends: VecDeque<BlockEnd>,
+}
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Header,
+    // The number of bytes of a stored block left.
+    Stored(usize),
+    Codes(Box<Codes>),
+    // Past the final block, or stuck on invalid data which the decompressor
+    // will report on its own.
+    Done,
+}
+
+#[derive(Debug)]
+struct Codes {
+    lengths: Huffman,
+    distances: Huffman,
+}
+
+// Why scanning stopped short of a whole step.
+enum Stop {
+    Starved,
+    Invalid,
+}
+
+impl Scanner {
+    /// Returns the number of bytes of the stream the scanner was given.
+    pub(crate) fn fed(&self) -> u64 {
+        self.start + self.buf.len() as u64
+    }
+
+    /// Scans `data`, which follows whatever the scanner was given so far.
+    pub(crate) fn feed(&mut self, data: &[u8]) {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.extend_from_slice(data);
+        loop {
+            let mut bits = Bits {
+                buf: &buf,
+                pos: self.pos,
+            };
+            match self.step(&mut bits) {
+                Ok(()) => self.pos = bits.pos,
+                Err(Stop::Starved) => break,
+                Err(Stop::Invalid) => {
+                    self.state = State::Done;
+                    break;
+                }
+            }
+        }
+        let scanned = self.pos / 8;
+        buf.drain(..scanned);
+        self.buf = buf;
+        self.pos %= 8;
+        self.start += scanned as u64;
+    }
+
+    /// Returns the end of the first block the decompressor didn't get past
+    /// yet, if it was found.
+    pub(crate) fn next_end(&self) -> Option<BlockEnd> {
+        self.ends.front().copied()
+    }
+
+    /// Forgets the end returned by `next_end`, once the decompressor is past
+    /// it.
+    pub(crate) fn pop_end(&mut self) {
+        self.ends.pop_front();
+    }
+
+    // Scans the next block header, symbol or run of stored bytes, leaving the
+    // scanner as it was if it can't.
+    fn step(&mut self, bits: &mut Bits<'_>) -> Result<(), Stop> {
+        match &mut self.state {
+            State::Header => {
+                let last = bits.take(1)? == 1;
+                match bits.take(2)? {
+                    0 => {
+                        bits.align();
+                        let len = bits.take(16)?;
+                        if bits.take(16)? != !len & 0xffff {
+                            return Err(Stop::Invalid);
+                        }
+                        self.state = State::Stored(len as usize);
+                    }
+                    1 => self.state = State::Codes(Box::new(Codes::fixed())),
+                    2 => self.state = State::Codes(Box::new(Codes::dynamic(bits)?)),
+                    _ => return Err(Stop::Invalid),
+                }
+                self.last = last;
+            }
+            State::Stored(0) => self.end_block(bits),
+            State::Stored(left) => {
+                let n = (*left).min(bits.buf.len() - bits.pos / 8);
+                if n == 0 {
+                    return Err(Stop::Starved);
+                }
+                bits.pos += n * 8;
+                *left -= n;
+            }
+            State::Codes(codes) => match codes.lengths.decode(bits)? {
+                0..=255 => {}
+                256 => self.end_block(bits),
+                sym @ 257..=285 => {
+                    bits.take(LENGTH_EXTRA[usize::from(sym - 257)])?;
+                    match codes.distances.decode(bits)? {
+                        dist @ 0..=29 => {
+                            bits.take(DISTANCE_EXTRA[usize::from(dist)])?;
+                        }
+                        _ => return Err(Stop::Invalid),
+                    }
+                }
+                _ => return Err(Stop::Invalid),
+            },
+            State::Done => return Err(Stop::Starved),
+        }
+        Ok(())
+    }
+
+    fn end_block(&mut self, bits: &Bits<'_>) {
+        self.ends.push_back(BlockEnd {
+            bits: self.start * 8 + bits.pos as u64,
+            last: self.last,
+        });
+        self.state = if self.last {
+            State::Done
+        } else {
+            State::Header
+        };
+    }
+}
+
+// Reads the LSB-first bit stream of deflate.
+struct Bits<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, n: u32) -> Result<u32, Stop> {
+        if self.pos + n as usize > self.buf.len() * 8 {
+            return Err(Stop::Starved);
+        }
+        let acc = self.buf[self.pos / 8..]
+            .iter()
+            .take(3)
+            .enumerate()
+            .fold(0, |acc, (i, &b)| acc | u32::from(b) << (8 * i));
+        let value = (acc >> (self.pos % 8)) & ((1 << n) - 1);
+        self.pos += n as usize;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+}
+
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// The order the lengths of the code length code are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+impl Codes {
+    fn fixed() -> Codes {
+        let mut lengths = [0; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        Codes {
+            lengths: Huffman::new(&lengths),
+            distances: Huffman::new(&[5; 30]),
+        }
+    }
+
+    fn dynamic(bits: &mut Bits<'_>) -> Result<Codes, Stop> {
+        let nlen = bits.take(5)? as usize + 257;
+        let ndist = bits.take(5)? as usize + 1;
+        let ncode = bits.take(4)? as usize + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err(Stop::Invalid);
+        }
+        let mut lengths = [0; 19];
+        for &i in &CODE_LENGTH_ORDER[..ncode] {
+            lengths[i] = bits.take(3)? as u8;
+        }
+        let code = Huffman::new(&lengths);
+
+        let mut lengths = [0; 286 + 30];
+        let mut i = 0;
+        while i < nlen + ndist {
+            let (len, repeat) = match code.decode(bits)? {
+                sym @ 0..=15 => (sym as u8, 1),
+                16 if i == 0 => return Err(Stop::Invalid),
+                16 => (lengths[i - 1], 3 + bits.take(2)?),
+                17 => (0, 3 + bits.take(3)?),
+                _ => (0, 11 + bits.take(7)?),
+            };
+            let repeat = repeat as usize;
+            if i + repeat > nlen + ndist {
+                return Err(Stop::Invalid);
+            }
+            lengths[i..i + repeat].fill(len);
+            i += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(Stop::Invalid);
+        }
+        Ok(Codes {
+            lengths: Huffman::new(&lengths[..nlen]),
+            distances: Huffman::new(&lengths[nlen..nlen + ndist]),
+        })
+    }
+}
+
+// A canonical Huffman code, decoded a bit at a time as zlib's `puff.c` does.
+#[derive(Debug)]
+struct Huffman {
+    // The number of symbols of every code length.
+    count: [u16; 16],
+    // The symbols ordered by their codes.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut count = [0; 16];
+        for &len in lengths {
+            count[usize::from(len)] += 1;
+        }
+        let mut offsets = [0; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + count[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                let offset = &mut offsets[usize::from(len)];
+                symbols[usize::from(*offset)] = sym as u16;
+                *offset += 1;
+            }
+        }
+        Huffman { count, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> Result<u16, Stop> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.count[1..] {
+            code |= bits.take(1)? as u16;
+            if code < first + count {
+                return Ok(self.symbols[usize::from(index + code - first)]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Stop::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Compress, Compression, FlushCompress};
+
+    #[test]
+    fn finds_every_block() {
+        let data: Vec<u8> = (0..200_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect();
+        let mut encoder = Compress::new(Compression::default(), false);
+        let mut encoded = Vec::with_capacity(1 << 20);
+        let mut ends = Vec::new();
+        for (i, chunk) in data.chunks(100_000).enumerate() {
+            let flush = match i {
+                0 | 2 => FlushCompress::Sync,
+                5 => FlushCompress::Full,
+                7 => FlushCompress::Finish,
+                _ => FlushCompress::None,
+            };
+            encoder.compress_vec(chunk, &mut encoded, flush).unwrap();
+            if flush != FlushCompress::None {
+                ends.push(encoded.len() as u64 * 8);
+            }
+        }
+
+        let mut scanner = Scanner::default();
+        for chunk in encoded.chunks(1000) {
+            scanner.feed(chunk);
+        }
+        assert_eq!(scanner.fed(), encoded.len() as u64);
+        let mut found = Vec::new();
+        while let Some(end) = scanner.next_end() {
+            found.push(end);
+            scanner.pop_end();
+        }
+        assert!(found.windows(2).all(|w| w[0].bits < w[1].bits));
+        assert!(found[..found.len() - 1].iter().all(|end| !end.last));
+        let last = found.last().unwrap();
+        assert!(last.last);
+        assert_eq!(last.bits.div_ceil(8), encoded.len() as u64);
+        // Every flush ends with an empty stored block.
+        for end in &ends[..ends.len() - 1] {
+            assert!(found.iter().any(|found| found.bits == *end));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-//! Implementation for C backends.
+//! Implementation for C backends, and for zlib-rs which exposes the same API.
 use std::alloc::{self, Layout};
 use std::cmp;
 use std::convert::TryFrom;
@@ -11,15 +11,6 @@ use std::ptr;
 use super::*;
 use crate::mem::{self, FlushDecompress, Status};
 
-#[derive(Default)]
-pub struct ErrorMessage(Option<&'static str>);
-
-impl ErrorMessage {
-    pub fn get(&self) -> Option<&str> {
-        self.0
-    }
-}
-
 pub struct StreamWrapper {
     pub inner: Box<mz_stream>,
 }
@@ -46,13 +37,25 @@ impl Default for StreamWrapper {
                 reserved: 0,
                 opaque: ptr::null_mut(),
                 state: ptr::null_mut(),
-                #[cfg(all(feature = "any_zlib", not(feature = "cloudflare-zlib-sys")))]
+                #[cfg(not(all(
+                    not(feature = "zlib-ng"),
+                    any(feature = "cloudflare_zlib", feature = "zlib-rs")
+                )))]
                 zalloc,
-                #[cfg(all(feature = "any_zlib", not(feature = "cloudflare-zlib-sys")))]
+                #[cfg(not(all(
+                    not(feature = "zlib-ng"),
+                    any(feature = "cloudflare_zlib", feature = "zlib-rs")
+                )))]
                 zfree,
-                #[cfg(not(all(feature = "any_zlib", not(feature = "cloudflare-zlib-sys"))))]
+                #[cfg(all(
+                    not(feature = "zlib-ng"),
+                    any(feature = "cloudflare_zlib", feature = "zlib-rs")
+                ))]
                 zalloc: Some(zalloc),
-                #[cfg(not(all(feature = "any_zlib", not(feature = "cloudflare-zlib-sys"))))]
+                #[cfg(all(
+                    not(feature = "zlib-ng"),
+                    any(feature = "cloudflare_zlib", feature = "zlib-rs")
+                ))]
                 zfree: Some(zfree),
             }),
         }
@@ -180,6 +183,24 @@ impl Direction for DirDecompress {
 #[derive(Debug)]
 pub struct Inflate {
     pub inner: Stream<DirDecompress>,
+    zlib_header: bool,
+    window_bits: u8,
+    verify_checksum: bool,
+    // zlib can't be told to skip the Adler-32 check, so when it isn't to be
+    // verified, the zlib header and trailer are read here and zlib only
+    // inflates the raw deflate data in between. This is where in the stream
+    // that is, or `None` when zlib reads the whole stream.
+    unwrapped: Option<Unwrapped>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Unwrapped {
+    // The bytes of the header read so far, 2 or 6 with a dictionary id.
+    Header([u8; 6], usize),
+    Body,
+    // The number of trailer bytes skipped so far.
+    Trailer(usize),
+    Done,
 }
 
 impl InflateBackend for Inflate {
@@ -202,6 +223,10 @@ impl InflateBackend for Inflate {
                     total_out: 0,
                     _marker: marker::PhantomData,
                 },
+                zlib_header,
+                window_bits,
+                verify_checksum: true,
+                unwrapped: None,
             }
         }
     }
@@ -211,6 +236,183 @@ impl InflateBackend for Inflate {
         input: &[u8],
         output: &mut [u8],
         flush: FlushDecompress,
+    ) -> Result<Status, DecompressError> {
+        if self.unwrapped.is_some() {
+            self.decompress_unwrapped(input, output, flush)
+        } else {
+            self.inflate(input, output, flush as c_int)
+        }
+    }
+
+    fn reset(&mut self, zlib_header: bool) {
+        self.zlib_header = zlib_header;
+        self.window_bits = MZ_DEFAULT_WINDOW_BITS as u8;
+        self.reset_stream();
+        self.inner.total_out = 0;
+        self.inner.total_in = 0;
+    }
+
+    fn set_verify_checksum(&mut self, verify: bool) {
+        self.verify_checksum = verify;
+        self.reset_stream();
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, DecompressError> {
+        let stream = &mut *self.inner.stream_wrapper;
+        stream.msg = ptr::null_mut();
+        let rc = unsafe {
+            assert!(dictionary.len() < uInt::MAX as usize);
+            inflateSetDictionary(stream, dictionary.as_ptr(), dictionary.len() as uInt)
+        };
+
+        match rc {
+            MZ_STREAM_ERROR => mem::decompress_failed(self.inner.msg()),
+            MZ_DATA_ERROR => mem::decompress_need_dict(stream.adler as u32),
+            // A raw stream doesn't compute the id of its dictionary.
+            MZ_OK if self.unwrapped.is_some() => {
+                let mut adler = crate::adler::Adler32::new();
+                adler.update(dictionary);
+                Ok(adler.sum())
+            }
+            MZ_OK => Ok(stream.adler as u32),
+            c => panic!("unknown return code: {}", c),
+        }
+    }
+}
+
+impl Inflate {
+    // Resets zlib for a new stream, leaving the wrapper of zlib streams whose
+    // checksum isn't verified to be read here.
+    fn reset_stream(&mut self) {
+        let unwrap = self.zlib_header && !self.verify_checksum && self.window_bits <= 15;
+        let bits = if self.zlib_header && !unwrap {
+            c_int::from(self.window_bits)
+        } else {
+            -c_int::from(self.window_bits)
+        };
+        unsafe {
+            inflateReset2(&mut *self.inner.stream_wrapper, bits);
+        }
+        self.unwrapped = if unwrap {
+            Some(Unwrapped::Header([0; 6], 0))
+        } else {
+            None
+        };
+    }
+
+    fn decompress_unwrapped(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushDecompress,
+    ) -> Result<Status, DecompressError> {
+        let mut pos = 0;
+        loop {
+            let stage = self.unwrapped.unwrap();
+            match stage {
+                Unwrapped::Header(mut header, mut len) => {
+                    let want = if len >= 2 && header[1] & 0x20 != 0 {
+                        6
+                    } else {
+                        2
+                    };
+                    let n = cmp::min(want - len, input.len() - pos);
+                    header[len..len + n].copy_from_slice(&input[pos..pos + n]);
+                    len += n;
+                    pos += n;
+                    self.inner.total_in += n as u64;
+                    self.unwrapped = Some(Unwrapped::Header(header, len));
+                    if len < want {
+                        break;
+                    }
+                    if want == 2 {
+                        let (cmf, flg) = (header[0], header[1]);
+                        if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+                            return mem::decompress_failed(ErrorMessage::new(
+                                "incorrect header check",
+                            ));
+                        }
+                        if cmf & 15 != 8 {
+                            return mem::decompress_failed(ErrorMessage::new(
+                                "unknown compression method",
+                            ));
+                        }
+                        if (cmf >> 4) + 8 > self.window_bits {
+                            return mem::decompress_failed(ErrorMessage::new(
+                                "invalid window size",
+                            ));
+                        }
+                        if flg & 0x20 != 0 {
+                            continue;
+                        }
+                    }
+                    self.unwrapped = Some(Unwrapped::Body);
+                    if want == 6 {
+                        let id = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+                        return mem::decompress_need_dict(id);
+                    }
+                }
+                Unwrapped::Body => {
+                    let before = self.inner.total_in;
+                    let status = self.inflate(&input[pos..], output, flush as c_int)?;
+                    pos += (self.inner.total_in - before) as usize;
+                    match status {
+                        Status::StreamEnd => self.unwrapped = Some(Unwrapped::Trailer(0)),
+                        Status::BufError if pos > 0 => return Ok(Status::Ok),
+                        status => return Ok(status),
+                    }
+                }
+                Unwrapped::Trailer(skipped) => {
+                    let n = cmp::min(4 - skipped, input.len() - pos);
+                    pos += n;
+                    self.inner.total_in += n as u64;
+                    if skipped + n < 4 {
+                        self.unwrapped = Some(Unwrapped::Trailer(skipped + n));
+                        break;
+                    }
+                    self.unwrapped = Some(Unwrapped::Done);
+                }
+                Unwrapped::Done => return Ok(Status::StreamEnd),
+            }
+        }
+        // Out of input before the end of the header or trailer.
+        Ok(if pos == 0 || flush == FlushDecompress::Finish {
+            Status::BufError
+        } else {
+            Status::Ok
+        })
+    }
+
+    pub fn prime(&mut self, bits: u8, value: u16) -> Result<(), DecompressError> {
+        let stream = &mut *self.inner.stream_wrapper;
+        stream.msg = ptr::null_mut();
+
+        let rc = unsafe { inflatePrime(stream, c_int::from(bits), c_int::from(value)) };
+
+        match rc {
+            MZ_OK => Ok(()),
+            MZ_STREAM_ERROR => mem::decompress_failed(self.inner.msg()),
+            c => panic!("unknown return code: {}", c),
+        }
+    }
+
+    /// Decompresses like `decompress` does without flushing, but also returns
+    /// at the end of every deflate block, along with zlib's `data_type` which
+    /// describes where decoding stopped.
+    pub fn decompress_block(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(Status, c_int), DecompressError> {
+        let status = self.inflate(input, output, MZ_BLOCK)?;
+        Ok((status, self.inner.stream_wrapper.data_type))
+    }
+
+    fn inflate(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: c_int,
     ) -> Result<Status, DecompressError> {
         let raw = &mut *self.inner.stream_wrapper;
         raw.msg = ptr::null_mut();
@@ -219,7 +421,7 @@ impl InflateBackend for Inflate {
         raw.next_out = output.as_mut_ptr();
         raw.avail_out = cmp::min(output.len(), c_uint::MAX as usize) as c_uint;
 
-        let rc = unsafe { mz_inflate(raw, flush as c_int) };
+        let rc = unsafe { mz_inflate(raw, flush) };
 
         // Unfortunately the total counters provided by zlib might be only
         // 32 bits wide and overflow while processing large amounts of data.
@@ -231,29 +433,17 @@ impl InflateBackend for Inflate {
         raw.avail_in = 0;
         raw.next_out = ptr::null_mut();
         raw.avail_out = 0;
+        let adler = raw.adler as u32;
 
         match rc {
             MZ_DATA_ERROR | MZ_STREAM_ERROR => mem::decompress_failed(self.inner.msg()),
             MZ_OK => Ok(Status::Ok),
             MZ_BUF_ERROR => Ok(Status::BufError),
             MZ_STREAM_END => Ok(Status::StreamEnd),
-            MZ_NEED_DICT => mem::decompress_need_dict(raw.adler as u32),
+            MZ_NEED_DICT => mem::decompress_need_dict(adler),
             c => panic!("unknown return code: {}", c),
         }
     }
-
-    fn reset(&mut self, zlib_header: bool) {
-        let bits = if zlib_header {
-            MZ_DEFAULT_WINDOW_BITS
-        } else {
-            -MZ_DEFAULT_WINDOW_BITS
-        };
-        unsafe {
-            inflateReset2(&mut *self.inner.stream_wrapper, bits);
-        }
-        self.inner.total_out = 0;
-        self.inner.total_in = 0;
-    }
 }
 
 impl Backend for Inflate {
@@ -279,7 +469,7 @@ impl DeflateBackend for Deflate {
             let mut state = StreamWrapper::default();
             let ret = mz_deflateInit2(
                 &mut *state,
-                level.0 as c_int,
+                level.level() as c_int,
                 MZ_DEFLATED,
                 if zlib_header {
                     window_bits as c_int
@@ -341,6 +531,54 @@ impl DeflateBackend for Deflate {
         let rc = unsafe { mz_deflateReset(&mut *self.inner.stream_wrapper) };
         assert_eq!(rc, MZ_OK);
     }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, CompressError> {
+        let stream = &mut *self.inner.stream_wrapper;
+        stream.msg = ptr::null_mut();
+        let rc = unsafe {
+            assert!(dictionary.len() < uInt::MAX as usize);
+            deflateSetDictionary(stream, dictionary.as_ptr(), dictionary.len() as uInt)
+        };
+
+        match rc {
+            MZ_STREAM_ERROR => mem::compress_failed(self.inner.msg()),
+            MZ_OK => Ok(stream.adler as u32),
+            c => panic!("unknown return code: {}", c),
+        }
+    }
+
+    fn set_level(&mut self, level: Compression) -> Result<(), CompressError> {
+        let stream = &mut *self.inner.stream_wrapper;
+        stream.msg = ptr::null_mut();
+        // zlib flushes the current block when the new level needs it, and
+        // refuses to do so without an output buffer, even an empty one.
+        let mut out = [0u8; 0];
+        stream.next_out = out.as_mut_ptr();
+
+        let rc = unsafe { deflateParams(stream, level.level() as c_int, MZ_DEFAULT_STRATEGY) };
+        stream.next_out = ptr::null_mut();
+
+        match rc {
+            MZ_OK => Ok(()),
+            MZ_BUF_ERROR | MZ_STREAM_ERROR => mem::compress_failed(self.inner.msg()),
+            c => panic!("unknown return code: {}", c),
+        }
+    }
+}
+
+impl Deflate {
+    pub fn prime(&mut self, bits: u8, value: u16) -> Result<(), CompressError> {
+        let stream = &mut *self.inner.stream_wrapper;
+        stream.msg = ptr::null_mut();
+
+        let rc = unsafe { deflatePrime(stream, c_int::from(bits), c_int::from(value)) };
+
+        match rc {
+            MZ_OK => Ok(()),
+            MZ_STREAM_ERROR | MZ_BUF_ERROR => mem::compress_failed(self.inner.msg()),
+            c => panic!("unknown return code: {}", c),
+        }
+    }
 }
 
 impl Backend for Deflate {
@@ -369,7 +607,18 @@ mod c_backend {
     #[cfg(all(not(feature = "zlib-ng"), feature = "cloudflare_zlib"))]
     use cloudflare_zlib_sys as libz;
 
-    #[cfg(all(not(feature = "cloudflare_zlib"), not(feature = "zlib-ng")))]
+    #[cfg(all(
+        not(feature = "zlib-ng"),
+        not(feature = "cloudflare_zlib"),
+        feature = "zlib-rs"
+    ))]
+    use libz_rs_sys as libz;
+
+    #[cfg(all(
+        not(feature = "cloudflare_zlib"),
+        not(feature = "zlib-ng"),
+        not(feature = "zlib-rs")
+    ))]
     use libz_sys as libz;
 
     pub use libz::deflate as mz_deflate;
@@ -385,19 +634,12 @@ mod c_backend {
     pub use libz::Z_DATA_ERROR as MZ_DATA_ERROR;
     pub use libz::Z_DEFAULT_STRATEGY as MZ_DEFAULT_STRATEGY;
     pub use libz::Z_DEFLATED as MZ_DEFLATED;
-    pub use libz::Z_FINISH as MZ_FINISH;
-    pub use libz::Z_FULL_FLUSH as MZ_FULL_FLUSH;
     pub use libz::Z_NEED_DICT as MZ_NEED_DICT;
-    pub use libz::Z_NO_FLUSH as MZ_NO_FLUSH;
     pub use libz::Z_OK as MZ_OK;
-    pub use libz::Z_PARTIAL_FLUSH as MZ_PARTIAL_FLUSH;
     pub use libz::Z_STREAM_END as MZ_STREAM_END;
     pub use libz::Z_STREAM_ERROR as MZ_STREAM_ERROR;
-    pub use libz::Z_SYNC_FLUSH as MZ_SYNC_FLUSH;
     pub type AllocSize = libz::uInt;
 
-    pub const MZ_DEFAULT_WINDOW_BITS: c_int = 15;
-
     #[cfg(feature = "zlib-ng")]
     const ZLIB_VERSION: &'static str = "2.1.0.devel\0";
     #[cfg(not(feature = "zlib-ng"))]
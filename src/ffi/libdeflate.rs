@@ -0,0 +1,170 @@
+//! Whole-buffer compression and decompression with libdeflate, behind the
+//! functions of the `oneshot` module.
+
+use std::convert::TryInto;
+use std::io;
+use std::ptr::NonNull;
+
+use libdeflate_sys::{
+    libdeflate_alloc_decompressor, libdeflate_decompressor, libdeflate_deflate_decompress_ex,
+    libdeflate_free_decompressor, libdeflate_result,
+    libdeflate_result_LIBDEFLATE_INSUFFICIENT_SPACE as LIBDEFLATE_INSUFFICIENT_SPACE,
+    libdeflate_result_LIBDEFLATE_SUCCESS as LIBDEFLATE_SUCCESS,
+};
+use libdeflater::{CompressionLvl, Compressor};
+
+use crate::gz::{check_trailer, GzHeaderParser};
+use crate::{oneshot, Compression, Crc, Error, Format};
+
+// The most deflate data can expand to, with matches of 258 bytes taking one
+// bit each and a little more for the headers of its blocks.
+const MAX_RATIO: usize = 1032;
+
+/// Compresses `data` as a whole, or returns `None` for levels libdeflate
+/// doesn't have a counterpart of.
+pub(crate) fn compress(data: &[u8], format: Format, level: Compression) -> Option<Vec<u8>> {
+    if level.is_exhaustive() || level.level() > Compression::best().level() {
+        return None;
+    }
+    let mut c = Compressor::new(CompressionLvl::new(level.level() as i32).ok()?);
+    let bound = match format {
+        Format::Gzip => c.gzip_compress_bound(data.len()),
+        Format::Zlib => c.zlib_compress_bound(data.len()),
+        _ => c.deflate_compress_bound(data.len()),
+    };
+    let mut out = vec![0; bound];
+    let len = match format {
+        Format::Gzip => c.gzip_compress(data, &mut out),
+        Format::Zlib => c.zlib_compress(data, &mut out),
+        _ => c.deflate_compress(data, &mut out),
+    };
+    out.truncate(len.expect("output space is the bound given by libdeflate"));
+    Some(out)
+}
+
+/// Decompresses the stream at the start of `data`, growing the output space
+/// until it fits.
+///
+/// libdeflate only inflates the deflate data: the header and trailer are read
+/// here, so that a checksum mismatch is reported at the offset of the trailer.
+/// When the deflate data itself is invalid, libdeflate can't tell where, nor
+/// whether it is merely cut short, so the streaming decoders are run over the
+/// input to find out.
+pub(crate) fn decompress(data: &[u8], format: Format) -> io::Result<Vec<u8>> {
+    let start = match format {
+        Format::Gzip => {
+            let mut parser = GzHeaderParser::new();
+            match parser.parse(&mut &data[..]) {
+                Ok(()) => parser.len() as usize,
+                Err(_) => return oneshot::decompress_streaming(data, format),
+            }
+        }
+        Format::Zlib => match *data {
+            [cmf, flg, ..]
+                if (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0
+                    && cmf & 15 == 8
+                    && cmf >> 4 <= 7
+                    && flg & 0x20 == 0 =>
+            {
+                2
+            }
+            _ => return oneshot::decompress_streaming(data, format),
+        },
+        _ => 0,
+    };
+
+    let body = &data[start..];
+    let max = body.len().saturating_mul(MAX_RATIO).max(1024);
+    // The size in the trailer of a gzip member is usually right.
+    let mut len = match format {
+        Format::Gzip if data.len() >= 4 => {
+            let isize = u32::from_le_bytes(data[data.len() - 4..].try_into().unwrap());
+            (isize as usize).min(max)
+        }
+        _ => body.len().saturating_mul(4).min(max),
+    };
+    let d = Decompressor::new();
+    let (out, consumed) = loop {
+        let mut out = vec![0; len];
+        match d.inflate(body, &mut out) {
+            Ok((consumed, n)) => {
+                out.truncate(n);
+                break (out, consumed);
+            }
+            Err(LIBDEFLATE_INSUFFICIENT_SPACE) if len < max => {
+                len = len.saturating_mul(2).clamp(1024, max);
+            }
+            // Data expanding further than deflate can is just as invalid,
+            // and the output can't be short with its actual size asked for.
+            // Results of later versions of libdeflate are left to the
+            // streaming decoders as well.
+            Err(_) => return oneshot::decompress_streaming(data, format),
+        }
+    };
+
+    let trailer_start = start + consumed;
+    let trailer = &data[trailer_start..];
+    let truncated = || Error::Truncated {
+        offset: data.len() as u64,
+    };
+    match format {
+        Format::Gzip => {
+            let trailer = trailer.get(..8).ok_or_else(truncated)?;
+            let mut crc = Crc::new();
+            crc.update(&out);
+            check_trailer(trailer, &crc, trailer_start as u64)?;
+        }
+        Format::Zlib => {
+            let trailer = trailer.get(..4).ok_or_else(truncated)?;
+            if *trailer != libdeflater::adler32(&out).to_be_bytes() {
+                let offset = trailer_start as u64;
+                return Err(Error::DataCrcMismatch { offset }.into());
+            }
+        }
+        _ => {}
+    }
+    Ok(out)
+}
+
+// A libdeflate decompressor, used directly since `libdeflater` doesn't say how
+// much of the input its deflate data takes up.
+struct Decompressor(NonNull<libdeflate_decompressor>);
+
+impl Decompressor {
+    fn new() -> Decompressor {
+        let ptr = unsafe { libdeflate_alloc_decompressor() };
+        Decompressor(NonNull::new(ptr).expect("failed to allocate a libdeflate decompressor"))
+    }
+
+    // Inflates the deflate data at the start of `input`, returning how many
+    // bytes it takes up and how many bytes it decompresses to.
+    fn inflate(
+        &self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(usize, usize), libdeflate_result> {
+        let mut consumed = 0;
+        let mut written = 0;
+        let ret = unsafe {
+            libdeflate_deflate_decompress_ex(
+                self.0.as_ptr(),
+                input.as_ptr().cast(),
+                input.len(),
+                output.as_mut_ptr().cast(),
+                output.len(),
+                &mut consumed,
+                &mut written,
+            )
+        };
+        match ret {
+            LIBDEFLATE_SUCCESS => Ok((consumed, written)),
+            err => Err(err),
+        }
+    }
+}
+
+impl Drop for Decompressor {
+    fn drop(&mut self) {
+        unsafe { libdeflate_free_decompressor(self.0.as_ptr()) }
+    }
+}
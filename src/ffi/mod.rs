@@ -21,6 +21,8 @@ pub trait InflateBackend: Backend {
         flush: FlushDecompress,
     ) -> Result<Status, DecompressError>;
     fn reset(&mut self, zlib_header: bool);
+    fn set_verify_checksum(&mut self, verify: bool);
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, DecompressError>;
 }
 
 pub trait DeflateBackend: Backend {
@@ -32,21 +34,200 @@ pub trait DeflateBackend: Backend {
         flush: FlushCompress,
     ) -> Result<Status, CompressError>;
     fn reset(&mut self);
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, CompressError>;
+    fn set_level(&mut self, level: Compression) -> Result<(), CompressError>;
 }
 
-// Default to Rust implementation unless explicitly opted in to a different backend.
+// Every backend enabled is compiled in, and picked by the `Backend` given
+// when creating a stream.
+#[cfg(feature = "miniz_oxide")]
+mod blocks;
 #[cfg(feature = "any_zlib")]
-mod c;
-#[cfg(feature = "any_zlib")]
-pub use self::c::*;
+pub(crate) mod c;
+#[cfg(feature = "miniz_oxide")]
+pub(crate) mod rust;
+
+// The flush modes and default window size, which zlib and miniz_oxide agree
+// on.
+pub const MZ_NO_FLUSH: isize = 0;
+pub const MZ_PARTIAL_FLUSH: isize = 1;
+pub const MZ_SYNC_FLUSH: isize = 2;
+pub const MZ_FULL_FLUSH: isize = 3;
+pub const MZ_FINISH: isize = 4;
+pub const MZ_DEFAULT_WINDOW_BITS: i32 = 15;
+
+#[cfg(feature = "libdeflate")]
+pub(crate) mod libdeflate;
 
-#[cfg(all(not(feature = "any_zlib"), feature = "miniz_oxide"))]
-mod rust;
-#[cfg(all(not(feature = "any_zlib"), feature = "miniz_oxide"))]
-pub use self::rust::*;
+#[derive(Default)]
+pub struct ErrorMessage(Option<&'static str>);
+
+impl ErrorMessage {
+    pub(crate) fn new(msg: &'static str) -> ErrorMessage {
+        ErrorMessage(Some(msg))
+    }
+
+    pub fn get(&self) -> Option<&str> {
+        self.0
+    }
+}
 
 impl std::fmt::Debug for ErrorMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         self.get().fmt(f)
     }
 }
+
+// Forwards a call to whichever backend `$self` is.
+macro_rules! dispatch {
+    ($self:expr, $inner:ident => $e:expr) => {
+        match $self {
+            #[cfg(feature = "any_zlib")]
+            Self::Zlib($inner) => $e,
+            #[cfg(feature = "miniz_oxide")]
+            Self::Rust($inner) => $e,
+        }
+    };
+}
+
+/// A decompressor of the backend picked for the stream.
+#[derive(Debug)]
+pub enum Inflate {
+    #[cfg(feature = "any_zlib")]
+    Zlib(c::Inflate),
+    #[cfg(feature = "miniz_oxide")]
+    Rust(rust::Inflate),
+}
+
+impl Inflate {
+    pub fn make_with(backend: crate::Backend, zlib_header: bool, window_bits: u8) -> Inflate {
+        match backend {
+            #[cfg(feature = "any_zlib")]
+            crate::Backend::Zlib => Inflate::Zlib(c::Inflate::make(zlib_header, window_bits)),
+            #[cfg(feature = "miniz_oxide")]
+            crate::Backend::Rust => Inflate::Rust(rust::Inflate::make(zlib_header, window_bits)),
+        }
+    }
+
+    /// Decompresses like `decompress` does without flushing, but also returns
+    /// at the end of every deflate block, along with zlib's `data_type` which
+    /// describes where decoding stopped.
+    pub fn decompress_block(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(Status, std::os::raw::c_int), DecompressError> {
+        dispatch!(self, inner => inner.decompress_block(input, output))
+    }
+
+    pub fn prime(&mut self, bits: u8, value: u16) -> Result<(), DecompressError> {
+        dispatch!(self, inner => inner.prime(bits, value))
+    }
+}
+
+impl Backend for Inflate {
+    fn total_in(&self) -> u64 {
+        dispatch!(self, inner => inner.total_in())
+    }
+
+    fn total_out(&self) -> u64 {
+        dispatch!(self, inner => inner.total_out())
+    }
+}
+
+impl InflateBackend for Inflate {
+    fn make(zlib_header: bool, window_bits: u8) -> Inflate {
+        Inflate::make_with(crate::Backend::default(), zlib_header, window_bits)
+    }
+
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushDecompress,
+    ) -> Result<Status, DecompressError> {
+        dispatch!(self, inner => inner.decompress(input, output, flush))
+    }
+
+    fn reset(&mut self, zlib_header: bool) {
+        dispatch!(self, inner => inner.reset(zlib_header))
+    }
+
+    fn set_verify_checksum(&mut self, verify: bool) {
+        dispatch!(self, inner => inner.set_verify_checksum(verify))
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, DecompressError> {
+        dispatch!(self, inner => inner.set_dictionary(dictionary))
+    }
+}
+
+/// A compressor of the backend picked for the stream.
+#[derive(Debug)]
+pub enum Deflate {
+    #[cfg(feature = "any_zlib")]
+    Zlib(c::Deflate),
+    #[cfg(feature = "miniz_oxide")]
+    Rust(rust::Deflate),
+}
+
+impl Deflate {
+    pub fn make_with(
+        backend: crate::Backend,
+        level: Compression,
+        zlib_header: bool,
+        window_bits: u8,
+    ) -> Deflate {
+        match backend {
+            #[cfg(feature = "any_zlib")]
+            crate::Backend::Zlib => {
+                Deflate::Zlib(c::Deflate::make(level, zlib_header, window_bits))
+            }
+            #[cfg(feature = "miniz_oxide")]
+            crate::Backend::Rust => {
+                Deflate::Rust(rust::Deflate::make(level, zlib_header, window_bits))
+            }
+        }
+    }
+
+    pub fn prime(&mut self, bits: u8, value: u16) -> Result<(), CompressError> {
+        dispatch!(self, inner => inner.prime(bits, value))
+    }
+}
+
+impl Backend for Deflate {
+    fn total_in(&self) -> u64 {
+        dispatch!(self, inner => inner.total_in())
+    }
+
+    fn total_out(&self) -> u64 {
+        dispatch!(self, inner => inner.total_out())
+    }
+}
+
+impl DeflateBackend for Deflate {
+    fn make(level: Compression, zlib_header: bool, window_bits: u8) -> Deflate {
+        Deflate::make_with(crate::Backend::default(), level, zlib_header, window_bits)
+    }
+
+    fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushCompress,
+    ) -> Result<Status, CompressError> {
+        dispatch!(self, inner => inner.compress(input, output, flush))
+    }
+
+    fn reset(&mut self) {
+        dispatch!(self, inner => inner.reset())
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, CompressError> {
+        dispatch!(self, inner => inner.set_dictionary(dictionary))
+    }
+
+    fn set_level(&mut self, level: Compression) -> Result<(), CompressError> {
+        dispatch!(self, inner => inner.set_level(level))
+    }
+}
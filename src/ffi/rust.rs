@@ -1,36 +1,159 @@
 //! Implementation for `miniz_oxide` rust backend.
+//!
+//! `miniz_oxide` only ever sees raw deflate data here: the zlib and gzip
+//! wrappers are written and parsed by this module, so that preset dictionaries
+//! can be used, which `miniz_oxide` has no support for. The compressor is given
+//! a dictionary by compressing it with a sync flush and dropping the output,
+//! and the decompressor by decompressing a stored block holding it, both of
+//! which leave the dictionary in their window.
+//!
+//! The window of `miniz_oxide` is always 32 KiB, so a smaller one is emulated
+//! by a full flush whenever that much input went in, which clears the window.
+//! Primed bits are inserted by shifting the data on its way to or from
+//! `miniz_oxide`, and the ends of blocks are found by scanning the input ahead
+//! of the decompressor.
 
 use std::convert::TryInto;
 use std::fmt;
+use std::io;
+use std::os::raw::c_int;
 
 use miniz_oxide::deflate::core::CompressorOxide;
 use miniz_oxide::inflate::stream::InflateState;
 pub use miniz_oxide::*;
 
-pub const MZ_NO_FLUSH: isize = MZFlush::None as isize;
-pub const MZ_PARTIAL_FLUSH: isize = MZFlush::Partial as isize;
-pub const MZ_SYNC_FLUSH: isize = MZFlush::Sync as isize;
-pub const MZ_FULL_FLUSH: isize = MZFlush::Full as isize;
-pub const MZ_FINISH: isize = MZFlush::Finish as isize;
-
+use super::blocks::Scanner;
 use super::*;
-use crate::mem;
+use crate::crc::Crc;
+use crate::gz::{GzBuilder, GzHeaderParser};
+use crate::{mem, Error};
+
+// The size of the window of `miniz_oxide`, which doesn't support any other.
+const WINDOW_SIZE: usize = 32 * 1024;
+
+// The most input to compress between full flushes for a window of
+// `window_bits`, without any limit for the window of `miniz_oxide`.
+fn window_size(window_bits: u8) -> usize {
+    match window_bits {
+        15 => usize::MAX,
+        bits => 1 << bits,
+    }
+}
+
+/// The format of the data around the raw deflate stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wrapper {
+    Raw,
+    Zlib,
+    Gzip,
+}
+
+impl Wrapper {
+    // As with zlib, window bits above 15 ask for gzip.
+    fn new(zlib_header: bool, window_bits: u8) -> Wrapper {
+        if window_bits > 15 {
+            Wrapper::Gzip
+        } else if zlib_header {
+            Wrapper::Zlib
+        } else {
+            Wrapper::Raw
+        }
+    }
+
+    fn trailer_len(self) -> usize {
+        match self {
+            Wrapper::Raw => 0,
+            Wrapper::Zlib => 4,
+            Wrapper::Gzip => 8,
+        }
+    }
+}
+
+/// How far a stream has got, its wrapper included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Header,
+    // The zlib header asks for the dictionary with this Adler-32 checksum.
+    Dictionary(u32),
+    Body,
+    Trailer,
+    Done,
+}
+
+/// The checksum of the uncompressed data that the wrapper calls for.
+#[derive(Debug)]
+struct Check {
+    adler: u32,
+    crc: Crc,
+}
 
-// miniz_oxide doesn't provide any error messages (yet?)
-#[derive(Default)]
-pub struct ErrorMessage;
+impl Check {
+    fn new() -> Check {
+        Check {
+            adler: MZ_ADLER32_INIT,
+            crc: Crc::new(),
+        }
+    }
 
-impl ErrorMessage {
-    pub fn get(&self) -> Option<&str> {
-        None
+    fn update(&mut self, wrapper: Wrapper, data: &[u8]) {
+        match wrapper {
+            Wrapper::Raw => {}
+            Wrapper::Zlib => self.adler = mz_adler32_oxide(self.adler, data),
+            Wrapper::Gzip => self.crc.update(data),
+        }
     }
 }
 
-fn format_from_bool(zlib_header: bool) -> DataFormat {
-    if zlib_header {
-        DataFormat::Zlib
+fn adler32(data: &[u8]) -> u32 {
+    mz_adler32_oxide(MZ_ADLER32_INIT, data)
+}
+
+// The base-2 logarithm of the window, which for gzip is given plus 16.
+fn window_log(window_bits: u8) -> u8 {
+    if window_bits > 15 {
+        window_bits - 16
     } else {
-        DataFormat::Raw
+        window_bits
+    }
+}
+
+/// Bits inserted ahead of the data, made of whole bytes followed by the low
+/// `bits` bits of `carry`.
+#[derive(Debug, Default)]
+struct Primed {
+    bytes: Vec<u8>,
+    carry: u8,
+    bits: u8,
+}
+
+impl Primed {
+    fn push(&mut self, bits: u8, value: u16) {
+        let value = u32::from(value) & ((1 << bits) - 1);
+        let mut acc = u32::from(self.carry) | value << self.bits;
+        let mut n = self.bits + bits;
+        while n >= 8 {
+            self.bytes.push(acc as u8);
+            acc >>= 8;
+            n -= 8;
+        }
+        self.carry = acc as u8;
+        self.bits = n;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty() && self.bits == 0
+    }
+
+    // Shifts `data` up by the carried bits, carrying its own top bits over.
+    fn shift(&mut self, data: &mut [u8]) {
+        if self.bits == 0 {
+            return;
+        }
+        for byte in data {
+            let top = *byte >> (8 - self.bits);
+            *byte = self.carry | *byte << self.bits;
+            self.carry = top;
+        }
     }
 }
 
@@ -38,6 +161,18 @@ pub struct Inflate {
     inner: Box<InflateState>,
     total_in: u64,
     total_out: u64,
+    wrapper: Wrapper,
+    stage: Stage,
+    // The part of the zlib header or of the trailer read so far.
+    buffer: Vec<u8>,
+    gzip: Option<Box<GzHeaderParser>>,
+    check: Check,
+    verify_checksum: bool,
+    // The largest window the zlib header may ask for.
+    window_bits: u8,
+    primed: Primed,
+    // Finds where blocks end, once asked to stop there.
+    blocks: Option<Box<Scanner>>,
 }
 
 impl fmt::Debug for Inflate {
@@ -51,13 +186,20 @@ impl fmt::Debug for Inflate {
 }
 
 impl InflateBackend for Inflate {
-    fn make(zlib_header: bool, _window_bits: u8) -> Self {
-        let format = format_from_bool(zlib_header);
-
+    fn make(zlib_header: bool, window_bits: u8) -> Self {
         Inflate {
-            inner: InflateState::new_boxed(format),
+            inner: InflateState::new_boxed(DataFormat::Raw),
             total_in: 0,
             total_out: 0,
+            wrapper: Wrapper::new(zlib_header, window_bits),
+            stage: Stage::Header,
+            buffer: Vec::new(),
+            gzip: None,
+            check: Check::new(),
+            verify_checksum: true,
+            window_bits: window_log(window_bits),
+            primed: Primed::default(),
+            blocks: None,
         }
     }
 
@@ -67,31 +209,319 @@ impl InflateBackend for Inflate {
         output: &mut [u8],
         flush: FlushDecompress,
     ) -> Result<Status, DecompressError> {
-        let flush = MZFlush::new(flush as i32).unwrap();
-
-        let res = inflate::stream::inflate(&mut self.inner, input, output, flush);
-        self.total_in += res.bytes_consumed as u64;
-        self.total_out += res.bytes_written as u64;
-
-        match res.status {
-            Ok(status) => match status {
-                MZStatus::Ok => Ok(Status::Ok),
-                MZStatus::StreamEnd => Ok(Status::StreamEnd),
-                MZStatus::NeedDict => {
-                    mem::decompress_need_dict(self.inner.decompressor().adler32().unwrap_or(0))
-                }
-            },
-            Err(status) => match status {
-                MZError::Buf => Ok(Status::BufError),
-                _ => mem::decompress_failed(ErrorMessage),
-            },
-        }
+        let mut consumed = 0;
+        let mut written = 0;
+        let ret = if self.primed.is_empty() {
+            self.inflate(input, output, flush, &mut consumed, &mut written)
+        } else {
+            self.inflate_primed(input, output, flush, &mut consumed, &mut written)
+        };
+        self.total_in += consumed as u64;
+        self.total_out += written as u64;
+        ret
     }
 
     fn reset(&mut self, zlib_header: bool) {
-        self.inner.reset(format_from_bool(zlib_header));
+        self.inner.reset(DataFormat::Raw);
         self.total_in = 0;
         self.total_out = 0;
+        self.wrapper = Wrapper::new(zlib_header, 0);
+        self.stage = Stage::Header;
+        self.buffer.clear();
+        self.gzip = None;
+        self.check = Check::new();
+        self.window_bits = super::MZ_DEFAULT_WINDOW_BITS as u8;
+        self.primed = Primed::default();
+        self.blocks = None;
+    }
+
+    fn set_verify_checksum(&mut self, verify: bool) {
+        self.verify_checksum = verify;
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, DecompressError> {
+        let adler = adler32(dictionary);
+        match self.stage {
+            Stage::Dictionary(id) if id != adler => return mem::decompress_need_dict(id),
+            Stage::Dictionary(_) => self.stage = Stage::Body,
+            Stage::Header | Stage::Body if self.wrapper == Wrapper::Raw && self.total_in == 0 => {}
+            _ => return mem::decompress_failed(ErrorMessage::new("unexpected dictionary")),
+        }
+
+        // Only the end of the dictionary fits in the window.
+        let window = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+        if !window.is_empty() {
+            let len = window.len() as u16;
+            // A stored block which isn't the last one of the stream.
+            let mut block = vec![0];
+            block.extend_from_slice(&len.to_le_bytes());
+            block.extend_from_slice(&(!len).to_le_bytes());
+            block.extend_from_slice(window);
+            let mut out = vec![0; window.len()];
+            let res = inflate::stream::inflate(&mut self.inner, &block, &mut out, MZFlush::None);
+            debug_assert!(res.status.is_ok() && res.bytes_written == out.len());
+        }
+        Ok(adler)
+    }
+}
+
+impl Inflate {
+    pub fn prime(&mut self, bits: u8, value: u16) -> Result<(), DecompressError> {
+        if bits > 16 {
+            return mem::decompress_failed(ErrorMessage::new("invalid bits to prime"));
+        }
+        self.primed.push(bits, value);
+        Ok(())
+    }
+
+    /// Decompresses like `decompress` does without flushing, but also returns
+    /// at the end of every deflate block, along with what zlib's `data_type`
+    /// would be.
+    ///
+    /// Only raw deflate data is supported, without any primed bits.
+    pub fn decompress_block(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(Status, c_int), DecompressError> {
+        if self.wrapper != Wrapper::Raw || !self.primed.is_empty() {
+            return mem::decompress_failed(ErrorMessage::new(
+                "stopping at blocks needs raw deflate data",
+            ));
+        }
+        let blocks = self.blocks.get_or_insert_with(Box::default);
+        // The caller hands back whatever input wasn't consumed.
+        let seen = (blocks.fed() - self.total_in) as usize;
+        blocks.feed(&input[seen.min(input.len())..]);
+
+        // The decompressor never gets past the byte the block ends in.
+        let end = blocks.next_end();
+        let limit = end.map_or(input.len(), |end| {
+            (end.bits.div_ceil(8) - self.total_in) as usize
+        });
+        let before = self.total_out;
+        let mut status = self.decompress(
+            &input[..limit.min(input.len())],
+            output,
+            FlushDecompress::None,
+        )?;
+        let written = (self.total_out - before) as usize;
+
+        let mut data_type = 0;
+        if let Some(end) = end {
+            // All the data of the block is out once output is left over.
+            if self.total_in * 8 >= end.bits
+                && (written < output.len() || status == Status::StreamEnd)
+            {
+                self.blocks.as_mut().unwrap().pop_end();
+                data_type =
+                    128 | c_int::from(end.last) << 6 | (self.total_in * 8 - end.bits) as c_int;
+                if status == Status::BufError {
+                    status = Status::Ok;
+                }
+            }
+        }
+        Ok((status, data_type))
+    }
+
+    // Decompresses like `inflate`, with the primed bits ahead of `input`.
+    // `miniz_oxide` gets the primed bytes, then `input` shifted up by the
+    // remaining bits, the top bits of the last byte it consumed being carried
+    // over to the next call.
+    fn inflate_primed(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushDecompress,
+        consumed: &mut usize,
+        written: &mut usize,
+    ) -> Result<Status, DecompressError> {
+        if !self.primed.bytes.is_empty() {
+            let bytes = std::mem::take(&mut self.primed.bytes);
+            let mut used = 0;
+            let status = self.inflate(&bytes, output, FlushDecompress::None, &mut used, written)?;
+            self.primed.bytes = bytes[used..].to_vec();
+            if used < bytes.len() || status == Status::StreamEnd {
+                return Ok(status);
+            }
+        }
+        let bits = self.primed.bits;
+        if bits == 0 {
+            return self.inflate(input, output, flush, consumed, written);
+        }
+
+        let mut shifted = input.to_vec();
+        let carry = self.primed.carry;
+        self.primed.shift(&mut shifted);
+        // The last bits only go in at the end of the input.
+        if flush == FlushDecompress::Finish {
+            shifted.push(self.primed.carry);
+        }
+        self.primed.carry = carry;
+        let mut used = 0;
+        let ret = self.inflate(&shifted, output, flush, &mut used, written);
+        if used > input.len() {
+            self.primed = Primed::default();
+        } else if used > 0 {
+            self.primed.carry = input[used - 1] >> (8 - bits);
+        }
+        *consumed += used.min(input.len());
+        ret
+    }
+
+    // Decompresses `input` into `output`, going through the wrapper and the
+    // deflate data as far as they go, and keeping track of how much of both
+    // buffers was used.
+    fn inflate(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushDecompress,
+        consumed: &mut usize,
+        written: &mut usize,
+    ) -> Result<Status, DecompressError> {
+        loop {
+            let input = &input[*consumed..];
+            match self.stage {
+                Stage::Header => {
+                    *consumed += self.read_header(input)?;
+                    if self.stage == Stage::Header {
+                        return Ok(starved(*consumed, flush));
+                    }
+                }
+                Stage::Dictionary(id) => return mem::decompress_need_dict(id),
+                Stage::Body => {
+                    let output = &mut output[*written..];
+                    let flush = MZFlush::new(flush as i32).unwrap();
+                    let res = inflate::stream::inflate(&mut self.inner, input, output, flush);
+                    self.check
+                        .update(self.wrapper, &output[..res.bytes_written]);
+                    *consumed += res.bytes_consumed;
+                    *written += res.bytes_written;
+
+                    match res.status {
+                        Ok(MZStatus::StreamEnd) => self.stage = Stage::Trailer,
+                        Ok(_) => return Ok(Status::Ok),
+                        Err(MZError::Buf) => return Ok(Status::BufError),
+                        Err(_) => return mem::decompress_failed(ErrorMessage::default()),
+                    }
+                }
+                Stage::Trailer => {
+                    *consumed += self.read_trailer(input)?;
+                    if self.stage == Stage::Trailer {
+                        return Ok(starved(*consumed, flush));
+                    }
+                }
+                Stage::Done => return Ok(Status::StreamEnd),
+            }
+        }
+    }
+
+    // Reads what it can of the header from `input`, returning how much of it
+    // was used. The stage moves on once the header is complete.
+    fn read_header(&mut self, input: &[u8]) -> Result<usize, DecompressError> {
+        match self.wrapper {
+            Wrapper::Raw => {
+                self.stage = Stage::Body;
+                Ok(0)
+            }
+            Wrapper::Zlib => {
+                let mut n = self.fill(input, 2);
+                if self.buffer.len() < 2 {
+                    return Ok(n);
+                }
+                let (cmf, flg) = (self.buffer[0], self.buffer[1]);
+                if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+                    return mem::decompress_failed(ErrorMessage::new("incorrect header check"));
+                }
+                if cmf & 15 != 8 {
+                    return mem::decompress_failed(ErrorMessage::new("unknown compression method"));
+                }
+                if (cmf >> 4) + 8 > self.window_bits {
+                    return mem::decompress_failed(ErrorMessage::new("invalid window size"));
+                }
+                if flg & 0x20 == 0 {
+                    self.stage = Stage::Body;
+                } else {
+                    n += self.fill(&input[n..], 6);
+                    if self.buffer.len() < 6 {
+                        return Ok(n);
+                    }
+                    let id = u32::from_be_bytes(self.buffer[2..6].try_into().unwrap());
+                    self.stage = Stage::Dictionary(id);
+                }
+                self.buffer.clear();
+                Ok(n)
+            }
+            Wrapper::Gzip => {
+                let parser = self
+                    .gzip
+                    .get_or_insert_with(|| Box::new(GzHeaderParser::new()));
+                let mut rest = input;
+                match parser.parse(&mut rest) {
+                    Ok(()) => self.stage = Stage::Body,
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && rest.is_empty() => {}
+                    Err(err) => return mem::decompress_failed(ErrorMessage::new(gzip_error(&err))),
+                }
+                Ok(input.len() - rest.len())
+            }
+        }
+    }
+
+    // Reads what it can of the trailer from `input`, returning how much of it
+    // was used, and checks it once complete.
+    fn read_trailer(&mut self, input: &[u8]) -> Result<usize, DecompressError> {
+        let len = self.wrapper.trailer_len();
+        let n = self.fill(input, len);
+        if self.buffer.len() < len {
+            return Ok(n);
+        }
+        let (data, size) = match self.wrapper {
+            Wrapper::Raw => (true, true),
+            Wrapper::Zlib => (self.buffer[..] == self.check.adler.to_be_bytes(), true),
+            Wrapper::Gzip => (
+                self.buffer[..4] == self.check.crc.sum().to_le_bytes(),
+                self.buffer[4..] == self.check.crc.amount().to_le_bytes(),
+            ),
+        };
+        // As with zlib, only the check of the data itself can be skipped.
+        if !data && self.verify_checksum {
+            return mem::decompress_failed(ErrorMessage::new("incorrect data check"));
+        }
+        if !size {
+            return mem::decompress_failed(ErrorMessage::new("incorrect length check"));
+        }
+        self.buffer.clear();
+        self.stage = Stage::Done;
+        Ok(n)
+    }
+
+    // Moves bytes of `input` to the buffer until it holds at least `len` of
+    // them.
+    fn fill(&mut self, input: &[u8], len: usize) -> usize {
+        let n = input.len().min(len.saturating_sub(self.buffer.len()));
+        self.buffer.extend_from_slice(&input[..n]);
+        n
+    }
+}
+
+// The status when the header or trailer is waiting for more input.
+fn starved(consumed: usize, flush: FlushDecompress) -> Status {
+    if consumed == 0 || flush == FlushDecompress::Finish {
+        Status::BufError
+    } else {
+        Status::Ok
+    }
+}
+
+// Matches the failure to parse a gzip header with the message zlib gives.
+fn gzip_error(err: &io::Error) -> &'static str {
+    match err.get_ref().and_then(|err| err.downcast_ref::<Error>()) {
+        Some(Error::BadMagic { .. }) => "incorrect header check",
+        Some(Error::UnsupportedMethod { .. }) => "unknown compression method",
+        Some(Error::ReservedFlags { .. }) => "unknown header flags set",
+        Some(Error::HeaderCrcMismatch { .. }) => "header crc mismatch",
+        _ => "invalid gzip header",
     }
 }
 
@@ -111,6 +541,21 @@ pub struct Deflate {
     inner: Box<CompressorOxide>,
     total_in: u64,
     total_out: u64,
+    level: Compression,
+    wrapper: Wrapper,
+    stage: Stage,
+    // The Adler-32 checksum of the dictionary, recorded in the zlib header.
+    dictionary: Option<u32>,
+    // The part of the header or of the trailer yet to be output.
+    pending: Vec<u8>,
+    check: Check,
+    // Whether input was taken since everything was last flushed.
+    unflushed: bool,
+    // The base-2 logarithm of the window, along with how much more input
+    // can go in before a full flush has to clear it.
+    window_bits: u8,
+    window_left: usize,
+    primed: Primed,
 }
 
 impl fmt::Debug for Deflate {
@@ -124,18 +569,27 @@ impl fmt::Debug for Deflate {
 }
 
 impl DeflateBackend for Deflate {
-    fn make(level: Compression, zlib_header: bool, _window_bits: u8) -> Self {
+    fn make(level: Compression, zlib_header: bool, window_bits: u8) -> Self {
         // Check in case the integer value changes at some point.
         debug_assert!(level.level() <= 10);
 
         let mut inner: Box<CompressorOxide> = Box::default();
-        let format = format_from_bool(zlib_header);
-        inner.set_format_and_level(format, level.level().try_into().unwrap_or(1));
+        inner.set_format_and_level(DataFormat::Raw, level.level().try_into().unwrap_or(1));
 
         Deflate {
             inner,
             total_in: 0,
             total_out: 0,
+            level,
+            wrapper: Wrapper::new(zlib_header, window_bits),
+            stage: Stage::Header,
+            dictionary: None,
+            pending: Vec::new(),
+            check: Check::new(),
+            unflushed: false,
+            window_bits: window_log(window_bits),
+            window_left: window_size(window_log(window_bits)),
+            primed: Primed::default(),
         }
     }
 
@@ -145,28 +599,215 @@ impl DeflateBackend for Deflate {
         output: &mut [u8],
         flush: FlushCompress,
     ) -> Result<Status, CompressError> {
-        let flush = MZFlush::new(flush as i32).unwrap();
-        let res = deflate::stream::deflate(&mut self.inner, input, output, flush);
-        self.total_in += res.bytes_consumed as u64;
-        self.total_out += res.bytes_written as u64;
+        if self.stage == Stage::Header {
+            let primed = self.primed();
+            self.pending = self.header();
+            self.pending.extend(primed);
+            self.stage = Stage::Body;
+        }
+        let mut written = self.drain(output);
 
-        match res.status {
-            Ok(status) => match status {
-                MZStatus::Ok => Ok(Status::Ok),
-                MZStatus::StreamEnd => Ok(Status::StreamEnd),
-                MZStatus::NeedDict => mem::compress_failed(ErrorMessage),
-            },
-            Err(status) => match status {
-                MZError::Buf => Ok(Status::BufError),
-                _ => mem::compress_failed(ErrorMessage),
-            },
+        let mut consumed = 0;
+        let mut ret = Ok(Status::Ok);
+        while self.stage == Stage::Body && self.pending.is_empty() {
+            let out = &mut output[written..];
+            let rest = &input[consumed..];
+            let chunk = &rest[..rest.len().min(self.window_left)];
+            let whole = chunk.len() == rest.len();
+            let mz_flush = if self.window_left == 0 && !rest.is_empty() {
+                MZFlush::Full
+            } else if !whole {
+                MZFlush::None
+            } else {
+                MZFlush::new(flush as i32).unwrap()
+            };
+            let res = deflate::stream::deflate(&mut self.inner, chunk, out, mz_flush);
+            self.check
+                .update(self.wrapper, &chunk[..res.bytes_consumed]);
+            consumed += res.bytes_consumed;
+            written += res.bytes_written;
+            self.window_left -= res.bytes_consumed;
+            if res.bytes_consumed > 0 {
+                self.unflushed = true;
+            }
+            let flushed = mz_flush != MZFlush::None
+                && res.bytes_consumed == chunk.len()
+                && res.bytes_written < out.len();
+            if flushed && whole {
+                self.unflushed = false;
+            }
+            if flushed && mz_flush == MZFlush::Full {
+                // miniz_oxide only clears its hash chains, and goes on finding
+                // matches at positions depending on all the input so far. With
+                // nothing left to output, starting over leaves none of it.
+                self.inner.reset();
+                self.window_left = window_size(self.window_bits);
+            }
+
+            match res.status {
+                Ok(MZStatus::StreamEnd) => {
+                    self.pending = self.trailer();
+                    self.stage = Stage::Trailer;
+                }
+                Ok(_) => {}
+                Err(MZError::Buf) if written == 0 => ret = Ok(Status::BufError),
+                Err(MZError::Buf) => {}
+                Err(_) => ret = mem::compress_failed(ErrorMessage::default()),
+            }
+            written += self.drain(&mut output[written..]);
+            // Only going through a full window, or clearing it, calls for
+            // going on with the rest of the input.
+            let cleared = mz_flush != MZFlush::Full || flushed;
+            if ret.is_err() || whole || res.bytes_consumed < chunk.len() || !cleared {
+                break;
+            }
+        }
+        self.total_in += consumed as u64;
+        self.total_out += written as u64;
+
+        if self.stage == Stage::Trailer && self.pending.is_empty() {
+            self.stage = Stage::Done;
+        }
+        if self.stage == Stage::Done {
+            return Ok(Status::StreamEnd);
         }
+        ret
     }
 
     fn reset(&mut self) {
         self.total_in = 0;
         self.total_out = 0;
         self.inner.reset();
+        self.stage = Stage::Header;
+        self.dictionary = None;
+        self.pending.clear();
+        self.check = Check::new();
+        self.unflushed = false;
+        self.window_left = window_size(self.window_bits);
+        self.primed = Primed::default();
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, CompressError> {
+        if self.wrapper == Wrapper::Gzip {
+            return mem::compress_failed(ErrorMessage::new("no dictionary with gzip"));
+        }
+        if self.stage != Stage::Header {
+            return mem::compress_failed(ErrorMessage::new("dictionary set too late"));
+        }
+
+        // Only the end of the dictionary fits in the window. With a smaller
+        // window than `miniz_oxide` has, half of it is left to the data so
+        // that the dictionary isn't cleared right away.
+        let keep = match window_size(self.window_bits) {
+            usize::MAX => WINDOW_SIZE,
+            size => size / 2,
+        };
+        let mut window = &dictionary[dictionary.len().saturating_sub(keep)..];
+        self.window_left = self.window_left.saturating_sub(window.len());
+        let mut out = vec![0; window.len() + 1024];
+        while !window.is_empty() {
+            let res = deflate::stream::deflate(&mut self.inner, window, &mut out, MZFlush::Sync);
+            window = &window[res.bytes_consumed..];
+            if res.status.is_err() {
+                return mem::compress_failed(ErrorMessage::default());
+            }
+        }
+
+        let adler = adler32(dictionary);
+        if self.wrapper == Wrapper::Zlib {
+            self.dictionary = Some(adler);
+        }
+        Ok(adler)
+    }
+
+    fn set_level(&mut self, level: Compression) -> Result<(), CompressError> {
+        // miniz_oxide gets confused by a new level with input still pending,
+        // which is also when zlib fails for lack of output space.
+        if level == self.level {
+            return Ok(());
+        }
+        if self.unflushed {
+            return mem::compress_failed(ErrorMessage::new("flush before changing the level"));
+        }
+        self.inner
+            .set_compression_level_raw(level.level().try_into().unwrap_or(1));
+        self.level = level;
+        Ok(())
+    }
+}
+
+impl Deflate {
+    pub fn prime(&mut self, bits: u8, value: u16) -> Result<(), CompressError> {
+        if bits > 16 {
+            return mem::compress_failed(ErrorMessage::new("invalid bits to prime"));
+        }
+        if self.stage != Stage::Header {
+            return mem::compress_failed(ErrorMessage::new("prime before compressing"));
+        }
+        self.primed.push(bits, value);
+        Ok(())
+    }
+
+    // Returns the primed bits, which `miniz_oxide` knows nothing of. Unless
+    // they are whole bytes, they are followed by an empty stored block, which
+    // pads them up to a byte boundary for the data to follow as is.
+    fn primed(&mut self) -> Vec<u8> {
+        let mut primed = std::mem::take(&mut self.primed);
+        if primed.bits > 0 {
+            primed.bytes.push(primed.carry);
+            if primed.bits > 5 {
+                primed.bytes.push(0);
+            }
+            primed.bytes.extend_from_slice(&[0, 0, 0xff, 0xff]);
+        }
+        primed.bytes
+    }
+
+    fn header(&self) -> Vec<u8> {
+        match self.wrapper {
+            Wrapper::Raw => Vec::new(),
+            Wrapper::Zlib => {
+                // Deflate, with the size of the window.
+                let cmf = (self.window_bits - 8) << 4 | 8;
+                // The level hint, picked as zlib does.
+                let mut flg = match self.level.level() {
+                    0 | 1 => 0,
+                    2..=5 => 1,
+                    6 => 2,
+                    _ => 3,
+                } << 6;
+                if self.dictionary.is_some() {
+                    flg |= 0x20;
+                }
+                flg += (31 - (u16::from(cmf) << 8 | u16::from(flg)) % 31) as u8 % 31;
+                let mut header = vec![cmf, flg];
+                if let Some(id) = self.dictionary {
+                    header.extend_from_slice(&id.to_be_bytes());
+                }
+                header
+            }
+            Wrapper::Gzip => GzBuilder::new().into_header(self.level),
+        }
+    }
+
+    fn trailer(&self) -> Vec<u8> {
+        match self.wrapper {
+            Wrapper::Raw => Vec::new(),
+            Wrapper::Zlib => self.check.adler.to_be_bytes().to_vec(),
+            Wrapper::Gzip => {
+                let mut trailer = self.check.crc.sum().to_le_bytes().to_vec();
+                trailer.extend_from_slice(&self.check.crc.amount().to_le_bytes());
+                trailer
+            }
+        }
+    }
+
+    // Moves as much of the pending header or trailer as fits to `output`.
+    fn drain(&mut self, output: &mut [u8]) -> usize {
+        let n = self.pending.len().min(output.len());
+        output[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        n
     }
 }
 
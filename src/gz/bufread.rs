@@ -3,10 +3,12 @@ use std::io;
 use std::io::prelude::*;
 use std::mem;
 
-use super::{corrupt, read_into, GzBuilder, GzHeader, GzHeaderParser};
+use super::{
+    check_trailer, is_member_start, read_into, GzBuilder, GzHeader, GzHeaderParser, GzMember,
+};
 use crate::crc::CrcReader;
 use crate::deflate;
-use crate::Compression;
+use crate::{Compress, Compression, DecoderOptions, Decompress, EncoderOptions, Error, Limits};
 
 fn copy(into: &mut [u8], from: &[u8], pos: &mut usize) -> usize {
     let min = cmp::min(into.len(), from.len() - *pos);
@@ -55,10 +57,10 @@ pub struct GzEncoder<R> {
     eof: bool,
 }
 
-pub fn gz_encoder<R: BufRead>(header: Vec<u8>, r: R, lvl: Compression) -> GzEncoder<R> {
+pub fn gz_encoder<R: BufRead>(header: Vec<u8>, r: R, data: Compress) -> GzEncoder<R> {
     let crc = CrcReader::new(r);
     GzEncoder {
-        inner: deflate::bufread::DeflateEncoder::new(crc, lvl),
+        inner: deflate::bufread::DeflateEncoder::new_with_compress(crc, data),
         header,
         pos: 0,
         eof: false,
@@ -77,6 +79,22 @@ impl<R: BufRead> GzEncoder<R> {
         GzBuilder::new().buf_read(r, level)
     }
 
+    /// Creates a new encoder like `new`, which also applies the given
+    /// `options`.
+    pub fn new_with_options(r: R, level: Compression, options: EncoderOptions) -> GzEncoder<R> {
+        GzBuilder::new().options(options).buf_read(r, level)
+    }
+
+    /// Creates a new encoder with the given `compression` settings, the data
+    /// read from the stream `r` being compressed and available through the
+    /// returned reader.
+    ///
+    /// The header emitted doesn't tell how hard the data was compressed.
+    pub fn new_with_compress(r: R, compression: Compress) -> GzEncoder<R> {
+        let header = GzBuilder::new().into_header(Compression::default());
+        gz_encoder(header, r, compression)
+    }
+
     fn read_footer(&mut self, into: &mut [u8]) -> io::Result<usize> {
         if self.pos == 8 {
             return Ok(0);
@@ -97,6 +115,25 @@ impl<R: BufRead> GzEncoder<R> {
 }
 
 impl<R> GzEncoder<R> {
+    /// Resets the state of this encoder entirely, swapping out the input
+    /// stream for another.
+    ///
+    /// This function will reset the internal state of this encoder and replace
+    /// the input stream with the one provided, returning the previous input
+    /// stream. Future data read from this encoder will be a new gzip stream,
+    /// with the same header, holding the compressed version of `r`'s data.
+    pub fn reset(&mut self, r: R) -> R {
+        self.reset_state();
+        mem::replace(self.get_mut(), r)
+    }
+
+    pub(crate) fn reset_state(&mut self) {
+        deflate::bufread::reset_encoder_data(&mut self.inner);
+        self.inner.get_mut().reset();
+        self.pos = 0;
+        self.eof = false;
+    }
+
     /// Acquires a reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         self.inner.get_ref().get_ref()
@@ -116,19 +153,6 @@ impl<R> GzEncoder<R> {
     }
 }
 
-#[inline]
-fn finish(buf: &[u8; 8]) -> (u32, u32) {
-    let crc = ((buf[0] as u32) << 0)
-        | ((buf[1] as u32) << 8)
-        | ((buf[2] as u32) << 16)
-        | ((buf[3] as u32) << 24);
-    let amt = ((buf[4] as u32) << 0)
-        | ((buf[5] as u32) << 8)
-        | ((buf[6] as u32) << 16)
-        | ((buf[7] as u32) << 24);
-    (crc, amt)
-}
-
 impl<R: BufRead> Read for GzEncoder<R> {
     fn read(&mut self, mut into: &mut [u8]) -> io::Result<usize> {
         let mut amt = 0;
@@ -212,6 +236,19 @@ pub struct GzDecoder<R> {
     state: GzState,
     reader: CrcReader<deflate::bufread::DeflateDecoder<R>>,
     multi: bool,
+    options: DecoderOptions,
+    // The offsets of the current member and of its compressed data.
+    member_start: u64,
+    data_start: u64,
+    // The number of zeros skipped after the current member.
+    skipped: u64,
+    // The offset of the current member in the uncompressed data, and the
+    // members completed so far, only kept track of when decoding them all
+    // and asked to record them.
+    out_start: u64,
+    members: Vec<GzMember>,
+    record: bool,
+    recorded: bool,
 }
 
 #[derive(Debug)]
@@ -226,10 +263,39 @@ enum GzState {
 impl<R: BufRead> GzDecoder<R> {
     /// Creates a new decoder from the given reader, immediately parsing the
     /// gzip header.
-    pub fn new(mut r: R) -> GzDecoder<R> {
-        let mut header_parser = GzHeaderParser::new();
+    pub fn new(r: R) -> GzDecoder<R> {
+        GzDecoder::new_with_limits(r, Limits::new())
+    }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// gzip header, and failing once any of the given `limits` is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> GzDecoder<R> {
+        GzDecoder::new_with_options(r, DecoderOptions::new().limits(limits))
+    }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// gzip header, and configured by `options`.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> GzDecoder<R> {
+        GzDecoder::new_with_decompress_and_options(r, options.decompress(false), options)
+    }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// gzip header, and inflating the data following it with the given
+    /// `decompression` settings, which must expect raw deflate data.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> GzDecoder<R> {
+        GzDecoder::new_with_decompress_and_options(r, decompression, DecoderOptions::new())
+    }
+
+    pub(crate) fn new_with_decompress_and_options(
+        mut r: R,
+        data: Decompress,
+        options: DecoderOptions,
+    ) -> GzDecoder<R> {
+        let mut header_parser = GzHeaderParser::new_with_options(&options);
 
-        let state = match header_parser.parse(&mut r) {
+        let ret = header_parser.parse(&mut r);
+        let data_start = header_parser.len();
+        let state = match ret {
             Ok(_) => GzState::Body(GzHeader::from(header_parser)),
             Err(ref err) if io::ErrorKind::WouldBlock == err.kind() => {
                 GzState::Header(header_parser)
@@ -237,10 +303,19 @@ impl<R: BufRead> GzDecoder<R> {
             Err(err) => GzState::Err(err),
         };
 
+        let r = deflate::bufread::DeflateDecoder::new_with_decompress_and_options(r, data, options);
         GzDecoder {
             state,
-            reader: CrcReader::new(deflate::bufread::DeflateDecoder::new(r)),
+            reader: CrcReader::new(r),
             multi: false,
+            options,
+            member_start: 0,
+            data_start,
+            skipped: 0,
+            out_start: 0,
+            members: Vec::new(),
+            record: false,
+            recorded: false,
         }
     }
 
@@ -251,6 +326,30 @@ impl<R: BufRead> GzDecoder<R> {
 }
 
 impl<R> GzDecoder<R> {
+    /// Resets the state of this decoder entirely, swapping out the input
+    /// stream for another.
+    ///
+    /// This will reset the internal state of this decoder and replace the
+    /// input stream with the one provided, returning the previous input
+    /// stream. The header of the new stream is parsed on the next read, and
+    /// the limits start over.
+    pub fn reset(&mut self, r: R) -> R {
+        self.reset_state();
+        mem::replace(self.get_mut(), r)
+    }
+
+    pub(crate) fn reset_state(&mut self) {
+        self.reader.reset();
+        deflate::bufread::reset_decoder_data(self.reader.get_mut());
+        self.state = GzState::Header(GzHeaderParser::new_with_options(&self.options));
+        self.member_start = 0;
+        self.data_start = 0;
+        self.skipped = 0;
+        self.out_start = 0;
+        self.members.clear();
+        self.recorded = false;
+    }
+
     /// Returns the header associated with this stream, if it was valid
     pub fn header(&self) -> Option<&GzHeader> {
         match &self.state {
@@ -284,14 +383,20 @@ impl<R: BufRead> Read for GzDecoder<R> {
         loop {
             match &mut self.state {
                 GzState::Header(parser) => {
-                    parser.parse(self.reader.get_mut().get_mut())?;
+                    let member_start = self.member_start;
+                    parser
+                        .parse(self.reader.get_mut().get_mut())
+                        .map_err(|err| Error::rebase(err, member_start))?;
+                    self.data_start = self.member_start + parser.len();
                     self.state = GzState::Body(GzHeader::from(mem::take(parser)));
                 }
                 GzState::Body(header) => {
                     if into.is_empty() {
                         return Ok(0);
                     }
-                    match self.reader.read(into)? {
+                    let data_start = self.data_start;
+                    let read = self.reader.read(into);
+                    match read.map_err(|err| Error::rebase(err, data_start))? {
                         0 => {
                             self.state = GzState::Finished(mem::take(header), 0, [0; 8]);
                         }
@@ -301,32 +406,72 @@ impl<R: BufRead> Read for GzDecoder<R> {
                     }
                 }
                 GzState::Finished(header, pos, buf) => {
+                    let trailer_start = self.data_start + self.reader.get_ref().total_in();
                     if *pos < buf.len() {
-                        *pos += read_into(self.reader.get_mut().get_mut(), &mut buf[*pos..])?;
-                    } else {
-                        let (crc, amt) = finish(&buf);
+                        *pos += match read_into(self.reader.get_mut().get_mut(), &mut buf[*pos..]) {
+                            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                                let offset = trailer_start + *pos as u64;
+                                return Err(Error::Truncated { offset }.into());
+                            }
+                            read => read?,
+                        };
+                    } else if let Err(err) = match self.options.verify_checksums {
+                        true => check_trailer(buf, self.reader.crc(), trailer_start),
+                        false => Ok(()),
+                    } {
+                        self.state = GzState::End(Some(mem::take(header)));
+                        return Err(err);
+                    } else if self.multi {
+                        if self.record && !self.recorded {
+                            let out_len = self.reader.get_ref().total_out();
+                            self.members.push(GzMember {
+                                header: header.clone(),
+                                compressed: self.member_start..trailer_start + 8,
+                                uncompressed: self.out_start..self.out_start + out_len,
+                                crc: self.reader.crc().sum(),
+                            });
+                            self.recorded = true;
+                        }
 
-                        if crc != self.reader.crc().sum() || amt != self.reader.crc().amount() {
-                            self.state = GzState::End(Some(mem::take(header)));
-                            return Err(corrupt());
-                        } else if self.multi {
-                            let is_eof = self
-                                .reader
-                                .get_mut()
-                                .get_mut()
-                                .fill_buf()
-                                .map(|buf| buf.is_empty())?;
-
-                            if is_eof {
-                                self.state = GzState::End(Some(mem::take(header)));
-                            } else {
-                                self.reader.reset();
-                                self.reader.get_mut().reset_data();
-                                self.state = GzState::Header(GzHeaderParser::new())
+                        // Find out whether another member follows, skipping
+                        // zeros and stopping at garbage if asked to.
+                        let reader = self.reader.get_mut().get_mut();
+                        let next = loop {
+                            let buf = reader.fill_buf()?;
+                            let zeros = match self.options.ignore_trailing_zeros {
+                                true => buf.iter().take_while(|&&b| b == 0).count(),
+                                false => 0,
+                            };
+                            if zeros == 0 {
+                                break (!buf.is_empty()).then(|| is_member_start(buf));
                             }
-                        } else {
+                            reader.consume(zeros);
+                            self.skipped += zeros as u64;
+                        };
+                        let more = match next {
+                            Some(is_member) => is_member || !self.options.ignore_trailing_garbage,
+                            None => false,
+                        };
+                        let member_start = trailer_start + 8 + self.skipped;
+
+                        if !more {
+                            self.state = GzState::End(Some(mem::take(header)));
+                        } else if let Err(limit) = self.reader.get_mut().limiter().next_member() {
                             self.state = GzState::End(Some(mem::take(header)));
+                            let offset = member_start;
+                            return Err(Error::LimitExceeded { limit, offset }.into());
+                        } else {
+                            self.out_start += self.reader.get_ref().total_out();
+                            self.reader.reset();
+                            self.reader.get_mut().reset_data();
+                            self.member_start = member_start;
+                            self.skipped = 0;
+                            self.recorded = false;
+                            self.state =
+                                GzState::Header(GzHeaderParser::new_with_options(&self.options))
                         }
+                    } else {
+                        self.state = GzState::End(Some(mem::take(header)));
                     }
                 }
                 GzState::Err(err) => {
@@ -402,14 +547,67 @@ impl<R: BufRead> MultiGzDecoder<R> {
     pub fn new(r: R) -> MultiGzDecoder<R> {
         MultiGzDecoder(GzDecoder::new(r).multi(true))
     }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// first gzip header, and failing once any of the given `limits` is
+    /// exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> MultiGzDecoder<R> {
+        MultiGzDecoder(GzDecoder::new_with_limits(r, limits).multi(true))
+    }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// first gzip header, and configured by `options`.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> MultiGzDecoder<R> {
+        MultiGzDecoder(GzDecoder::new_with_options(r, options).multi(true))
+    }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// first gzip header, and inflating the members with the given
+    /// `decompression` settings, which must expect raw deflate data.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> MultiGzDecoder<R> {
+        MultiGzDecoder(GzDecoder::new_with_decompress(r, decompression).multi(true))
+    }
 }
 
 impl<R> MultiGzDecoder<R> {
+    /// Resets the state of this decoder entirely, swapping out the input
+    /// stream for another.
+    ///
+    /// This will reset the internal state of this decoder, forgetting about
+    /// the members not taken yet, and replace the input stream with the one
+    /// provided, returning the previous input stream.
+    pub fn reset(&mut self, r: R) -> R {
+        self.0.reset(r)
+    }
+
+    pub(crate) fn reset_state(&mut self) {
+        self.0.reset_state()
+    }
+
     /// Returns the current header associated with this stream, if it's valid
     pub fn header(&self) -> Option<&GzHeader> {
         self.0.header()
     }
 
+    /// Sets whether the members are recorded as they complete, to be
+    /// returned by [`take_members`](Self::take_members). They aren't by
+    /// default, as a long stream can have a great many of them.
+    pub fn record_members(mut self, record: bool) -> MultiGzDecoder<R> {
+        self.0.record = record;
+        self
+    }
+
+    /// Returns the members that were decoded to the end since the last call,
+    /// with their headers and where they lie in the input and in the output.
+    ///
+    /// The members are kept track of until this is called, which can be done
+    /// after every read to learn about them as they complete, and only if
+    /// [`record_members`](Self::record_members) was set, so that this returns
+    /// nothing otherwise.
+    pub fn take_members(&mut self) -> Vec<GzMember> {
+        mem::take(&mut self.0.members)
+    }
+
     /// Acquires a reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         self.0.get_ref()
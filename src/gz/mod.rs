@@ -1,9 +1,12 @@
 use std::ffi::CString;
 use std::io::{BufRead, Error, ErrorKind, Read, Result, Write};
+use std::ops::Range;
 use std::time;
 
 use crate::bufreader::BufReader;
-use crate::{Compression, Crc};
+use crate::{Compression, Crc, DecoderOptions, EncoderOptions};
+
+type FlateError = crate::Error;
 
 pub static FHCRC: u8 = 1 << 1;
 pub static FEXTRA: u8 = 1 << 2;
@@ -17,7 +20,7 @@ pub mod write;
 
 // The maximum length of the header filename and comment fields. More than
 // enough for these fields in reasonable use, but prevents possible attacks.
-const MAX_HEADER_BUF: usize = 65535;
+pub(crate) const MAX_HEADER_BUF: usize = 65535;
 
 /// A structure representing the header of a gzip stream.
 ///
@@ -87,6 +90,45 @@ impl GzHeader {
     }
 }
 
+/// A member of a multi-member gzip stream, as reported by `MultiGzDecoder`
+/// once it is decoded and its trailer checked.
+#[derive(PartialEq, Clone, Debug)]
+pub struct GzMember {
+    header: GzHeader,
+    compressed: Range<u64>,
+    uncompressed: Range<u64>,
+    crc: u32,
+}
+
+impl GzMember {
+    /// Returns the header of the member.
+    pub fn header(&self) -> &GzHeader {
+        &self.header
+    }
+
+    /// Returns the range of the member in the compressed stream, from the
+    /// start of its header to the end of its trailer.
+    pub fn compressed(&self) -> Range<u64> {
+        self.compressed.clone()
+    }
+
+    /// Returns the range of the data decoded from the member in the
+    /// uncompressed output of the whole stream.
+    pub fn uncompressed(&self) -> Range<u64> {
+        self.uncompressed.clone()
+    }
+
+    /// Returns the length of the data decoded from the member.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.uncompressed.end - self.uncompressed.start
+    }
+
+    /// Returns the CRC-32 of the data decoded from the member.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+}
+
 #[derive(Debug)]
 pub enum GzHeaderState {
     Start(u8, [u8; 10]),
@@ -109,18 +151,45 @@ pub struct GzHeaderParser {
     state: GzHeaderState,
     flags: u8,
     header: GzHeader,
+    len: u64,
+    max_field_len: usize,
+    verify_crc: bool,
 }
 
 impl GzHeaderParser {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
+        GzHeaderParser::new_with_options(&DecoderOptions::new())
+    }
+
+    pub(crate) fn new_with_options(options: &DecoderOptions) -> Self {
         GzHeaderParser {
             state: GzHeaderState::Start(0, [0; 10]),
             flags: 0,
             header: GzHeader::default(),
+            len: 0,
+            max_field_len: options.max_header_field_len,
+            verify_crc: options.verify_checksums,
+        }
+    }
+
+    /// The number of bytes of the header parsed so far.
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn parse<R: Read>(&mut self, r: &mut R) -> Result<()> {
+        let mut r = Counted { inner: r, count: 0 };
+        let ret = self.parse_counted(&mut r);
+        self.len += r.count;
+        match ret {
+            Err(ref err) if err.kind() == ErrorKind::UnexpectedEof && err.get_ref().is_none() => {
+                Err(FlateError::Truncated { offset: self.len }.into())
+            }
+            ret => ret,
         }
     }
 
-    fn parse<'a, R: Read>(&mut self, r: &'a mut R) -> Result<()> {
+    fn parse_counted<R: Read>(&mut self, r: &mut Counted<'_, R>) -> Result<()> {
         loop {
             match &mut self.state {
                 GzHeaderState::Start(count, buffer) => {
@@ -129,16 +198,18 @@ impl GzHeaderParser {
                     }
                     // Gzip identification bytes
                     if buffer[0] != 0x1f || buffer[1] != 0x8b {
-                        return Err(bad_header());
+                        return Err(FlateError::BadMagic { offset: 0 }.into());
                     }
                     // Gzip compression method (8 = deflate)
                     if buffer[2] != 8 {
-                        return Err(bad_header());
+                        let method = buffer[2];
+                        return Err(FlateError::UnsupportedMethod { method, offset: 2 }.into());
                     }
                     self.flags = buffer[3];
                     // RFC1952: "must give an error indication if any reserved bit is non-zero"
                     if self.flags & FRESERVED != 0 {
-                        return Err(bad_header());
+                        let flags = self.flags;
+                        return Err(FlateError::ReservedFlags { flags, offset: 3 }.into());
                     }
                     self.header.mtime = ((buffer[4] as u32) << 0)
                         | ((buffer[5] as u32) << 8)
@@ -184,7 +255,7 @@ impl GzHeaderParser {
                 GzHeaderState::Filename(crc) => {
                     if self.flags & FNAME != 0 {
                         let filename = self.header.filename.get_or_insert_with(Vec::new);
-                        read_to_nul(r, filename)?;
+                        read_to_nul(r, filename, self.max_field_len, self.len)?;
                         if let Some(crc) = crc {
                             crc.update(filename);
                             crc.update(b"\0");
@@ -195,7 +266,7 @@ impl GzHeaderParser {
                 GzHeaderState::Comment(crc) => {
                     if self.flags & FCOMMENT != 0 {
                         let comment = self.header.comment.get_or_insert_with(Vec::new);
-                        read_to_nul(r, comment)?;
+                        read_to_nul(r, comment, self.max_field_len, self.len)?;
                         if let Some(crc) = crc {
                             crc.update(comment);
                             crc.update(b"\0");
@@ -211,8 +282,9 @@ impl GzHeaderParser {
                         }
                         let stored_crc = parse_le_u16(&buffer);
                         let calced_crc = crc.sum() as u16;
-                        if stored_crc != calced_crc {
-                            return Err(corrupt());
+                        if self.verify_crc && stored_crc != calced_crc {
+                            let offset = self.len + r.count - 2;
+                            return Err(FlateError::HeaderCrcMismatch { offset }.into());
                         }
                     }
                     self.state = GzHeaderState::Complete;
@@ -224,7 +296,7 @@ impl GzHeaderParser {
         }
     }
 
-    fn header(&self) -> Option<&GzHeader> {
+    pub(crate) fn header(&self) -> Option<&GzHeader> {
         match self.state {
             GzHeaderState::Complete => Some(&self.header),
             _ => None,
@@ -252,26 +324,41 @@ fn read_into<R: Read>(r: &mut R, buffer: &mut [u8]) -> Result<usize> {
     }
 }
 
-// Read `r` up to the first nul byte, pushing non-nul bytes to `buffer`.
-fn read_to_nul<R: Read>(r: &mut R, buffer: &mut Vec<u8>) -> Result<()> {
-    let mut bytes = r.bytes();
+// Counts the bytes read from `inner`.
+struct Counted<'a, R> {
+    inner: &'a mut R,
+    count: u64,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+// Read `r` up to the first nul byte, pushing non-nul bytes to `buffer`, which
+// may hold up to `max` bytes. The header parsed before this call is `base`
+// bytes long.
+fn read_to_nul<R: Read>(
+    r: &mut Counted<'_, R>,
+    buffer: &mut Vec<u8>,
+    max: usize,
+    base: u64,
+) -> Result<()> {
+    let mut byte = [0];
     loop {
-        match bytes.next().transpose()? {
-            Some(byte) if byte == 0 => {
-                return Ok(());
-            }
-            Some(_) if buffer.len() == MAX_HEADER_BUF => {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "gzip header field too long",
-                ));
-            }
-            Some(byte) => {
-                buffer.push(byte);
-            }
-            None => {
-                return Err(ErrorKind::UnexpectedEof.into());
+        match r.read(&mut byte) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) if byte[0] == 0 => return Ok(()),
+            Ok(_) if buffer.len() >= max => {
+                let offset = base + r.count - 1;
+                return Err(FlateError::HeaderFieldTooLong { offset }.into());
             }
+            Ok(_) => buffer.push(byte[0]),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
         }
     }
 }
@@ -280,15 +367,54 @@ fn parse_le_u16(buffer: &[u8; 2]) -> u16 {
     (buffer[0] as u16) | ((buffer[1] as u16) << 8)
 }
 
-fn bad_header() -> Error {
+pub(crate) fn bad_header() -> Error {
     Error::new(ErrorKind::InvalidInput, "invalid gzip header")
 }
 
-fn corrupt() -> Error {
-    Error::new(
-        ErrorKind::InvalidInput,
-        "corrupt gzip stream does not have a matching checksum",
-    )
+// Checks the 8-byte gzip `trailer` found at `offset` against the checksum of
+// the decompressed data.
+pub(crate) fn check_trailer(trailer: &[u8], crc: &Crc, offset: u64) -> Result<()> {
+    if trailer[..4] != crc.sum().to_le_bytes() {
+        return Err(FlateError::DataCrcMismatch { offset }.into());
+    }
+    if trailer[4..8] != crc.amount().to_le_bytes() {
+        let offset = offset + 4;
+        return Err(FlateError::IsizeMismatch { offset }.into());
+    }
+    Ok(())
+}
+
+// Returns whether `buf`, which isn't empty, may be the start of a gzip member.
+pub(crate) fn is_member_start(buf: &[u8]) -> bool {
+    let n = buf.len().min(2);
+    buf[..n] == [0x1f, 0x8b][..n]
+}
+
+// Completes `byte`, the last byte of the data of a member whose final block
+// was made non-final, of which the top `unused` bits aren't used. Empty blocks
+// either end the joined stream, or pad it up to a byte boundary so that the
+// data of the next member, stored blocks included, can follow as is.
+fn splice(byte: u8, unused: u8, last: bool) -> Vec<u8> {
+    let mut w = crate::index::BitWriter::default();
+    let used = u32::from(8 - unused);
+    w.put(u32::from(byte) & ((1 << used) - 1), used);
+    if last {
+        // An empty final block using the fixed Huffman codes.
+        w.put(3, 3);
+        w.put(0, 7);
+    } else if unused % 2 == 1 {
+        // An empty stored block, which ends on a byte boundary.
+        w.put(0, 3);
+        let mut out = w.finish();
+        out.extend_from_slice(&[0, 0, 0xff, 0xff]);
+        return out;
+    } else {
+        // Empty blocks using the fixed Huffman codes take 10 bits each.
+        for _ in 0..unused / 2 {
+            w.fixed_block(&[]);
+        }
+    }
+    w.finish()
 }
 
 /// A builder structure to create a new gzip Encoder.
@@ -324,6 +450,7 @@ pub struct GzBuilder {
     comment: Option<CString>,
     operating_system: Option<u8>,
     mtime: u32,
+    options: EncoderOptions,
 }
 
 impl Default for GzBuilder {
@@ -341,6 +468,7 @@ impl GzBuilder {
             comment: None,
             operating_system: None,
             mtime: 0,
+            options: EncoderOptions::new(),
         }
     }
 
@@ -382,12 +510,24 @@ impl GzBuilder {
         self
     }
 
+    /// Configure the [`EncoderOptions`] of the encoders this builder creates.
+    ///
+    /// These don't apply to the encoders of a [`ParBuilder`], which split
+    /// their data into blocks of their own.
+    ///
+    /// [`ParBuilder`]: crate::ParBuilder
+    pub fn options(mut self, options: EncoderOptions) -> GzBuilder {
+        self.options = options;
+        self
+    }
+
     /// Consume this builder, creating a writer encoder in the process.
     ///
     /// The data written to the returned encoder will be compressed and then
     /// written out to the supplied parameter `w`.
     pub fn write<W: Write>(self, w: W, lvl: Compression) -> write::GzEncoder<W> {
-        write::gz_encoder(self.into_header(lvl), w, lvl)
+        let data = self.options.compress(lvl, false);
+        write::gz_encoder(self.into_header(lvl), w, lvl, data)
     }
 
     /// Consume this builder, creating a reader encoder in the process.
@@ -406,16 +546,229 @@ impl GzBuilder {
     where
         R: BufRead,
     {
-        bufread::gz_encoder(self.into_header(lvl), r, lvl)
+        let data = self.options.compress(lvl, false);
+        bufread::gz_encoder(self.into_header(lvl), r, data)
+    }
+
+    /// Consume this builder, creating an asynchronous writer encoder in the
+    /// process.
+    ///
+    /// The data written to the returned encoder will be compressed and then
+    /// written out to the supplied parameter `w`.
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    pub fn async_write<W>(self, w: W, lvl: Compression) -> crate::aio::write::GzEncoder<W> {
+        let data = self.options.compress(lvl, false);
+        crate::aio::write::gz_encoder(self.into_header(lvl), w, data)
     }
 
-    fn into_header(self, lvl: Compression) -> Vec<u8> {
+    /// Consume this builder, creating an asynchronous reader encoder in the
+    /// process.
+    ///
+    /// Data read from the returned encoder will be the compressed version of
+    /// the data read from the given reader.
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    pub fn async_read<R>(self, r: R, lvl: Compression) -> crate::aio::read::GzEncoder<R> {
+        crate::aio::read::gz_encoder(self.async_buf_read(crate::aio::BufReader::new(r), lvl))
+    }
+
+    /// Consume this builder, creating an asynchronous reader encoder in the
+    /// process.
+    ///
+    /// Data read from the returned encoder will be the compressed version of
+    /// the data read from the given buffered reader.
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    pub fn async_buf_read<R>(self, r: R, lvl: Compression) -> crate::aio::bufread::GzEncoder<R> {
+        let data = self.options.compress(lvl, false);
+        crate::aio::bufread::gz_encoder(self.into_header(lvl), r, data)
+    }
+
+    /// Joins the gzip files read from `inputs` into a single gzip member with
+    /// this header, written to `w`, which is returned once done.
+    ///
+    /// Some consumers only read the first member of a gzip file, which makes
+    /// simply concatenating gzip files not always an option. Rather than
+    /// decompressing and compressing everything again, this copies the
+    /// compressed data of every member of the inputs as is, as zlib's
+    /// `gzjoin` example does. The members are still decompressed to find the
+    /// end of their final block and to check them, but their data is only used
+    /// to compute their checksum, which [`Crc::combine`] then merges into the
+    /// one of the joined member. Joining no input at all writes an empty
+    /// member. The members are decompressed with the backend set in the
+    /// [`EncoderOptions`] of this builder, if any.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error is returned, as well as errors for invalid gzip data,
+    /// including checksum mismatches and data following the last member of an
+    /// input. `w` may have been written to by then.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::prelude::*;
+    /// use flate2::read::GzDecoder;
+    /// use flate2::write::GzEncoder;
+    /// use flate2::{Compression, GzBuilder};
+    ///
+    /// # fn main() -> std::io::Result<()> {
+    /// let mut files = Vec::new();
+    /// for part in ["Hello", " ", "World"] {
+    ///     let mut e = GzEncoder::new(Vec::new(), Compression::default());
+    ///     e.write_all(part.as_bytes())?;
+    ///     files.push(e.finish()?);
+    /// }
+    ///
+    /// let joined = GzBuilder::new()
+    ///     .filename("hello.txt")
+    ///     .join(files.iter().map(|file| &file[..]), Vec::new())?;
+    ///
+    /// let mut s = String::new();
+    /// GzDecoder::new(&joined[..]).read_to_string(&mut s)?;
+    /// assert_eq!(s, "Hello World");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn join<I, W>(self, inputs: I, mut w: W) -> Result<W>
+    where
+        I: IntoIterator,
+        I::Item: Read,
+        W: Write,
+    {
+        use crate::index::Input;
+        use crate::{Decompress, Status};
+
+        let backend = self.options.backend.unwrap_or_default();
+        w.write_all(&self.into_header(Compression::default()))?;
+        let mut data = Decompress::new_with_backend(false, backend);
+        let mut out = vec![0; 32 * 1024];
+        let mut crc = Crc::new();
+        // The last byte of the data copied so far along with the number of its
+        // bits which are unused, held back until what follows it is known.
+        let mut tail = None;
+
+        for r in inputs {
+            let mut input = Input::new(r);
+            loop {
+                let start = input.offset;
+                GzHeaderParser::new()
+                    .parse(&mut input)
+                    .map_err(|err| FlateError::rebase(err, start))?;
+                if let Some((byte, unused)) = tail.take() {
+                    w.write_all(&splice(byte, unused, false))?;
+                }
+                data.reset(false);
+                let mut member_crc = Crc::new();
+                // The data consumed but not copied yet, always keeping the
+                // last byte consumed as it may hold the start of a block.
+                let mut held = Vec::new();
+                // Whether the next byte consumed starts a block.
+                let mut block_start = true;
+                let mut unused = 0;
+
+                loop {
+                    let buf = input.fill_buf()?;
+                    let eof = buf.is_empty();
+                    let before_in = data.total_in();
+                    let before_out = data.total_out();
+                    let ret = data.decompress_block(buf, &mut out);
+                    let consumed = (data.total_in() - before_in) as usize;
+                    let produced = (data.total_out() - before_out) as usize;
+                    held.extend_from_slice(&buf[..consumed]);
+                    input.consume(consumed);
+                    let (status, boundary) =
+                        ret.map_err(|err| FlateError::decompress(err, input.offset))?;
+                    member_crc.update(&out[..produced]);
+
+                    // Every block is made non-final by clearing its first bit,
+                    // which only the final block has set.
+                    if block_start && consumed > 0 {
+                        let first = held.len() - consumed;
+                        held[first] &= !1;
+                        block_start = false;
+                    }
+                    match boundary {
+                        Some((0, false)) => block_start = true,
+                        Some((bits, false)) => *held.last_mut().unwrap() &= !(1 << (8 - bits)),
+                        Some((bits, true)) => unused = bits,
+                        None => {}
+                    }
+                    if status == Status::StreamEnd {
+                        break;
+                    }
+                    if held.len() > 1 {
+                        w.write_all(&held[..held.len() - 1])?;
+                        held.drain(..held.len() - 1);
+                    }
+                    if eof && consumed == 0 && produced == 0 {
+                        let offset = input.offset;
+                        return Err(FlateError::Truncated { offset }.into());
+                    }
+                }
+
+                let trailer_start = input.offset;
+                let mut trailer = [0; 8];
+                input.read_exact(&mut trailer)?;
+                check_trailer(&trailer, &member_crc, trailer_start)?;
+                crc.combine(&member_crc);
+                let byte = held.pop().unwrap();
+                w.write_all(&held)?;
+                tail = Some((byte, unused));
+
+                match input.fill_buf()? {
+                    [] => break,
+                    buf if !is_member_start(buf) => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "data after the last gzip member",
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        match tail {
+            Some((byte, unused)) => w.write_all(&splice(byte, unused, true))?,
+            None => w.write_all(&splice(0, 8, true))?,
+        }
+        w.write_all(&crc.sum().to_le_bytes())?;
+        w.write_all(&crc.amount().to_le_bytes())?;
+        Ok(w)
+    }
+
+    /// A builder writing the same header as `header`.
+    pub(crate) fn from_header(header: &GzHeader) -> GzBuilder {
+        GzBuilder {
+            extra: header.extra.clone(),
+            filename: header.filename.clone().and_then(|v| CString::new(v).ok()),
+            comment: header.comment.clone().and_then(|v| CString::new(v).ok()),
+            operating_system: Some(header.operating_system),
+            mtime: header.mtime,
+            options: EncoderOptions::new(),
+        }
+    }
+
+    /// The length of the `extra` field configured so far.
+    pub(crate) fn extra_len(&self) -> usize {
+        self.extra.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Inserts `subfield` at the start of the `extra` field.
+    pub(crate) fn prepend_extra(mut self, subfield: &[u8]) -> GzBuilder {
+        let mut extra = subfield.to_vec();
+        extra.extend(self.extra.take().unwrap_or_default());
+        self.extra = Some(extra);
+        self
+    }
+
+    pub(crate) fn into_header(self, lvl: Compression) -> Vec<u8> {
         let GzBuilder {
             extra,
             filename,
             comment,
             operating_system,
             mtime,
+            options: _,
         } = self;
         let mut flg = 0;
         let mut header = vec![0u8; 10];
@@ -441,9 +794,9 @@ impl GzBuilder {
         header[5] = (mtime >> 8) as u8;
         header[6] = (mtime >> 16) as u8;
         header[7] = (mtime >> 24) as u8;
-        header[8] = if lvl.0 >= Compression::best().0 {
+        header[8] = if lvl.level() >= Compression::best().level() {
             2
-        } else if lvl.0 <= Compression::fast().0 {
+        } else if lvl.level() <= Compression::fast().level() {
             4
         } else {
             0
@@ -462,8 +815,8 @@ impl GzBuilder {
 mod tests {
     use std::io::prelude::*;
 
-    use super::{read, write, GzBuilder, GzHeaderParser};
-    use crate::{Compression, GzHeader};
+    use super::{bufread, read, write, GzBuilder, GzHeaderParser};
+    use crate::{Compression, DecoderOptions, Error, GzHeader, GzMember, LimitKind, Limits};
     use rand::{thread_rng, Rng};
 
     #[test]
@@ -487,6 +840,23 @@ mod tests {
         assert_eq!(s, "");
     }
 
+    #[test]
+    fn roundtrip_exhaustive() {
+        let data = b"foo bar baz foo bar baz, baz bar foo".repeat(50);
+        let mut e = GzBuilder::new()
+            .filename("foo.txt")
+            .write(Vec::new(), Compression::exhaustive());
+        e.write_all(&data[..1000]).unwrap();
+        e.flush().unwrap();
+        e.write_all(&data[1000..]).unwrap();
+        let inner = e.finish().unwrap();
+        let mut d = read::GzDecoder::new(&inner[..]);
+        let mut v = Vec::new();
+        d.read_to_end(&mut v).unwrap();
+        assert_eq!(v, data);
+        assert_eq!(d.header().unwrap().filename(), Some(&b"foo.txt"[..]));
+    }
+
     #[test]
     fn roundtrip_big() {
         let mut real = Vec::new();
@@ -641,4 +1011,572 @@ mod tests {
         write!(f, "Hello world").unwrap();
         f.flush().unwrap();
     }
+
+    #[test]
+    fn member_limits() {
+        let mut data = Vec::new();
+        for _ in 0..3 {
+            let mut e = write::GzEncoder::new(&mut data, Compression::default());
+            e.write_all(&[1; 1000]).unwrap();
+            e.finish().unwrap();
+        }
+        let exceeded = |err: std::io::Error| match flate_error(err) {
+            Error::LimitExceeded { limit, .. } => limit,
+            err => panic!("unexpected error: {}", err),
+        };
+
+        let limits = Limits::new().max_members(3);
+        let mut r = bufread::MultiGzDecoder::new_with_limits(&data[..], limits);
+        assert_eq!(r.read_to_end(&mut Vec::new()).unwrap(), 3000);
+
+        let limits = Limits::new().max_members(2);
+        let mut r = read::MultiGzDecoder::new_with_limits(&data[..], limits);
+        let mut out = Vec::new();
+        let err = exceeded(r.read_to_end(&mut out).unwrap_err());
+        assert_eq!((err.kind(), err.limit()), (LimitKind::Members, 2));
+        assert_eq!(out.len(), 2000);
+
+        let limits = Limits::new().max_output(2500);
+        let mut r = read::MultiGzDecoder::new_with_limits(&data[..], limits);
+        let err = exceeded(r.read_to_end(&mut Vec::new()).unwrap_err());
+        assert_eq!(err.kind(), LimitKind::Output);
+
+        let limits = Limits::new().max_members(2).max_output(2500);
+        let mut w = write::MultiGzDecoder::new_with_limits(Vec::new(), limits);
+        let err = exceeded(w.write_all(&data).unwrap_err());
+        assert_eq!(err.kind(), LimitKind::Members);
+        assert_eq!(w.get_ref().len(), 2000);
+
+        let limits = Limits::new().max_output(2500);
+        let mut w = write::MultiGzDecoder::new_with_limits(Vec::new(), limits);
+        let err = exceeded(w.write_all(&data).unwrap_err());
+        assert_eq!(err.kind(), LimitKind::Output);
+        assert_eq!(w.get_ref().len(), 2500);
+    }
+
+    fn flate_error(err: std::io::Error) -> Error {
+        *err.into_inner().unwrap().downcast::<Error>().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = write::GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    #[test]
+    fn header_errors() {
+        let decode = |data: &[u8]| {
+            let mut r = read::GzDecoder::new(data);
+            flate_error(r.read_to_end(&mut Vec::new()).unwrap_err())
+        };
+        let mut data = gzip(b"hello");
+
+        data[2] = 7;
+        match decode(&data) {
+            Error::UnsupportedMethod { method: 7, offset } => assert_eq!(offset, 2),
+            err => panic!("unexpected error: {}", err),
+        }
+        data[2] = 8;
+        data[3] = 0xe0;
+        match decode(&data) {
+            Error::ReservedFlags { offset, .. } => assert_eq!(offset, 3),
+            err => panic!("unexpected error: {}", err),
+        }
+        match decode(&data[..6]) {
+            Error::Truncated { offset } => assert_eq!(offset, 6),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn trailer_errors() {
+        let mut data = gzip(b"hello");
+        let len = data.len() as u64;
+        data.extend(gzip(b"world"));
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let mut r = read::MultiGzDecoder::new(&data[..]);
+        let mut out = Vec::new();
+        match flate_error(r.read_to_end(&mut out).unwrap_err()) {
+            Error::IsizeMismatch { offset } => assert_eq!(offset, 2 * len - 4),
+            err => panic!("unexpected error: {}", err),
+        }
+        assert_eq!(out, b"helloworld");
+
+        data[last] ^= 1;
+        data[last - 7] ^= 1;
+        let mut w = write::MultiGzDecoder::new(Vec::new());
+        w.write_all(&data).unwrap();
+        match flate_error(w.finish().unwrap_err()) {
+            Error::DataCrcMismatch { offset } => assert_eq!(offset, 2 * len - 8),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let mut r = bufread::GzDecoder::new(&data[..len as usize - 3]);
+        match flate_error(r.read_to_end(&mut Vec::new()).unwrap_err()) {
+            Error::Truncated { offset } => assert_eq!(offset, len - 3),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn trailing_zeros_and_garbage() {
+        let mut data = gzip(b"hello");
+        let len = data.len() as u64;
+        data.extend(gzip(b"world"));
+        data.extend([0; 100]);
+
+        let mut r = read::MultiGzDecoder::new(&data[..]);
+        match flate_error(r.read_to_end(&mut Vec::new()).unwrap_err()) {
+            Error::BadMagic { offset } => assert_eq!(offset, 2 * len),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let options = DecoderOptions::new().ignore_trailing_zeros(true);
+        let mut r = read::MultiGzDecoder::new_with_options(&data[..], options);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"helloworld");
+        let mut w = write::MultiGzDecoder::new_with_options(Vec::new(), options);
+        w.write_all(&data).unwrap();
+        assert_eq!(w.finish().unwrap(), b"helloworld");
+
+        data.extend(b"this is garbage");
+        let mut r = read::MultiGzDecoder::new_with_options(&data[..], options);
+        match flate_error(r.read_to_end(&mut Vec::new()).unwrap_err()) {
+            Error::BadMagic { offset } => assert_eq!(offset, 2 * len + 100),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let options = DecoderOptions::new().ignore_trailing_garbage(true);
+        let mut r = bufread::MultiGzDecoder::new_with_options(&data[..], options);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"helloworld");
+        assert_eq!(r.into_inner(), &data[2 * len as usize..]);
+        let mut w = write::MultiGzDecoder::new_with_options(Vec::new(), options);
+        w.write_all(&data).unwrap();
+        assert_eq!(w.finish().unwrap(), b"helloworld");
+    }
+
+    #[test]
+    fn skip_checksums() {
+        let mut data = gzip(b"hello");
+        let len = data.len();
+        data[len - 8] ^= 1;
+        data[len - 1] ^= 1;
+
+        let options = DecoderOptions::new().verify_checksums(false);
+        let mut r = read::GzDecoder::new_with_options(&data[..], options);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello");
+        let mut w = write::GzDecoder::new_with_options(Vec::new(), options);
+        w.write_all(&data).unwrap();
+        assert_eq!(w.finish().unwrap(), b"hello");
+
+        let mut header = GzBuilder::new().into_header(Compression::fast());
+        header[3] |= super::FHCRC;
+        header.extend([0, 0]);
+        let mut parser = GzHeaderParser::new();
+        match flate_error(parser.parse(&mut &header[..]).unwrap_err()) {
+            Error::HeaderCrcMismatch { offset } => assert_eq!(offset, 10),
+            err => panic!("unexpected error: {}", err),
+        }
+        let mut parser = GzHeaderParser::new_with_options(&options);
+        parser.parse(&mut &header[..]).unwrap();
+    }
+
+    #[test]
+    fn max_header_field_len() {
+        let mut e = GzBuilder::new()
+            .filename("filename")
+            .write(Vec::new(), Compression::default());
+        e.write_all(b"hello").unwrap();
+        let data = e.finish().unwrap();
+
+        let options = DecoderOptions::new().max_header_field_len(4);
+        let mut r = read::GzDecoder::new_with_options(&data[..], options);
+        match flate_error(r.read_to_end(&mut Vec::new()).unwrap_err()) {
+            Error::HeaderFieldTooLong { offset } => assert_eq!(offset, 14),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let options = options.max_header_field_len(8);
+        let mut r = read::GzDecoder::new_with_options(&data[..], options);
+        r.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(r.header().unwrap().filename(), Some(&b"filename"[..]));
+    }
+
+    #[test]
+    fn report_truncation() {
+        let data = crate::random_bytes().take(100_000).collect::<Vec<_>>();
+        let compressed = gzip(&data);
+        let truncated = &compressed[..compressed.len() / 2];
+        let truncated_at = |err| match flate_error(err) {
+            Error::Truncated { offset } => assert_eq!(offset, truncated.len() as u64),
+            err => panic!("unexpected error: {}", err),
+        };
+
+        let options = DecoderOptions::new().report_truncation(true);
+        let mut r = read::MultiGzDecoder::new_with_options(truncated, options);
+        let mut out = Vec::new();
+        truncated_at(r.read_to_end(&mut out).unwrap_err());
+        assert_eq!(out, &data[..out.len()]);
+        assert!(out.len() >= truncated.len() - 100);
+
+        let mut w = write::GzDecoder::new_with_options(Vec::new(), options);
+        w.write_all(truncated).unwrap();
+        truncated_at(w.try_finish().unwrap_err());
+        assert_eq!(w.get_ref(), &out);
+    }
+
+    #[test]
+    fn members() {
+        let mut data = Vec::new();
+        let mut expected = Vec::new();
+        for (i, part) in [&b"hello "[..], b"", b"world"].iter().enumerate() {
+            let name = format!("part{}", i);
+            let mut e = GzBuilder::new()
+                .filename(name.as_str())
+                .write(Vec::new(), Compression::default());
+            e.write_all(part).unwrap();
+            let start = data.len() as u64;
+            data.extend(e.finish().unwrap());
+            expected.push((name, start..data.len() as u64, part.len() as u64));
+        }
+        // Zeros between members aren't part of either.
+        data.extend([0; 4]);
+        let end = data.len() as u64;
+        let mut e = write::GzEncoder::new(data, Compression::default());
+        e.write_all(b"!").unwrap();
+        let data = e.finish().unwrap();
+        expected.push((String::new(), end..data.len() as u64, 1));
+
+        let check = |members: Vec<GzMember>| {
+            assert_eq!(members.len(), expected.len());
+            let mut out_start = 0;
+            for (member, (name, compressed, len)) in members.iter().zip(&expected) {
+                let filename = member.header().filename().unwrap_or_default();
+                assert_eq!(filename, name.as_bytes());
+                assert_eq!(member.compressed(), *compressed);
+                assert_eq!(member.uncompressed(), out_start..out_start + len);
+                assert_eq!(member.uncompressed_len(), *len);
+                out_start += len;
+            }
+            assert_eq!(members[1].crc(), crate::Crc::new().sum());
+            let mut crc = crate::Crc::new();
+            crc.update(b"world");
+            assert_eq!(members[2].crc(), crc.sum());
+        };
+        let options = DecoderOptions::new().ignore_trailing_zeros(true);
+
+        let mut r = read::MultiGzDecoder::new_with_options(&data[..], options).record_members(true);
+        let mut out = String::new();
+        r.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello world!");
+        check(r.take_members());
+        assert!(r.take_members().is_empty());
+
+        // Nothing is recorded unless asked for.
+        let mut r = read::MultiGzDecoder::new_with_options(&data[..], options);
+        r.read_to_end(&mut Vec::new()).unwrap();
+        assert!(r.take_members().is_empty());
+        let mut w = write::MultiGzDecoder::new_with_options(Vec::new(), options);
+        w.write_all(&data).unwrap();
+        w.try_finish().unwrap();
+        assert!(w.take_members().is_empty());
+
+        // Members are reported as they complete.
+        let input = std::io::BufReader::with_capacity(5, &data[..]);
+        let mut r = bufread::MultiGzDecoder::new_with_options(input, options).record_members(true);
+        let mut members = Vec::new();
+        let mut buf = [0; 1];
+        let mut read = 0;
+        while r.read(&mut buf).unwrap() > 0 {
+            read += 1;
+            let completed = r.take_members();
+            assert!(completed.iter().all(|m| m.uncompressed().end <= read));
+            members.extend(completed);
+        }
+        members.extend(r.take_members());
+        check(members);
+
+        let mut w =
+            write::MultiGzDecoder::new_with_options(Vec::new(), options).record_members(true);
+        for chunk in data.chunks(3) {
+            w.write_all(chunk).unwrap();
+        }
+        w.try_finish().unwrap();
+        w.try_finish().unwrap();
+        check(w.take_members());
+        assert_eq!(w.finish().unwrap(), b"hello world!");
+    }
+
+    #[test]
+    fn finish_member() {
+        let mut e = GzBuilder::new()
+            .filename("first")
+            .write(Vec::new(), Compression::fast());
+        e.write_all(b"hello ").unwrap();
+        e.finish_member(GzBuilder::new().filename("second"))
+            .unwrap();
+        e.finish_member(GzBuilder::new().comment("empty")).unwrap();
+        e.write_all(b"world").unwrap();
+        let data = e.finish().unwrap();
+
+        let mut r = read::MultiGzDecoder::new(&data[..]).record_members(true);
+        let mut out = String::new();
+        r.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello world");
+        let members = r.take_members();
+        assert_eq!(members.len(), 3);
+        assert_eq!(members[0].header().filename(), Some(&b"first"[..]));
+        assert_eq!(members[0].uncompressed_len(), 6);
+        assert_eq!(members[1].header().filename(), Some(&b"second"[..]));
+        assert_eq!(members[1].uncompressed_len(), 0);
+        assert_eq!(members[2].header().comment(), Some(&b"empty"[..]));
+        assert_eq!(members[2].uncompressed_len(), 5);
+
+        // Each member is a complete gzip stream on its own.
+        let mut r = read::GzDecoder::new(&data[members[2].compressed().start as usize..]);
+        let mut out = String::new();
+        r.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "world");
+    }
+
+    #[test]
+    fn reset() {
+        let gzip = |data: &[u8]| {
+            let mut e = write::GzEncoder::new(Vec::new(), Compression::default());
+            e.write_all(data).unwrap();
+            e.finish().unwrap()
+        };
+        let (hello, world) = (gzip(b"hello"), gzip(b"world"));
+
+        let mut e = write::GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(b"hello").unwrap();
+        assert_eq!(e.reset(Vec::new()).unwrap(), hello);
+        e.write_all(b"world").unwrap();
+        assert_eq!(e.finish().unwrap(), world);
+
+        let mut e = read::GzEncoder::new(&b"hello"[..], Compression::default());
+        let mut out = Vec::new();
+        e.read_to_end(&mut out).unwrap();
+        assert_eq!(out, hello);
+        e.reset(&b"world"[..]);
+        out.clear();
+        e.read_to_end(&mut out).unwrap();
+        assert_eq!(out, world);
+
+        let mut e = bufread::GzEncoder::new(&b"hello"[..], Compression::default());
+        e.read_to_end(&mut Vec::new()).unwrap();
+        e.reset(&b"world"[..]);
+        out.clear();
+        e.read_to_end(&mut out).unwrap();
+        assert_eq!(out, world);
+
+        // Resetting midway through a stream starts over.
+        let mut d = read::GzDecoder::new(&hello[..]);
+        d.read_exact(&mut [0; 2]).unwrap();
+        d.reset(&world[..]);
+        let mut out = String::new();
+        d.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "world");
+        assert!(d.header().is_some());
+
+        let mut d = bufread::MultiGzDecoder::new(&hello[..]).record_members(true);
+        d.read_to_end(&mut Vec::new()).unwrap();
+        d.reset(&world[..]);
+        let mut out = String::new();
+        d.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "world");
+        let members = d.take_members();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].compressed(), 0..world.len() as u64);
+
+        let mut d = read::MultiGzDecoder::new(&hello[..]);
+        d.read_to_end(&mut Vec::new()).unwrap();
+        d.reset(&world[..]);
+        let mut out = String::new();
+        d.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "world");
+
+        let mut w = write::GzDecoder::new(Vec::new());
+        w.write_all(&hello).unwrap();
+        assert_eq!(w.reset(Vec::new()).unwrap(), b"hello");
+        w.write_all(&world).unwrap();
+        assert_eq!(w.finish().unwrap(), b"world");
+
+        let mut w = write::MultiGzDecoder::new(Vec::new()).record_members(true);
+        w.write_all(&hello).unwrap();
+        w.write_all(&hello).unwrap();
+        assert_eq!(w.reset(Vec::new()).unwrap(), b"hellohello");
+        w.write_all(&world).unwrap();
+        w.try_finish().unwrap();
+        let members = w.take_members();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].compressed(), 0..world.len() as u64);
+        assert_eq!(w.finish().unwrap(), b"world");
+    }
+
+    #[test]
+    fn append() {
+        use std::io::Cursor;
+
+        let mut rng = thread_rng();
+        let chunks = [
+            Vec::new(),
+            b"hello".to_vec(),
+            (0..200_000).map(|_| rng.gen_range(b'a'..=b'd')).collect(),
+            (0..70_000).map(|_| rng.gen()).collect(),
+            b"world".to_vec(),
+        ];
+        let levels = [
+            Compression::none(),
+            Compression::fast(),
+            Compression::default(),
+            Compression::best(),
+        ];
+        for first in levels {
+            let e = GzBuilder::new()
+                .filename("log")
+                .write(Cursor::new(Vec::new()), first);
+            let mut file = e.finish().unwrap();
+            let mut expected = Vec::new();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let level = levels[i % levels.len()];
+                let before = file.get_ref().clone();
+                let mut e = write::GzEncoder::append(file, level).unwrap();
+                e.write_all(chunk).unwrap();
+                file = e.finish().unwrap();
+                expected.extend_from_slice(chunk);
+
+                // Short of the trailer and the byte the last block ends in,
+                // only the mark of the last block is changed.
+                let data = file.get_ref();
+                let kept = before.len() - 9;
+                let changed = (0..kept).filter(|&i| before[i] != data[i]).count();
+                assert_eq!(changed, 1);
+                let mut r = read::MultiGzDecoder::new(&data[..]).record_members(true);
+                let mut out = Vec::new();
+                r.read_to_end(&mut out).unwrap();
+                assert!(out == expected);
+                let members = r.take_members();
+                assert_eq!(members.len(), 1);
+                assert_eq!(members[0].header().filename(), Some(&b"log"[..]));
+            }
+        }
+
+        // Only the last member is continued.
+        let mut e = write::GzEncoder::new(Cursor::new(Vec::new()), Compression::default());
+        e.write_all(b"hello").unwrap();
+        e.finish_member(GzBuilder::new()).unwrap();
+        e.write_all(b" world").unwrap();
+        let mut e = write::GzEncoder::append(e.finish().unwrap(), Compression::default()).unwrap();
+        e.write_all(b"!").unwrap();
+        let data = e.finish().unwrap().into_inner();
+        let mut r = read::MultiGzDecoder::new(&data[..]).record_members(true);
+        let mut out = String::new();
+        r.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello world!");
+        let members = r.take_members();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[1].uncompressed_len(), 7);
+
+        // Invalid files are left untouched.
+        let mut garbage = data.clone();
+        garbage.extend_from_slice(b"garbage");
+        let mut file = Cursor::new(garbage.clone());
+        assert!(write::GzEncoder::append(&mut file, Compression::default()).is_err());
+        assert_eq!(file.get_ref(), &garbage);
+        let mut corrupt = data;
+        let len = corrupt.len();
+        corrupt[len - 5] ^= 1;
+        let mut file = Cursor::new(corrupt.clone());
+        assert!(write::GzEncoder::append(&mut file, Compression::default()).is_err());
+        assert_eq!(file.get_ref(), &corrupt);
+
+        // Every append may go through another backend.
+        #[cfg(all(feature = "any_zlib", feature = "miniz_oxide"))]
+        {
+            let e = write::GzEncoder::new(Cursor::new(Vec::new()), Compression::default());
+            let mut file = e.finish().unwrap();
+            let backends = [crate::Backend::Rust, crate::Backend::Zlib];
+            for (i, chunk) in chunks.iter().enumerate() {
+                let options = crate::EncoderOptions::new()
+                    .backend(backends[i % 2])
+                    .rsyncable(i % 3 == 0);
+                let level = Compression::default();
+                let mut e = write::GzEncoder::append_with_options(file, level, options).unwrap();
+                e.write_all(chunk).unwrap();
+                file = e.finish().unwrap();
+            }
+            let mut out = Vec::new();
+            read::GzDecoder::new(&file.get_ref()[..])
+                .read_to_end(&mut out)
+                .unwrap();
+            assert!(out == chunks.concat());
+        }
+    }
+
+    #[test]
+    fn join() {
+        let mut rng = thread_rng();
+        let levels = [
+            Compression::none(),
+            Compression::fast(),
+            Compression::default(),
+            Compression::best(),
+        ];
+        let mut files = Vec::new();
+        let mut expected = Vec::new();
+        for i in 0..40 {
+            let len = match i % 4 {
+                0 => 0,
+                1 => rng.gen_range(1..10),
+                2 => rng.gen_range(10..1000),
+                _ => rng.gen_range(1000..100_000),
+            };
+            let data: Vec<u8> = (0..len).map(|_| rng.gen_range(b'a'..=b'a' + i)).collect();
+            let mut e = write::GzEncoder::new(Vec::new(), levels[i as usize % levels.len()]);
+            e.write_all(&data).unwrap();
+            // Some inputs have several members, all of which are joined.
+            if i % 5 == 0 {
+                e.finish_member(GzBuilder::new()).unwrap();
+                e.write_all(&data).unwrap();
+                expected.extend_from_slice(&data);
+            }
+            files.push(e.finish().unwrap());
+            expected.extend_from_slice(&data);
+        }
+
+        let joined = GzBuilder::new()
+            .filename("joined")
+            .join(files.iter().map(|file| &file[..]), Vec::new())
+            .unwrap();
+        let mut r = read::GzDecoder::new(&joined[..]);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert!(out == expected);
+        assert_eq!(r.header().unwrap().filename(), Some(&b"joined"[..]));
+        let mut r = read::MultiGzDecoder::new(&joined[..]).record_members(true);
+        r.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(r.take_members().len(), 1);
+
+        // Joining nothing gives an empty member.
+        let empty = GzBuilder::new()
+            .join(Vec::<&[u8]>::new(), Vec::new())
+            .unwrap();
+        let mut r = read::GzDecoder::new(&empty[..]);
+        assert_eq!(r.read(&mut [0; 1]).unwrap(), 0);
+
+        let mut garbage = files[1].clone();
+        garbage.extend_from_slice(b"garbage");
+        assert!(GzBuilder::new()
+            .join(vec![&files[0][..], &garbage[..]], Vec::new())
+            .is_err());
+    }
 }
@@ -2,9 +2,9 @@ use std::io;
 use std::io::prelude::*;
 
 use super::bufread;
-use super::{GzBuilder, GzHeader};
+use super::{GzBuilder, GzHeader, GzMember};
 use crate::bufreader::BufReader;
-use crate::Compression;
+use crate::{Compress, Compression, DecoderOptions, Decompress, EncoderOptions, Limits};
 
 /// A gzip streaming encoder
 ///
@@ -51,9 +51,39 @@ impl<R: Read> GzEncoder<R> {
     pub fn new(r: R, level: Compression) -> GzEncoder<R> {
         GzBuilder::new().read(r, level)
     }
+
+    /// Creates a new encoder like `new`, which also applies the given
+    /// `options`.
+    pub fn new_with_options(r: R, level: Compression, options: EncoderOptions) -> GzEncoder<R> {
+        GzBuilder::new().options(options).read(r, level)
+    }
+
+    /// Creates a new encoder with the given `compression` settings, the data
+    /// read from the stream `r` being compressed and available through the
+    /// returned reader.
+    ///
+    /// The header emitted doesn't tell how hard the data was compressed.
+    pub fn new_with_compress(r: R, compression: Compress) -> GzEncoder<R> {
+        gz_encoder(bufread::GzEncoder::new_with_compress(
+            BufReader::new(r),
+            compression,
+        ))
+    }
 }
 
 impl<R> GzEncoder<R> {
+    /// Resets the state of this encoder entirely, swapping out the input
+    /// stream for another.
+    ///
+    /// This function will reset the internal state of this encoder and replace
+    /// the input stream with the one provided, returning the previous input
+    /// stream. Future data read from this encoder will be a new gzip stream,
+    /// with the same header, holding the compressed version of `r`'s data.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.reset_state();
+        self.inner.get_mut().reset(r)
+    }
+
     /// Acquires a reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         self.inner.get_ref().get_ref()
@@ -145,9 +175,46 @@ impl<R: Read> GzDecoder<R> {
             inner: bufread::GzDecoder::new(BufReader::new(r)),
         }
     }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// gzip header, and failing once any of the given `limits` is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> GzDecoder<R> {
+        GzDecoder {
+            inner: bufread::GzDecoder::new_with_limits(BufReader::new(r), limits),
+        }
+    }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// gzip header, and configured by `options`.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> GzDecoder<R> {
+        GzDecoder {
+            inner: bufread::GzDecoder::new_with_options(BufReader::new(r), options),
+        }
+    }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// gzip header, and inflating the data following it with the given
+    /// `decompression` settings, which must expect raw deflate data.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> GzDecoder<R> {
+        GzDecoder {
+            inner: bufread::GzDecoder::new_with_decompress(BufReader::new(r), decompression),
+        }
+    }
 }
 
 impl<R> GzDecoder<R> {
+    /// Resets the state of this decoder entirely, swapping out the input
+    /// stream for another.
+    ///
+    /// This will reset the internal state of this decoder and replace the
+    /// input stream with the one provided, returning the previous input
+    /// stream. The header of the new stream is parsed on the next read, and
+    /// the limits start over.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.reset_state();
+        self.inner.get_mut().reset(r)
+    }
+
     /// Returns the header associated with this stream, if it was valid.
     pub fn header(&self) -> Option<&GzHeader> {
         self.inner.header()
@@ -253,14 +320,70 @@ impl<R: Read> MultiGzDecoder<R> {
             inner: bufread::MultiGzDecoder::new(BufReader::new(r)),
         }
     }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// first gzip header, and failing once any of the given `limits` is
+    /// exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> MultiGzDecoder<R> {
+        MultiGzDecoder {
+            inner: bufread::MultiGzDecoder::new_with_limits(BufReader::new(r), limits),
+        }
+    }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// first gzip header, and configured by `options`.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> MultiGzDecoder<R> {
+        MultiGzDecoder {
+            inner: bufread::MultiGzDecoder::new_with_options(BufReader::new(r), options),
+        }
+    }
+
+    /// Creates a new decoder from the given reader, immediately parsing the
+    /// first gzip header, and inflating the members with the given
+    /// `decompression` settings, which must expect raw deflate data.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> MultiGzDecoder<R> {
+        MultiGzDecoder {
+            inner: bufread::MultiGzDecoder::new_with_decompress(BufReader::new(r), decompression),
+        }
+    }
 }
 
 impl<R> MultiGzDecoder<R> {
+    /// Resets the state of this decoder entirely, swapping out the input
+    /// stream for another.
+    ///
+    /// This will reset the internal state of this decoder, forgetting about
+    /// the members not taken yet, and replace the input stream with the one
+    /// provided, returning the previous input stream.
+    pub fn reset(&mut self, r: R) -> R {
+        self.inner.reset_state();
+        self.inner.get_mut().reset(r)
+    }
+
     /// Returns the current header associated with this stream, if it's valid.
     pub fn header(&self) -> Option<&GzHeader> {
         self.inner.header()
     }
 
+    /// Sets whether the members are recorded as they complete, to be
+    /// returned by [`take_members`](Self::take_members). They aren't by
+    /// default, as a long stream can have a great many of them.
+    pub fn record_members(mut self, record: bool) -> MultiGzDecoder<R> {
+        self.inner = self.inner.record_members(record);
+        self
+    }
+
+    /// Returns the members that were decoded to the end since the last call,
+    /// with their headers and where they lie in the input and in the output.
+    ///
+    /// The members are kept track of until this is called, which can be done
+    /// after every read to learn about them as they complete, and only if
+    /// [`record_members`](Self::record_members) was set, so that this returns
+    /// nothing otherwise.
+    pub fn take_members(&mut self) -> Vec<GzMember> {
+        self.inner.take_members()
+    }
+
     /// Acquires a reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         self.inner.get_ref().get_ref()
@@ -1,17 +1,27 @@
 use std::cmp;
 use std::io;
 use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::mem;
 
-use super::{corrupt, GzBuilder, GzHeader, GzHeaderParser};
+use super::{check_trailer, is_member_start, GzBuilder, GzHeader, GzHeaderParser, GzMember};
 use crate::crc::{Crc, CrcWriter};
+use crate::gzlog::LogFile;
+use crate::index::{Checkpoint, Format, Index, Input, WINDOW_SIZE};
 use crate::zio;
-use crate::{Compress, Compression, Decompress, Status};
+use crate::{
+    Backend, Compress, Compression, DecoderOptions, Decompress, EncoderOptions, Error,
+    FlushCompress, Limits, Status,
+};
 
 /// A gzip streaming encoder
 ///
 /// This structure exposes a [`Write`] interface that will emit compressed data
 /// to the underlying writer `W`.
 ///
+/// The data can be split into several members, each with its own header, with
+/// [`finish_member`](GzEncoder::finish_member).
+///
 /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
 ///
 /// # Examples
@@ -35,14 +45,23 @@ pub struct GzEncoder<W: Write> {
     crc: Crc,
     crc_bytes_written: usize,
     header: Vec<u8>,
+    header_written: usize,
+    level: Compression,
 }
 
-pub fn gz_encoder<W: Write>(header: Vec<u8>, w: W, lvl: Compression) -> GzEncoder<W> {
+pub fn gz_encoder<W: Write>(
+    header: Vec<u8>,
+    w: W,
+    lvl: Compression,
+    data: Compress,
+) -> GzEncoder<W> {
     GzEncoder {
-        inner: zio::Writer::new(w, Compress::new(lvl, false)),
+        inner: zio::Writer::new(w, data),
         crc: Crc::new(),
         header,
+        header_written: 0,
         crc_bytes_written: 0,
+        level: lvl,
     }
 }
 
@@ -58,6 +77,21 @@ impl<W: Write> GzEncoder<W> {
         GzBuilder::new().write(w, level)
     }
 
+    /// Creates a new encoder like `new`, which also applies the given
+    /// `options`.
+    pub fn new_with_options(w: W, level: Compression, options: EncoderOptions) -> GzEncoder<W> {
+        GzBuilder::new().options(options).write(w, level)
+    }
+
+    /// Creates a new encoder which will write the data compressed with the
+    /// given `compression` settings to the stream `w`.
+    ///
+    /// The header emitted doesn't tell how hard the data was compressed.
+    pub fn new_with_compress(w: W, compression: Compress) -> GzEncoder<W> {
+        let level = Compression::default();
+        gz_encoder(GzBuilder::new().into_header(level), w, level, compression)
+    }
+
     /// Acquires a reference to the underlying writer.
     pub fn get_ref(&self) -> &W {
         self.inner.get_ref()
@@ -127,15 +161,286 @@ impl<W: Write> GzEncoder<W> {
         Ok(self.inner.take_inner())
     }
 
+    /// Finishes the current member, writing out its trailer, and starts a new
+    /// one with the header configured by `header`.
+    ///
+    /// The next member is compressed with the same level as the current one,
+    /// reusing its compressor. Members written this way are read back as a
+    /// whole by a [`MultiGzDecoder`](crate::read::MultiGzDecoder).
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete the current member, and any
+    /// I/O errors which occur will be returned from this function.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::prelude::*;
+    /// use flate2::read::MultiGzDecoder;
+    /// use flate2::{Compression, GzBuilder};
+    ///
+    /// # fn main() -> std::io::Result<()> {
+    /// let mut e = GzBuilder::new()
+    ///     .filename("first")
+    ///     .write(Vec::new(), Compression::default());
+    /// e.write_all(b"Hello ")?;
+    /// e.finish_member(GzBuilder::new().filename("second"))?;
+    /// e.write_all(b"World")?;
+    /// let bytes = e.finish()?;
+    ///
+    /// let mut d = MultiGzDecoder::new(&bytes[..]).record_members(true);
+    /// let mut s = String::new();
+    /// d.read_to_string(&mut s)?;
+    /// assert_eq!(s, "Hello World");
+    /// let members = d.take_members();
+    /// assert_eq!(members[1].header().filename(), Some(&b"second"[..]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn finish_member(&mut self, header: GzBuilder) -> io::Result<()> {
+        self.try_finish()?;
+        self.header = header.into_header(self.level);
+        self.reset_member();
+        Ok(())
+    }
+
+    /// Resets the state of this encoder entirely, swapping out the output
+    /// stream for another.
+    ///
+    /// This function will finish encoding the current stream into the current
+    /// output stream before swapping out the two output streams. The new
+    /// stream starts with the same header as the current member.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn reset(&mut self, w: W) -> io::Result<W> {
+        self.try_finish()?;
+        self.reset_member();
+        Ok(self.inner.replace(w))
+    }
+
+    /// Flushes the compressor with `flush`, after the header if it wasn't
+    /// written yet, without flushing the underlying writer.
+    pub(crate) fn flush_with(&mut self, flush: FlushCompress) -> io::Result<()> {
+        self.write_header()?;
+        self.inner.flush_with(flush)
+    }
+
+    pub(crate) fn is_present(&self) -> bool {
+        self.inner.is_present()
+    }
+
+    // Note that this should only be called once the stream is finished, and
+    // the encoder is about to be dropped.
+    pub(crate) fn take_inner(&mut self) -> W {
+        self.inner.take_inner()
+    }
+
+    fn reset_member(&mut self) {
+        self.inner.data.reset();
+        self.crc.reset();
+        self.crc_bytes_written = 0;
+        self.header_written = 0;
+    }
+
     fn write_header(&mut self) -> io::Result<()> {
-        while !self.header.is_empty() {
-            let n = self.inner.get_mut().write(&self.header)?;
-            self.header.drain(..n);
+        while self.header_written < self.header.len() {
+            let n = self
+                .inner
+                .get_mut()
+                .write(&self.header[self.header_written..])?;
+            self.header_written += n;
         }
         Ok(())
     }
 }
 
+impl<W: LogFile> GzEncoder<W> {
+    /// Opens the gzip file `w` to append data to its last member, without
+    /// compressing again what's already there.
+    ///
+    /// As zlib's `gzappend` example does, the last member is decompressed to
+    /// find its last deflate block, the one marked as final. That mark is
+    /// cleared in place, the file is cut at the byte the block ends in, and the
+    /// compressor is primed with the bits of that byte belonging to the block
+    /// and given the data preceding its end as dictionary. It then compresses
+    /// whatever is written to the encoder, so that the member goes on after
+    /// the block. Once finished, the member ends with a trailer covering all
+    /// of its data, so the file still has as many members as before.
+    ///
+    /// The file is truncated by this function, and the last member is only
+    /// complete again once the encoder is finished.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error is returned, as well as errors for invalid gzip data,
+    /// including checksum mismatches and data following the last member, in
+    /// which case `w` is left untouched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::prelude::*;
+    /// use std::io::Cursor;
+    /// use flate2::read::GzDecoder;
+    /// use flate2::write::GzEncoder;
+    /// use flate2::Compression;
+    ///
+    /// # fn main() -> std::io::Result<()> {
+    /// let mut e = GzEncoder::new(Cursor::new(Vec::new()), Compression::default());
+    /// e.write_all(b"Hello ")?;
+    /// let file = e.finish()?;
+    ///
+    /// let mut e = GzEncoder::append(file, Compression::default())?;
+    /// e.write_all(b"World")?;
+    /// let file = e.finish()?.into_inner();
+    ///
+    /// let mut s = String::new();
+    /// GzDecoder::new(&file[..]).read_to_string(&mut s)?;
+    /// assert_eq!(s, "Hello World");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn append(w: W, level: Compression) -> io::Result<GzEncoder<W>> {
+        GzEncoder::append_with_options(w, level, EncoderOptions::new())
+    }
+
+    /// Opens the gzip file `w` to append data to its last member as `append`
+    /// does, with the last member decompressed and the data compressed as
+    /// configured by `options`.
+    pub fn append_with_options(
+        mut w: W,
+        level: Compression,
+        options: EncoderOptions,
+    ) -> io::Result<GzEncoder<W>> {
+        w.seek(SeekFrom::Start(0))?;
+        let last = last_block(&mut w, options.backend.unwrap_or_default())?;
+        let (marked, mask) = (last.start / 8, 1 << (last.start % 8));
+        let (end, bits) = (last.end / 8, (last.end % 8) as u8);
+        let mut byte = [0];
+        w.seek(SeekFrom::Start(marked))?;
+        w.read_exact(&mut byte)?;
+        let cleared = byte[0] & !mask;
+        if bits > 0 {
+            w.seek(SeekFrom::Start(end))?;
+            w.read_exact(&mut byte)?;
+        }
+        // The block may end in the byte it is marked as final in.
+        if end == marked {
+            byte[0] = cleared;
+        }
+
+        // Everything that can fail short of writing is done before the file
+        // is touched, so that it is left intact on failure.
+        let mut data = options.compress(level, false);
+        data.prime(bits, u16::from(byte[0] & !(0xff << bits)))?;
+        if !last.window.is_empty() {
+            data.set_dictionary(&last.window)?;
+        }
+
+        if end > marked {
+            w.seek(SeekFrom::Start(marked))?;
+            w.write_all(&[cleared])?;
+        }
+        w.set_len(end)?;
+        w.seek(SeekFrom::Start(end))?;
+        Ok(GzEncoder {
+            inner: zio::Writer::new(w, data),
+            crc: last.crc,
+            crc_bytes_written: 0,
+            header: Vec::new(),
+            header_written: 0,
+            level,
+        })
+    }
+}
+
+// The last deflate block of a gzip file, as found by `last_block`.
+struct LastBlock {
+    // The bit offsets of the start and of the end of the block.
+    start: u64,
+    end: u64,
+    // The data preceding the end of the block in its member, up to a window
+    // of it.
+    window: Vec<u8>,
+    // The checksum of all the data of the member.
+    crc: Crc,
+}
+
+fn last_block<R: Read>(r: R, backend: Backend) -> io::Result<LastBlock> {
+    let mut input = Input::new(r);
+    let mut data = Decompress::new_with_backend(false, backend);
+    let mut out = vec![0; 32 * 1024];
+    loop {
+        let start = input.offset;
+        GzHeaderParser::new()
+            .parse(&mut input)
+            .map_err(|err| Error::rebase(err, start))?;
+        data.reset(false);
+        let mut crc = Crc::new();
+        // The window before the current block, followed by its data.
+        let mut history = Vec::new();
+        let mut block_start = input.offset * 8;
+        let mut block_end = block_start;
+
+        loop {
+            let buf = input.fill_buf()?;
+            let eof = buf.is_empty();
+            let before_in = data.total_in();
+            let before_out = data.total_out();
+            let ret = data.decompress_block(buf, &mut out);
+            let consumed = (data.total_in() - before_in) as usize;
+            let produced = (data.total_out() - before_out) as usize;
+            input.consume(consumed);
+            let (status, boundary) = ret.map_err(|err| Error::decompress(err, input.offset))?;
+
+            crc.update(&out[..produced]);
+            history.extend_from_slice(&out[..produced]);
+            match boundary {
+                Some((bits, false)) => {
+                    block_start = input.offset * 8 - u64::from(bits);
+                    history.drain(..history.len().saturating_sub(WINDOW_SIZE));
+                }
+                Some((bits, true)) => block_end = input.offset * 8 - u64::from(bits),
+                None => {}
+            }
+            if status == Status::StreamEnd {
+                break;
+            }
+            if eof && consumed == 0 && produced == 0 {
+                let offset = input.offset;
+                return Err(Error::Truncated { offset }.into());
+            }
+        }
+
+        let trailer_start = input.offset;
+        let mut trailer = [0; 8];
+        input.read_exact(&mut trailer)?;
+        check_trailer(&trailer, &crc, trailer_start)?;
+        match input.fill_buf()? {
+            [] => {
+                history.drain(..history.len().saturating_sub(WINDOW_SIZE));
+                return Ok(LastBlock {
+                    start: block_start,
+                    end: block_end,
+                    window: history,
+                    crc,
+                });
+            }
+            buf if !is_member_start(buf) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "data after the last gzip member",
+                ))
+            }
+            _ => {}
+        }
+    }
+}
+
 impl<W: Write> Write for GzEncoder<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         assert_eq!(self.crc_bytes_written, 0);
@@ -166,6 +471,199 @@ impl<W: Write> Drop for GzEncoder<W> {
     }
 }
 
+/// A gzip encoder producing files which can be read from arbitrary positions.
+///
+/// This behaves like a [`GzEncoder`], except that every `span` bytes of
+/// uncompressed data it performs a full flush of the compressor, after which
+/// the data doesn't refer to anything preceding it. Decoding can then start
+/// from any of these flush points, as recorded in an [`Index`] which can be
+/// handed to a [`SeekableGzDecoder`] without any further pass over the data.
+///
+/// The index can either be stored separately, in a sidecar file for instance,
+/// or embedded at the end of the file, see
+/// [`new_with_embedded_index`](SeekableGzEncoder::new_with_embedded_index).
+/// Full flushes cost a little compression ratio, so the span shouldn't be too
+/// small.
+///
+/// The offsets of the flush points are relative to the position of the
+/// underlying writer when the encoder was created.
+///
+/// [`SeekableGzDecoder`]: crate::index::SeekableGzDecoder
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use std::io::{Cursor, SeekFrom};
+/// use flate2::index::SeekableGzDecoder;
+/// use flate2::write::SeekableGzEncoder;
+/// use flate2::Compression;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let data: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+/// let mut e = SeekableGzEncoder::new(Vec::new(), Compression::default(), 64 * 1024);
+/// e.write_all(&data)?;
+/// e.try_finish()?;
+/// let index = e.index();
+/// let compressed = e.finish()?;
+///
+/// let mut d = SeekableGzDecoder::new(Cursor::new(compressed), index);
+/// d.seek(SeekFrom::Start(4 * 12_345))?;
+/// let mut buf = [0; 4];
+/// d.read_exact(&mut buf)?;
+/// assert_eq!(u32::from_le_bytes(buf), 12_345);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SeekableGzEncoder<W: Write> {
+    inner: GzEncoder<W>,
+    span: usize,
+    pending: usize,
+    header_len: u64,
+    checkpoints: Vec<Checkpoint>,
+    len: u64,
+    embed: bool,
+    // The embedded index left to write, once the stream is finished.
+    embedded: Option<Vec<u8>>,
+}
+
+impl<W: Write> SeekableGzEncoder<W> {
+    /// Creates a new encoder which will use the given compression level,
+    /// performing a full flush every `span` bytes of uncompressed data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `span` is zero.
+    pub fn new(w: W, level: Compression, span: usize) -> SeekableGzEncoder<W> {
+        assert!(span > 0, "the span can't be zero");
+        let inner = GzEncoder::new(w, level);
+        let header_len = inner.header.len() as u64;
+        SeekableGzEncoder {
+            inner,
+            span,
+            pending: 0,
+            header_len,
+            checkpoints: vec![Checkpoint::new(header_len * 8, 0, Vec::new())],
+            len: 0,
+            embed: false,
+            embedded: None,
+        }
+    }
+
+    /// Creates a new encoder like [`new`](SeekableGzEncoder::new), which also
+    /// appends the index to the file when finishing it.
+    ///
+    /// The index is stored in empty gzip members, so that the file can still
+    /// be read by any gzip decoder handling multiple members, and can be
+    /// retrieved with [`Index::read_embedded`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `span` is zero.
+    pub fn new_with_embedded_index(w: W, level: Compression, span: usize) -> SeekableGzEncoder<W> {
+        let mut e = SeekableGzEncoder::new(w, level, span);
+        e.embed = true;
+        e
+    }
+
+    /// Returns the index of the flush points written so far.
+    ///
+    /// The index only covers the whole stream once it is finished.
+    pub fn index(&self) -> Index {
+        Index::new(Format::Gzip, self.checkpoints.clone(), self.len)
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutation of the writer may result in surprising results if
+    /// this encoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    /// Attempt to finish this output stream, writing out final chunks of data
+    /// and the embedded index if there is one.
+    ///
+    /// # Panics
+    ///
+    /// Attempts to write data to this stream may result in a panic after this
+    /// function is called.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.inner.try_finish()?;
+        if self.embed && self.embedded.is_none() {
+            self.embedded = Some(self.index().to_embedded());
+        }
+        if let Some(embedded) = &mut self.embedded {
+            while !embedded.is_empty() {
+                let n = self.inner.inner.get_mut().write(embedded)?;
+                if n == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                embedded.drain(..n);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish encoding this stream, returning the underlying writer once the
+    /// encoding is done.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.inner.take_inner())
+    }
+
+    fn flush_point(&mut self) -> io::Result<()> {
+        self.inner.flush_with(FlushCompress::Full)?;
+        let offset = self.header_len + self.inner.inner.data.total_out();
+        self.checkpoints
+            .push(Checkpoint::new(offset * 8, self.len, Vec::new()));
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SeekableGzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Flush points are only added once there is data following them.
+        if self.pending == self.span && !buf.is_empty() {
+            self.flush_point()?;
+        }
+        let n = cmp::min(buf.len(), self.span - self.pending);
+        let n = self.inner.write(&buf[..n])?;
+        self.pending += n;
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for SeekableGzEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.inner.is_present() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
 /// A decoder for a single member of a [gzip file].
 ///
 /// This structure exposes a [`Write`] interface, receiving compressed data and
@@ -212,6 +710,10 @@ pub struct GzDecoder<W: Write> {
     inner: zio::Writer<CrcWriter<W>, Decompress>,
     crc_bytes: Vec<u8>,
     header_parser: GzHeaderParser,
+    options: DecoderOptions,
+    // The offset of this member, for members after the first one of a
+    // multi-member stream.
+    member_start: u64,
 }
 
 const CRC_BYTES_LEN: usize = 8;
@@ -222,13 +724,73 @@ impl<W: Write> GzDecoder<W> {
     /// When this encoder is dropped or unwrapped the final pieces of data will
     /// be flushed.
     pub fn new(w: W) -> GzDecoder<W> {
+        GzDecoder::new_with_limits(w, Limits::new())
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// failing once any of the given `limits` is exceeded.
+    ///
+    /// Output beyond the limits is never written to the stream.
+    pub fn new_with_limits(w: W, limits: Limits) -> GzDecoder<W> {
+        GzDecoder::new_with_options(w, DecoderOptions::new().limits(limits))
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// as configured by `options`.
+    pub fn new_with_options(w: W, options: DecoderOptions) -> GzDecoder<W> {
+        GzDecoder::new_with_decompress_and_options(w, options.decompress(false), options)
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream
+    /// `w`, inflating it with the given `decompression` settings, which must
+    /// expect raw deflate data.
+    pub fn new_with_decompress(w: W, decompression: Decompress) -> GzDecoder<W> {
+        GzDecoder::new_with_decompress_and_options(w, decompression, DecoderOptions::new())
+    }
+
+    fn new_with_decompress_and_options(
+        w: W,
+        data: Decompress,
+        options: DecoderOptions,
+    ) -> GzDecoder<W> {
         GzDecoder {
-            inner: zio::Writer::new(CrcWriter::new(w), Decompress::new(false)),
+            inner: zio::Writer::new_with_options(CrcWriter::new(w), data, &options),
             crc_bytes: Vec::with_capacity(CRC_BYTES_LEN),
-            header_parser: GzHeaderParser::new(),
+            header_parser: GzHeaderParser::new_with_options(&options),
+            options,
+            member_start: 0,
         }
     }
 
+    /// Resets the state of this decoder entirely, swapping out the output
+    /// stream for another.
+    ///
+    /// This function will finish decoding the current stream into the current
+    /// output stream before swapping out the two output streams, without
+    /// checking that the stream was complete.
+    ///
+    /// This will then reset the internal state of this decoder and replace the
+    /// output stream with the one provided, returning the previous output
+    /// stream. Future data written to this decoder will be decompressed into
+    /// the output stream `w`.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to finish the stream, and if that I/O
+    /// returns an error then that will be returned from this function.
+    pub fn reset(&mut self, w: W) -> io::Result<W> {
+        let data_start = self.data_start();
+        self.inner
+            .finish()
+            .map_err(|err| Error::rebase(err, data_start))?;
+        self.inner.data.reset(false);
+        self.crc_bytes.clear();
+        self.header_parser = GzHeaderParser::new_with_options(&self.options);
+        self.member_start = 0;
+        let w = self.inner.replace(CrcWriter::new(w));
+        Ok(w.into_inner())
+    }
+
     /// Returns the header associated with this stream.
     pub fn header(&self) -> Option<&GzHeader> {
         self.header_parser.header()
@@ -287,28 +849,28 @@ impl<W: Write> GzDecoder<W> {
         Ok(self.inner.take_inner().into_inner())
     }
 
+    fn data_start(&self) -> u64 {
+        self.member_start + self.header_parser.len()
+    }
+
     fn finish_and_check_crc(&mut self) -> io::Result<()> {
-        self.inner.finish()?;
+        let data_start = self.data_start();
+        self.inner
+            .finish()
+            .map_err(|err| Error::rebase(err, data_start))?;
 
-        if self.crc_bytes.len() != 8 {
-            return Err(corrupt());
+        let trailer_start = data_start + self.inner.data.total_in();
+        if self.header().is_none() {
+            return Err(Error::Truncated { offset: data_start }.into());
         }
-
-        let crc = ((self.crc_bytes[0] as u32) << 0)
-            | ((self.crc_bytes[1] as u32) << 8)
-            | ((self.crc_bytes[2] as u32) << 16)
-            | ((self.crc_bytes[3] as u32) << 24);
-        let amt = ((self.crc_bytes[4] as u32) << 0)
-            | ((self.crc_bytes[5] as u32) << 8)
-            | ((self.crc_bytes[6] as u32) << 16)
-            | ((self.crc_bytes[7] as u32) << 24);
-        if crc != self.inner.get_ref().crc().sum() {
-            return Err(corrupt());
+        if self.crc_bytes.len() != CRC_BYTES_LEN {
+            let offset = trailer_start + self.crc_bytes.len() as u64;
+            return Err(Error::Truncated { offset }.into());
         }
-        if amt != self.inner.get_ref().crc().amount() {
-            return Err(corrupt());
+        if !self.options.verify_checksums {
+            return Ok(());
         }
-        Ok(())
+        check_trailer(&self.crc_bytes, self.inner.get_ref().crc(), trailer_start)
     }
 }
 
@@ -322,7 +884,7 @@ impl<W: Write> Write for GzDecoder<W> {
                         // all data read but header still not complete
                         Ok(buflen)
                     } else {
-                        Err(err)
+                        Err(Error::rebase(err, self.member_start))
                     }
                 }
                 Ok(_) => {
@@ -333,7 +895,11 @@ impl<W: Write> Write for GzDecoder<W> {
                 }
             }
         } else {
-            let (n, status) = self.inner.write_with_status(buf)?;
+            let data_start = self.data_start();
+            let (n, status) = self
+                .inner
+                .write_with_status(buf)
+                .map_err(|err| Error::rebase(err, data_start))?;
 
             if status == Status::StreamEnd && n < buf.len() && self.crc_bytes.len() < 8 {
                 let remaining = buf.len() - n;
@@ -372,22 +938,129 @@ impl<W: Read + Write> Read for GzDecoder<W> {
 #[derive(Debug)]
 pub struct MultiGzDecoder<W: Write> {
     inner: GzDecoder<W>,
+    // The number of zeros skipped after the current member.
+    skipped: u64,
+    // Whether the current member is followed by garbage, which is ignored.
+    garbage: bool,
+    // The offset of the current member in the uncompressed data, the members
+    // completed so far if they are recorded, and whether the current member
+    // is complete, with its trailer checked.
+    out_start: u64,
+    members: Vec<GzMember>,
+    record: bool,
+    finished: bool,
 }
 
 impl<W: Write> MultiGzDecoder<W> {
     /// Creates a new decoder which will write uncompressed data to the stream.
     /// If the gzip stream contains multiple members all will be decoded.
     pub fn new(w: W) -> MultiGzDecoder<W> {
+        MultiGzDecoder::new_with_options(w, DecoderOptions::new())
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// failing once any of the given `limits` is exceeded.
+    ///
+    /// Output beyond the limits is never written to the stream.
+    pub fn new_with_limits(w: W, limits: Limits) -> MultiGzDecoder<W> {
+        MultiGzDecoder::new_with_options(w, DecoderOptions::new().limits(limits))
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// as configured by `options`.
+    pub fn new_with_options(w: W, options: DecoderOptions) -> MultiGzDecoder<W> {
+        MultiGzDecoder::from_decoder(GzDecoder::new_with_options(w, options))
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream
+    /// `w`, inflating the members with the given `decompression` settings,
+    /// which must expect raw deflate data.
+    pub fn new_with_decompress(w: W, decompression: Decompress) -> MultiGzDecoder<W> {
+        MultiGzDecoder::from_decoder(GzDecoder::new_with_decompress(w, decompression))
+    }
+
+    fn from_decoder(inner: GzDecoder<W>) -> MultiGzDecoder<W> {
         MultiGzDecoder {
-            inner: GzDecoder::new(w),
+            inner,
+            skipped: 0,
+            garbage: false,
+            out_start: 0,
+            members: Vec::new(),
+            record: false,
+            finished: false,
         }
     }
 
+    /// Sets whether the members are recorded as they complete, to be
+    /// returned by [`take_members`](Self::take_members). They aren't by
+    /// default, as a long stream can have a great many of them.
+    pub fn record_members(mut self, record: bool) -> MultiGzDecoder<W> {
+        self.record = record;
+        self
+    }
+
+    /// Resets the state of this decoder entirely, swapping out the output
+    /// stream for another.
+    ///
+    /// This function will finish decoding the current member into the current
+    /// output stream before swapping out the two output streams, without
+    /// checking that it was complete, and forgets about the members not taken
+    /// yet.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to finish the stream, and if that I/O
+    /// returns an error then that will be returned from this function.
+    pub fn reset(&mut self, w: W) -> io::Result<W> {
+        let w = self.inner.reset(w)?;
+        self.skipped = 0;
+        self.garbage = false;
+        self.out_start = 0;
+        self.members.clear();
+        self.finished = false;
+        Ok(w)
+    }
+
     /// Returns the header associated with the current member.
     pub fn header(&self) -> Option<&GzHeader> {
         self.inner.header()
     }
 
+    /// Returns the members that were decoded to the end since the last call,
+    /// with their headers and where they lie in the input and in the output.
+    ///
+    /// A member is complete once the start of the next one is written, or
+    /// when [`try_finish`](Self::try_finish) is called for the last one. The
+    /// members are kept track of until this is called, and only if
+    /// [`record_members`](Self::record_members) was set, so that this returns
+    /// nothing otherwise.
+    pub fn take_members(&mut self) -> Vec<GzMember> {
+        mem::take(&mut self.members)
+    }
+
+    // Marks the current member as complete once its trailer was checked,
+    // recording it if asked to.
+    fn record_member(&mut self) {
+        if self.finished {
+            return;
+        }
+        let inner = &self.inner;
+        if let Some(header) = inner.header() {
+            self.finished = true;
+            if !self.record {
+                return;
+            }
+            let trailer_end = inner.data_start() + inner.inner.data.total_in() + 8;
+            let out_len = inner.inner.data.total_out();
+            self.members.push(GzMember {
+                header: header.clone(),
+                compressed: inner.member_start..trailer_end,
+                uncompressed: self.out_start..self.out_start + out_len,
+                crc: inner.inner.get_ref().crc().sum(),
+            });
+        }
+    }
+
     /// Acquires a reference to the underlying writer.
     pub fn get_ref(&self) -> &W {
         self.inner.get_ref()
@@ -417,7 +1090,9 @@ impl<W: Write> MultiGzDecoder<W> {
     /// This function will perform I/O to finish the stream, returning any
     /// errors which happen.
     pub fn try_finish(&mut self) -> io::Result<()> {
-        self.inner.try_finish()
+        self.inner.try_finish()?;
+        self.record_member();
+        Ok(())
     }
 
     /// Consumes this decoder, flushing the output stream.
@@ -443,20 +1118,46 @@ impl<W: Write> MultiGzDecoder<W> {
 impl<W: Write> Write for MultiGzDecoder<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         if buf.is_empty() {
-            Ok(0)
-        } else {
-            match self.inner.write(buf) {
-                Ok(0) => {
-                    // When the GzDecoder indicates that it has finished
-                    // create a new GzDecoder to handle additional data.
+            return Ok(0);
+        } else if self.garbage {
+            return Ok(buf.len());
+        }
+        if !self.finished {
+            match self.inner.write(buf)? {
+                // When the GzDecoder indicates that it has finished
+                // create a new GzDecoder to handle additional data.
+                0 => {
                     self.inner.try_finish()?;
-                    let w = self.inner.inner.take_inner().into_inner();
-                    self.inner = GzDecoder::new(w);
-                    self.inner.write(buf)
+                    self.record_member();
                 }
-                res => res,
+                n => return Ok(n),
             }
         }
+        let options = self.inner.options;
+        if options.ignore_trailing_zeros && buf[0] == 0 {
+            let zeros = buf.iter().take_while(|&&b| b == 0).count();
+            self.skipped += zeros as u64;
+            return Ok(zeros);
+        }
+        if options.ignore_trailing_garbage && !is_member_start(buf) {
+            self.garbage = true;
+            return Ok(buf.len());
+        }
+        let member_start =
+            self.inner.data_start() + self.inner.inner.data.total_in() + 8 + self.skipped;
+        let mut limiter = self.inner.inner.limiter.clone();
+        if let Err(limit) = limiter.next_member() {
+            let offset = member_start;
+            return Err(Error::LimitExceeded { limit, offset }.into());
+        }
+        self.out_start += self.inner.inner.data.total_out();
+        let w = self.inner.inner.take_inner().into_inner();
+        self.inner = GzDecoder::new_with_options(w, options);
+        self.inner.inner.limiter = limiter;
+        self.inner.member_start = member_start;
+        self.skipped = 0;
+        self.finished = false;
+        self.inner.write(buf)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -638,4 +1339,72 @@ mod tests {
         assert_eq!(actual, STR);
         assert_eq!(&compressed[consumed_bytes..], b"x");
     }
+
+    fn seekable_data() -> Vec<u8> {
+        (0..200_000u32)
+            .flat_map(|i| (i % 1000).to_le_bytes())
+            .collect()
+    }
+
+    fn check_seekable(compressed: Vec<u8>, index: Index, data: &[u8]) {
+        use crate::index::SeekableGzDecoder;
+        use std::io::{Cursor, Seek, SeekFrom};
+
+        let mut all = Vec::new();
+        crate::read::MultiGzDecoder::new(&compressed[..])
+            .read_to_end(&mut all)
+            .unwrap();
+        assert!(all == data);
+
+        let mut d = SeekableGzDecoder::new(Cursor::new(compressed), index);
+        for &pos in &[700_000, 3, 123_457, data.len() - 100, 0] {
+            d.seek(SeekFrom::Start(pos as u64)).unwrap();
+            let mut buf = [0; 100];
+            d.read_exact(&mut buf).unwrap();
+            assert_eq!(buf[..], data[pos..pos + 100]);
+        }
+    }
+
+    #[test]
+    fn seekable_encoder_sidecar() {
+        let data = seekable_data();
+        let mut e = SeekableGzEncoder::new(Vec::new(), Compression::default(), 50_000);
+        e.write_all(&data).unwrap();
+        e.try_finish().unwrap();
+        let index = e.index();
+        let compressed = e.finish().unwrap();
+        assert_eq!(index.checkpoints().len(), data.len() / 50_000);
+        assert!(index.checkpoints().iter().all(|c| c.window().is_empty()));
+
+        let mut sidecar = Vec::new();
+        index.write_to(&mut sidecar).unwrap();
+        let index = Index::read_from(&sidecar[..]).unwrap();
+        check_seekable(compressed, index, &data);
+    }
+
+    #[test]
+    fn seekable_encoder_embedded() {
+        let data = seekable_data();
+        // Enough flush points to need several members to embed them.
+        let mut e =
+            SeekableGzEncoder::new_with_embedded_index(Vec::new(), Compression::none(), 190);
+        for chunk in data.chunks(1000) {
+            e.write_all(chunk).unwrap();
+        }
+        let expected = e.index();
+        let compressed = e.finish().unwrap();
+
+        let index = Index::read_embedded(std::io::Cursor::new(&compressed)).unwrap();
+        assert_eq!(index, expected);
+        assert!(index.checkpoints().len() > 4000);
+        check_seekable(compressed, index, &data);
+    }
+
+    #[test]
+    fn seekable_encoder_no_embedded_index() {
+        let mut e = GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(STR.as_bytes()).unwrap();
+        let compressed = e.finish().unwrap();
+        assert!(Index::read_embedded(std::io::Cursor::new(compressed)).is_err());
+    }
 }
@@ -0,0 +1,682 @@
+//! Support for gzip logs which stay readable after a crash.
+//!
+//! A [`GzLogWriter`] appends data to a gzip file, flushing the compressor and
+//! syncing the file to storage every so often. Everything written before the
+//! last of these flush points survives a crash, even one leaving a torn tail
+//! of partially written data at the end of the file.
+//!
+//! When a log is opened, the existing file is scanned for the end of its last
+//! intact member or flush point, and whatever follows is truncated away. A
+//! member cut short at a flush point is closed with an empty final block and
+//! a trailer. New data then goes to a new member appended to the file, so that
+//! the log always is a valid multi-member gzip file which
+//! [`MultiGzDecoder`](crate::read::MultiGzDecoder) reads back.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::prelude::*;
+//! use std::io::Cursor;
+//! use flate2::gzlog::GzLogWriter;
+//! use flate2::read::MultiGzDecoder;
+//! use flate2::Compression;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let mut log = GzLogWriter::open(Cursor::new(Vec::new()), Compression::default())?;
+//! log.write_all(b"first line\n")?;
+//! let file = log.finish()?;
+//!
+//! let mut log = GzLogWriter::open(file, Compression::default())?;
+//! log.write_all(b"second line\n")?;
+//! let file = log.finish()?.into_inner();
+//!
+//! let mut s = String::new();
+//! MultiGzDecoder::new(&file[..]).read_to_string(&mut s)?;
+//! assert_eq!(s, "first line\nsecond line\n");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+
+use crate::gz::{check_trailer, is_member_start, GzBuilder, GzHeaderParser};
+use crate::write::GzEncoder;
+use crate::{Compression, Crc, Decompress, FlushCompress, FlushDecompress, Status};
+
+/// The default amount of uncompressed data between flush points.
+pub const DEFAULT_FLUSH_INTERVAL: usize = 64 * 1024;
+
+// An empty final block with fixed Huffman codes, closing a deflate stream cut
+// at a flush point.
+const CLOSING_BLOCK: [u8; 2] = [0x03, 0x00];
+
+// The last four bytes of a sync or full flush, making up an empty stored block.
+const FLUSH_MARKER: u32 = 0x0000_ffff;
+
+/// A file holding a gzip log, which can be truncated and synced to storage.
+pub trait LogFile: Read + Write + Seek {
+    /// Truncates or extends the file to `len` bytes, like [`File::set_len`].
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+
+    /// Makes sure that the data written so far reaches storage, like
+    /// [`File::sync_data`].
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+impl LogFile for File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_data()
+    }
+}
+
+impl LogFile for Cursor<Vec<u8>> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: LogFile + ?Sized> LogFile for &mut F {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        (**self).set_len(len)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        (**self).sync()
+    }
+}
+
+/// What was done to an existing log to make it a valid gzip file again, as
+/// returned by [`recover`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recovery {
+    intact_len: u64,
+    truncated_len: u64,
+    closed_member: bool,
+}
+
+impl Recovery {
+    /// The length of the intact part of the log, which was kept.
+    pub fn intact_len(&self) -> u64 {
+        self.intact_len
+    }
+
+    /// The length of the torn tail which was truncated away.
+    pub fn truncated_len(&self) -> u64 {
+        self.truncated_len
+    }
+
+    /// Whether the last member was cut short at a flush point, and had to be
+    /// closed.
+    pub fn closed_member(&self) -> bool {
+        self.closed_member
+    }
+
+    /// Whether the log was left untouched, as it was intact already.
+    pub fn is_clean(&self) -> bool {
+        self.truncated_len == 0 && !self.closed_member
+    }
+}
+
+// A point of a member where all the data compressed so far was flushed, with
+// the checksum and length of that data.
+#[derive(Debug)]
+struct FlushPoint {
+    offset: u64,
+    crc: u32,
+    amount: u32,
+}
+
+// How an existing log ends.
+#[derive(Debug)]
+enum Scan {
+    // With a complete member, or nothing at all.
+    Complete(u64),
+    // With the member at `start`, or garbage, cut short.
+    Torn {
+        start: u64,
+        data_start: u64,
+        flush_points: Vec<FlushPoint>,
+    },
+}
+
+/// Scans the log in `f` for its intact part, truncating any torn tail and
+/// closing the last member at its last flush point if it was cut short.
+///
+/// Afterwards `f` is positioned at the end of the log. This is what
+/// [`GzLogWriter`] does when opening a log, and can be used on its own to read
+/// a log after a crash without appending to it.
+///
+/// The whole log is decompressed to check it, so this takes about as long as
+/// reading it.
+///
+/// # Errors
+///
+/// Fails if reading or writing `f` fails, or if it doesn't start like a gzip
+/// file, in which case it is left untouched.
+pub fn recover<F: LogFile>(f: &mut F) -> io::Result<Recovery> {
+    f.seek(SeekFrom::Start(0))?;
+    let scan = scan(&mut *f)?;
+    let len = f.seek(SeekFrom::End(0))?;
+    let (intact_len, closing) = match scan {
+        Scan::Complete(end) => (end, None),
+        Scan::Torn {
+            start,
+            data_start,
+            flush_points,
+        } => {
+            // A flush marker may also appear in the middle of a block, so the
+            // member must check out once cut and closed at it.
+            let mut closing = None;
+            for point in flush_points.into_iter().rev() {
+                if closes(f, data_start, &point)? {
+                    closing = Some(point);
+                    break;
+                }
+            }
+            match closing {
+                Some(point) => (point.offset, Some(point)),
+                None => (start, None),
+            }
+        }
+    };
+
+    let recovery = Recovery {
+        intact_len,
+        truncated_len: len - intact_len,
+        closed_member: closing.is_some(),
+    };
+    if recovery.is_clean() {
+        return Ok(recovery);
+    }
+    f.set_len(intact_len)?;
+    f.seek(SeekFrom::Start(intact_len))?;
+    if let Some(point) = closing {
+        f.write_all(&CLOSING_BLOCK)?;
+        f.write_all(&point.crc.to_le_bytes())?;
+        f.write_all(&point.amount.to_le_bytes())?;
+    }
+    f.flush()?;
+    f.sync()?;
+    Ok(recovery)
+}
+
+fn scan<R: Read>(r: R) -> io::Result<Scan> {
+    let mut r = io::BufReader::new(Source {
+        inner: r,
+        error: None,
+    });
+    let mut out = vec![0; 32 * 1024];
+    let mut pos = 0;
+    loop {
+        let start = pos;
+        let buf = r.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(Scan::Complete(start));
+        } else if start == 0 && !is_member_start(buf) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a gzip log"));
+        }
+        let torn = Scan::Torn {
+            start,
+            data_start: start,
+            flush_points: Vec::new(),
+        };
+
+        let mut parser = GzHeaderParser::new();
+        if parser.parse(&mut r).is_err() {
+            return match r.get_mut().error.take() {
+                Some(err) => Err(err),
+                None => Ok(torn),
+            };
+        }
+        let data_start = start + parser.len();
+        pos = data_start;
+
+        let mut data = Decompress::new(false);
+        let mut crc = Crc::new();
+        let mut flush_points = Vec::<FlushPoint>::new();
+        // The last four bytes of compressed data.
+        let mut window = !0u32;
+        let ended = loop {
+            let buf = r.fill_buf()?;
+            if buf.is_empty() {
+                break false;
+            }
+            // Stop right after the next flush marker, if any, to tell whether
+            // it ends a flush.
+            let mut next = window;
+            let len = buf
+                .iter()
+                .position(|&b| {
+                    next = next << 8 | b as u32;
+                    next == FLUSH_MARKER
+                })
+                .map_or(buf.len(), |i| i + 1);
+
+            let (before_in, before_out) = (data.total_in(), data.total_out());
+            let status = data.decompress(&buf[..len], &mut out, FlushDecompress::None);
+            let consumed = (data.total_in() - before_in) as usize;
+            let produced = (data.total_out() - before_out) as usize;
+            for &b in &buf[..consumed] {
+                window = window << 8 | b as u32;
+            }
+            r.consume(consumed);
+            pos += consumed as u64;
+            crc.update(&out[..produced]);
+
+            match status {
+                Ok(Status::StreamEnd) => break true,
+                // Some backends stop making progress on invalid data rather
+                // than failing.
+                Ok(_) if consumed == 0 && produced == 0 => break false,
+                Ok(_) => {}
+                Err(_) => break false,
+            }
+            let known = flush_points.last().map(|point| point.offset) == Some(pos);
+            if window == FLUSH_MARKER && produced < out.len() && !known {
+                flush_points.push(FlushPoint {
+                    offset: pos,
+                    crc: crc.sum(),
+                    amount: crc.amount(),
+                });
+            }
+        };
+
+        let torn = Scan::Torn {
+            start,
+            data_start,
+            flush_points,
+        };
+        if !ended {
+            return Ok(torn);
+        }
+        let mut trailer = [0; 8];
+        match r.read_exact(&mut trailer) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(torn),
+            Err(err) => return Err(err),
+            Ok(()) => {}
+        }
+        if check_trailer(&trailer, &crc, pos).is_err() {
+            return Ok(torn);
+        }
+        pos += trailer.len() as u64;
+    }
+}
+
+// Returns whether the data of the member starting at `data_start` makes up a
+// complete deflate stream once cut at `point` and closed.
+fn closes<R: Read + Seek>(r: &mut R, data_start: u64, point: &FlushPoint) -> io::Result<bool> {
+    r.seek(SeekFrom::Start(data_start))?;
+    let input = r.take(point.offset - data_start).chain(&CLOSING_BLOCK[..]);
+    let mut input = io::BufReader::new(input);
+    let mut data = Decompress::new(false);
+    let mut out = vec![0; 32 * 1024];
+    loop {
+        let buf = input.fill_buf()?;
+        let (before_in, before_out) = (data.total_in(), data.total_out());
+        let status = data.decompress(buf, &mut out, FlushDecompress::None);
+        let consumed = (data.total_in() - before_in) as usize;
+        let produced = (data.total_out() - before_out) as usize;
+        input.consume(consumed);
+        match status {
+            Ok(Status::StreamEnd) => {
+                let rest = input.fill_buf()?.len();
+                return Ok(rest == 0 && data.total_out() as u32 == point.amount);
+            }
+            Ok(_) if consumed == 0 && produced == 0 => return Ok(false),
+            Ok(_) => {}
+            Err(_) => return Ok(false),
+        }
+    }
+}
+
+// A reader keeping the I/O errors it runs into aside, so that they can be told
+// apart from errors due to a torn tail once returned by the header parser.
+struct Source<R> {
+    inner: R,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> Read for Source<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|err| {
+            let kind = err.kind();
+            self.error = Some(err);
+            kind.into()
+        })
+    }
+}
+
+/// A builder for gzip log writers, configuring the header of the members they
+/// append and how often they flush.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use std::io::Cursor;
+/// use flate2::gzlog::GzLogBuilder;
+/// use flate2::{Compression, GzBuilder};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut log = GzLogBuilder::new()
+///     .gz_header(GzBuilder::new().comment("service log"))
+///     .flush_interval(16 * 1024)
+///     .open(Cursor::new(Vec::new()), Compression::fast())?;
+/// log.write_all(b"started\n")?;
+/// log.flush()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct GzLogBuilder {
+    header: GzBuilder,
+    flush_interval: usize,
+    flush: FlushCompress,
+}
+
+impl Default for GzLogBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GzLogBuilder {
+    /// Creates a new builder, with a default gzip header, a flush point every
+    /// [`DEFAULT_FLUSH_INTERVAL`] bytes of data and sync flushes.
+    pub fn new() -> GzLogBuilder {
+        GzLogBuilder {
+            header: GzBuilder::new(),
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            flush: FlushCompress::Sync,
+        }
+    }
+
+    /// Configures the header of the member appended to the log.
+    pub fn gz_header(mut self, header: GzBuilder) -> GzLogBuilder {
+        self.header = header;
+        self
+    }
+
+    /// Configures the amount of uncompressed data between flush points, which
+    /// is at most what a crash loses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `flush_interval` is zero.
+    pub fn flush_interval(mut self, flush_interval: usize) -> GzLogBuilder {
+        assert!(flush_interval > 0, "the flush interval can't be zero");
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    /// Configures whether flush points are full flushes, after which the data
+    /// can be decompressed without what precedes it, rather than sync flushes,
+    /// which cost less compression ratio.
+    pub fn full_flush(mut self, full: bool) -> GzLogBuilder {
+        self.flush = if full {
+            FlushCompress::Full
+        } else {
+            FlushCompress::Sync
+        };
+        self
+    }
+
+    /// Opens the log in `f`, recovering its intact part as [`recover`] does,
+    /// and returns a writer appending a new member compressed with `level`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the recovery does.
+    pub fn open<F: LogFile>(self, mut f: F, level: Compression) -> io::Result<GzLogWriter<F>> {
+        let recovery = recover(&mut f)?;
+        Ok(GzLogWriter {
+            inner: self.header.write(f, level),
+            flush_interval: self.flush_interval,
+            flush: self.flush,
+            pending: 0,
+            recovery,
+        })
+    }
+}
+
+/// A writer appending data to a gzip log, in a way that survives crashes.
+///
+/// Data written to the log is compressed into a new member, with a flush point
+/// every [`flush_interval`](GzLogBuilder::flush_interval) bytes at which the
+/// compressor is flushed and the file synced to storage. Calling
+/// [`flush`](Write::flush) adds a flush point right away. Once finished or
+/// dropped, the member is complete and the file synced.
+///
+/// See the [module documentation](self) for how the log is recovered after a
+/// crash.
+#[derive(Debug)]
+pub struct GzLogWriter<F: LogFile> {
+    inner: GzEncoder<F>,
+    flush_interval: usize,
+    flush: FlushCompress,
+    // The amount of data written since the last flush point.
+    pending: usize,
+    recovery: Recovery,
+}
+
+impl<F: LogFile> GzLogWriter<F> {
+    /// Opens the log in `f` with the default configuration of a
+    /// [`GzLogBuilder`], compressing new data with `level`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the recovery of the existing log does, see [`recover`].
+    pub fn open(f: F, level: Compression) -> io::Result<GzLogWriter<F>> {
+        GzLogBuilder::new().open(f, level)
+    }
+
+    /// Returns what was done to the existing log when opening it.
+    pub fn recovery(&self) -> &Recovery {
+        &self.recovery
+    }
+
+    /// Acquires a reference to the underlying file.
+    pub fn get_ref(&self) -> &F {
+        self.inner.get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying file.
+    ///
+    /// Note that mutation of the file may result in surprising results if this
+    /// writer is continued to be used.
+    pub fn get_mut(&mut self) -> &mut F {
+        self.inner.get_mut()
+    }
+
+    /// Attempt to finish the member, writing out its trailer and syncing the
+    /// file.
+    ///
+    /// # Panics
+    ///
+    /// Attempts to write data to this log may result in a panic after this
+    /// function is called.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete the member, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.inner.try_finish()?;
+        let f = self.inner.get_mut();
+        f.flush()?;
+        f.sync()
+    }
+
+    /// Finishes the member, returning the underlying file once it is synced.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete the member, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn finish(mut self) -> io::Result<F> {
+        self.try_finish()?;
+        Ok(self.inner.take_inner())
+    }
+
+    fn flush_point(&mut self) -> io::Result<()> {
+        if self.pending > 0 {
+            self.inner.flush_with(self.flush)?;
+            self.pending = 0;
+        }
+        let f = self.inner.get_mut();
+        f.flush()?;
+        f.sync()
+    }
+}
+
+impl<F: LogFile> Write for GzLogWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pending == self.flush_interval && !buf.is_empty() {
+            self.flush_point()?;
+        }
+        let n = cmp::min(buf.len(), self.flush_interval - self.pending);
+        let n = self.inner.write(&buf[..n])?;
+        self.pending += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_point()
+    }
+}
+
+impl<F: LogFile> Drop for GzLogWriter<F> {
+    fn drop(&mut self) {
+        if self.inner.is_present() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::io::Cursor;
+
+    use super::{recover, GzLogBuilder, GzLogWriter};
+    use crate::read::MultiGzDecoder;
+    use crate::Compression;
+
+    fn decode(file: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        if file.is_empty() {
+            return out;
+        }
+        MultiGzDecoder::new(file).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn append() {
+        let mut file = Cursor::new(Vec::new());
+        for part in [&b"Hello "[..], b"", b"World"] {
+            let mut log = GzLogWriter::open(file, Compression::default()).unwrap();
+            assert!(log.recovery().is_clean());
+            log.write_all(part).unwrap();
+            file = log.finish().unwrap();
+        }
+        assert_eq!(decode(file.get_ref()), b"Hello World");
+
+        // Dropping the writer finishes the member as well.
+        let mut log = GzLogWriter::open(&mut file, Compression::default()).unwrap();
+        log.write_all(b"!").unwrap();
+        drop(log);
+        assert_eq!(decode(file.get_ref()), b"Hello World!");
+    }
+
+    #[test]
+    fn torn_tails() {
+        let data = crate::test_data(60_000);
+        for full in [false, true] {
+            // Take snapshots of the file as a crash would leave it, after each
+            // flush point and in between.
+            let mut log = GzLogBuilder::new()
+                .flush_interval(10_000)
+                .full_flush(full)
+                .open(Cursor::new(Vec::new()), Compression::default())
+                .unwrap();
+            let mut flushed = vec![(0, 0)];
+            for chunk in data.chunks(3_000) {
+                log.write_all(chunk).unwrap();
+                log.flush().unwrap();
+                let written = flushed.last().unwrap().1 + chunk.len();
+                flushed.push((log.get_ref().get_ref().len(), written));
+            }
+            let file = log.get_ref().get_ref().clone();
+            drop(log);
+
+            for len in (0..file.len())
+                .step_by(97)
+                .chain(flushed.iter().map(|f| f.0))
+            {
+                let mut torn = Cursor::new(file[..len].to_vec());
+                let recovery = recover(&mut torn).unwrap();
+                assert_eq!(
+                    recovery.truncated_len(),
+                    (len as u64) - recovery.intact_len()
+                );
+                let out = decode(torn.get_ref());
+                let (_, expected) = flushed.iter().rev().find(|f| f.0 <= len).unwrap();
+                assert_eq!(out, data[..*expected]);
+
+                // Recovering again finds the log intact, and it can be
+                // appended to.
+                let mut log = GzLogWriter::open(torn, Compression::default()).unwrap();
+                assert!(log.recovery().is_clean());
+                log.write_all(b"end").unwrap();
+                let torn = log.finish().unwrap();
+                assert_eq!(
+                    decode(torn.get_ref()),
+                    [&data[..*expected], b"end"].concat()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn garbage() {
+        let mut log = GzLogWriter::open(Cursor::new(Vec::new()), Compression::default()).unwrap();
+        log.write_all(b"Hello World").unwrap();
+        let mut file = log.finish().unwrap().into_inner();
+        let len = file.len() as u64;
+
+        for tail in [&[0; 512][..], b"\x1f\x8b\x08", b"garbage"] {
+            let mut torn = Cursor::new([&file[..], tail].concat());
+            let recovery = recover(&mut torn).unwrap();
+            assert_eq!(recovery.intact_len(), len);
+            assert_eq!(recovery.truncated_len(), tail.len() as u64);
+            assert!(!recovery.closed_member());
+            assert_eq!(torn.into_inner(), file);
+        }
+
+        // A corrupt trailer makes the member torn.
+        let n = file.len();
+        file[n - 8] ^= 1;
+        let mut torn = Cursor::new(file.clone());
+        let recovery = recover(&mut torn).unwrap();
+        assert_eq!(recovery.intact_len(), 0);
+        assert!(torn.get_ref().is_empty());
+
+        let mut other = Cursor::new(b"not a log".to_vec());
+        assert!(recover(&mut other).is_err());
+        assert_eq!(other.into_inner(), b"not a log");
+    }
+}
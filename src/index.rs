@@ -0,0 +1,1091 @@
+//! Random access into gzip and zlib streams through a checkpoint index.
+//!
+//! Deflate streams can normally only be decoded from their start. An [`Index`]
+//! records checkpoints along a stream, each made of the bit offset at which a
+//! deflate block starts, the matching offset in the uncompressed data and the
+//! 32 KiB of uncompressed data preceding it. Decoding can then resume from
+//! any checkpoint, so that reaching an arbitrary position only requires
+//! decompressing the data between it and the checkpoint before it.
+//!
+//! Indexes are built in a single pass over the compressed data by an
+//! [`IndexBuilder`]. They can be saved alongside the compressed file with
+//! [`Index::write_to`], and are used by [`SeekableGzDecoder`] and
+//! [`SeekableZlibDecoder`] to implement [`Seek`].
+//!
+//! This is the approach of the `zran.c` example shipped with zlib.
+//!
+//! # Examples
+//!
+//! ```
+//! # fn main() -> std::io::Result<()> {
+//! use std::io::prelude::*;
+//! use std::io::{Cursor, SeekFrom};
+//! use flate2::index::{IndexBuilder, SeekableGzDecoder};
+//! use flate2::write::GzEncoder;
+//! use flate2::Compression;
+//!
+//! let data: Vec<u8> = (0..1_000_000u32).flat_map(|i| i.to_le_bytes()).collect();
+//! let mut e = GzEncoder::new(Vec::new(), Compression::default());
+//! e.write_all(&data)?;
+//! let compressed = e.finish()?;
+//!
+//! let index = IndexBuilder::new().span(256 * 1024).build_gz(&compressed[..])?;
+//! let mut d = SeekableGzDecoder::new(Cursor::new(compressed), index);
+//! d.seek(SeekFrom::Start(3_000_000))?;
+//! let mut buf = [0; 4];
+//! d.read_exact(&mut buf)?;
+//! assert_eq!(u32::from_le_bytes(buf), 750_000);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp;
+use std::convert::TryInto;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use crate::adler::Adler32;
+use crate::gz::{check_trailer, GzBuilder, GzHeaderParser};
+use crate::{Backend, Compression, Crc, Decompress, Error, FlushDecompress, Status};
+
+/// The size of the deflate window, which is all the history a checkpoint
+/// needs to keep.
+pub(crate) const WINDOW_SIZE: usize = 32 * 1024;
+
+const READ_SIZE: usize = 32 * 1024;
+
+const MAGIC: &[u8; 8] = b"FLATE2IX";
+const VERSION: u8 = 1;
+
+// How many flush points fit in the extra field of an embedded index member.
+const EMBEDDED_ENTRIES: usize = 4000;
+// The `IT` subfield and what follows it in the last embedded index member.
+const EMBEDDED_TAIL: usize = 20 + EMPTY_MEMBER_END.len();
+// An empty final deflate block and the trailer of an empty gzip member.
+const EMPTY_MEMBER_END: [u8; 10] = [3, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// The container format of an indexed stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Gzip,
+    Zlib,
+}
+
+/// A point from which decoding can resume.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    bits: u64,
+    out: u64,
+    window: Vec<u8>,
+}
+
+impl Checkpoint {
+    pub(crate) fn new(bits: u64, out: u64, window: Vec<u8>) -> Checkpoint {
+        debug_assert!(window.len() <= WINDOW_SIZE);
+        Checkpoint { bits, out, window }
+    }
+
+    /// The offset, in bits, of the start of the deflate block in the
+    /// compressed stream.
+    pub fn compressed_bit_offset(&self) -> u64 {
+        self.bits
+    }
+
+    /// The offset in the uncompressed data corresponding to the start of the
+    /// block.
+    pub fn uncompressed_offset(&self) -> u64 {
+        self.out
+    }
+
+    /// The uncompressed data preceding the block which it may refer to, at
+    /// most 32 KiB of it.
+    ///
+    /// This is empty at the start of a stream, or when the compressor was
+    /// flushed with [`FlushCompress::Full`](crate::FlushCompress::Full) right
+    /// before the block.
+    pub fn window(&self) -> &[u8] {
+        &self.window
+    }
+}
+
+/// A list of checkpoints into a gzip or zlib stream, allowing decoding to
+/// start close to any position in its uncompressed data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Index {
+    format: Format,
+    checkpoints: Vec<Checkpoint>,
+    len: u64,
+}
+
+impl Index {
+    pub(crate) fn new(format: Format, checkpoints: Vec<Checkpoint>, len: u64) -> Index {
+        debug_assert_eq!(checkpoints.first().map(|c| c.out), Some(0));
+        Index {
+            format,
+            checkpoints,
+            len,
+        }
+    }
+
+    /// The checkpoints of this index, in stream order.
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// The total size of the uncompressed data of the stream.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.len
+    }
+
+    // The last checkpoint at or before `pos`.
+    fn checkpoint_before(&self, pos: u64) -> &Checkpoint {
+        let i = self.checkpoints.partition_point(|c| c.out <= pos);
+        &self.checkpoints[i.saturating_sub(1)]
+    }
+
+    /// Serializes this index to `w`, typically a sidecar file stored next to
+    /// the compressed file.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error writing to `w` is returned.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let format = match self.format {
+            Format::Gzip => 1,
+            Format::Zlib => 2,
+        };
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION, format])?;
+        w.write_all(&self.len.to_le_bytes())?;
+        w.write_all(&(self.checkpoints.len() as u64).to_le_bytes())?;
+        for c in &self.checkpoints {
+            w.write_all(&c.bits.to_le_bytes())?;
+            w.write_all(&c.out.to_le_bytes())?;
+            w.write_all(&(c.window.len() as u32).to_le_bytes())?;
+            w.write_all(&c.window)?;
+        }
+        Ok(())
+    }
+
+    /// Deserializes an index previously written with
+    /// [`write_to`](Index::write_to).
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error reading from `r` is returned, and data which isn't a
+    /// valid index results in an error of kind `InvalidData`.
+    pub fn read_from<R: Read>(mut r: R) -> io::Result<Index> {
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        let mut version = [0; 2];
+        r.read_exact(&mut version)?;
+        let format = match version {
+            [VERSION, 1] if magic == *MAGIC => Format::Gzip,
+            [VERSION, 2] if magic == *MAGIC => Format::Zlib,
+            _ => return Err(invalid_index()),
+        };
+        let len = read_u64(&mut r)?;
+        let count = read_u64(&mut r)?;
+
+        let mut checkpoints: Vec<Checkpoint> = Vec::new();
+        for _ in 0..count {
+            let bits = read_u64(&mut r)?;
+            let out = read_u64(&mut r)?;
+            let mut window_len = [0; 4];
+            r.read_exact(&mut window_len)?;
+            let window_len = u32::from_le_bytes(window_len) as usize;
+            if window_len > WINDOW_SIZE {
+                return Err(invalid_index());
+            }
+            let mut window = vec![0; window_len];
+            r.read_exact(&mut window)?;
+            checkpoints.push(Checkpoint::new(bits, out, window));
+        }
+        Index::validated(format, checkpoints, len)
+    }
+
+    /// Reads the index embedded at the end of a gzip file written by a
+    /// [`SeekableGzEncoder`](crate::write::SeekableGzEncoder).
+    ///
+    /// The index is stored in the extra field of empty gzip members appended
+    /// to the file, which decoders unaware of it skip over as they produce no
+    /// data. Each member carries an `IX` subfield listing the compressed and
+    /// uncompressed offsets of flush points, as pairs of little endian 64 bit
+    /// integers. The extra field of the last member ends with an `IT`
+    /// subfield holding the total size of these members followed by the size
+    /// of the uncompressed data, so that they can be located from the end of
+    /// the file.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error reading from `r` is returned, and a file without an
+    /// embedded index results in an error of kind `InvalidData`.
+    pub fn read_embedded<R: Read + Seek>(mut r: R) -> io::Result<Index> {
+        let end = r.seek(SeekFrom::End(0))?;
+        if end < EMBEDDED_TAIL as u64 {
+            return Err(invalid_index());
+        }
+        r.seek(SeekFrom::End(-(EMBEDDED_TAIL as i64)))?;
+        let mut tail = [0; EMBEDDED_TAIL];
+        r.read_exact(&mut tail)?;
+        if tail[..4] != *b"IT\x10\x00" || tail[20..] != EMPTY_MEMBER_END {
+            return Err(invalid_index());
+        }
+        let size = u64::from_le_bytes(tail[4..12].try_into().unwrap());
+        let len = u64::from_le_bytes(tail[12..20].try_into().unwrap());
+        if size > end {
+            return Err(invalid_index());
+        }
+
+        r.seek(SeekFrom::Start(end - size))?;
+        let mut members = r.take(size);
+        let mut checkpoints = Vec::new();
+        while members.limit() > 0 {
+            let mut parser = GzHeaderParser::new();
+            parser.parse(&mut members)?;
+            let mut extra = parser.header().and_then(|h| h.extra()).unwrap_or(&[]);
+            while extra.len() >= 4 {
+                let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+                let data = extra.get(4..4 + len).ok_or_else(invalid_index)?;
+                if extra[..2] == *b"IX" {
+                    let entries = data.chunks_exact(16);
+                    if !entries.remainder().is_empty() {
+                        return Err(invalid_index());
+                    }
+                    for entry in entries {
+                        let compressed = u64::from_le_bytes(entry[..8].try_into().unwrap());
+                        let out = u64::from_le_bytes(entry[8..].try_into().unwrap());
+                        let bits = compressed.checked_mul(8).ok_or_else(invalid_index)?;
+                        checkpoints.push(Checkpoint::new(bits, out, Vec::new()));
+                    }
+                }
+                extra = &extra[4 + len..];
+            }
+            let mut end = [0; 10];
+            members.read_exact(&mut end)?;
+            if end != EMPTY_MEMBER_END {
+                return Err(invalid_index());
+            }
+        }
+        Index::validated(Format::Gzip, checkpoints, len)
+    }
+
+    /// Encodes the checkpoints, which must be byte aligned and without
+    /// window, as the empty gzip members read by `read_embedded`.
+    pub(crate) fn to_embedded(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let chunks = self.checkpoints.chunks(EMBEDDED_ENTRIES);
+        let last = chunks.len() - 1;
+        for (i, chunk) in chunks.enumerate() {
+            let mut extra = b"IX".to_vec();
+            extra.extend_from_slice(&(chunk.len() as u16 * 16).to_le_bytes());
+            for c in chunk {
+                debug_assert!(c.bits % 8 == 0 && c.window.is_empty());
+                extra.extend_from_slice(&(c.bits / 8).to_le_bytes());
+                extra.extend_from_slice(&c.out.to_le_bytes());
+            }
+            if i == last {
+                // The total size is patched in below.
+                extra.extend_from_slice(b"IT\x10\x00");
+                extra.extend_from_slice(&[0; 8]);
+                extra.extend_from_slice(&self.len.to_le_bytes());
+            }
+            out.extend(
+                GzBuilder::new()
+                    .extra(extra)
+                    .into_header(Compression::none()),
+            );
+            out.extend_from_slice(&EMPTY_MEMBER_END);
+        }
+        let size = (out.len() as u64).to_le_bytes();
+        let at = out.len() - EMBEDDED_TAIL + 4;
+        out[at..at + 8].copy_from_slice(&size);
+        out
+    }
+
+    // Checks that `checkpoints` are in order and cover `len` bytes of
+    // uncompressed data from its start.
+    fn validated(format: Format, checkpoints: Vec<Checkpoint>, len: u64) -> io::Result<Index> {
+        let ordered = checkpoints
+            .windows(2)
+            .all(|w| w[0].bits < w[1].bits && w[0].out < w[1].out);
+        match (checkpoints.first(), checkpoints.last()) {
+            (Some(first), Some(last)) if ordered && first.out == 0 && last.out <= len => {
+                Ok(Index::new(format, checkpoints, len))
+            }
+            _ => Err(invalid_index()),
+        }
+    }
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn invalid_index() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid index")
+}
+
+/// Builds an [`Index`] for a gzip or zlib stream by decompressing it once.
+///
+/// Checkpoints are recorded at the start of the first deflate block beginning
+/// after every `span` bytes of uncompressed data. Smaller spans make seeking
+/// cheaper, at the expense of 32 KiB of window per checkpoint.
+#[derive(Debug)]
+pub struct IndexBuilder {
+    span: u64,
+    backend: Option<Backend>,
+}
+
+impl Default for IndexBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IndexBuilder {
+    /// Create a new builder recording a checkpoint every MiB of uncompressed
+    /// data.
+    pub fn new() -> IndexBuilder {
+        IndexBuilder {
+            span: 1024 * 1024,
+            backend: None,
+        }
+    }
+
+    /// Configure the minimum amount of uncompressed data between two
+    /// checkpoints.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `span` is zero.
+    pub fn span(mut self, span: u64) -> IndexBuilder {
+        assert!(span > 0, "the span can't be zero");
+        self.span = span;
+        self
+    }
+
+    /// Sets the built-in [`Backend`] decompressing the stream, rather than the
+    /// default one at the time the index is built.
+    pub fn backend(mut self, backend: Backend) -> IndexBuilder {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Reads the gzip stream from `r` to the end, returning its index.
+    ///
+    /// All the members of multi-member streams are indexed.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error reading from `r` is returned, as well as errors for
+    /// invalid gzip data, including checksum mismatches.
+    pub fn build_gz<R: Read>(&self, r: R) -> io::Result<Index> {
+        self.build(r, Format::Gzip)
+    }
+
+    /// Reads the zlib stream from `r` to its end, returning its index.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error reading from `r` is returned, as well as errors for
+    /// invalid zlib data, including checksum mismatches.
+    pub fn build_zlib<R: Read>(&self, r: R) -> io::Result<Index> {
+        self.build(r, Format::Zlib)
+    }
+
+    fn build<R: Read>(&self, r: R, format: Format) -> io::Result<Index> {
+        let mut input = Input::new(r);
+        let mut data = Decompress::new_with_backend(false, self.backend.unwrap_or_default());
+        let mut out = vec![0; READ_SIZE];
+        let mut history = Vec::with_capacity(2 * WINDOW_SIZE + READ_SIZE);
+        let mut checkpoints = Vec::new();
+        let mut len = 0;
+        let mut last = 0;
+
+        loop {
+            let start = input.offset;
+            match format {
+                Format::Gzip => GzHeaderParser::new()
+                    .parse(&mut input)
+                    .map_err(|err| Error::rebase(err, start))?,
+                Format::Zlib => read_zlib_header(&mut input)?,
+            }
+            data.reset(false);
+            history.clear();
+            let mut crc = Crc::new();
+            let mut adler = Adler32::new();
+
+            // The start of every member can serve as a checkpoint without
+            // any window.
+            if checkpoints.is_empty() || len - last >= self.span {
+                checkpoints.push(Checkpoint::new(input.offset * 8, len, Vec::new()));
+                last = len;
+            }
+
+            loop {
+                let buf = input.fill_buf()?;
+                let eof = buf.is_empty();
+                let before_in = data.total_in();
+                let before_out = data.total_out();
+                let ret = data.decompress_block(buf, &mut out);
+                let consumed = (data.total_in() - before_in) as usize;
+                let produced = (data.total_out() - before_out) as usize;
+                input.consume(consumed);
+                let (status, boundary) = ret.map_err(|err| Error::decompress(err, input.offset))?;
+
+                let chunk = &out[..produced];
+                match format {
+                    Format::Gzip => crc.update(chunk),
+                    Format::Zlib => adler.update(chunk),
+                }
+                history.extend_from_slice(chunk);
+                if history.len() > 2 * WINDOW_SIZE {
+                    history.drain(..history.len() - WINDOW_SIZE);
+                }
+                len += produced as u64;
+
+                if status == Status::StreamEnd {
+                    break;
+                }
+                if let Some((bits, false)) = boundary {
+                    if len - last >= self.span {
+                        let window = &history[history.len().saturating_sub(WINDOW_SIZE)..];
+                        let offset = input.offset * 8 - u64::from(bits);
+                        checkpoints.push(Checkpoint::new(offset, len, window.to_vec()));
+                        last = len;
+                    }
+                }
+                if eof && consumed == 0 && produced == 0 {
+                    return Err(Error::Truncated {
+                        offset: input.offset,
+                    }
+                    .into());
+                }
+            }
+
+            let trailer_start = input.offset;
+            match format {
+                Format::Gzip => {
+                    let mut trailer = [0; 8];
+                    input.read_exact(&mut trailer)?;
+                    check_trailer(&trailer, &crc, trailer_start)?;
+                    if input.fill_buf()?.is_empty() {
+                        break;
+                    }
+                }
+                Format::Zlib => {
+                    let mut trailer = [0; 4];
+                    input.read_exact(&mut trailer)?;
+                    if trailer != adler.sum().to_be_bytes() {
+                        let offset = trailer_start;
+                        return Err(Error::DataCrcMismatch { offset }.into());
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok(Index::new(format, checkpoints, len))
+    }
+}
+
+fn read_zlib_header<R: Read>(r: &mut R) -> io::Result<()> {
+    let mut header = [0; 2];
+    r.read_exact(&mut header)?;
+    if !crate::zlib::is_header(header) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid zlib header",
+        ));
+    }
+    if header[1] & 0x20 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "zlib streams with a preset dictionary can't be indexed",
+        ));
+    }
+    Ok(())
+}
+
+/// A buffered reader keeping track of how much of the underlying stream was
+/// consumed.
+#[derive(Debug)]
+pub(crate) struct Input<R> {
+    obj: R,
+    buf: Vec<u8>,
+    pos: usize,
+    pub(crate) offset: u64,
+}
+
+impl<R> Input<R> {
+    pub(crate) fn new(obj: R) -> Input<R> {
+        Input {
+            obj,
+            buf: Vec::new(),
+            pos: 0,
+            offset: 0,
+        }
+    }
+
+    // Replaces the buffered data with `data`, to be read before the rest of
+    // the underlying stream.
+    fn reset(&mut self, data: Vec<u8>) {
+        self.buf = data;
+        self.pos = 0;
+    }
+}
+
+impl<R: Read> Read for Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = cmp::min(available.len(), buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for Input<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.buf.resize(READ_SIZE, 0);
+            let n = loop {
+                match self.obj.read(&mut self.buf) {
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    res => break res,
+                }
+            };
+            self.buf.truncate(*n.as_ref().unwrap_or(&0));
+            self.pos = 0;
+            n?;
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+        self.offset += amt as u64;
+    }
+}
+
+/// Writes the LSB-first bit stream of deflate.
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    pub(crate) fn put(&mut self, value: u32, bits: u32) {
+        self.acc |= value << self.bits;
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    // Huffman codes are stored starting from their most significant bit.
+    fn put_code(&mut self, code: u32, bits: u32) {
+        self.put(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    // A non-final block using the fixed Huffman codes, holding `literals`.
+    pub(crate) fn fixed_block(&mut self, literals: &[u8]) {
+        self.put(0, 1);
+        self.put(1, 2);
+        for &lit in literals {
+            match literal_bits(lit) {
+                8 => self.put_code(0x30 + u32::from(lit), 8),
+                _ => self.put_code(0x190 + u32::from(lit) - 144, 9),
+            }
+        }
+        self.put_code(0, 7);
+    }
+
+    // Returns the bytes written, padding the last one with zero bits.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn literal_bits(lit: u8) -> u32 {
+    if lit < 144 {
+        8
+    } else {
+        9
+    }
+}
+
+// Builds the input priming a raw inflater to resume at a checkpoint starting
+// at bit `bits` of `byte`, returning it along with the amount of output it
+// produces before the data of the checkpoint.
+//
+// The backends can't be handed a window and a partial byte directly, so this
+// emits deflate blocks outputting the window and ending `bits` bits into a
+// byte, which is then completed with the remaining bits of `byte`. The data
+// following the checkpoint is then byte aligned as in the original stream.
+fn prime(window: &[u8], bits: u8, byte: u8) -> (Vec<u8>, usize) {
+    let bits = u32::from(bits);
+    let window_bits = match window {
+        [] => 0,
+        _ => 10 + window.iter().map(|&lit| literal_bits(lit)).sum::<u32>(),
+    };
+    // A block with a single 9 bit literal takes 19 bits and fixes the parity,
+    // while empty blocks take 10 bits each to make up for the rest. The
+    // literal goes before the window so that it never ends up within reach of
+    // the data following the checkpoint.
+    let junk = window_bits % 2 != bits % 2;
+    let total = window_bits + if junk { 19 } else { 0 };
+    let empty_blocks = (bits + 8 - total % 8) % 8 / 2;
+
+    let mut w = BitWriter::default();
+    if junk {
+        w.fixed_block(&[0xff]);
+    }
+    if !window.is_empty() {
+        w.fixed_block(window);
+    }
+    for _ in 0..empty_blocks {
+        w.fixed_block(&[]);
+    }
+    debug_assert_eq!(w.bits, bits);
+    if bits > 0 {
+        w.out.push(w.acc as u8 | byte & (0xff << bits));
+    }
+    (w.out, usize::from(junk) + window.len())
+}
+
+/// Where a seekable decoder is in the stream.
+#[derive(Debug)]
+enum State {
+    /// Decoding needs to restart from the checkpoint before the position.
+    Seek,
+    /// Decoding the body of a deflate stream.
+    Body,
+    /// Past the end of a gzip member, expecting another one.
+    Trailer,
+    /// Past the end of the stream.
+    Done,
+}
+
+#[derive(Debug)]
+struct Seekable<R> {
+    input: Input<R>,
+    index: Index,
+    format: Format,
+    data: Decompress,
+    state: State,
+    pos: u64,
+    skip: u64,
+    scratch: Vec<u8>,
+}
+
+impl<R> Seekable<R> {
+    fn new(r: R, index: Index, format: Format) -> Seekable<R> {
+        Seekable {
+            input: Input::new(r),
+            index,
+            format,
+            data: Decompress::new(false),
+            state: State::Seek,
+            pos: 0,
+            skip: 0,
+            scratch: Vec::new(),
+        }
+    }
+
+    fn seek_to(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.index.len.checked_add_signed(n),
+        };
+        let target = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        // Moving forward within the data following the current checkpoint
+        // is cheaper than starting over from it.
+        let resume = match self.state {
+            State::Body | State::Trailer => {
+                target >= self.pos && self.index.checkpoint_before(target).out <= self.pos
+            }
+            State::Done => target >= self.pos,
+            State::Seek => false,
+        };
+        if resume {
+            self.skip += target - self.pos;
+        } else {
+            self.state = State::Seek;
+        }
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+impl<R: Read + Seek> Seekable<R> {
+    fn restart(&mut self) -> io::Result<()> {
+        if self.format != self.index.format {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the index was built for another format",
+            ));
+        }
+        let checkpoint = self.index.checkpoint_before(self.pos);
+        let bits = (checkpoint.bits % 8) as u8;
+        self.input.obj.seek(SeekFrom::Start(checkpoint.bits / 8))?;
+        let mut byte = [0];
+        if bits > 0 {
+            self.input.obj.read_exact(&mut byte)?;
+        }
+        let (prefix, skip) = prime(&checkpoint.window, bits, byte[0]);
+        self.skip = skip as u64 + (self.pos - checkpoint.out);
+        self.input.reset(prefix);
+        self.data.reset(false);
+        self.state = State::Body;
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Seek => self.restart()?,
+                State::Body => {}
+                State::Trailer => {
+                    let mut trailer = [0; 8];
+                    self.input.read_exact(&mut trailer)?;
+                    // The checksums can't be verified as decoding usually
+                    // starts in the middle of a member.
+                    if self.pos.saturating_sub(self.skip) >= self.index.len {
+                        self.state = State::Done;
+                    } else {
+                        let start = self.input.offset;
+                        GzHeaderParser::new()
+                            .parse(&mut self.input)
+                            .map_err(|err| Error::rebase(err, start))?;
+                        self.data.reset(false);
+                        self.state = State::Body;
+                    }
+                    continue;
+                }
+                State::Done => return Ok(0),
+            }
+
+            let skipping = self.skip > 0;
+            let out = if skipping {
+                self.scratch.resize(READ_SIZE, 0);
+                let n = cmp::min(self.skip, READ_SIZE as u64) as usize;
+                &mut self.scratch[..n]
+            } else if buf.is_empty() {
+                return Ok(0);
+            } else {
+                &mut *buf
+            };
+
+            let input = self.input.fill_buf()?;
+            let eof = input.is_empty();
+            let before_in = self.data.total_in();
+            let before_out = self.data.total_out();
+            let flush = if eof {
+                FlushDecompress::Finish
+            } else {
+                FlushDecompress::None
+            };
+            let ret = self.data.decompress(input, out, flush);
+            let consumed = (self.data.total_in() - before_in) as usize;
+            let produced = (self.data.total_out() - before_out) as usize;
+            self.input.consume(consumed);
+            let offset = self.input.offset;
+            let status = ret.map_err(|err| Error::decompress(err, offset))?;
+
+            if skipping {
+                self.skip -= produced as u64;
+            } else {
+                self.pos += produced as u64;
+            }
+            if status == Status::StreamEnd {
+                self.state = match self.format {
+                    Format::Gzip => State::Trailer,
+                    Format::Zlib => State::Done,
+                };
+            } else if eof && consumed == 0 && produced == 0 {
+                return Err(Error::Truncated { offset }.into());
+            }
+            if !skipping && produced > 0 {
+                return Ok(produced);
+            }
+        }
+    }
+}
+
+macro_rules! seekable_decoder {
+    ($(#[$attr:meta])* $name:ident, $format:expr) => {
+        $(#[$attr])*
+        #[derive(Debug)]
+        pub struct $name<R> {
+            inner: Seekable<R>,
+        }
+
+        impl<R> $name<R> {
+            /// Creates a new decoder for the stream read from `r`, seeking
+            /// with the help of `index`, which must have been built for the
+            /// same stream.
+            pub fn new(r: R, index: Index) -> $name<R> {
+                $name {
+                    inner: Seekable::new(r, index, $format),
+                }
+            }
+
+            /// Returns the index used by this decoder.
+            pub fn index(&self) -> &Index {
+                &self.inner.index
+            }
+
+            /// Acquires a reference to the underlying reader.
+            pub fn get_ref(&self) -> &R {
+                &self.inner.input.obj
+            }
+
+            /// Acquires a mutable reference to the underlying reader.
+            ///
+            /// Note that mutation of the reader may result in surprising
+            /// results if this decoder is continued to be used.
+            pub fn get_mut(&mut self) -> &mut R {
+                &mut self.inner.input.obj
+            }
+
+            /// Consumes this decoder, returning the underlying reader.
+            pub fn into_inner(self) -> R {
+                self.inner.input.obj
+            }
+        }
+
+        impl<R: Read + Seek> Read for $name<R> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.inner.read(buf)
+            }
+        }
+
+        impl<R: Read + Seek> Seek for $name<R> {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                self.inner.seek_to(pos)
+            }
+
+            fn stream_position(&mut self) -> io::Result<u64> {
+                Ok(self.inner.pos)
+            }
+        }
+    };
+}
+
+seekable_decoder!(
+    /// A gzip decoder supporting random access through an [`Index`].
+    ///
+    /// This decoder implements [`Seek`] in terms of positions in the
+    /// uncompressed data. Seeking restarts decompression from the checkpoint
+    /// preceding the new position, unless it lies a little ahead of the
+    /// current position, and is only performed on the next read.
+    ///
+    /// All the members of multi-member streams are read. Since decoding
+    /// usually starts in the middle of a member, checksums aren't verified;
+    /// they are when building the index.
+    SeekableGzDecoder,
+    Format::Gzip
+);
+
+seekable_decoder!(
+    /// A zlib decoder supporting random access through an [`Index`].
+    ///
+    /// This decoder implements [`Seek`] in terms of positions in the
+    /// uncompressed data. Seeking restarts decompression from the checkpoint
+    /// preceding the new position, unless it lies a little ahead of the
+    /// current position, and is only performed on the next read.
+    ///
+    /// Since decoding usually starts in the middle of the stream, its checksum
+    /// isn't verified; it is when building the index.
+    SeekableZlibDecoder,
+    Format::Zlib
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Compression;
+    use rand::{thread_rng, Rng};
+    use std::io::Cursor;
+
+    #[test]
+    fn prime_aligns_every_offset() {
+        let mut rng = thread_rng();
+        let window: Vec<u8> = (0..1000).map(|_| rng.gen()).collect();
+        for bits in 0..8 {
+            for window in [&[][..], &window[..1], &window[..]] {
+                // A final empty stored block right where the checkpoint is.
+                let (mut prefix, skip) = prime(window, bits, 1 << bits);
+                if bits == 0 {
+                    prefix.push(1);
+                } else if bits > 5 {
+                    prefix.push(0);
+                }
+                prefix.extend_from_slice(&[0, 0, 0xff, 0xff]);
+                let mut out = Vec::with_capacity(skip + 1);
+                let status = Decompress::new(false)
+                    .decompress_vec(&prefix, &mut out, FlushDecompress::Finish)
+                    .unwrap();
+                assert_eq!(status, Status::StreamEnd);
+                assert_eq!(out.len(), skip);
+                assert!(out.ends_with(window));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_index() {
+        assert!(Index::read_from(&b"FLATE2IX\x01\x03"[..]).is_err());
+        assert!(Index::read_from(&b"nope"[..]).is_err());
+    }
+
+    // Seeks the decoder around, comparing with the original data.
+    fn check_seeks<D: Read + Seek>(d: &mut D, data: &[u8]) {
+        let mut rng = thread_rng();
+        let mut all = Vec::new();
+        d.read_to_end(&mut all).unwrap();
+        assert!(all == data);
+
+        for _ in 0..50 {
+            let pos = rng.gen_range(0..data.len());
+            let len = cmp::min(rng.gen_range(0..5000), data.len() - pos);
+            assert_eq!(d.seek(SeekFrom::Start(pos as u64)).unwrap(), pos as u64);
+            let mut buf = vec![0; len];
+            d.read_exact(&mut buf).unwrap();
+            assert!(buf == data[pos..pos + len]);
+        }
+
+        d.seek(SeekFrom::End(-10)).unwrap();
+        let mut tail = Vec::new();
+        d.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, data[data.len() - 10..]);
+        d.seek(SeekFrom::Current(-20)).unwrap();
+        d.read_exact(&mut tail).unwrap();
+        assert_eq!(tail, data[data.len() - 20..data.len() - 10]);
+        assert!(d.seek(SeekFrom::Current(-(data.len() as i64))).is_err());
+    }
+
+    // Indexes of streams ending with empty stored blocks at byte boundaries,
+    // which every backend can produce, so that the decoder gets tested with
+    // all of them.
+    #[test]
+    fn flush_points() {
+        let data = crate::test_data(1_500_000);
+        let mut e = crate::write::GzEncoder::new(Vec::new(), Compression::default());
+        let mut checkpoints = vec![Checkpoint::new(10 * 8, 0, Vec::new())];
+        for (i, chunk) in data.chunks(100_000).enumerate() {
+            e.write_all(chunk).unwrap();
+            e.flush().unwrap();
+            let out = (i * 100_000 + chunk.len()) as u64;
+            let start = out.saturating_sub(WINDOW_SIZE as u64) as usize;
+            let window = data[start..out as usize].to_vec();
+            let bits = e.get_ref().len() as u64 * 8;
+            checkpoints.push(Checkpoint::new(bits, out, window));
+        }
+        let compressed = e.finish().unwrap();
+        let index = Index::new(Format::Gzip, checkpoints, data.len() as u64);
+
+        let mut d = SeekableGzDecoder::new(Cursor::new(compressed), index);
+        check_seeks(&mut d, &data);
+    }
+
+    #[test]
+    fn gz() {
+        let data = crate::test_data(1_500_000);
+        let mut e = crate::write::GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(&data).unwrap();
+        let compressed = e.finish().unwrap();
+
+        let index = IndexBuilder::new()
+            .span(64 * 1024)
+            .build_gz(&compressed[..])
+            .unwrap();
+        assert!(index.checkpoints().len() > 3);
+        assert!(index.checkpoints().iter().any(|c| c.bits % 8 != 0));
+        assert_eq!(index.uncompressed_len(), data.len() as u64);
+
+        let mut d = SeekableGzDecoder::new(Cursor::new(compressed), index);
+        check_seeks(&mut d, &data);
+    }
+
+    #[test]
+    fn zlib() {
+        let data = crate::test_data(1_500_000);
+        let mut e = crate::write::ZlibEncoder::new(Vec::new(), Compression::best());
+        e.write_all(&data).unwrap();
+        let compressed = e.finish().unwrap();
+
+        let index = IndexBuilder::new()
+            .span(100_000)
+            .build_zlib(&compressed[..])
+            .unwrap();
+        let mut d = SeekableZlibDecoder::new(Cursor::new(compressed.clone()), index.clone());
+        check_seeks(&mut d, &data);
+
+        let mut d = SeekableGzDecoder::new(Cursor::new(compressed), index);
+        assert!(d.read(&mut [0; 10]).is_err());
+    }
+
+    #[test]
+    fn multi_member() {
+        let data = crate::test_data(1_500_000);
+        let mut compressed = Vec::new();
+        for chunk in data.chunks(300_000) {
+            let mut e = crate::write::GzEncoder::new(Vec::new(), Compression::fast());
+            e.write_all(chunk).unwrap();
+            compressed.extend(e.finish().unwrap());
+        }
+
+        let index = IndexBuilder::new()
+            .span(50_000)
+            .build_gz(&compressed[..])
+            .unwrap();
+        assert_eq!(index.uncompressed_len(), data.len() as u64);
+        let mut d = SeekableGzDecoder::new(Cursor::new(compressed), index);
+        check_seeks(&mut d, &data);
+    }
+
+    #[test]
+    fn serialize() {
+        let data = crate::test_data(1_500_000);
+        let mut e = crate::write::GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(&data).unwrap();
+        let compressed = e.finish().unwrap();
+
+        let index = IndexBuilder::new().build_gz(&compressed[..]).unwrap();
+        let mut sidecar = Vec::new();
+        index.write_to(&mut sidecar).unwrap();
+        assert_eq!(Index::read_from(&sidecar[..]).unwrap(), index);
+        assert!(Index::read_from(&sidecar[..sidecar.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn corrupt_stream() {
+        let mut e = crate::write::GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(b"hello world").unwrap();
+        let mut compressed = e.finish().unwrap();
+        let n = compressed.len();
+        compressed[n - 5] ^= 1;
+        assert!(IndexBuilder::new().build_gz(&compressed[..]).is_err());
+        assert!(IndexBuilder::new().build_gz(&compressed[..n - 3]).is_err());
+    }
+}
@@ -11,6 +11,10 @@
 //! headers/footers. This crate has three types in each submodule for dealing
 //! with these three formats.
 //!
+//! When the format of the input isn't known in advance, [`read::AutoDecoder`]
+//! and [`bufread::AutoDecoder`] detect it, passing through anything that isn't
+//! compressed.
+//!
 //! # Implementation
 //!
 //! In addition to supporting three formats, this crate supports several different
@@ -24,6 +28,9 @@
 //!   Linux systems by default. If the library isn't found to already be on the system it will be
 //!   compiled from source (this is a C library).
 //!
+//! * `zlib-rs` - this feature uses the `zlib-rs` crate, a port of zlib to Rust, through the same
+//!   zlib API as the C libraries, so that it behaves as zlib does without requiring a C compiler.
+//!
 //! There's various tradeoffs associated with each implementation, but in general you probably
 //! won't have to tweak the defaults. The default choice is selected to avoid the need for a C
 //! compiler at build time. `zlib-ng-compat` is useful if you're using zlib for compatibility but
@@ -32,6 +39,20 @@
 //! and performance of each of these feature should be roughly comparable, but you'll likely want
 //! to run your own tests if you're curious about the performance.
 //!
+//! Enabling `rust_backend` along with one of the zlib features compiles both
+//! backends in. Streams then use zlib by default, and can be given either of
+//! them with [`EncoderOptions::backend`], [`DecoderOptions::backend`],
+//! [`index::IndexBuilder::backend`] and the `_with_backend` constructors of
+//! [`Compress`] and [`Decompress`], while [`Backend::set_default`] changes the
+//! default for the whole process.
+//!
+//! Independently of the backend, the `libdeflate` feature makes the whole-buffer
+//! functions of the [`oneshot`] module use the libdeflate library, which is
+//! much faster than the streaming backends when all of the data is in memory.
+//!
+//! Compression engines other than these can be plugged into the streams of
+//! this crate through the traits of the [`backend`] module.
+//!
 //! # Organization
 //!
 //! This crate consists mainly of three modules, [`read`], [`write`], and
@@ -65,6 +86,50 @@
 //! `Write` trait if `T: Write`. That is, the "dual trait" is forwarded directly
 //! to the underlying object if available.
 //!
+//! # Multithreaded compression
+//!
+//! Compressing large amounts of data is usually bound by a single core. The
+//! [`write::ParGzEncoder`], [`write::ParZlibEncoder`] and
+//! [`write::ParDeflateEncoder`] types split their input into blocks which are
+//! compressed on a pool of worker threads and stitched back together into a
+//! single regular stream, readable by any decoder. The number of threads and
+//! the size of the blocks are configured through a [`ParBuilder`].
+//!
+//! Likewise [`read::ParMultiGzDecoder`] decodes the members of multi-member
+//! gzip files, such as BGZF files, concurrently.
+//!
+//! # Random access
+//!
+//! Gzip and zlib streams can be read starting from arbitrary positions with the
+//! help of an index of checkpoints built in a single pass over them, see the
+//! [`index`] module. Files written with a [`write::SeekableGzEncoder`] come
+//! with such an index without any extra pass. The [`dictzip`] module supports
+//! the dictzip format, which stores a chunk table in the gzip header instead.
+//!
+//! # Untrusted input
+//!
+//! A tiny compressed stream can decompress to gigabytes of data. The
+//! `new_with_limits` constructors of the decoders, blocking, asynchronous and
+//! multithreaded alike, take [`Limits`] on the size of the output, the
+//! compression ratio and the number of gzip members, and fail with an
+//! [`Error::LimitExceeded`] error once one is exceeded. The
+//! `new_with_options` constructors of the blocking decoders take
+//! [`DecoderOptions`], which also control how strictly the input is checked.
+//!
+//! # Errors
+//!
+//! Decoders report invalid or truncated input as an [`io::Error`] wrapping an
+//! [`Error`], which tells what went wrong and at which offset of the
+//! compressed input.
+//!
+//! [`io::Error`]: std::io::Error
+//!
+//! # Converting between formats
+//!
+//! Compressed data can be moved from one of the three formats to another
+//! without compressing it again with the functions of the [`rewrap`] module,
+//! which also allow changing the header of gzip files.
+//!
 //! # About multi-member Gzip files
 //!
 //! While most `gzip` files one encounters will have a single *member* that can be read
@@ -81,6 +146,19 @@
 //! emit an error after decoding the gzip data. This behavior matches the `gzip`,
 //! `gunzip`, and `zcat` command line tools.
 //!
+//! BGZF files, a multi-member format made of small blocks which allows random
+//! access, can additionally be written and seeked into with the types in the
+//! [`bgzf`] module.
+//!
+//! Files read by consumers only looking at their first member can be turned
+//! into a single member with `GzBuilder::join`, and data can be added to the
+//! last member of an existing file with `write::GzEncoder::append`, both of
+//! which avoid compressing the existing data again.
+//!
+//! Logs which must stay readable after a crash can be written with the types
+//! in the [`gzlog`] module, which recover the intact part of an existing log
+//! before appending new members to it.
+//!
 //! [`read`]: read/index.html
 //! [`bufread`]: bufread/index.html
 //! [`write`]: write/index.html
@@ -99,21 +177,47 @@
 #[cfg(not(feature = "any_impl",))]
 compile_error!("You need to choose a zlib backend");
 
+pub use crate::auto::Format;
+pub use crate::backend::Backend;
 pub use crate::crc::{Crc, CrcReader, CrcWriter};
+pub use crate::error::Error;
 pub use crate::gz::GzBuilder;
 pub use crate::gz::GzHeader;
+pub use crate::gz::GzMember;
+pub use crate::limits::{LimitExceeded, LimitKind, Limits};
 pub use crate::mem::{Compress, CompressError, Decompress, DecompressError, Status};
 pub use crate::mem::{FlushCompress, FlushDecompress};
+pub use crate::multi::StreamRange;
+pub use crate::options::{DecoderOptions, EncoderOptions};
+pub use crate::par::ParBuilder;
 
+mod adler;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+mod aio;
+mod auto;
 mod bufreader;
 mod crc;
 mod deflate;
+mod error;
+mod exhaustive;
 mod ffi;
 mod gz;
+mod limits;
 mod mem;
+mod multi;
+mod options;
+mod par;
 mod zio;
 mod zlib;
 
+pub mod backend;
+pub mod bgzf;
+pub mod dictzip;
+pub mod gzlog;
+pub mod index;
+pub mod oneshot;
+pub mod rewrap;
+
 /// Types which operate over [`Read`] streams, both encoders and decoders for
 /// various formats.
 ///
@@ -126,11 +230,15 @@ mod zlib;
 /// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
 /// [`BufReader`]: https://doc.rust-lang.org/std/io/struct.BufReader.html
 pub mod read {
+    pub use crate::auto::read::AutoDecoder;
     pub use crate::deflate::read::DeflateDecoder;
     pub use crate::deflate::read::DeflateEncoder;
+    pub use crate::deflate::read::MultiDeflateDecoder;
     pub use crate::gz::read::GzDecoder;
     pub use crate::gz::read::GzEncoder;
     pub use crate::gz::read::MultiGzDecoder;
+    pub use crate::par::read::ParMultiGzDecoder;
+    pub use crate::zlib::read::MultiZlibDecoder;
     pub use crate::zlib::read::ZlibDecoder;
     pub use crate::zlib::read::ZlibEncoder;
 }
@@ -142,9 +250,15 @@ pub mod read {
 pub mod write {
     pub use crate::deflate::write::DeflateDecoder;
     pub use crate::deflate::write::DeflateEncoder;
+    pub use crate::deflate::write::MultiDeflateDecoder;
     pub use crate::gz::write::GzDecoder;
     pub use crate::gz::write::GzEncoder;
     pub use crate::gz::write::MultiGzDecoder;
+    pub use crate::gz::write::SeekableGzEncoder;
+    pub use crate::par::write::ParDeflateEncoder;
+    pub use crate::par::write::ParGzEncoder;
+    pub use crate::par::write::ParZlibEncoder;
+    pub use crate::zlib::write::MultiZlibDecoder;
     pub use crate::zlib::write::ZlibDecoder;
     pub use crate::zlib::write::ZlibEncoder;
 }
@@ -154,15 +268,82 @@ pub mod write {
 ///
 /// [`BufRead`]: https://doc.rust-lang.org/std/io/trait.BufRead.html
 pub mod bufread {
+    pub use crate::auto::bufread::AutoDecoder;
     pub use crate::deflate::bufread::DeflateDecoder;
     pub use crate::deflate::bufread::DeflateEncoder;
+    pub use crate::deflate::bufread::MultiDeflateDecoder;
     pub use crate::gz::bufread::GzDecoder;
     pub use crate::gz::bufread::GzEncoder;
     pub use crate::gz::bufread::MultiGzDecoder;
+    pub use crate::zlib::bufread::MultiZlibDecoder;
     pub use crate::zlib::bufread::ZlibDecoder;
     pub use crate::zlib::bufread::ZlibEncoder;
 }
 
+/// Asynchronous encoders and decoders for the [`tokio`] I/O traits.
+///
+/// The types in here mirror the ones of the blocking modules of the same name
+/// and are available when the `tokio` feature is enabled. Gzip encoders write
+/// their trailer when shut down, gzip decoders verify it when reaching the end
+/// of the member, or when shut down for the `write` flavor.
+///
+/// [`tokio`]: https://docs.rs/tokio
+#[cfg(feature = "tokio")]
+pub mod tokio {
+    /// Types which operate over [`AsyncRead`] streams.
+    ///
+    /// [`AsyncRead`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncRead.html
+    pub mod read {
+        pub use crate::aio::read::*;
+    }
+
+    /// Types which operate over [`AsyncWrite`] streams.
+    ///
+    /// [`AsyncWrite`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncWrite.html
+    pub mod write {
+        pub use crate::aio::write::*;
+    }
+
+    /// Types which operate over [`AsyncBufRead`] streams.
+    ///
+    /// [`AsyncBufRead`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncBufRead.html
+    pub mod bufread {
+        pub use crate::aio::bufread::*;
+    }
+}
+
+/// Asynchronous encoders and decoders for the [`futures-io`] I/O traits.
+///
+/// The types in here mirror the ones of the blocking modules of the same name
+/// and are available when the `futures-io` feature is enabled. They are the
+/// same types as the ones in the `tokio` module, so enabling both features
+/// makes them implement both sets of traits.
+///
+/// [`futures-io`]: https://docs.rs/futures-io
+#[cfg(feature = "futures-io")]
+pub mod futures {
+    /// Types which operate over [`AsyncRead`] streams.
+    ///
+    /// [`AsyncRead`]: https://docs.rs/futures-io/0.3/futures_io/trait.AsyncRead.html
+    pub mod read {
+        pub use crate::aio::read::*;
+    }
+
+    /// Types which operate over [`AsyncWrite`] streams.
+    ///
+    /// [`AsyncWrite`]: https://docs.rs/futures-io/0.3/futures_io/trait.AsyncWrite.html
+    pub mod write {
+        pub use crate::aio::write::*;
+    }
+
+    /// Types which operate over [`AsyncBufRead`] streams.
+    ///
+    /// [`AsyncBufRead`]: https://docs.rs/futures-io/0.3/futures_io/trait.AsyncBufRead.html
+    pub mod bufread {
+        pub use crate::aio::bufread::*;
+    }
+}
+
 fn _assert_send_sync() {
     fn _assert_send_sync<T: Send + Sync>() {}
 
@@ -173,18 +354,24 @@ fn _assert_send_sync() {
     _assert_send_sync::<read::GzEncoder<&[u8]>>();
     _assert_send_sync::<read::GzDecoder<&[u8]>>();
     _assert_send_sync::<read::MultiGzDecoder<&[u8]>>();
+    _assert_send_sync::<read::ParMultiGzDecoder<&[u8]>>();
     _assert_send_sync::<write::DeflateEncoder<Vec<u8>>>();
     _assert_send_sync::<write::DeflateDecoder<Vec<u8>>>();
     _assert_send_sync::<write::ZlibEncoder<Vec<u8>>>();
     _assert_send_sync::<write::ZlibDecoder<Vec<u8>>>();
     _assert_send_sync::<write::GzEncoder<Vec<u8>>>();
+    _assert_send_sync::<write::SeekableGzEncoder<Vec<u8>>>();
     _assert_send_sync::<write::GzDecoder<Vec<u8>>>();
+    _assert_send_sync::<write::ParGzEncoder<Vec<u8>>>();
 }
 
 /// When compressing data, the compression level can be specified by a value in
 /// this struct.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub struct Compression(u32);
+pub struct Compression {
+    level: u32,
+    exhaustive: bool,
+}
 
 impl Compression {
     /// Creates a new description of the compression level with an explicitly
@@ -193,35 +380,60 @@ impl Compression {
     /// The integer here is typically on a scale of 0-9 where 0 means "no
     /// compression" and 9 means "take as long as you'd like".
     pub const fn new(level: u32) -> Compression {
-        Compression(level)
+        Compression {
+            level,
+            exhaustive: false,
+        }
     }
 
     /// No compression is to be performed, this may actually inflate data
     /// slightly when encoding.
     pub const fn none() -> Compression {
-        Compression(0)
+        Compression::new(0)
     }
 
     /// Optimize for the best speed of encoding.
     pub const fn fast() -> Compression {
-        Compression(1)
+        Compression::new(1)
     }
 
     /// Optimize for the size of data being encoded.
     pub const fn best() -> Compression {
-        Compression(9)
+        Compression::new(9)
+    }
+
+    /// Spend as long as it takes to find the smallest encoding of the data, in
+    /// the manner of Zopfli.
+    ///
+    /// This is around a hundred times slower than [`best`](Self::best) for
+    /// output a few percent smaller, which pays off for data compressed once
+    /// and decompressed many times, like static web assets. The output is
+    /// regular deflate data, produced by this crate itself rather than by the
+    /// backend.
+    ///
+    /// This isn't a numeric level: its [`level`](Self::level) is that of
+    /// `best`, and `Compression::new(10)` remains the backend's own level 10.
+    pub const fn exhaustive() -> Compression {
+        Compression {
+            level: 9,
+            exhaustive: true,
+        }
     }
 
     /// Returns an integer representing the compression level, typically on a
     /// scale of 0-9
     pub fn level(&self) -> u32 {
-        self.0
+        self.level
+    }
+
+    pub(crate) fn is_exhaustive(&self) -> bool {
+        self.exhaustive
     }
 }
 
 impl Default for Compression {
     fn default() -> Compression {
-        Compression(6)
+        Compression::new(6)
     }
 }
 
@@ -232,3 +444,22 @@ fn random_bytes() -> impl Iterator<Item = u8> {
 
     iter::repeat(()).map(|_| rand::thread_rng().gen())
 }
+
+// Returns `len` bytes of words with the odd random byte in between, which
+// compress well but not trivially.
+#[cfg(test)]
+fn test_data(len: usize) -> Vec<u8> {
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    let words: &[&[u8]] = &[b"alpha ", b"beta ", b"gamma ", b"\xff\xfe ", b"\n"];
+    let mut data = Vec::with_capacity(len + 8);
+    while data.len() < len {
+        data.extend_from_slice(words[rng.gen_range(0..words.len())]);
+        if rng.gen_ratio(1, 50) {
+            data.push(rng.gen());
+        }
+    }
+    data.truncate(len);
+    data
+}
@@ -0,0 +1,280 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+/// Limits on the resources a decoder may use, protecting against
+/// decompression bombs.
+///
+/// A small compressed stream can expand to an enormous amount of data, so
+/// decoding untrusted input without bounds can exhaust memory or disk space.
+/// Decoders created with limits stop with an [`Error::LimitExceeded`] error as
+/// soon as one of them trips. By default, nothing is limited.
+///
+/// [`Error::LimitExceeded`]: crate::Error::LimitExceeded
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::read::ZlibDecoder;
+/// use flate2::write::ZlibEncoder;
+/// use flate2::{Compression, LimitKind, Limits};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+/// e.write_all(&[0; 100_000])?;
+/// let bytes = e.finish()?;
+///
+/// let limits = Limits::new().max_output(10_000);
+/// let mut d = ZlibDecoder::new_with_limits(&bytes[..], limits);
+/// let err = d.read_to_end(&mut Vec::new()).unwrap_err();
+/// match err.get_ref().and_then(|e| e.downcast_ref::<flate2::Error>()) {
+///     Some(flate2::Error::LimitExceeded { limit, .. }) => {
+///         assert_eq!(limit.kind(), LimitKind::Output)
+///     }
+///     _ => panic!("unexpected error: {}", err),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    max_output: Option<u64>,
+    max_ratio: Option<u64>,
+    max_members: Option<u64>,
+}
+
+impl Limits {
+    /// Creates a new set of limits, with nothing limited.
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+
+    /// Limits the total number of decompressed bytes.
+    ///
+    /// For multi-member gzip streams this covers all members together. A
+    /// decoder never produces more than `bytes` bytes before failing.
+    pub fn max_output(mut self, bytes: u64) -> Limits {
+        self.max_output = Some(bytes);
+        self
+    }
+
+    /// Limits the ratio between the number of decompressed bytes and the
+    /// number of compressed bytes consumed to produce them.
+    ///
+    /// The ratio is checked over everything decoded so far, excluding gzip
+    /// headers and trailers. Since deflate cannot expand data by more than a
+    /// factor of about 1032, only lower ratios have any effect.
+    pub fn max_ratio(mut self, ratio: u64) -> Limits {
+        self.max_ratio = Some(ratio);
+        self
+    }
+
+    /// Limits the number of members of a gzip stream.
+    ///
+    /// This only has an effect on multi-member gzip decoders.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `members` is zero.
+    pub fn max_members(mut self, members: u64) -> Limits {
+        assert!(members > 0, "at least one member must be allowed");
+        self.max_members = Some(members);
+        self
+    }
+}
+
+/// The limit of a [`Limits`] that was exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LimitKind {
+    /// The total number of decompressed bytes.
+    Output,
+    /// The ratio between decompressed and compressed bytes.
+    Ratio,
+    /// The number of gzip members.
+    Members,
+}
+
+/// Describes which of its [`Limits`] a decoder exceeded.
+///
+/// It is reported as an [`Error::LimitExceeded`]. Once a limit has been
+/// exceeded, all further reads or writes fail with the same error.
+///
+/// [`Error::LimitExceeded`]: crate::Error::LimitExceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    kind: LimitKind,
+    limit: u64,
+}
+
+impl LimitExceeded {
+    /// Returns which limit was exceeded.
+    pub fn kind(&self) -> LimitKind {
+        self.kind
+    }
+
+    /// Returns the value of the limit that was exceeded.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            LimitKind::Output => write!(f, "decompressed size exceeds {} bytes", self.limit),
+            LimitKind::Ratio => write!(f, "compression ratio exceeds {}", self.limit),
+            LimitKind::Members => write!(f, "gzip stream has more than {} members", self.limit),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/// Tracks what a decoder has consumed and produced against its limits.
+#[derive(Debug, Clone)]
+pub(crate) struct Limiter {
+    limits: Limits,
+    total_in: u64,
+    total_out: u64,
+    members: u64,
+    exceeded: Option<LimitExceeded>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limits: Limits) -> Limiter {
+        Limiter {
+            limits,
+            total_in: 0,
+            total_out: 0,
+            members: 1,
+            exceeded: None,
+        }
+    }
+
+    /// Forgets everything accounted so far, keeping the limits.
+    pub(crate) fn reset(&mut self) {
+        *self = Limiter::new(self.limits);
+    }
+
+    /// Fails if a limit has already been exceeded.
+    pub(crate) fn check(&self) -> Result<(), LimitExceeded> {
+        match self.exceeded {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns how many of `len` bytes of output may be asked for, which is
+    /// one more than allowed so that going over the limit can be noticed.
+    pub(crate) fn clamp(&self, len: usize) -> usize {
+        match self.limits.max_output {
+            Some(max) => {
+                let left = max.saturating_sub(self.total_out).saturating_add(1);
+                len.min(usize::try_from(left).unwrap_or(usize::MAX))
+            }
+            None => len,
+        }
+    }
+
+    /// Returns how many bytes of output were produced beyond the limit.
+    pub(crate) fn excess(&self) -> u64 {
+        self.limits
+            .max_output
+            .map_or(0, |max| self.total_out.saturating_sub(max))
+    }
+
+    /// Returns the most output a piece of the stream may produce on its own
+    /// without exceeding the limits, when the input up to its end amounts to
+    /// `total_in` bytes.
+    pub(crate) fn max_output_within(&self, total_in: u64) -> u64 {
+        let max_output = self.limits.max_output.unwrap_or(u64::MAX);
+        let max_ratio = self
+            .limits
+            .max_ratio
+            .map_or(u64::MAX, |ratio| ratio.saturating_mul(total_in));
+        max_output.min(max_ratio)
+    }
+
+    /// Records that `consumed` bytes of input produced `produced` bytes of
+    /// output.
+    pub(crate) fn account(&mut self, consumed: u64, produced: u64) -> Result<(), LimitExceeded> {
+        self.check()?;
+        self.total_in += consumed;
+        self.total_out += produced;
+        if let Some(max) = self.limits.max_output {
+            if self.total_out > max {
+                return self.exceed(LimitKind::Output, max);
+            }
+        }
+        if let Some(ratio) = self.limits.max_ratio {
+            if self.total_out > self.total_in.saturating_mul(ratio) {
+                return self.exceed(LimitKind::Ratio, ratio);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the start of another gzip member.
+    pub(crate) fn next_member(&mut self) -> Result<(), LimitExceeded> {
+        self.check()?;
+        self.members += 1;
+        match self.limits.max_members {
+            Some(max) if self.members > max => self.exceed(LimitKind::Members, max),
+            _ => Ok(()),
+        }
+    }
+
+    fn exceed(&mut self, kind: LimitKind, limit: u64) -> Result<(), LimitExceeded> {
+        let err = LimitExceeded { kind, limit };
+        self.exceeded = Some(err);
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited() {
+        let mut limiter = Limiter::new(Limits::new());
+        assert_eq!(limiter.clamp(100), 100);
+        limiter.account(1, u32::MAX as u64).unwrap();
+        limiter.next_member().unwrap();
+        assert_eq!(limiter.excess(), 0);
+    }
+
+    #[test]
+    fn output() {
+        let mut limiter = Limiter::new(Limits::new().max_output(10));
+        assert_eq!(limiter.clamp(100), 11);
+        limiter.account(5, 8).unwrap();
+        assert_eq!(limiter.clamp(100), 3);
+        let err = limiter.account(1, 5).unwrap_err();
+        assert_eq!(err.kind(), LimitKind::Output);
+        assert_eq!(limiter.excess(), 3);
+        assert_eq!(limiter.check().unwrap_err().kind(), LimitKind::Output);
+
+        limiter.reset();
+        limiter.account(1, 10).unwrap();
+    }
+
+    #[test]
+    fn ratio() {
+        let mut limiter = Limiter::new(Limits::new().max_ratio(4));
+        limiter.account(2, 8).unwrap();
+        limiter.account(0, 0).unwrap();
+        let err = limiter.account(0, 1).unwrap_err();
+        assert_eq!(err.kind(), LimitKind::Ratio);
+    }
+
+    #[test]
+    fn members() {
+        let mut limiter = Limiter::new(Limits::new().max_members(2));
+        limiter.next_member().unwrap();
+        let err = limiter.next_member().unwrap_err();
+        assert_eq!(err.kind(), LimitKind::Members);
+    }
+}
@@ -2,8 +2,12 @@ use std::error::Error;
 use std::fmt;
 use std::io;
 
-use crate::ffi::{self, Backend, Deflate, DeflateBackend, ErrorMessage, Inflate, InflateBackend};
-use crate::Compression;
+use crate::backend::{CompressBackend, DecompressBackend};
+use crate::exhaustive::Exhaustive;
+use crate::ffi::{
+    self, Backend as _, Deflate, DeflateBackend, ErrorMessage, Inflate, InflateBackend,
+};
+use crate::{Backend, Compression};
 
 /// Raw in-memory compression stream for blocks of data.
 ///
@@ -19,7 +23,111 @@ use crate::Compression;
 /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
 #[derive(Debug)]
 pub struct Compress {
-    inner: Deflate,
+    inner: Engine,
+    rsync: Option<Rsync>,
+}
+
+/// What compresses the data of a `Compress`: the backend, unless exhaustive
+/// compression or a custom backend was asked for.
+enum Engine {
+    Backend(Deflate),
+    Exhaustive(Box<Exhaustive>),
+    Custom(Box<dyn CompressBackend>),
+}
+
+impl fmt::Debug for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Engine::Backend(inner) => f.debug_tuple("Backend").field(inner).finish(),
+            Engine::Exhaustive(inner) => f.debug_tuple("Exhaustive").field(inner).finish(),
+            Engine::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl Engine {
+    fn make(backend: Backend, level: Compression, zlib_header: bool, window_bits: u8) -> Engine {
+        if level.is_exhaustive() {
+            Engine::Exhaustive(Box::new(Exhaustive::new(zlib_header, window_bits)))
+        } else {
+            Engine::Backend(Deflate::make_with(backend, level, zlib_header, window_bits))
+        }
+    }
+
+    fn total_in(&self) -> u64 {
+        match self {
+            Engine::Backend(inner) => inner.total_in(),
+            Engine::Exhaustive(inner) => inner.total_in(),
+            Engine::Custom(inner) => inner.total_in(),
+        }
+    }
+
+    fn total_out(&self) -> u64 {
+        match self {
+            Engine::Backend(inner) => inner.total_out(),
+            Engine::Exhaustive(inner) => inner.total_out(),
+            Engine::Custom(inner) => inner.total_out(),
+        }
+    }
+
+    fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushCompress,
+    ) -> Result<Status, CompressError> {
+        match self {
+            Engine::Backend(inner) => inner.compress(input, output, flush),
+            Engine::Exhaustive(inner) => inner.compress(input, output, flush),
+            Engine::Custom(inner) => inner.compress(input, output, flush),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Engine::Backend(inner) => inner.reset(),
+            Engine::Exhaustive(inner) => inner.reset(),
+            Engine::Custom(inner) => inner.reset(),
+        }
+    }
+}
+
+// The rolling hash pigz uses for `--rsyncable`, which only depends on the last
+// `RSYNC_BITS` bytes of input and hits `RSYNC_HIT` every 4 KiB of random data
+// on average, somewhat less often on text.
+const RSYNC_BITS: u32 = 12;
+const RSYNC_MASK: u32 = (1 << RSYNC_BITS) - 1;
+const RSYNC_HIT: u32 = RSYNC_MASK >> 1;
+
+/// Where a compressor producing rsyncable output is in its input.
+#[derive(Debug, Default)]
+struct Rsync {
+    /// The hash of the input consumed so far.
+    hash: u32,
+    /// The output of the last full flush which didn't fit in the output space
+    /// given, already counted in the total output of the compressor.
+    pending: Vec<u8>,
+}
+
+impl Rsync {
+    // Returns the length of the start of `input` which ends with a flush
+    // point, if any.
+    fn find(&self, input: &[u8]) -> Option<usize> {
+        let mut hash = self.hash;
+        input
+            .iter()
+            .position(|&byte| {
+                hash = ((hash << 1) ^ u32::from(byte)) & RSYNC_MASK;
+                hash == RSYNC_HIT
+            })
+            .map(|pos| pos + 1)
+    }
+
+    fn update(&mut self, input: &[u8]) {
+        for &byte in input {
+            self.hash = ((self.hash << 1) ^ u32::from(byte)) & RSYNC_MASK;
+        }
+    }
 }
 
 /// Raw in-memory decompression stream for blocks of data.
@@ -36,7 +144,65 @@ pub struct Compress {
 /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
 #[derive(Debug)]
 pub struct Decompress {
-    inner: Inflate,
+    inner: Decoder,
+}
+
+/// What decompresses the data of a `Decompress`: the backend, unless a custom
+/// one was asked for.
+enum Decoder {
+    Backend(Inflate),
+    Custom(Box<dyn DecompressBackend>),
+}
+
+impl fmt::Debug for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Decoder::Backend(inner) => f.debug_tuple("Backend").field(inner).finish(),
+            Decoder::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl Decoder {
+    fn total_in(&self) -> u64 {
+        match self {
+            Decoder::Backend(inner) => inner.total_in(),
+            Decoder::Custom(inner) => inner.total_in(),
+        }
+    }
+
+    fn total_out(&self) -> u64 {
+        match self {
+            Decoder::Backend(inner) => inner.total_out(),
+            Decoder::Custom(inner) => inner.total_out(),
+        }
+    }
+
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushDecompress,
+    ) -> Result<Status, DecompressError> {
+        match self {
+            Decoder::Backend(inner) => inner.decompress(input, output, flush),
+            Decoder::Custom(inner) => inner.decompress(input, output, flush),
+        }
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, DecompressError> {
+        match self {
+            Decoder::Backend(inner) => inner.set_dictionary(dictionary),
+            Decoder::Custom(inner) => inner.set_dictionary(dictionary),
+        }
+    }
+
+    fn reset(&mut self, zlib_header: bool) {
+        match self {
+            Decoder::Backend(inner) => inner.reset(zlib_header),
+            Decoder::Custom(inner) => inner.reset(zlib_header),
+        }
+    }
 }
 
 /// Values which indicate the form of flushing to be used when compressing
@@ -121,6 +287,19 @@ pub(crate) enum DecompressErrorInner {
 pub struct DecompressError(pub(crate) DecompressErrorInner);
 
 impl DecompressError {
+    /// Creates an error with the given message, for use by custom backends.
+    pub fn new(msg: &'static str) -> DecompressError {
+        DecompressError(DecompressErrorInner::General {
+            msg: ErrorMessage::new(msg),
+        })
+    }
+
+    /// Creates an error telling that the dictionary with the given Adler-32
+    /// checksum is needed, for use by custom backends.
+    pub fn new_needs_dictionary(adler: u32) -> DecompressError {
+        DecompressError(DecompressErrorInner::NeedsDictionary(adler))
+    }
+
     /// Indicates whether decompression failed due to requiring a dictionary.
     ///
     /// The resulting integer is the Adler-32 checksum of the dictionary
@@ -193,8 +372,20 @@ impl Compress {
     /// to be performed, and the `zlib_header` argument indicates whether the
     /// output data should have a zlib header or not.
     pub fn new(level: Compression, zlib_header: bool) -> Compress {
+        Compress::new_with_backend(level, zlib_header, Backend::default())
+    }
+
+    /// Creates a new object like `new`, compressing data with the given
+    /// built-in `backend` rather than the default one.
+    pub fn new_with_backend(level: Compression, zlib_header: bool, backend: Backend) -> Compress {
         Compress {
-            inner: Deflate::make(level, zlib_header, ffi::MZ_DEFAULT_WINDOW_BITS as u8),
+            inner: Engine::make(
+                backend,
+                level,
+                zlib_header,
+                ffi::MZ_DEFAULT_WINDOW_BITS as u8,
+            ),
+            rsync: None,
         }
     }
 
@@ -206,22 +397,47 @@ impl Compress {
     /// indicates the base-2 logarithm of the sliding window size and must be
     /// between 9 and 15.
     ///
+    /// The `miniz_oxide` backend has no smaller window than 32 KiB, so with a
+    /// smaller one it clears its window with a full flush every time that much
+    /// input went in, at some cost in compression.
+    ///
     /// # Panics
     ///
     /// If `window_bits` does not fall into the range 9 ..= 15,
     /// `new_with_window_bits` will panic.
-    #[cfg(feature = "any_zlib")]
     pub fn new_with_window_bits(
         level: Compression,
         zlib_header: bool,
         window_bits: u8,
+    ) -> Compress {
+        Compress::new_with_window_bits_and_backend(
+            level,
+            zlib_header,
+            window_bits,
+            Backend::default(),
+        )
+    }
+
+    /// Creates a new object like `new_with_window_bits`, compressing data with
+    /// the given built-in `backend` rather than the default one.
+    ///
+    /// # Panics
+    ///
+    /// If `window_bits` does not fall into the range 9 ..= 15,
+    /// `new_with_window_bits_and_backend` will panic.
+    pub fn new_with_window_bits_and_backend(
+        level: Compression,
+        zlib_header: bool,
+        window_bits: u8,
+        backend: Backend,
     ) -> Compress {
         assert!(
             window_bits > 8 && window_bits < 16,
             "window_bits must be within 9 ..= 15"
         );
         Compress {
-            inner: Deflate::make(level, zlib_header, window_bits),
+            inner: Engine::make(backend, level, zlib_header, window_bits),
+            rsync: None,
         }
     }
 
@@ -237,14 +453,41 @@ impl Compress {
     ///
     /// If `window_bits` does not fall into the range 9 ..= 15,
     /// `new_with_window_bits` will panic.
-    #[cfg(feature = "any_zlib")]
     pub fn new_gzip(level: Compression, window_bits: u8) -> Compress {
+        Compress::new_gzip_with_backend(level, window_bits, Backend::default())
+    }
+
+    /// Creates a new object like `new_gzip`, compressing data with the given
+    /// built-in `backend` rather than the default one.
+    ///
+    /// # Panics
+    ///
+    /// If `window_bits` does not fall into the range 9 ..= 15,
+    /// `new_gzip_with_backend` will panic.
+    pub fn new_gzip_with_backend(
+        level: Compression,
+        window_bits: u8,
+        backend: Backend,
+    ) -> Compress {
         assert!(
             window_bits > 8 && window_bits < 16,
             "window_bits must be within 9 ..= 15"
         );
         Compress {
-            inner: Deflate::make(level, true, window_bits + 16),
+            inner: Engine::make(backend, level, true, window_bits + 16),
+            rsync: None,
+        }
+    }
+
+    /// Creates a new object compressing data with the given custom `backend`
+    /// rather than the one this crate was built with.
+    ///
+    /// See the [`backend`](crate::backend) module for what the backend has to
+    /// do. `prime` isn't supported with it.
+    pub fn with_backend<B: CompressBackend + 'static>(backend: B) -> Compress {
+        Compress {
+            inner: Engine::Custom(Box::new(backend)),
+            rsync: None,
         }
     }
 
@@ -257,25 +500,33 @@ impl Compress {
     /// Returns the total number of output bytes which have been produced by
     /// this compression object.
     pub fn total_out(&self) -> u64 {
-        self.inner.total_out()
+        let pending = self.rsync.as_ref().map_or(0, |rsync| rsync.pending.len());
+        self.inner.total_out() - pending as u64
     }
 
     /// Specifies the compression dictionary to use.
     ///
     /// Returns the Adler-32 checksum of the dictionary.
-    #[cfg(feature = "any_zlib")]
     pub fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, CompressError> {
-        let stream = &mut *self.inner.inner.stream_wrapper;
-        stream.msg = std::ptr::null_mut();
-        let rc = unsafe {
-            assert!(dictionary.len() < ffi::uInt::MAX as usize);
-            ffi::deflateSetDictionary(stream, dictionary.as_ptr(), dictionary.len() as ffi::uInt)
-        };
+        match &mut self.inner {
+            Engine::Backend(inner) => inner.set_dictionary(dictionary),
+            Engine::Exhaustive(inner) => inner.set_dictionary(dictionary),
+            Engine::Custom(inner) => inner.set_dictionary(dictionary),
+        }
+    }
 
-        match rc {
-            ffi::MZ_STREAM_ERROR => compress_failed(self.inner.inner.msg()),
-            ffi::MZ_OK => Ok(stream.adler as u32),
-            c => panic!("unknown return code: {}", c),
+    /// Inserts the `bits` low bits of `value` into the output, ahead of the
+    /// compressed data.
+    ///
+    /// This lets a raw deflate stream continue another one ending in the
+    /// middle of a byte, along with [`set_dictionary`](Self::set_dictionary)
+    /// to give it the data preceding it. `bits` can't be more than 16. The
+    /// `miniz_oxide` backend only supports this before compressing anything.
+    pub fn prime(&mut self, bits: u8, value: u16) -> Result<(), CompressError> {
+        match &mut self.inner {
+            Engine::Backend(inner) => inner.prime(bits, value),
+            Engine::Exhaustive(inner) => inner.prime(bits, value),
+            Engine::Custom(_) => Err(CompressError::new("priming is not supported")),
         }
     }
 
@@ -284,6 +535,27 @@ impl Compress {
     /// This is equivalent to dropping this object and then creating a new one.
     pub fn reset(&mut self) {
         self.inner.reset();
+        if let Some(rsync) = &mut self.rsync {
+            *rsync = Rsync::default();
+        }
+    }
+
+    /// Sets whether the output is made rsyncable, like with the
+    /// `--rsyncable` option of gzip and pigz.
+    ///
+    /// A rolling hash over the input then picks points depending only on the
+    /// data around them, a few KiB apart on average, where the compressor does
+    /// a full flush. The compressed data following such a point only depends on
+    /// the input following it, so that the output of slightly different inputs
+    /// is the same again past the first point after every difference, at the
+    /// expense of a slightly worse compression ratio. This must be set before
+    /// compressing anything, and survives resets.
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        self.rsync = if rsyncable {
+            Some(Rsync::default())
+        } else {
+            None
+        };
     }
 
     /// Dynamically updates the compression level.
@@ -295,19 +567,17 @@ impl Compress {
     /// This may return an error if there wasn't enough output space to complete
     /// the compression of the available input data before changing the
     /// compression level. Flushing the stream before calling this method
-    /// ensures that the function will succeed on the first call.
-    #[cfg(feature = "any_zlib")]
+    /// ensures that the function will succeed on the first call. Switching to
+    /// or from [`Compression::exhaustive`] isn't supported.
     pub fn set_level(&mut self, level: Compression) -> Result<(), CompressError> {
-        use std::os::raw::c_int;
-        let stream = &mut *self.inner.inner.stream_wrapper;
-        stream.msg = std::ptr::null_mut();
-
-        let rc = unsafe { ffi::deflateParams(stream, level.0 as c_int, ffi::MZ_DEFAULT_STRATEGY) };
-
-        match rc {
-            ffi::MZ_OK => Ok(()),
-            ffi::MZ_BUF_ERROR => compress_failed(self.inner.inner.msg()),
-            c => panic!("unknown return code: {}", c),
+        match (&mut self.inner, level.is_exhaustive()) {
+            (Engine::Backend(inner), false) => inner.set_level(level),
+            (Engine::Exhaustive(_), true) => Ok(()),
+            (Engine::Custom(inner), _) => inner.set_level(level),
+            _ => {
+                let msg = "can't switch to or from exhaustive compression";
+                compress_failed(ErrorMessage::new(msg))
+            }
         }
     }
 
@@ -324,7 +594,49 @@ impl Compress {
         output: &mut [u8],
         flush: FlushCompress,
     ) -> Result<Status, CompressError> {
-        self.inner.compress(input, output, flush)
+        let rsync = match &mut self.rsync {
+            Some(rsync) => rsync,
+            None => return self.inner.compress(input, output, flush),
+        };
+
+        // Whatever is left of the last full flush goes out first.
+        if !rsync.pending.is_empty() {
+            let n = output.len().min(rsync.pending.len());
+            output[..n].copy_from_slice(&rsync.pending[..n]);
+            rsync.pending.drain(..n);
+            return Ok(Status::Ok);
+        }
+
+        let (len, flush) = match rsync.find(input) {
+            Some(len) if len < input.len() || flush != FlushCompress::Finish => {
+                (len, FlushCompress::Full)
+            }
+            _ => (input.len(), flush),
+        };
+        let before_in = self.inner.total_in();
+        let before_out = self.inner.total_out();
+        let status = self.inner.compress(&input[..len], output, flush)?;
+        let consumed = (self.inner.total_in() - before_in) as usize;
+        let produced = (self.inner.total_out() - before_out) as usize;
+        rsync.update(&input[..consumed]);
+
+        // A full flush is only complete once it leaves some output space
+        // unused. Rather than relying on the caller to keep on flushing, which
+        // zlib needs more than a few bytes of output space for, it's completed
+        // right away into a buffer of its own.
+        if flush == FlushCompress::Full && consumed == len && produced == output.len() {
+            let mut buf = [0; 64];
+            loop {
+                let before = self.inner.total_out();
+                self.inner.compress(&[], &mut buf, FlushCompress::Full)?;
+                let n = (self.inner.total_out() - before) as usize;
+                rsync.pending.extend_from_slice(&buf[..n]);
+                if n < buf.len() {
+                    break;
+                }
+            }
+        }
+        Ok(status)
     }
 
     /// Compresses the input data into the extra space of the output, consuming
@@ -356,8 +668,18 @@ impl Decompress {
     /// The `zlib_header` argument indicates whether the input data is expected
     /// to have a zlib header or not.
     pub fn new(zlib_header: bool) -> Decompress {
+        Decompress::new_with_backend(zlib_header, Backend::default())
+    }
+
+    /// Creates a new object like `new`, decompressing data with the given
+    /// built-in `backend` rather than the default one.
+    pub fn new_with_backend(zlib_header: bool, backend: Backend) -> Decompress {
         Decompress {
-            inner: Inflate::make(zlib_header, ffi::MZ_DEFAULT_WINDOW_BITS as u8),
+            inner: Decoder::Backend(Inflate::make_with(
+                backend,
+                zlib_header,
+                ffi::MZ_DEFAULT_WINDOW_BITS as u8,
+            )),
         }
     }
 
@@ -371,14 +693,28 @@ impl Decompress {
     ///
     /// If `window_bits` does not fall into the range 9 ..= 15,
     /// `new_with_window_bits` will panic.
-    #[cfg(feature = "any_zlib")]
     pub fn new_with_window_bits(zlib_header: bool, window_bits: u8) -> Decompress {
+        Decompress::new_with_window_bits_and_backend(zlib_header, window_bits, Backend::default())
+    }
+
+    /// Creates a new object like `new_with_window_bits`, decompressing data
+    /// with the given built-in `backend` rather than the default one.
+    ///
+    /// # Panics
+    ///
+    /// If `window_bits` does not fall into the range 9 ..= 15,
+    /// `new_with_window_bits_and_backend` will panic.
+    pub fn new_with_window_bits_and_backend(
+        zlib_header: bool,
+        window_bits: u8,
+        backend: Backend,
+    ) -> Decompress {
         assert!(
             window_bits > 8 && window_bits < 16,
             "window_bits must be within 9 ..= 15"
         );
         Decompress {
-            inner: Inflate::make(zlib_header, window_bits),
+            inner: Decoder::Backend(Inflate::make_with(backend, zlib_header, window_bits)),
         }
     }
 
@@ -391,14 +727,36 @@ impl Decompress {
     ///
     /// If `window_bits` does not fall into the range 9 ..= 15,
     /// `new_with_window_bits` will panic.
-    #[cfg(feature = "any_zlib")]
     pub fn new_gzip(window_bits: u8) -> Decompress {
+        Decompress::new_gzip_with_backend(window_bits, Backend::default())
+    }
+
+    /// Creates a new object like `new_gzip`, decompressing data with the given
+    /// built-in `backend` rather than the default one.
+    ///
+    /// # Panics
+    ///
+    /// If `window_bits` does not fall into the range 9 ..= 15,
+    /// `new_gzip_with_backend` will panic.
+    pub fn new_gzip_with_backend(window_bits: u8, backend: Backend) -> Decompress {
         assert!(
             window_bits > 8 && window_bits < 16,
             "window_bits must be within 9 ..= 15"
         );
         Decompress {
-            inner: Inflate::make(true, window_bits + 16),
+            inner: Decoder::Backend(Inflate::make_with(backend, true, window_bits + 16)),
+        }
+    }
+
+    /// Creates a new object decompressing data with the given custom `backend`
+    /// rather than the one this crate was built with.
+    ///
+    /// See the [`backend`](crate::backend) module for what the backend has to
+    /// do. `prime` isn't supported with it, and the Adler-32
+    /// checksum of zlib streams is verified or not as the backend sees fit.
+    pub fn with_backend<B: DecompressBackend + 'static>(backend: B) -> Decompress {
+        Decompress {
+            inner: Decoder::Custom(Box::new(backend)),
         }
     }
 
@@ -474,23 +832,52 @@ impl Decompress {
     }
 
     /// Specifies the decompression dictionary to use.
-    #[cfg(feature = "any_zlib")]
+    ///
+    /// With a zlib header this is called once `decompress` has returned an
+    /// error asking for the dictionary, and without one before decompressing
+    /// anything. Returns the Adler-32 checksum of the dictionary.
     pub fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<u32, DecompressError> {
-        let stream = &mut *self.inner.inner.stream_wrapper;
-        stream.msg = std::ptr::null_mut();
-        let rc = unsafe {
-            assert!(dictionary.len() < ffi::uInt::MAX as usize);
-            ffi::inflateSetDictionary(stream, dictionary.as_ptr(), dictionary.len() as ffi::uInt)
-        };
+        self.inner.set_dictionary(dictionary)
+    }
 
-        match rc {
-            ffi::MZ_STREAM_ERROR => decompress_failed(self.inner.inner.msg()),
-            ffi::MZ_DATA_ERROR => decompress_need_dict(stream.adler as u32),
-            ffi::MZ_OK => Ok(stream.adler as u32),
-            c => panic!("unknown return code: {}", c),
+    /// Inserts the `bits` low bits of `value` into the input, as if they
+    /// preceded the next input byte.
+    ///
+    /// This lets a raw deflate stream be decompressed starting from the middle
+    /// of a byte. `bits` can't be more than 16.
+    pub fn prime(&mut self, bits: u8, value: u16) -> Result<(), DecompressError> {
+        match &mut self.inner {
+            Decoder::Backend(inner) => inner.prime(bits, value),
+            Decoder::Custom(_) => Err(DecompressError::new("priming is not supported")),
         }
     }
 
+    /// Decompresses like `decompress` with `FlushDecompress::None`, but also
+    /// stops at the end of every deflate block.
+    ///
+    /// When stopped right after the end of a block this also returns the
+    /// number of bits of the last input byte consumed which don't belong to
+    /// that block, and whether it was the final block of the stream. Unless it
+    /// was, these bits start the next block. The `miniz_oxide` backend only
+    /// supports this for raw deflate data which isn't primed.
+    pub(crate) fn decompress_block(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(Status, Option<(u8, bool)>), DecompressError> {
+        let (status, data_type) = match &mut self.inner {
+            Decoder::Backend(inner) => inner.decompress_block(input, output)?,
+            Decoder::Custom(_) => {
+                return Err(DecompressError::new("stopping at blocks is not supported"))
+            }
+        };
+        let end = (data_type & 7) as u8;
+        Ok((
+            status,
+            Some((end, data_type & 64 != 0)).filter(|_| data_type & 128 != 0),
+        ))
+    }
+
     /// Performs the equivalent of replacing this decompression state with a
     /// freshly allocated copy.
     ///
@@ -502,6 +889,15 @@ impl Decompress {
     pub fn reset(&mut self, zlib_header: bool) {
         self.inner.reset(zlib_header);
     }
+
+    /// Sets whether the Adler-32 checksum of zlib streams is verified, which
+    /// it is by default. This must be set before decompressing anything, and
+    /// survives resets.
+    pub(crate) fn set_verify_checksum(&mut self, verify: bool) {
+        if let Decoder::Backend(inner) = &mut self.inner {
+            inner.set_verify_checksum(verify);
+        }
+    }
 }
 
 impl Error for DecompressError {}
@@ -538,6 +934,13 @@ impl fmt::Display for DecompressError {
 impl Error for CompressError {}
 
 impl CompressError {
+    /// Creates an error with the given message, for use by custom backends.
+    pub fn new(msg: &'static str) -> CompressError {
+        CompressError {
+            msg: ErrorMessage::new(msg),
+        }
+    }
+
     /// Retrieve the implementation's message about why the operation failed, if one exists.
     pub fn message(&self) -> Option<&str> {
         self.msg.get()
@@ -584,13 +987,13 @@ fn write_to_spare_capacity_of_vec<T>(
 
 #[cfg(test)]
 mod tests {
-    use std::io::Write;
+    use std::cmp;
+    use std::io::{Read, Write};
 
     use crate::write;
     use crate::{Compression, Decompress, FlushDecompress};
 
-    #[cfg(feature = "any_zlib")]
-    use crate::{Compress, FlushCompress};
+    use crate::{Compress, FlushCompress, Status};
 
     #[test]
     fn issue51() {
@@ -650,7 +1053,6 @@ mod tests {
         assert!(dst.starts_with(string));
     }
 
-    #[cfg(feature = "any_zlib")]
     #[test]
     fn set_dictionary_with_zlib_header() {
         let string = "hello, hello!".as_bytes();
@@ -699,7 +1101,6 @@ mod tests {
         assert_eq!(&decoded[..decoder.total_out() as usize], string);
     }
 
-    #[cfg(feature = "any_zlib")]
     #[test]
     fn set_dictionary_raw() {
         let string = "hello, hello!".as_bytes();
@@ -730,7 +1131,6 @@ mod tests {
         assert_eq!(&decoded[..decoder.total_out() as usize], string);
     }
 
-    #[cfg(feature = "any_zlib")]
     #[test]
     fn test_gzip_flate() {
         let string = "hello, hello!".as_bytes();
@@ -756,6 +1156,154 @@ mod tests {
         assert_eq!(&decoded[..decoder.total_out() as usize], string);
     }
 
+    #[test]
+    fn gzip_byte_by_byte() {
+        let string = "hello, hello!".repeat(100);
+        let mut e = crate::GzBuilder::new()
+            .filename("hello.txt")
+            .comment("greetings")
+            .extra(&b"xx"[..])
+            .write(Vec::new(), Compression::default());
+        e.write_all(string.as_bytes()).unwrap();
+        let encoded = e.finish().unwrap();
+
+        let mut decoder = Decompress::new_gzip(15);
+        let mut decoded = Vec::with_capacity(string.len());
+        let mut status = Status::Ok;
+        for byte in encoded.chunks(1) {
+            assert_ne!(status, Status::StreamEnd);
+            status = decoder
+                .decompress_vec(byte, &mut decoded, FlushDecompress::None)
+                .unwrap();
+        }
+        assert_eq!(status, Status::StreamEnd);
+        assert_eq!(decoder.total_in(), encoded.len() as u64);
+        assert_eq!(decoded, string.as_bytes());
+
+        let mut corrupt = encoded.clone();
+        let n = corrupt.len();
+        corrupt[n - 8] ^= 1;
+        let mut decoder = Decompress::new_gzip(15);
+        let mut decoded = Vec::with_capacity(string.len());
+        let err = decoder
+            .decompress_vec(&corrupt, &mut decoded, FlushDecompress::Finish)
+            .unwrap_err();
+        assert_eq!(err.message(), Some("incorrect data check"));
+    }
+
+    // Every backend skips the Adler-32 check the same way, whether the stream
+    // arrives in one piece or byte by byte, and still fails on corruption of
+    // the deflate data itself.
+    #[test]
+    fn skip_checksum() {
+        let string = "hello, hello!".repeat(100);
+        let dictionary = b"hello";
+        for &with_dictionary in &[false, true] {
+            let mut encoder = Compress::new(Compression::default(), true);
+            if with_dictionary {
+                encoder.set_dictionary(dictionary).unwrap();
+            }
+            let mut encoded = Vec::with_capacity(1024);
+            encoder
+                .compress_vec(string.as_bytes(), &mut encoded, FlushCompress::Finish)
+                .unwrap();
+            let n = encoded.len();
+            encoded[n - 1] ^= 1;
+
+            let decode = |input: &[u8], chunk: usize| {
+                let mut decoder = Decompress::new(true);
+                decoder.set_verify_checksum(false);
+                let mut decoded = Vec::with_capacity(string.len());
+                let mut status = Status::Ok;
+                let mut pos = 0;
+                while pos < input.len() && status != Status::StreamEnd {
+                    let end = cmp::min(pos + chunk, input.len());
+                    let before = decoder.total_in();
+                    status = match decoder.decompress_vec(
+                        &input[pos..end],
+                        &mut decoded,
+                        FlushDecompress::None,
+                    ) {
+                        Err(err) => match err.needs_dictionary() {
+                            Some(id) => {
+                                assert_eq!(decoder.set_dictionary(dictionary).unwrap(), id);
+                                Status::Ok
+                            }
+                            None => return Err(err),
+                        },
+                        Ok(status) => status,
+                    };
+                    pos += (decoder.total_in() - before) as usize;
+                }
+                assert_eq!(status, Status::StreamEnd);
+                assert_eq!(decoder.total_in(), input.len() as u64);
+                Ok(decoded)
+            };
+            assert_eq!(decode(&encoded, n).unwrap(), string.as_bytes());
+            assert_eq!(decode(&encoded, 1).unwrap(), string.as_bytes());
+
+            let mut decoder = Decompress::new(true);
+            let mut decoded = Vec::with_capacity(string.len());
+            if with_dictionary {
+                let err = decoder
+                    .decompress_vec(&encoded, &mut decoded, FlushDecompress::Finish)
+                    .unwrap_err();
+                assert!(err.needs_dictionary().is_some());
+                decoder.set_dictionary(dictionary).unwrap();
+            }
+            let rest = &encoded[decoder.total_in() as usize..];
+            let err = decoder
+                .decompress_vec(rest, &mut decoded, FlushDecompress::Finish)
+                .unwrap_err();
+            assert_eq!(err.message(), Some("incorrect data check"));
+
+            let mut corrupt = encoded.clone();
+            corrupt[n / 2] ^= 0x55;
+            corrupt[n / 2 + 1] ^= 0x55;
+            assert!(decode(&corrupt, n).is_err());
+        }
+    }
+
+    #[test]
+    fn set_level() {
+        let string = "hello, hello!".repeat(1000);
+        let mut encoder = Compress::new_with_window_bits(Compression::none(), true, 12);
+        let mut encoded = Vec::with_capacity(string.len() * 2);
+        for (i, chunk) in string.as_bytes().chunks(1000).enumerate() {
+            encoder.set_level(Compression::new(i as u32 % 10)).unwrap();
+            encoder
+                .compress_vec(chunk, &mut encoded, FlushCompress::Sync)
+                .unwrap();
+        }
+        encoder
+            .compress_vec(&[], &mut encoded, FlushCompress::Finish)
+            .unwrap();
+        assert!(encoded.len() < string.len() / 2);
+
+        let mut decoded = String::new();
+        crate::read::ZlibDecoder::new(&encoded[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, string);
+    }
+
+    #[test]
+    fn set_level_exhaustive() {
+        assert_ne!(Compression::new(10), Compression::exhaustive());
+        assert_eq!(
+            Compression::exhaustive().level(),
+            Compression::best().level()
+        );
+
+        let mut encoder = Compress::new(Compression::exhaustive(), false);
+        encoder.set_level(Compression::exhaustive()).unwrap();
+        assert!(encoder.set_level(Compression::best()).is_err());
+        let mut encoder = Compress::new(Compression::best(), false);
+        assert!(encoder.set_level(Compression::exhaustive()).is_err());
+        #[cfg(not(feature = "any_zlib"))]
+        encoder.set_level(Compression::new(10)).unwrap();
+    }
+
     #[cfg(feature = "any_zlib")]
     #[test]
     fn test_error_message() {
@@ -769,4 +1317,189 @@ mod tests {
 
         assert_eq!(err.message(), Some("invalid stored block lengths"));
     }
+
+    // Drops the first `bits` bits of `data`.
+    fn shift(data: &[u8], bits: u8) -> Vec<u8> {
+        (0..data.len())
+            .map(|i| {
+                let next = data.get(i + 1).map_or(0, |&b| u16::from(b) << 8);
+                ((u16::from(data[i]) | next) >> bits) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prime() {
+        let string = "hello, hello, hello!".repeat(10);
+        // The literal 200 takes 9 bits with the fixed Huffman codes.
+        let literal = 0x1c8u16.reverse_bits() >> 7;
+        for blocks in 0..4 {
+            for with_literal in [false, true] {
+                // The stream is started with empty blocks using the fixed
+                // Huffman codes, of 10 bits each, and then a block holding a
+                // literal, of 19 bits, all of which are primed.
+                let mut encoder = Compress::new(Compression::default(), false);
+                for _ in 0..blocks {
+                    encoder.prime(10, 2).unwrap();
+                }
+                if with_literal {
+                    encoder.prime(3, 2).unwrap();
+                    encoder.prime(9, literal).unwrap();
+                    encoder.prime(7, 0).unwrap();
+                }
+                let mut encoded = Vec::with_capacity(1024);
+                encoder
+                    .compress_vec(string.as_bytes(), &mut encoded, FlushCompress::Finish)
+                    .unwrap();
+                let mut expected = Vec::new();
+                if with_literal {
+                    expected.push(200);
+                }
+                expected.extend_from_slice(string.as_bytes());
+
+                let mut decoder = Decompress::new(false);
+                let mut decoded = [0; 1024];
+                decoder
+                    .decompress(&encoded, &mut decoded, FlushDecompress::Finish)
+                    .unwrap();
+                assert_eq!(&decoded[..decoder.total_out() as usize], &expected[..]);
+
+                // The first bits can be handed to the decoder separately.
+                let bits = (10 * blocks + if with_literal { 19 } else { 0 }).min(16);
+                let first = u32::from(encoded[0]) | u32::from(encoded[1]) << 8;
+                let first = (first & ((1 << bits) - 1)) as u16;
+                let rest = shift(&encoded[bits as usize / 8..], bits % 8);
+                let mut decoder = Decompress::new(false);
+                decoder.prime(bits, first).unwrap();
+                decoder
+                    .decompress(&rest, &mut decoded, FlushDecompress::Finish)
+                    .unwrap();
+                assert_eq!(&decoded[..decoder.total_out() as usize], &expected[..]);
+            }
+        }
+    }
+
+    // Decompresses `data` a block at a time, recording the totals and the
+    // unused bits at the end of every block.
+    #[cfg(all(feature = "any_zlib", feature = "miniz_oxide"))]
+    fn block_ends(backend: crate::Backend, data: &[u8], chunk: usize) -> Vec<(u64, u64, u8)> {
+        let mut decoder = Decompress::new_with_backend(false, backend);
+        let mut out = vec![0; 10_000];
+        let mut ends = Vec::new();
+        loop {
+            let start = decoder.total_in() as usize;
+            let input = &data[start..cmp::min(start + chunk, data.len())];
+            let (status, end) = decoder.decompress_block(input, &mut out).unwrap();
+            if let Some((bits, _)) = end {
+                ends.push((decoder.total_in(), decoder.total_out(), bits));
+            }
+            if status == Status::StreamEnd {
+                return ends;
+            }
+        }
+    }
+
+    #[cfg(all(feature = "any_zlib", feature = "miniz_oxide"))]
+    #[test]
+    fn decompress_block_backends() {
+        let mut data = "hello, hello, hello!".repeat(5_000).into_bytes();
+        let mut x = 1u32;
+        data.extend((0..100_000).map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (x >> 16) as u8
+        }));
+        for backend in [crate::Backend::Zlib, crate::Backend::Rust] {
+            for level in [0, 1, 6, 9] {
+                let mut encoder =
+                    Compress::new_with_backend(Compression::new(level), false, backend);
+                let mut encoded = Vec::with_capacity(data.len() + 1024);
+                encoder
+                    .compress_vec(&data[..50_000], &mut encoded, FlushCompress::Sync)
+                    .unwrap();
+                encoder
+                    .compress_vec(&data[50_000..], &mut encoded, FlushCompress::Finish)
+                    .unwrap();
+                for chunk in [7, 4096, encoded.len()] {
+                    let ends = block_ends(crate::Backend::Zlib, &encoded, chunk);
+                    assert!(ends.len() > 1);
+                    assert_eq!(block_ends(crate::Backend::Rust, &encoded, chunk), ends);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn window_bits() {
+        // Data repeating every KiB, which can't be compressed without a window
+        // reaching at least that far back.
+        let mut x = 1u32;
+        let period: Vec<u8> = (0..1024)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (x >> 16) as u8
+            })
+            .collect();
+        let data = period.repeat(64);
+        for bits in [9, 10, 15] {
+            let mut encoder = Compress::new_with_window_bits(Compression::default(), true, bits);
+            let mut encoded = Vec::with_capacity(2 * data.len());
+            encoder
+                .compress_vec(&data, &mut encoded, FlushCompress::Finish)
+                .unwrap();
+            assert_eq!(encoded[0] >> 4, bits - 8);
+            if bits < 15 {
+                assert!(encoded.len() > data.len() / 10 * 9);
+            } else {
+                assert!(encoded.len() < data.len() / 10);
+            }
+
+            let mut decoder = Decompress::new_with_window_bits(true, bits);
+            let mut decoded = vec![0; data.len()];
+            decoder
+                .decompress(&encoded, &mut decoded, FlushDecompress::Finish)
+                .unwrap();
+            assert!(decoded == data);
+
+            // The header asks for a larger window than the decoder has.
+            if bits == 9 {
+                continue;
+            }
+            let mut decoder = Decompress::new_with_window_bits(true, bits - 1);
+            let err = decoder
+                .decompress(&encoded, &mut decoded, FlushDecompress::Finish)
+                .unwrap_err();
+            assert_eq!(err.message(), Some("invalid window size"));
+        }
+    }
+
+    #[cfg(all(feature = "any_zlib", feature = "miniz_oxide"))]
+    #[test]
+    fn window_bits_backends() {
+        let mut x = 1u32;
+        let data: Vec<u8> = (0..100_000)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (x >> 16) as u8 & 3
+            })
+            .collect();
+        let backends = [crate::Backend::Zlib, crate::Backend::Rust];
+        for bits in [9, 12, 15] {
+            for (from, to) in backends.iter().flat_map(|&a| backends.map(|b| (a, b))) {
+                let level = Compression::default();
+                let mut encoder = Compress::new_gzip_with_backend(level, bits, from);
+                let mut encoded = Vec::with_capacity(data.len());
+                encoder
+                    .compress_vec(&data, &mut encoded, FlushCompress::Finish)
+                    .unwrap();
+
+                let mut decoder = Decompress::new_gzip_with_backend(bits, to);
+                let mut decoded = vec![0; data.len()];
+                let status = decoder
+                    .decompress(&encoded, &mut decoded, FlushDecompress::Finish)
+                    .unwrap();
+                assert_eq!(status, Status::StreamEnd);
+                assert!(decoded == data);
+            }
+        }
+    }
 }
@@ -0,0 +1,136 @@
+use std::mem;
+use std::ops::Range;
+
+use crate::zio::Ops;
+use crate::{Decompress, DecompressError, FlushDecompress, Status};
+
+/// Where one stream of a concatenation lies in the compressed input and in
+/// the uncompressed output, as reported by the multi-stream decoders.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamRange {
+    compressed: Range<u64>,
+    uncompressed: Range<u64>,
+}
+
+impl StreamRange {
+    /// The range of the stream in the compressed input, from the start of its
+    /// header, if any, to the end of its trailer.
+    pub fn compressed(&self) -> Range<u64> {
+        self.compressed.clone()
+    }
+
+    /// The range of the data decoded from the stream in the uncompressed
+    /// output.
+    pub fn uncompressed(&self) -> Range<u64> {
+        self.uncompressed.clone()
+    }
+}
+
+// A `Decompress` going through a concatenation of streams, starting over after
+// the end of each.
+//
+// The end of a stream is only reported as such once the input is exhausted,
+// so that the readers and writers of `zio` keep going, and the totals cover
+// all the streams.
+#[derive(Debug)]
+pub(crate) struct MultiDecompress {
+    data: Decompress,
+    zlib_header: bool,
+    ended: bool,
+    // The totals of the streams before the current one.
+    base_in: u64,
+    base_out: u64,
+    streams: Vec<StreamRange>,
+}
+
+impl MultiDecompress {
+    // Takes over `data`, which is reset for each stream after the first.
+    pub(crate) fn new(data: Decompress, zlib_header: bool) -> MultiDecompress {
+        MultiDecompress {
+            data,
+            zlib_header,
+            ended: false,
+            base_in: 0,
+            base_out: 0,
+            streams: Vec::new(),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.data.reset(self.zlib_header);
+        self.ended = false;
+        self.base_in = 0;
+        self.base_out = 0;
+        self.streams.clear();
+    }
+
+    pub(crate) fn total_in(&self) -> u64 {
+        self.base_in + self.data.total_in()
+    }
+
+    pub(crate) fn total_out(&self) -> u64 {
+        self.base_out + self.data.total_out()
+    }
+
+    pub(crate) fn take_streams(&mut self) -> Vec<StreamRange> {
+        mem::take(&mut self.streams)
+    }
+
+    fn run_with<F>(&mut self, input: &[u8], run: F) -> Result<Status, DecompressError>
+    where
+        F: FnOnce(&mut Decompress, &[u8]) -> Result<Status, DecompressError>,
+    {
+        if self.ended {
+            if input.is_empty() {
+                return Ok(Status::StreamEnd);
+            }
+            self.base_in += self.data.total_in();
+            self.base_out += self.data.total_out();
+            self.data.reset(self.zlib_header);
+            self.ended = false;
+        }
+        match run(&mut self.data, input)? {
+            Status::StreamEnd => {
+                self.ended = true;
+                self.streams.push(StreamRange {
+                    compressed: self.base_in..self.total_in(),
+                    uncompressed: self.base_out..self.total_out(),
+                });
+                if input.is_empty() {
+                    Ok(Status::StreamEnd)
+                } else {
+                    Ok(Status::Ok)
+                }
+            }
+            status => Ok(status),
+        }
+    }
+}
+
+impl Ops for MultiDecompress {
+    type Flush = FlushDecompress;
+    fn total_in(&self) -> u64 {
+        self.total_in()
+    }
+    fn total_out(&self) -> u64 {
+        self.total_out()
+    }
+    fn run(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushDecompress,
+    ) -> Result<Status, DecompressError> {
+        self.run_with(input, |data, input| data.decompress(input, output, flush))
+    }
+    fn run_vec(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        flush: FlushDecompress,
+    ) -> Result<Status, DecompressError> {
+        self.run_with(input, |data, input| {
+            data.decompress_vec(input, output, flush)
+        })
+    }
+}
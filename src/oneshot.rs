@@ -0,0 +1,229 @@
+//! Compressing and decompressing whole buffers in a single call.
+//!
+//! When all of the data is in memory already, it can be handed over at once
+//! rather than through the [`read`](mod@crate::read) and [`write`](mod@crate::write)
+//! types. With the `libdeflate` feature enabled, the functions of this module
+//! use libdeflate, which is considerably faster than the streaming backends
+//! when given whole buffers. Otherwise, and for levels libdeflate doesn't
+//! have, they fall back to the backend the streaming types use.
+//!
+//! # Examples
+//!
+//! ```
+//! use flate2::{oneshot, Compression, Format};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let compressed = oneshot::compress(b"Hello World", Format::Gzip, Compression::default())?;
+//! let decompressed = oneshot::decompress(&compressed, Format::Gzip)?;
+//! assert_eq!(decompressed, b"Hello World");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::io::prelude::*;
+
+use crate::{bufread, write, Compression, DecoderOptions, Format};
+
+/// Compresses `data` into a single stream of the given `format`.
+///
+/// Gzip members are written with the default header, as with
+/// [`write::GzEncoder::new`].
+///
+/// # Errors
+///
+/// An error is returned for [`Format::Uncompressed`].
+pub fn compress(data: &[u8], format: Format, level: Compression) -> io::Result<Vec<u8>> {
+    if format == Format::Uncompressed {
+        return Err(uncompressed());
+    }
+    #[cfg(feature = "libdeflate")]
+    {
+        if let Some(out) = crate::ffi::libdeflate::compress(data, format, level) {
+            return Ok(out);
+        }
+    }
+    match format {
+        Format::Gzip => {
+            let mut e = write::GzEncoder::new(Vec::new(), level);
+            e.write_all(data)?;
+            e.finish()
+        }
+        Format::Zlib => {
+            let mut e = write::ZlibEncoder::new(Vec::new(), level);
+            e.write_all(data)?;
+            e.finish()
+        }
+        _ => {
+            let mut e = write::DeflateEncoder::new(Vec::new(), level);
+            e.write_all(data)?;
+            e.finish()
+        }
+    }
+}
+
+/// Decompresses the stream of the given `format` at the start of `data`.
+///
+/// Only the first member of gzip data is decompressed, and anything following
+/// the stream is ignored.
+///
+/// # Errors
+///
+/// An error is returned for invalid or truncated input, checksum mismatches
+/// included, and for [`Format::Uncompressed`].
+pub fn decompress(data: &[u8], format: Format) -> io::Result<Vec<u8>> {
+    if format == Format::Uncompressed {
+        return Err(uncompressed());
+    }
+    #[cfg(feature = "libdeflate")]
+    return crate::ffi::libdeflate::decompress(data, format);
+    #[cfg(not(feature = "libdeflate"))]
+    decompress_streaming(data, format)
+}
+
+// Decompresses like `decompress` does, with the streaming decoders.
+pub(crate) fn decompress_streaming(data: &[u8], format: Format) -> io::Result<Vec<u8>> {
+    let options = DecoderOptions::new().report_truncation(true);
+    let mut out = Vec::new();
+    match format {
+        Format::Gzip => bufread::GzDecoder::new_with_options(data, options).read_to_end(&mut out),
+        Format::Zlib => bufread::ZlibDecoder::new_with_options(data, options).read_to_end(&mut out),
+        Format::Deflate => {
+            bufread::DeflateDecoder::new_with_options(data, options).read_to_end(&mut out)
+        }
+        Format::Uncompressed => return Err(uncompressed()),
+    }?;
+    Ok(out)
+}
+
+fn uncompressed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "uncompressed data has no compressed format",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, GzBuilder};
+
+    #[test]
+    fn roundtrip() {
+        let data = crate::random_bytes()
+            .take(1000)
+            .collect::<Vec<_>>()
+            .repeat(100);
+        let levels = [
+            Compression::none(),
+            Compression::fast(),
+            Compression::best(),
+        ];
+        for &format in &[Format::Gzip, Format::Zlib, Format::Deflate] {
+            for &level in &levels {
+                let compressed = compress(&data, format, level).unwrap();
+                assert!(decompress(&compressed, format).unwrap() == data);
+            }
+            assert_eq!(
+                decompress(&compress(&[], format, levels[1]).unwrap(), format).unwrap(),
+                b""
+            );
+        }
+
+        let mut compressed = compress(b"hello", Format::Zlib, Compression::default()).unwrap();
+        compressed.extend_from_slice(b"trailing");
+        assert_eq!(decompress(&compressed, Format::Zlib).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn streams_interoperate() {
+        let mut e = GzBuilder::new()
+            .filename("hello.txt")
+            .write(Vec::new(), Compression::default());
+        e.write_all(b"hello world").unwrap();
+        let gz = e.finish().unwrap();
+        assert_eq!(decompress(&gz, Format::Gzip).unwrap(), b"hello world");
+
+        let compressed = compress(b"hello world", Format::Gzip, Compression::best()).unwrap();
+        let mut out = String::new();
+        bufread::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, "hello world");
+    }
+
+    #[test]
+    fn errors() {
+        let data = b"hello hello hello hello";
+        let compressed = compress(data, Format::Gzip, Compression::default()).unwrap();
+        for len in [0, 5, compressed.len() - 1] {
+            assert!(decompress(&compressed[..len], Format::Gzip).is_err());
+        }
+        let mut corrupt = compressed.clone();
+        let n = corrupt.len();
+        corrupt[n - 6] ^= 1;
+        let err = decompress(&corrupt, Format::Gzip).unwrap_err();
+        assert!(err.get_ref().unwrap().downcast_ref::<Error>().is_some());
+
+        // Whatever decompresses, the errors are those of the streaming
+        // decoders, but for the Adler-32 mismatches libdeflate reports at the
+        // zlib trailer.
+        let error = |data: &[u8], format: Format| {
+            let err = decompress(data, format).unwrap_err();
+            if cfg!(feature = "libdeflate") && format == Format::Zlib {
+                let inner = err.get_ref().and_then(|e| e.downcast_ref::<Error>());
+                if let Some(Error::DataCrcMismatch { offset }) = inner {
+                    return Error::DataCrcMismatch { offset: *offset };
+                }
+            }
+            let expected = decompress_streaming(data, format).unwrap_err();
+            assert_eq!(err.kind(), expected.kind());
+            let err = err.into_inner().unwrap().downcast::<Error>().unwrap();
+            let expected = expected.into_inner().unwrap().downcast::<Error>().unwrap();
+            assert_eq!(format!("{:?}", err), format!("{:?}", expected));
+            *err
+        };
+        for &format in &[Format::Gzip, Format::Zlib, Format::Deflate] {
+            let compressed = compress(data, format, Compression::default()).unwrap();
+            let n = compressed.len();
+            for len in [0, 1, 5, 12, n - 5, n - 1] {
+                error(&compressed[..len.min(n - 1)], format);
+            }
+            // Raw deflate data has no checksum to notice all corruption.
+            if format != Format::Deflate {
+                let mut corrupt = compressed.clone();
+                corrupt[n / 2] ^= 0x55;
+                error(&corrupt, format);
+            }
+        }
+        match error(&corrupt, Format::Gzip) {
+            Error::DataCrcMismatch { offset } => assert_eq!(offset, n as u64 - 8),
+            err => panic!("unexpected error: {}", err),
+        }
+        let mut corrupt = compressed.clone();
+        corrupt[n - 2] ^= 1;
+        match error(&corrupt, Format::Gzip) {
+            Error::IsizeMismatch { offset } => assert_eq!(offset, n as u64 - 4),
+            err => panic!("unexpected error: {}", err),
+        }
+        match error(&compressed[..n - 3], Format::Gzip) {
+            Error::Truncated { offset } => assert_eq!(offset, n as u64 - 3),
+            err => panic!("unexpected error: {}", err),
+        }
+        let mut corrupt = compress(data, Format::Zlib, Compression::default()).unwrap();
+        let n = corrupt.len();
+        corrupt[n - 1] ^= 1;
+        match error(&corrupt, Format::Zlib) {
+            Error::DataCrcMismatch { offset } if cfg!(feature = "libdeflate") => {
+                assert_eq!(offset, n as u64 - 4)
+            }
+            Error::Data { error, .. } if !cfg!(feature = "libdeflate") => {
+                assert_eq!(error.unwrap().message(), Some("incorrect data check"))
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+
+        assert!(compress(data, Format::Uncompressed, Compression::default()).is_err());
+        assert!(decompress(data, Format::Uncompressed).is_err());
+    }
+}
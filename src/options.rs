@@ -0,0 +1,216 @@
+use crate::{Backend, Compress, Compression, Decompress, Limits};
+
+/// Options controlling how strictly a decoder treats its input.
+///
+/// The defaults match the behavior of the decoders created with `new`: all
+/// checksums are verified, anything following the last member of a
+/// multi-member gzip stream must be another member, and nothing is limited.
+/// Decoders created with `new_with_options` can be made more lenient, for
+/// instance to read files padded with zeros or to recover what a truncated
+/// file holds, or faster by skipping checksums.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::read::MultiGzDecoder;
+/// use flate2::write::GzEncoder;
+/// use flate2::{Compression, DecoderOptions};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut e = GzEncoder::new(Vec::new(), Compression::default());
+/// e.write_all(b"Hello World")?;
+/// let mut bytes = e.finish()?;
+/// // Tape drives pad what they write with zeros up to the block size.
+/// bytes.resize(512, 0);
+///
+/// let options = DecoderOptions::new().ignore_trailing_zeros(true);
+/// let mut d = MultiGzDecoder::new_with_options(&bytes[..], options);
+/// let mut s = String::new();
+/// d.read_to_string(&mut s)?;
+/// assert_eq!(s, "Hello World");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecoderOptions {
+    pub(crate) limits: Limits,
+    pub(crate) ignore_trailing_zeros: bool,
+    pub(crate) ignore_trailing_garbage: bool,
+    pub(crate) report_truncation: bool,
+    pub(crate) verify_checksums: bool,
+    pub(crate) max_header_field_len: usize,
+    pub(crate) backend: Option<Backend>,
+}
+
+impl DecoderOptions {
+    /// Creates the default set of options.
+    pub fn new() -> DecoderOptions {
+        DecoderOptions {
+            limits: Limits::new(),
+            ignore_trailing_zeros: false,
+            ignore_trailing_garbage: false,
+            report_truncation: false,
+            verify_checksums: true,
+            max_header_field_len: crate::gz::MAX_HEADER_BUF,
+            backend: None,
+        }
+    }
+
+    /// Sets the [`Limits`] the decoder fails on, none by default.
+    pub fn limits(mut self, limits: Limits) -> DecoderOptions {
+        self.limits = limits;
+        self
+    }
+
+    /// Skips runs of zero bytes following a member of a multi-member gzip
+    /// stream, such as the padding added by tape drives, instead of failing
+    /// on them as an invalid header.
+    ///
+    /// This has no effect on other decoders, which never look past the end of
+    /// their stream.
+    pub fn ignore_trailing_zeros(mut self, ignore: bool) -> DecoderOptions {
+        self.ignore_trailing_zeros = ignore;
+        self
+    }
+
+    /// Stops decoding a multi-member gzip stream when a member is followed by
+    /// something other than the start of another member, instead of failing.
+    ///
+    /// Only the absence of the gzip magic bytes is taken as the end of the
+    /// stream: a member whose header is damaged past them is still an error.
+    /// When reading, the data that follows is left unread in the underlying
+    /// reader. This has no effect on other decoders, which never look past the
+    /// end of their stream.
+    pub fn ignore_trailing_garbage(mut self, ignore: bool) -> DecoderOptions {
+        self.ignore_trailing_garbage = ignore;
+        self
+    }
+
+    /// Reports input that ends before the stream is complete with an
+    /// [`Error::Truncated`] error, once all the data that could be decoded
+    /// from it has been returned or written.
+    ///
+    /// By default, depending on the backend, the data decoded last may be
+    /// lost and the stream reported as corrupt instead, and a truncated zlib
+    /// or deflate stream may even be taken as complete.
+    ///
+    /// [`Error::Truncated`]: crate::Error::Truncated
+    pub fn report_truncation(mut self, report: bool) -> DecoderOptions {
+        self.report_truncation = report;
+        self
+    }
+
+    /// Sets whether checksums are verified, which they are by default.
+    ///
+    /// Turning this off skips the CRC-32 and size check of gzip trailers, the
+    /// CRC of gzip headers, and the Adler-32 check of zlib streams. It saves a
+    /// little time on input that is known to be intact, but corruption that
+    /// still decodes as valid deflate data goes unnoticed.
+    pub fn verify_checksums(mut self, verify: bool) -> DecoderOptions {
+        self.verify_checksums = verify;
+        self
+    }
+
+    /// Sets the maximum length in bytes of the file name and comment fields of
+    /// gzip headers, 65535 by default.
+    ///
+    /// Longer fields fail with an [`Error::HeaderFieldTooLong`] error.
+    ///
+    /// [`Error::HeaderFieldTooLong`]: crate::Error::HeaderFieldTooLong
+    pub fn max_header_field_len(mut self, len: usize) -> DecoderOptions {
+        self.max_header_field_len = len;
+        self
+    }
+
+    /// Sets the built-in [`Backend`] decompressing the data, rather than the
+    /// default one at the time the decoder is created.
+    pub fn backend(mut self, backend: Backend) -> DecoderOptions {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub(crate) fn decompress(&self, zlib_header: bool) -> Decompress {
+        let backend = self.backend.unwrap_or_default();
+        let mut data = Decompress::new_with_backend(zlib_header, backend);
+        data.set_verify_checksum(self.verify_checksums);
+        data
+    }
+}
+
+impl Default for DecoderOptions {
+    fn default() -> DecoderOptions {
+        DecoderOptions::new()
+    }
+}
+
+/// Options controlling the output of an encoder beyond its compression level.
+///
+/// The defaults match the behavior of the encoders created with `new`.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::write::GzEncoder;
+/// use flate2::{Compression, EncoderOptions};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let options = EncoderOptions::new().rsyncable(true);
+/// let mut e = GzEncoder::new_with_options(Vec::new(), Compression::default(), options);
+/// e.write_all(b"Hello World")?;
+/// let compressed = e.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderOptions {
+    pub(crate) rsyncable: bool,
+    pub(crate) backend: Option<Backend>,
+}
+
+impl EncoderOptions {
+    /// Creates the default set of options.
+    pub fn new() -> EncoderOptions {
+        EncoderOptions {
+            rsyncable: false,
+            backend: None,
+        }
+    }
+
+    /// Makes the output rsyncable, like the `--rsyncable` option of gzip and
+    /// pigz do.
+    ///
+    /// The compressor then does a full flush at points picked by a rolling
+    /// hash of the input, so that after a local change to the input, the
+    /// output is the same again from the next such point on. Tools such as
+    /// rsync or deduplicating backups only have to deal with the part of the
+    /// compressed file around the change. This costs a little in compression
+    /// ratio. See [`Compress::set_rsyncable`] for details.
+    ///
+    /// [`Compress::set_rsyncable`]: crate::Compress::set_rsyncable
+    pub fn rsyncable(mut self, rsyncable: bool) -> EncoderOptions {
+        self.rsyncable = rsyncable;
+        self
+    }
+
+    /// Sets the built-in [`Backend`] compressing the data, rather than the
+    /// default one at the time the encoder is created.
+    pub fn backend(mut self, backend: Backend) -> EncoderOptions {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub(crate) fn compress(&self, level: Compression, zlib_header: bool) -> Compress {
+        let backend = self.backend.unwrap_or_default();
+        let mut data = Compress::new_with_backend(level, zlib_header, backend);
+        data.set_rsyncable(self.rsyncable);
+        data
+    }
+}
+
+impl Default for EncoderOptions {
+    fn default() -> EncoderOptions {
+        EncoderOptions::new()
+    }
+}
@@ -0,0 +1,271 @@
+//! Multithreaded encoders and decoders.
+//!
+//! The work is split into independent pieces which are handed to a small pool
+//! of worker threads, while the results are collected in order by the thread
+//! driving the stream. At most a bounded number of pieces is in flight at any
+//! time, so memory usage doesn't grow with the size of the stream.
+
+use std::collections::VecDeque;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::gz::GzBuilder;
+use crate::{Compression, Limits};
+
+pub mod read;
+pub mod write;
+
+const DEFAULT_BLOCK_SIZE: usize = 128 * 1024;
+
+/// A builder for the multithreaded encoders and decoders, configuring the
+/// number of worker threads and how the input is split up among them.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::{Compression, ParBuilder};
+///
+/// let mut e = ParBuilder::new()
+///     .threads(4)
+///     .block_size(64 * 1024)
+///     .gz_write(Vec::new(), Compression::default());
+/// e.write_all(b"Hello World").unwrap();
+/// let compressed = e.finish().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct ParBuilder {
+    threads: usize,
+    block_size: usize,
+    header: Option<GzBuilder>,
+    limits: Limits,
+}
+
+impl Default for ParBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParBuilder {
+    /// Create a new blank builder, using as many threads as there are
+    /// available cores and blocks of 128 KiB.
+    pub fn new() -> ParBuilder {
+        ParBuilder {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            block_size: DEFAULT_BLOCK_SIZE,
+            header: None,
+            limits: Limits::new(),
+        }
+    }
+
+    /// Configure the number of worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero.
+    pub fn threads(mut self, threads: usize) -> ParBuilder {
+        assert!(threads > 0, "at least one thread is required");
+        self.threads = threads;
+        self
+    }
+
+    /// Configure the size of the blocks the input is split into.
+    ///
+    /// Every block is compressed on its own, using the 32 KiB preceding it as
+    /// a preset dictionary. Smaller blocks allow more parallelism at the
+    /// expense of a slightly worse compression ratio.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block_size` is zero.
+    pub fn block_size(mut self, block_size: usize) -> ParBuilder {
+        assert!(block_size > 0, "the block size can't be zero");
+        self.block_size = block_size;
+        self
+    }
+
+    /// Configure the header emitted by the gzip encoder, which defaults to the
+    /// one of a blank `GzBuilder`.
+    pub fn gz_header(mut self, header: GzBuilder) -> ParBuilder {
+        self.header = Some(header);
+        self
+    }
+
+    /// Configure the [`Limits`] the decoder fails on, none by default.
+    pub fn limits(mut self, limits: Limits) -> ParBuilder {
+        self.limits = limits;
+        self
+    }
+
+    /// Consume this builder, creating a multithreaded gzip encoder writing
+    /// the compressed data to `w`.
+    pub fn gz_write<W: io::Write>(self, w: W, level: Compression) -> write::ParGzEncoder<W> {
+        write::gz_encoder(self, w, level)
+    }
+
+    /// Consume this builder, creating a multithreaded zlib encoder writing the
+    /// compressed data to `w`.
+    pub fn zlib_write<W: io::Write>(self, w: W, level: Compression) -> write::ParZlibEncoder<W> {
+        write::zlib_encoder(self, w, level)
+    }
+
+    /// Consume this builder, creating a multithreaded decoder for all the
+    /// members of the gzip file read from `r`.
+    ///
+    /// Only the number of threads and the limits apply to decoding.
+    pub fn multi_gz_read<R: io::Read>(self, r: R) -> read::ParMultiGzDecoder<R> {
+        read::multi_gz_decoder(self, r)
+    }
+
+    /// Consume this builder, creating a multithreaded raw deflate encoder
+    /// writing the compressed data to `w`.
+    pub fn deflate_write<W: io::Write>(
+        self,
+        w: W,
+        level: Compression,
+    ) -> write::ParDeflateEncoder<W> {
+        write::deflate_encoder(self, w, level)
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of worker threads, spawned lazily on the first job.
+struct ThreadPool {
+    threads: usize,
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    fn new(threads: usize) -> ThreadPool {
+        ThreadPool {
+            threads,
+            jobs: None,
+            workers: Vec::new(),
+        }
+    }
+
+    fn spawn(&mut self, job: Job) -> io::Result<()> {
+        if self.jobs.is_none() {
+            let (tx, rx) = mpsc::channel::<Job>();
+            let rx = Arc::new(Mutex::new(rx));
+            for _ in 0..self.threads {
+                let rx = rx.clone();
+                let worker = thread::Builder::new()
+                    .name("flate2-worker".to_string())
+                    .spawn(move || loop {
+                        let job = match rx.lock() {
+                            Ok(rx) => rx.recv(),
+                            Err(_) => return,
+                        };
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => return,
+                        }
+                    })?;
+                self.workers.push(worker);
+            }
+            self.jobs = Some(tx);
+        }
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .map_err(|_| worker_lost())
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel makes the workers exit once the queued jobs are
+        // done.
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl std::fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThreadPool")
+            .field("threads", &self.threads)
+            .field("running", &self.workers.len())
+            .finish()
+    }
+}
+
+/// Runs jobs on a `ThreadPool` and hands their results back in submission
+/// order, with at most `2 * threads` of them in flight.
+#[derive(Debug)]
+pub(crate) struct Ordered<T> {
+    pool: ThreadPool,
+    pending: VecDeque<Arc<Slot<T>>>,
+}
+
+/// Where a job stores its result for the thread waiting on it.
+#[derive(Debug)]
+struct Slot<T> {
+    result: Mutex<Option<io::Result<T>>>,
+    ready: Condvar,
+}
+
+impl<T: Send + 'static> Ordered<T> {
+    pub(crate) fn new(threads: usize) -> Ordered<T> {
+        Ordered {
+            pool: ThreadPool::new(threads),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Whether another job can be submitted without going over the limit.
+    pub(crate) fn has_room(&self) -> bool {
+        self.pending.len() < 2 * self.pool.threads
+    }
+
+    /// Forgets about all the jobs in flight, whose results are thrown away.
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    pub(crate) fn submit<F>(&mut self, f: F) -> io::Result<()>
+    where
+        F: FnOnce() -> io::Result<T> + Send + 'static,
+    {
+        let slot = Arc::new(Slot {
+            result: Mutex::new(None),
+            ready: Condvar::new(),
+        });
+        let theirs = slot.clone();
+        self.pool.spawn(Box::new(move || {
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| Err(worker_lost()));
+            *theirs.result.lock().unwrap() = Some(result);
+            theirs.ready.notify_one();
+        }))?;
+        self.pending.push_back(slot);
+        Ok(())
+    }
+
+    /// Blocks until the oldest job completes, returning `None` if there is
+    /// nothing in flight.
+    pub(crate) fn next(&mut self) -> Option<io::Result<T>> {
+        let slot = self.pending.pop_front()?;
+        let mut result = slot.result.lock().unwrap();
+        loop {
+            match result.take() {
+                Some(result) => return Some(result),
+                None => result = slot.ready.wait(result).unwrap(),
+            }
+        }
+    }
+}
+
+fn worker_lost() -> io::Error {
+    io::Error::other("a worker thread panicked")
+}
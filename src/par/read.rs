@@ -0,0 +1,642 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+
+use super::{Ordered, ParBuilder};
+use crate::bgzf;
+use crate::gz::{bad_header, check_trailer, GzHeader, GzHeaderParser};
+use crate::limits::Limiter;
+use crate::{Crc, Decompress, Error, FlushDecompress, Limits, Status};
+
+// How much compressed data a member whose size isn't recorded in its header
+// may span before giving up on finding its end ahead of time, and decoding it
+// on the calling thread instead.
+const MAX_SPECULATIVE_SPAN: usize = 8 * 1024 * 1024;
+
+const READ_SIZE: usize = 64 * 1024;
+
+// Whether `data` looks like the start of a gzip member: the magic bytes, the
+// deflate method and no reserved flags.
+fn is_candidate(data: &[u8]) -> bool {
+    data[0] == 0x1f && data[1] == 0x8b && data[2] == 8 && data[3] & 0xe0 == 0
+}
+
+/// The outcome of inflating a span of the input.
+#[derive(Debug)]
+enum Inflated {
+    /// The uncompressed data of the member which exactly fills the span.
+    Member(Vec<u8>),
+    /// The member doesn't end within the span, so the span was wrongly guessed.
+    Truncated,
+}
+
+// Inflates the single member held by `span`, which starts at `start` and
+// whose header is `header_len` bytes long, and verifies its trailer. Stops
+// early once the output is larger than `max_output`, which is then more than
+// the limits allow for the member.
+fn inflate_member(
+    span: Vec<u8>,
+    start: u64,
+    header_len: usize,
+    max_output: u64,
+) -> io::Result<Inflated> {
+    let body = &span[header_len..];
+    let body_offset = start + header_len as u64;
+    let mut data = Decompress::new(false);
+    let mut out = Vec::with_capacity(body.len() * 3);
+    loop {
+        if out.len() == out.capacity() {
+            out.reserve(cmp::max(out.capacity(), 32 * 1024));
+        }
+        let consumed = data.total_in() as usize;
+        let status = data
+            .decompress_vec(&body[consumed..], &mut out, FlushDecompress::None)
+            .map_err(|err| Error::decompress(err, body_offset + data.total_in()))?;
+        if out.len() as u64 > max_output {
+            return Ok(Inflated::Member(out));
+        }
+        match status {
+            Status::StreamEnd => break,
+            Status::Ok | Status::BufError => {
+                if data.total_in() as usize == body.len() && out.len() < out.capacity() {
+                    return Ok(Inflated::Truncated);
+                }
+            }
+        }
+    }
+
+    let trailer = match body.get(data.total_in() as usize..) {
+        Some(trailer) if trailer.len() >= 8 => trailer,
+        _ => return Ok(Inflated::Truncated),
+    };
+    let mut crc = Crc::new();
+    crc.update(&out);
+    check_trailer(trailer, &crc, body_offset + data.total_in())?;
+    if trailer.len() > 8 {
+        // Whatever follows the member isn't another member.
+        return Err(bad_header());
+    }
+    Ok(Inflated::Member(out))
+}
+
+/// A member decoded on the calling thread, as its size couldn't be determined
+/// ahead of time.
+#[derive(Debug)]
+struct Streaming {
+    data: Decompress,
+    crc: Crc,
+}
+
+/// A multithreaded decoder for gzip files made of many members, such as the
+/// BGZF files used in bioinformatics or the output of `pigz --independent`.
+///
+/// The members are located ahead of time and inflated concurrently on a pool
+/// of worker threads, while the uncompressed data is handed out in order
+/// through the `Read` interface. The checksum and size of every member are
+/// verified just like [`MultiGzDecoder`](crate::read::MultiGzDecoder) does.
+///
+/// The size of BGZF blocks is recorded in their header. For other members the
+/// decoder speculatively looks for the header of the next member, and falls
+/// back to decoding a member on the calling thread when it is too large to
+/// find its end within a few megabytes. A file consisting of a single large
+/// member is thus decoded as fast as with `MultiGzDecoder`, but no faster.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::read::ParMultiGzDecoder;
+/// # use flate2::Compression;
+/// # use flate2::write::GzEncoder;
+///
+/// # let mut e = GzEncoder::new(Vec::new(), Compression::default());
+/// # e.write_all(b"Hello ").unwrap();
+/// # let mut bytes = e.finish().unwrap();
+/// # let mut e = GzEncoder::new(Vec::new(), Compression::default());
+/// # e.write_all(b"World").unwrap();
+/// # bytes.extend(e.finish().unwrap());
+/// let mut d = ParMultiGzDecoder::new(&bytes[..]);
+/// let mut s = String::new();
+/// d.read_to_string(&mut s).unwrap();
+/// assert_eq!(s, "Hello World");
+/// ```
+#[derive(Debug)]
+pub struct ParMultiGzDecoder<R> {
+    reader: R,
+    eof: bool,
+    // Compressed data from the start of the oldest member in flight onwards,
+    // `base` being the offset of `input[0]` in the stream.
+    input: Vec<u8>,
+    base: u64,
+    // Where the next member to schedule starts.
+    next: u64,
+    // When retrying a member after wrongly guessing where it ends, the offset
+    // before which it can't end.
+    min_end: u64,
+    jobs: Ordered<Inflated>,
+    spans: VecDeque<(u64, u64)>,
+    output: Vec<u8>,
+    output_pos: usize,
+    // Set once a member too large to schedule has been met at `next`.
+    large_member: Option<usize>,
+    streaming: Option<Streaming>,
+    limiter: Limiter,
+    // Whether the output of a member has been handed out yet.
+    started: bool,
+    // Where decoding stopped once a limit was exceeded.
+    exceeded_at: u64,
+}
+
+impl<R> ParMultiGzDecoder<R> {
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Acquires a mutable reference to the underlying stream.
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    ///
+    /// Note that the decoder reads ahead of the data it hands out, so the
+    /// position of the reader is unspecified.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+pub(crate) fn multi_gz_decoder<R: Read>(b: ParBuilder, r: R) -> ParMultiGzDecoder<R> {
+    ParMultiGzDecoder {
+        reader: r,
+        eof: false,
+        input: Vec::new(),
+        base: 0,
+        next: 0,
+        min_end: 0,
+        jobs: Ordered::new(b.threads),
+        spans: VecDeque::new(),
+        output: Vec::new(),
+        output_pos: 0,
+        large_member: None,
+        streaming: None,
+        limiter: Limiter::new(b.limits),
+        started: false,
+        exceeded_at: 0,
+    }
+}
+
+impl<R: Read> ParMultiGzDecoder<R> {
+    /// Creates a new decoder from the given reader, using as many threads as
+    /// there are available cores.
+    pub fn new(r: R) -> ParMultiGzDecoder<R> {
+        ParBuilder::new().multi_gz_read(r)
+    }
+
+    /// Creates a new decoder from the given reader, using as many threads as
+    /// there are available cores, and failing once any of the given `limits`
+    /// is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> ParMultiGzDecoder<R> {
+        ParBuilder::new().limits(limits).multi_gz_read(r)
+    }
+
+    fn end(&self) -> u64 {
+        self.base + self.input.len() as u64
+    }
+
+    fn slice(&self, from: u64) -> &[u8] {
+        &self.input[(from - self.base) as usize..]
+    }
+
+    // Reads more input, returning false at EOF.
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        let len = self.input.len();
+        self.input.resize(len + READ_SIZE, 0);
+        let ret = loop {
+            match self.reader.read(&mut self.input[len..]) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                ret => break ret,
+            }
+        };
+        let n = *ret.as_ref().unwrap_or(&0);
+        self.input.truncate(len + n);
+        ret?;
+        self.eof = n == 0;
+        Ok(!self.eof)
+    }
+
+    // Drops the input before `offset`, which nothing refers to anymore.
+    fn discard(&mut self, offset: u64) {
+        self.input.drain(..(offset - self.base) as usize);
+        self.base = offset;
+    }
+
+    // Parses the header of the member at `next`, returning it along with its
+    // length, or `None` at the end of the stream.
+    fn header(&mut self) -> io::Result<Option<(GzHeader, usize)>> {
+        loop {
+            let data = self.slice(self.next);
+            let mut rest = data;
+            let mut parser = GzHeaderParser::new();
+            match parser.parse(&mut rest) {
+                Ok(()) => {
+                    let len = data.len() - rest.len();
+                    return Ok(Some((parser.into(), len)));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    let empty = data.is_empty();
+                    if !self.fill()? {
+                        return if empty {
+                            Ok(None)
+                        } else {
+                            Err(Error::Truncated { offset: self.end() }.into())
+                        };
+                    }
+                }
+                Err(e) => return Err(Error::rebase(e, self.next)),
+            }
+        }
+    }
+
+    // Hands out members to the workers until enough of them are in flight,
+    // the end of the stream is reached, or a member is too large to locate.
+    fn schedule(&mut self) -> io::Result<()> {
+        while self.jobs.has_room() && self.large_member.is_none() {
+            let start = self.next;
+            let (header, header_len) = match self.header() {
+                Ok(Some(header)) => header,
+                Ok(None) => return Ok(()),
+                // The header may be a false positive, which is only known once
+                // the members scheduled before it are inflated.
+                Err(_) if !self.spans.is_empty() => return Ok(()),
+                Err(e) => return Err(e),
+            };
+
+            let (end, exact) = match bgzf::block_size(&header) {
+                Some(size) => {
+                    let end = start + size as u64;
+                    while self.end() < end {
+                        if !self.fill()? {
+                            if !self.spans.is_empty() {
+                                return Ok(());
+                            }
+                            return Err(Error::Truncated { offset: self.end() }.into());
+                        }
+                    }
+                    (end, true)
+                }
+                None => {
+                    let from = cmp::max(start + header_len as u64, self.min_end);
+                    match self.find_candidate(start, from)? {
+                        Some(end) => (end, false),
+                        None if self.eof => (self.end(), true),
+                        None => {
+                            self.large_member = Some(header_len);
+                            return Ok(());
+                        }
+                    }
+                }
+            };
+
+            let span =
+                self.input[(start - self.base) as usize..(end - self.base) as usize].to_vec();
+            let max_output = self.limiter.max_output_within(end);
+            self.jobs.submit(move || {
+                match inflate_member(span, start, header_len, max_output)? {
+                    Inflated::Truncated if exact => Err(Error::Truncated { offset: end }.into()),
+                    inflated => Ok(inflated),
+                }
+            })?;
+            self.spans.push_back((start, end));
+            self.next = end;
+            self.min_end = 0;
+        }
+        Ok(())
+    }
+
+    // Looks for the first candidate member header at or after `from`, reading
+    // until one is found, the end of the stream is reached, or the member
+    // starting at `start` spans too much data.
+    fn find_candidate(&mut self, start: u64, from: u64) -> io::Result<Option<u64>> {
+        let mut pos = from;
+        loop {
+            let data = self.slice(pos);
+            if let Some(i) = data.windows(4).position(is_candidate) {
+                return Ok(Some(pos + i as u64));
+            }
+            pos += data.len().saturating_sub(3) as u64;
+            if self.end() - start > MAX_SPECULATIVE_SPAN as u64 || !self.fill()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    // Records the start of the member at `start` against the limits.
+    fn start_member(&mut self, start: u64) -> io::Result<()> {
+        if mem::replace(&mut self.started, true) {
+            if let Err(limit) = self.limiter.next_member() {
+                self.exceeded_at = start;
+                return Err(Error::LimitExceeded {
+                    limit,
+                    offset: start,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    // Accounts for the output of the member ending at `end`, which took
+    // `consumed` bytes of input. Output past the limits is dropped, and the
+    // error reported once the rest has been read.
+    fn account(&mut self, consumed: u64, end: u64) {
+        let produced = self.output.len() as u64;
+        if self.limiter.account(consumed, produced).is_err() {
+            let excess = cmp::min(self.limiter.excess(), produced) as usize;
+            self.output.truncate(self.output.len() - excess);
+            self.exceeded_at = end;
+        }
+    }
+
+    // Decodes the large member at `next` on this thread.
+    fn read_streaming(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.limiter.clamp(buf.len());
+        let buf = &mut buf[..len];
+        loop {
+            let streaming = self.streaming.as_mut().unwrap();
+            let input = &self.input[(self.next - self.base) as usize..];
+            let before_in = streaming.data.total_in();
+            let before_out = streaming.data.total_out();
+            let offset = self.next;
+            let status = streaming
+                .data
+                .decompress(input, buf, FlushDecompress::None)
+                .map_err(|err| Error::decompress(err, offset))?;
+            let consumed = streaming.data.total_in() - before_in;
+            let read = (streaming.data.total_out() - before_out) as usize;
+            streaming.crc.update(&buf[..read]);
+            self.next += consumed;
+            self.discard(self.next);
+            if self.limiter.account(consumed, read as u64).is_err() {
+                // The error is reported by the next read.
+                self.exceeded_at = self.next;
+                return Ok(read - cmp::min(self.limiter.excess(), read as u64) as usize);
+            }
+
+            if status == Status::StreamEnd {
+                while self.end() < self.next + 8 {
+                    if !self.fill()? {
+                        return Err(Error::Truncated { offset: self.end() }.into());
+                    }
+                }
+                let crc = self.streaming.take().unwrap().crc;
+                check_trailer(self.slice(self.next), &crc, self.next)?;
+                self.next += 8;
+                self.discard(self.next);
+                return Ok(read);
+            }
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            if consumed == 0 && !self.fill()? {
+                return Err(Error::Truncated { offset: self.end() }.into());
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for ParMultiGzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.output_pos < self.output.len() {
+                let n = cmp::min(buf.len(), self.output.len() - self.output_pos);
+                buf[..n].copy_from_slice(&self.output[self.output_pos..self.output_pos + n]);
+                self.output_pos += n;
+                return Ok(n);
+            }
+            if let Err(limit) = self.limiter.check() {
+                let offset = self.exceeded_at;
+                return Err(Error::LimitExceeded { limit, offset }.into());
+            }
+            if self.streaming.is_some() {
+                match self.read_streaming(buf)? {
+                    0 if !buf.is_empty() => continue,
+                    n => return Ok(n),
+                }
+            }
+
+            self.schedule()?;
+            match self.jobs.next() {
+                Some(result) => {
+                    let (start, end) = self.spans.pop_front().unwrap();
+                    match result? {
+                        Inflated::Member(output) => {
+                            self.start_member(start)?;
+                            self.output = output;
+                            self.output_pos = 0;
+                            let oldest = self.spans.front().map_or(self.next, |span| span.0);
+                            self.discard(oldest);
+                            self.account(end - start, end);
+                        }
+                        Inflated::Truncated => {
+                            // The next header was a false positive, so all the
+                            // members scheduled after this one are bogus too.
+                            self.jobs.clear();
+                            self.spans.clear();
+                            self.large_member = None;
+                            self.next = start;
+                            self.min_end = end + 1;
+                        }
+                    }
+                }
+                None => match self.large_member.take() {
+                    Some(header_len) => {
+                        self.start_member(self.next)?;
+                        self.next += header_len as u64;
+                        self.discard(self.next);
+                        self.streaming = Some(Streaming {
+                            data: Decompress::new(false),
+                            crc: Crc::new(),
+                        });
+                    }
+                    None => return Ok(0),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::GzEncoder;
+    use crate::{Compression, GzBuilder, LimitKind};
+    use rand::{thread_rng, Rng};
+
+    fn members(count: usize, max_len: usize) -> (Vec<u8>, Vec<u8>) {
+        let mut rng = thread_rng();
+        let (mut data, mut compressed) = (Vec::new(), Vec::new());
+        for _ in 0..count {
+            let len = rng.gen_range(0..max_len);
+            let member: Vec<u8> = (0..len).map(|_| rng.gen_range(0..4)).collect();
+            let mut e = GzEncoder::new(Vec::new(), Compression::default());
+            e.write_all(&member).unwrap();
+            compressed.extend(e.finish().unwrap());
+            data.extend(member);
+        }
+        (data, compressed)
+    }
+
+    fn decode(compressed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut d = ParBuilder::new().threads(3).multi_gz_read(compressed);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn many_members() {
+        let (data, compressed) = members(100, 20_000);
+        assert_eq!(decode(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(decode(&[]).unwrap(), b"");
+        let (data, compressed) = members(1, 1);
+        assert_eq!(decode(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn false_positive_headers() {
+        // Stored blocks copy the input verbatim, planting fake member headers
+        // in the compressed data, some of which don't even parse.
+        let fake_headers = [
+            [0x1f, 0x8b, 8, 0].repeat(1000),
+            [0x1f, 0x8b, 8, 2, 0, 0, 0, 0, 0, 0xff, 0, 0].repeat(300),
+        ];
+        for member in fake_headers {
+            let mut compressed = Vec::new();
+            for _ in 0..5 {
+                let mut e = GzEncoder::new(Vec::new(), Compression::none());
+                e.write_all(&member).unwrap();
+                compressed.extend(e.finish().unwrap());
+            }
+            assert_eq!(decode(&compressed).unwrap(), member.repeat(5));
+        }
+    }
+
+    #[test]
+    fn large_member() {
+        let mut rng = thread_rng();
+        let big: Vec<u8> = (0..MAX_SPECULATIVE_SPAN + 100_000)
+            .map(|_| rng.gen())
+            .collect();
+        let mut e = GzEncoder::new(Vec::new(), Compression::fast());
+        e.write_all(&big).unwrap();
+        let mut compressed = e.finish().unwrap();
+        let (data, rest) = members(10, 1000);
+        compressed.extend(rest);
+
+        assert_eq!(decode(&compressed).unwrap(), [big, data].concat());
+    }
+
+    #[test]
+    fn bgzf_blocks() {
+        let mut rng = thread_rng();
+        let (mut data, mut compressed) = (Vec::new(), Vec::new());
+        for _ in 0..50 {
+            let block: Vec<u8> = (0..rng.gen_range(0..60_000))
+                .map(|_| rng.gen_range(0..8))
+                .collect();
+            let mut e = GzBuilder::new()
+                .extra(vec![b'B', b'C', 2, 0, 0, 0])
+                .write(Vec::new(), Compression::default());
+            e.write_all(&block).unwrap();
+            let mut member = e.finish().unwrap();
+            let bsize = (member.len() - 1) as u16;
+            member[16..18].copy_from_slice(&bsize.to_le_bytes());
+            compressed.extend(member);
+            data.extend(block);
+        }
+        assert_eq!(decode(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn corrupt_checksum() {
+        let (_, mut compressed) = members(10, 5000);
+        let len = compressed.len();
+        compressed[len - 6] ^= 1;
+        assert!(decode(&compressed).is_err());
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        let (_, mut compressed) = members(3, 5000);
+        compressed.extend_from_slice(b"garbage");
+        assert!(decode(&compressed).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let (_, compressed) = members(3, 5000);
+        assert!(decode(&compressed[..compressed.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn limits() {
+        fn limit_kind(err: io::Error) -> LimitKind {
+            match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+                Some(Error::LimitExceeded { limit, .. }) => limit.kind(),
+                _ => panic!("unexpected error: {}", err),
+            }
+        }
+
+        let mut compressed = Vec::new();
+        for _ in 0..10 {
+            let mut e = GzEncoder::new(Vec::new(), Compression::default());
+            e.write_all(&[7; 100_000]).unwrap();
+            compressed.extend(e.finish().unwrap());
+        }
+        let limits = Limits::new().max_output(250_000);
+        let d = ParBuilder::new().threads(3).limits(limits);
+        let mut out = Vec::new();
+        let err = d.multi_gz_read(&compressed[..]).read_to_end(&mut out);
+        assert_eq!(limit_kind(err.unwrap_err()), LimitKind::Output);
+        assert_eq!(out.len(), 250_000);
+
+        let limits = Limits::new().max_members(4);
+        let mut d = ParMultiGzDecoder::new_with_limits(&compressed[..], limits);
+        let err = d.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(limit_kind(err), LimitKind::Members);
+
+        let limits = Limits::new().max_ratio(10);
+        let mut d = ParMultiGzDecoder::new_with_limits(&compressed[..], limits);
+        let err = d.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(limit_kind(err), LimitKind::Ratio);
+
+        // A single member too large to be located ahead of time.
+        let mut rng = thread_rng();
+        let big: Vec<u8> = (0..MAX_SPECULATIVE_SPAN + 100_000)
+            .map(|_| rng.gen())
+            .collect();
+        let mut e = GzEncoder::new(Vec::new(), Compression::fast());
+        e.write_all(&big).unwrap();
+        let big = e.finish().unwrap();
+        let limits = Limits::new().max_output(1000);
+        let mut d = ParMultiGzDecoder::new_with_limits(&big[..], limits);
+        let mut out = Vec::new();
+        let err = d.read_to_end(&mut out).unwrap_err();
+        assert_eq!(limit_kind(err), LimitKind::Output);
+        assert_eq!(out.len(), 1000);
+    }
+}
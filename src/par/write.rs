@@ -0,0 +1,558 @@
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+
+use super::{Ordered, ParBuilder};
+use crate::adler::Adler32;
+use crate::{Compress, Compression, Crc, FlushCompress, Status};
+
+// The size of the deflate window, i.e. how much of the preceding input a
+// block can refer to.
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/// The checksum of a piece of the input, as required by the trailer of the
+/// format being written.
+#[derive(Debug)]
+enum Check {
+    None,
+    Crc(Crc),
+    Adler(Adler32),
+}
+
+impl Check {
+    fn new_like(&self) -> Check {
+        match self {
+            Check::None => Check::None,
+            Check::Crc(_) => Check::Crc(Crc::new()),
+            Check::Adler(_) => Check::Adler(Adler32::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Check::None => {}
+            Check::Crc(crc) => crc.update(data),
+            Check::Adler(adler) => adler.update(data),
+        }
+    }
+
+    fn combine(&mut self, other: &Check) {
+        match (self, other) {
+            (Check::Crc(crc), Check::Crc(other)) => crc.combine(other),
+            (Check::Adler(adler), Check::Adler(other)) => adler.combine(other),
+            _ => {}
+        }
+    }
+
+    fn trailer(&self) -> Vec<u8> {
+        match self {
+            Check::None => Vec::new(),
+            Check::Crc(crc) => {
+                let mut trailer = crc.sum().to_le_bytes().to_vec();
+                trailer.extend_from_slice(&crc.amount().to_le_bytes());
+                trailer
+            }
+            Check::Adler(adler) => adler.sum().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// A compressed block along with the checksum of its uncompressed data.
+#[derive(Debug)]
+struct Block {
+    data: Vec<u8>,
+    check: Check,
+}
+
+// Compresses `input` into raw deflate data ending on a byte boundary, either
+// with a sync flush so that the next block can be appended, or with the final
+// block of the stream.
+fn compress_block(
+    level: Compression,
+    dict: Vec<u8>,
+    input: Vec<u8>,
+    finish: bool,
+    mut check: Check,
+) -> io::Result<Block> {
+    check.update(&input);
+
+    let mut compress = Compress::new(level, false);
+    if !dict.is_empty() {
+        compress.set_dictionary(&dict)?;
+    }
+
+    let flush = if finish {
+        FlushCompress::Finish
+    } else {
+        FlushCompress::Sync
+    };
+    let mut data = Vec::with_capacity(input.len() / 2 + 128);
+    loop {
+        if data.len() == data.capacity() {
+            data.reserve(data.capacity());
+        }
+        let consumed = compress.total_in() as usize;
+        let status = compress.compress_vec(&input[consumed..], &mut data, flush)?;
+        let done = match status {
+            Status::StreamEnd => true,
+            Status::Ok | Status::BufError => {
+                !finish
+                    && compress.total_in() as usize == input.len()
+                    && data.len() < data.capacity()
+            }
+        };
+        if done {
+            return Ok(Block { data, check });
+        }
+    }
+}
+
+/// The format independent part of the multithreaded encoders.
+#[derive(Debug)]
+struct ParWriter<W: Write> {
+    obj: Option<W>,
+    level: Compression,
+    block_size: usize,
+    input: Vec<u8>,
+    dict: Vec<u8>,
+    jobs: Ordered<Block>,
+    check: Check,
+    // Output ready to be written, starting with the header of the stream.
+    buf: Vec<u8>,
+    finished: bool,
+    trailer_written: bool,
+}
+
+impl<W: Write> ParWriter<W> {
+    fn new(builder: &ParBuilder, w: W, level: Compression, header: Vec<u8>, check: Check) -> Self {
+        ParWriter {
+            obj: Some(w),
+            level,
+            block_size: builder.block_size,
+            input: Vec::new(),
+            dict: Vec::new(),
+            jobs: Ordered::new(builder.threads),
+            check,
+            buf: header,
+            finished: false,
+            trailer_written: false,
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        self.obj.as_ref().unwrap()
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        self.obj.as_mut().unwrap()
+    }
+
+    fn dump(&mut self) -> io::Result<()> {
+        while !self.buf.is_empty() {
+            let n = self.obj.as_mut().unwrap().write(&self.buf)?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.buf.drain(..n);
+        }
+        Ok(())
+    }
+
+    // Waits for the oldest block in flight and queues its data for output.
+    fn collect(&mut self) -> io::Result<bool> {
+        match self.jobs.next() {
+            Some(block) => {
+                let block = block?;
+                self.check.combine(&block.check);
+                self.buf.extend_from_slice(&block.data);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn submit(&mut self, finish: bool) -> io::Result<()> {
+        while !self.jobs.has_room() {
+            self.collect()?;
+            self.dump()?;
+        }
+
+        let input = mem::replace(&mut self.input, Vec::with_capacity(self.block_size));
+        let dict = self.dict.clone();
+        if input.len() >= WINDOW_SIZE {
+            self.dict.clear();
+            self.dict
+                .extend_from_slice(&input[input.len() - WINDOW_SIZE..]);
+        } else {
+            let keep = cmp::min(self.dict.len(), WINDOW_SIZE - input.len());
+            self.dict.drain(..self.dict.len() - keep);
+            self.dict.extend_from_slice(&input);
+        }
+
+        let (level, check) = (self.level, self.check.new_like());
+        self.jobs
+            .submit(move || compress_block(level, dict, input, finish, check))
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        assert!(!self.finished, "write after the stream was finished");
+        self.dump()?;
+        if self.input.len() == self.block_size {
+            self.submit(false)?;
+        }
+        let n = cmp::min(buf.len(), self.block_size - self.input.len());
+        self.input.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.finished && !self.input.is_empty() {
+            self.submit(false)?;
+        }
+        loop {
+            self.dump()?;
+            if !self.collect()? {
+                break;
+            }
+        }
+        self.obj.as_mut().unwrap().flush()
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.submit(true)?;
+            self.finished = true;
+        }
+        loop {
+            self.dump()?;
+            if !self.collect()? {
+                break;
+            }
+        }
+        if !self.trailer_written {
+            let trailer = self.check.trailer();
+            self.buf.extend_from_slice(&trailer);
+            self.trailer_written = true;
+        }
+        self.dump()
+    }
+
+    fn is_present(&self) -> bool {
+        self.obj.is_some()
+    }
+
+    fn finish(&mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.obj.take().unwrap())
+    }
+}
+
+impl<W: Write> Drop for ParWriter<W> {
+    fn drop(&mut self) {
+        if self.is_present() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+macro_rules! par_encoder {
+    ($(#[$attr:meta])* $name:ident, $format:literal) => {
+        $(#[$attr])*
+        #[derive(Debug)]
+        pub struct $name<W: Write> {
+            inner: ParWriter<W>,
+        }
+
+        impl<W: Write> $name<W> {
+            #[doc = concat!(
+                "Creates a new ", $format, " encoder writing the compressed data to `w`,\n",
+                "using the default settings of [`ParBuilder`]."
+            )]
+            pub fn new(w: W, level: Compression) -> $name<W> {
+                Self::with_builder(ParBuilder::new(), w, level)
+            }
+
+            /// Acquires a reference to the underlying writer.
+            pub fn get_ref(&self) -> &W {
+                self.inner.get_ref()
+            }
+
+            /// Acquires a mutable reference to the underlying writer.
+            ///
+            /// Note that mutating the output/input state of the stream may corrupt
+            /// this object, so care must be taken when using this method.
+            pub fn get_mut(&mut self) -> &mut W {
+                self.inner.get_mut()
+            }
+
+            /// Attempt to finish this output stream, waiting for all blocks to
+            /// be compressed and writing out the trailer.
+            ///
+            /// # Panics
+            ///
+            /// Attempts to write data to this stream may result in a panic after
+            /// this function is called.
+            ///
+            /// # Errors
+            ///
+            /// This function will perform I/O to complete this stream, and any I/O
+            /// errors which occur will be returned from this function.
+            pub fn try_finish(&mut self) -> io::Result<()> {
+                self.inner.try_finish()
+            }
+
+            /// Consumes this encoder, finishing the compressed stream and
+            /// returning the underlying writer.
+            ///
+            /// # Errors
+            ///
+            /// This function will perform I/O to complete this stream, and any I/O
+            /// errors which occur will be returned from this function.
+            pub fn finish(mut self) -> io::Result<W> {
+                self.inner.finish()
+            }
+        }
+
+        impl<W: Write> Write for $name<W> {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.inner.write(buf)
+            }
+
+            /// Compresses all the data written so far, ending it with a sync
+            /// flush, and waits for it to be written out.
+            fn flush(&mut self) -> io::Result<()> {
+                self.inner.flush()
+            }
+        }
+    };
+}
+
+par_encoder!(
+    /// A multithreaded gzip encoder.
+    ///
+    /// The input is split into blocks which are compressed concurrently on a
+    /// pool of worker threads, each one ending with a sync flush so that they
+    /// can be concatenated into a single gzip member. The checksum of the
+    /// member is computed from the checksums of the blocks.
+    ///
+    /// The output can be decoded by any gzip decoder.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::prelude::*;
+    /// use flate2::Compression;
+    /// use flate2::write::ParGzEncoder;
+    ///
+    /// let mut e = ParGzEncoder::new(Vec::new(), Compression::default());
+    /// e.write_all(b"Hello World").unwrap();
+    /// let compressed = e.finish().unwrap();
+    /// ```
+    ParGzEncoder,
+    "gzip"
+);
+
+par_encoder!(
+    /// A multithreaded zlib encoder.
+    ///
+    /// The input is split into blocks which are compressed concurrently on a
+    /// pool of worker threads, each one ending with a sync flush so that they
+    /// can be concatenated into a single zlib stream. The Adler-32 checksum of
+    /// the stream is computed from the checksums of the blocks.
+    ParZlibEncoder,
+    "zlib"
+);
+
+par_encoder!(
+    /// A multithreaded raw deflate encoder.
+    ///
+    /// The input is split into blocks which are compressed concurrently on a
+    /// pool of worker threads, each one ending with a sync flush so that they
+    /// can be concatenated into a single deflate stream.
+    ParDeflateEncoder,
+    "raw deflate"
+);
+
+impl<W: Write> ParGzEncoder<W> {
+    fn with_builder(mut builder: ParBuilder, w: W, level: Compression) -> Self {
+        let header = builder.header.take().unwrap_or_default().into_header(level);
+        ParGzEncoder {
+            inner: ParWriter::new(&builder, w, level, header, Check::Crc(Crc::new())),
+        }
+    }
+}
+
+impl<W: Write> ParZlibEncoder<W> {
+    fn with_builder(builder: ParBuilder, w: W, level: Compression) -> Self {
+        ParZlibEncoder {
+            inner: ParWriter::new(
+                &builder,
+                w,
+                level,
+                zlib_header(level).to_vec(),
+                Check::Adler(Adler32::new()),
+            ),
+        }
+    }
+}
+
+impl<W: Write> ParDeflateEncoder<W> {
+    fn with_builder(builder: ParBuilder, w: W, level: Compression) -> Self {
+        ParDeflateEncoder {
+            inner: ParWriter::new(&builder, w, level, Vec::new(), Check::None),
+        }
+    }
+}
+
+pub(crate) fn gz_encoder<W: Write>(b: ParBuilder, w: W, lvl: Compression) -> ParGzEncoder<W> {
+    ParGzEncoder::with_builder(b, w, lvl)
+}
+
+pub(crate) fn zlib_encoder<W: Write>(b: ParBuilder, w: W, lvl: Compression) -> ParZlibEncoder<W> {
+    ParZlibEncoder::with_builder(b, w, lvl)
+}
+
+pub(crate) fn deflate_encoder<W: Write>(
+    b: ParBuilder,
+    w: W,
+    lvl: Compression,
+) -> ParDeflateEncoder<W> {
+    ParDeflateEncoder::with_builder(b, w, lvl)
+}
+
+// The two byte zlib header for a 32 KiB window and no preset dictionary, with
+// the level hint zlib itself would emit.
+fn zlib_header(level: Compression) -> [u8; 2] {
+    let cmf = 0x78u8;
+    let flevel = match level.level() {
+        0 | 1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    let flg = flevel << 6;
+    let check = 31 - ((u16::from(cmf) << 8 | u16::from(flg)) % 31) as u8;
+    [cmf, flg | check]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read;
+    use crate::{GzBuilder, ParBuilder};
+
+    fn builder() -> ParBuilder {
+        ParBuilder::new().threads(3).block_size(40_000)
+    }
+
+    #[test]
+    fn gz_roundtrip() {
+        let data = crate::test_data(300_000);
+        let mut e = builder()
+            .gz_header(GzBuilder::new().filename("data"))
+            .gz_write(Vec::new(), Compression::default());
+        for chunk in data.chunks(7777) {
+            e.write_all(chunk).unwrap();
+        }
+        let compressed = e.finish().unwrap();
+
+        let mut d = read::GzDecoder::new(&compressed[..]);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+        assert_eq!(d.header().unwrap().filename(), Some(&b"data"[..]));
+    }
+
+    #[test]
+    fn zlib_roundtrip() {
+        let data = crate::test_data(300_000);
+        let mut e = builder().zlib_write(Vec::new(), Compression::best());
+        e.write_all(&data).unwrap();
+        let compressed = e.finish().unwrap();
+
+        let mut d = read::ZlibDecoder::new(&compressed[..]);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn deflate_roundtrip() {
+        let data = crate::test_data(300_000);
+        let mut e = ParBuilder::new()
+            .threads(1)
+            .block_size(1000)
+            .deflate_write(Vec::new(), Compression::fast());
+        e.write_all(&data).unwrap();
+        let compressed = e.finish().unwrap();
+
+        let mut d = read::DeflateDecoder::new(&compressed[..]);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn empty() {
+        let compressed = ParGzEncoder::new(Vec::new(), Compression::default())
+            .finish()
+            .unwrap();
+        let mut out = Vec::new();
+        read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out.is_empty());
+
+        let compressed = ParZlibEncoder::new(Vec::new(), Compression::default())
+            .finish()
+            .unwrap();
+        read::ZlibDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn flush_makes_data_decodable() {
+        let data = crate::test_data(300_000);
+        let mut e = builder().gz_write(Vec::new(), Compression::default());
+        e.write_all(&data[..100_000]).unwrap();
+        e.flush().unwrap();
+
+        let mut d = crate::write::GzDecoder::new(Vec::new());
+        d.write_all(e.get_ref()).unwrap();
+        d.flush().unwrap();
+        assert_eq!(d.get_ref()[..], data[..100_000]);
+
+        e.write_all(&data[100_000..]).unwrap();
+        let compressed = e.finish().unwrap();
+        let mut out = Vec::new();
+        read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn finished_on_drop() {
+        let mut compressed = Vec::new();
+        {
+            let mut e = builder().zlib_write(&mut compressed, Compression::default());
+            e.write_all(b"hello world").unwrap();
+        }
+        let mut out = Vec::new();
+        read::ZlibDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b"hello world");
+    }
+
+    #[test]
+    fn zlib_header_is_valid() {
+        for level in 0..=9 {
+            let header = zlib_header(Compression::new(level));
+            assert_eq!((u16::from(header[0]) << 8 | u16::from(header[1])) % 31, 0);
+        }
+    }
+}
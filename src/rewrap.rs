@@ -0,0 +1,459 @@
+//! Moving compressed data between the gzip, zlib and raw deflate formats.
+//!
+//! The three formats wrap the same deflate data, only differing in their
+//! headers and trailers. The functions of this module strip those of their
+//! input and copy its deflate data as is between the ones of the requested
+//! format, which is much cheaper than decompressing and compressing it again.
+//! The input is still decompressed once, without keeping the output around,
+//! to find the end of the deflate data and to compute the checksum of the new
+//! trailer. The checksum of the input is verified along the way.
+//!
+//! [`edit_gz_header`] goes through the same path to replace the header of a
+//! gzip member, such as its file name or modification time.
+//!
+//! A single stream is rewrapped by each call, which stops reading its input
+//! right after the end of that stream. Rewrapping a multi-member gzip file
+//! takes a call for each member.
+//!
+//! The input is decompressed with the default [`Backend`], unless another one
+//! is given to the `_with_backend` variant of each function.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::prelude::*;
+//! use flate2::read::GzDecoder;
+//! use flate2::write::ZlibEncoder;
+//! use flate2::{rewrap, Compression, Format, GzBuilder};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+//! e.write_all(b"Hello World")?;
+//! let zlib = e.finish()?;
+//!
+//! let header = GzBuilder::new().filename("hello.txt");
+//! let gz = rewrap::to_gz(&zlib[..], Format::Zlib, header, Vec::new())?;
+//!
+//! let mut d = GzDecoder::new(&gz[..]);
+//! let mut s = String::new();
+//! d.read_to_string(&mut s)?;
+//! assert_eq!(s, "Hello World");
+//! assert_eq!(d.header().unwrap().filename(), Some(&b"hello.txt"[..]));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::io::prelude::*;
+
+use crate::adler::Adler32;
+use crate::gz::{check_trailer, GzBuilder, GzHeader, GzHeaderParser};
+use crate::{Backend, Compression, Crc, Decompress, Error, FlushDecompress, Format, Status};
+
+// The header of the zlib streams written, declaring a 32 KiB window, which
+// fits any deflate data, and the default compression level.
+const ZLIB_HEADER: [u8; 2] = [0x78, 0x9c];
+
+/// Rewraps the stream of the given `format` read from `r` as a gzip member
+/// with the given header, written to `w`, which is returned once done.
+///
+/// # Errors
+///
+/// Any I/O error is returned, as well as errors for invalid or truncated
+/// input, including checksum mismatches, and for uncompressed input or zlib
+/// streams using a preset dictionary. `w` may have been written to by then.
+pub fn to_gz<R, W>(r: R, format: Format, header: GzBuilder, w: W) -> io::Result<W>
+where
+    R: BufRead,
+    W: Write,
+{
+    to_gz_with_backend(r, format, header, w, Backend::default())
+}
+
+/// Rewraps the stream read from `r` as [`to_gz`] does, decompressing it with
+/// the given built-in `backend`.
+pub fn to_gz_with_backend<R, W>(
+    r: R,
+    format: Format,
+    header: GzBuilder,
+    w: W,
+    backend: Backend,
+) -> io::Result<W>
+where
+    R: BufRead,
+    W: Write,
+{
+    rewrap(r, format, Format::Gzip, w, backend, |_| header)
+}
+
+/// Rewraps the stream of the given `format` read from `r` as a zlib stream,
+/// written to `w`, which is returned once done.
+///
+/// # Errors
+///
+/// Any I/O error is returned, as well as errors for invalid or truncated
+/// input, including checksum mismatches, and for uncompressed input or zlib
+/// streams using a preset dictionary. `w` may have been written to by then.
+pub fn to_zlib<R, W>(r: R, format: Format, w: W) -> io::Result<W>
+where
+    R: BufRead,
+    W: Write,
+{
+    to_zlib_with_backend(r, format, w, Backend::default())
+}
+
+/// Rewraps the stream read from `r` as [`to_zlib`] does, decompressing it
+/// with the given built-in `backend`.
+pub fn to_zlib_with_backend<R, W>(r: R, format: Format, w: W, backend: Backend) -> io::Result<W>
+where
+    R: BufRead,
+    W: Write,
+{
+    rewrap(r, format, Format::Zlib, w, backend, |_| GzBuilder::new())
+}
+
+/// Strips the headers and trailers of the stream of the given `format` read
+/// from `r`, writing its raw deflate data to `w`, which is returned once done.
+///
+/// # Errors
+///
+/// Any I/O error is returned, as well as errors for invalid or truncated
+/// input, including checksum mismatches, and for uncompressed input or zlib
+/// streams using a preset dictionary. `w` may have been written to by then.
+pub fn to_deflate<R, W>(r: R, format: Format, w: W) -> io::Result<W>
+where
+    R: BufRead,
+    W: Write,
+{
+    to_deflate_with_backend(r, format, w, Backend::default())
+}
+
+/// Strips the stream read from `r` as [`to_deflate`] does, decompressing it
+/// with the given built-in `backend`.
+pub fn to_deflate_with_backend<R, W>(r: R, format: Format, w: W, backend: Backend) -> io::Result<W>
+where
+    R: BufRead,
+    W: Write,
+{
+    rewrap(r, format, Format::Deflate, w, backend, |_| GzBuilder::new())
+}
+
+/// Copies the gzip member read from `r` to `w` with a new header, which is
+/// returned once done.
+///
+/// `edit` is handed a builder holding the fields of the current header, and
+/// returns the one building the new header.
+///
+/// # Errors
+///
+/// Any I/O error is returned, as well as errors for invalid or truncated
+/// input, including checksum mismatches. `w` may have been written to by then.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::read::GzDecoder;
+/// use flate2::rewrap;
+/// use flate2::{Compression, GzBuilder};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut e = GzBuilder::new()
+///     .filename("hello.txt")
+///     .mtime(1)
+///     .write(Vec::new(), Compression::default());
+/// e.write_all(b"Hello World")?;
+/// let gz = e.finish()?;
+///
+/// let gz = rewrap::edit_gz_header(&gz[..], Vec::new(), |header| {
+///     header.comment("greetings")
+/// })?;
+///
+/// let d = GzDecoder::new(&gz[..]);
+/// let header = d.header().unwrap();
+/// assert_eq!(header.filename(), Some(&b"hello.txt"[..]));
+/// assert_eq!(header.mtime(), 1);
+/// assert_eq!(header.comment(), Some(&b"greetings"[..]));
+/// # Ok(())
+/// # }
+/// ```
+pub fn edit_gz_header<R, W, F>(r: R, w: W, edit: F) -> io::Result<W>
+where
+    R: BufRead,
+    W: Write,
+    F: FnOnce(GzBuilder) -> GzBuilder,
+{
+    edit_gz_header_with_backend(r, w, edit, Backend::default())
+}
+
+/// Copies the gzip member read from `r` as [`edit_gz_header`] does,
+/// decompressing it with the given built-in `backend`.
+pub fn edit_gz_header_with_backend<R, W, F>(r: R, w: W, edit: F, backend: Backend) -> io::Result<W>
+where
+    R: BufRead,
+    W: Write,
+    F: FnOnce(GzBuilder) -> GzBuilder,
+{
+    rewrap(r, Format::Gzip, Format::Gzip, w, backend, |header| {
+        edit(GzBuilder::from_header(&header.unwrap()))
+    })
+}
+
+// Rewraps the stream of format `from` read from `r` as one of format `to`,
+// the gzip header of which is built by `header` out of the one of the input.
+fn rewrap<R, W, F>(
+    mut r: R,
+    from: Format,
+    to: Format,
+    mut w: W,
+    backend: Backend,
+    header: F,
+) -> io::Result<W>
+where
+    R: BufRead,
+    W: Write,
+    F: FnOnce(Option<GzHeader>) -> GzBuilder,
+{
+    let (input_header, mut offset) = match from {
+        Format::Gzip => {
+            let mut parser = GzHeaderParser::new();
+            parser.parse(&mut r)?;
+            let len = parser.len();
+            (Some(GzHeader::from(parser)), len)
+        }
+        Format::Zlib => {
+            read_zlib_header(&mut r)?;
+            (None, ZLIB_HEADER.len() as u64)
+        }
+        Format::Deflate => (None, 0),
+        Format::Uncompressed => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "uncompressed data can't be rewrapped",
+            ))
+        }
+    };
+
+    match to {
+        Format::Gzip => w.write_all(&header(input_header).into_header(Compression::default()))?,
+        Format::Zlib => w.write_all(&ZLIB_HEADER)?,
+        _ => {}
+    }
+
+    let mut data = Decompress::new_with_backend(false, backend);
+    let mut out = vec![0; 32 * 1024];
+    let mut crc = Crc::new();
+    let mut adler = Adler32::new();
+    loop {
+        let buf = r.fill_buf()?;
+        let eof = buf.is_empty();
+        let before_in = data.total_in();
+        let before_out = data.total_out();
+        let ret = data.decompress(buf, &mut out, FlushDecompress::None);
+        let consumed = (data.total_in() - before_in) as usize;
+        let produced = (data.total_out() - before_out) as usize;
+        w.write_all(&buf[..consumed])?;
+        r.consume(consumed);
+        offset += consumed as u64;
+        let status = ret.map_err(|err| Error::decompress(err, offset))?;
+
+        let chunk = &out[..produced];
+        if from == Format::Gzip || to == Format::Gzip {
+            crc.update(chunk);
+        }
+        if from == Format::Zlib || to == Format::Zlib {
+            adler.update(chunk);
+        }
+        if status == Status::StreamEnd {
+            break;
+        }
+        if eof && consumed == 0 && produced == 0 {
+            return Err(Error::Truncated { offset }.into());
+        }
+    }
+
+    match from {
+        Format::Gzip => {
+            let mut trailer = [0; 8];
+            r.read_exact(&mut trailer)?;
+            check_trailer(&trailer, &crc, offset)?;
+        }
+        Format::Zlib => {
+            let mut trailer = [0; 4];
+            r.read_exact(&mut trailer)?;
+            if trailer != adler.sum().to_be_bytes() {
+                return Err(Error::DataCrcMismatch { offset }.into());
+            }
+        }
+        _ => {}
+    }
+
+    match to {
+        Format::Gzip => {
+            w.write_all(&crc.sum().to_le_bytes())?;
+            w.write_all(&crc.amount().to_le_bytes())?;
+        }
+        Format::Zlib => w.write_all(&adler.sum().to_be_bytes())?,
+        _ => {}
+    }
+    Ok(w)
+}
+
+fn read_zlib_header<R: Read>(r: &mut R) -> io::Result<()> {
+    let mut header = [0; 2];
+    r.read_exact(&mut header)?;
+    if !crate::zlib::is_header(header) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid zlib header",
+        ));
+    }
+    if header[1] & 0x20 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "zlib streams with a preset dictionary can't be rewrapped",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+
+    use rand::{thread_rng, Rng};
+
+    use super::{edit_gz_header, to_deflate, to_gz, to_zlib};
+    use crate::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+    use crate::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use crate::{Compression, Format, GzBuilder};
+
+    fn compress(data: &[u8], format: Format) -> Vec<u8> {
+        match format {
+            Format::Gzip => {
+                let mut e = GzEncoder::new(Vec::new(), Compression::default());
+                e.write_all(data).unwrap();
+                e.finish().unwrap()
+            }
+            Format::Zlib => {
+                let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+                e.write_all(data).unwrap();
+                e.finish().unwrap()
+            }
+            _ => {
+                let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
+                e.write_all(data).unwrap();
+                e.finish().unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut rng = thread_rng();
+        let formats = [Format::Gzip, Format::Zlib, Format::Deflate];
+        for len in [0, 1, 1000, 200_000] {
+            let data: Vec<u8> = (0..len).map(|_| rng.gen_range(b'a'..=b'z')).collect();
+            for from in formats {
+                let input = compress(&data, from);
+                let deflate = compress(&data, Format::Deflate);
+
+                let gz = to_gz(&input[..], from, GzBuilder::new(), Vec::new()).unwrap();
+                let mut out = Vec::new();
+                GzDecoder::new(&gz[..]).read_to_end(&mut out).unwrap();
+                assert!(out == data);
+
+                let zlib = to_zlib(&input[..], from, Vec::new()).unwrap();
+                out.clear();
+                ZlibDecoder::new(&zlib[..]).read_to_end(&mut out).unwrap();
+                assert!(out == data);
+
+                let raw = to_deflate(&input[..], from, Vec::new()).unwrap();
+                out.clear();
+                DeflateDecoder::new(&raw[..]).read_to_end(&mut out).unwrap();
+                assert!(out == data);
+                assert!(raw == deflate);
+            }
+        }
+    }
+
+    #[cfg(all(feature = "any_zlib", feature = "miniz_oxide"))]
+    #[test]
+    fn backends() {
+        use super::{edit_gz_header_with_backend, to_gz_with_backend, to_zlib_with_backend};
+        use crate::Backend;
+
+        let data = b"hello world".repeat(1000);
+        let input = compress(&data, Format::Zlib);
+        for backend in [Backend::Zlib, Backend::Rust] {
+            let gz = to_gz_with_backend(
+                &input[..],
+                Format::Zlib,
+                GzBuilder::new(),
+                Vec::new(),
+                backend,
+            )
+            .unwrap();
+            let gz =
+                edit_gz_header_with_backend(&gz[..], Vec::new(), |h| h.mtime(1), backend).unwrap();
+            let zlib = to_zlib_with_backend(&gz[..], Format::Gzip, Vec::new(), backend).unwrap();
+            assert_eq!(zlib[2..], input[2..]);
+        }
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut input = compress(b"hello", Format::Zlib);
+        input.extend_from_slice(b"world");
+        let mut r = &input[..];
+        to_gz(&mut r, Format::Zlib, GzBuilder::new(), Vec::new()).unwrap();
+        assert_eq!(r, b"world");
+    }
+
+    #[test]
+    fn errors() {
+        let mut gz = compress(b"hello world", Format::Gzip);
+        let len = gz.len();
+        gz[len - 8] ^= 1;
+        assert!(to_zlib(&gz[..], Format::Gzip, Vec::new()).is_err());
+
+        let mut zlib = compress(b"hello world", Format::Zlib);
+        let len = zlib.len();
+        zlib[len - 1] ^= 1;
+        assert!(to_deflate(&zlib[..], Format::Zlib, Vec::new()).is_err());
+
+        let deflate = compress(b"hello world", Format::Deflate);
+        let truncated = &deflate[..deflate.len() - 1];
+        assert!(to_gz(truncated, Format::Deflate, GzBuilder::new(), Vec::new()).is_err());
+        assert!(to_zlib(&b"hello"[..], Format::Uncompressed, Vec::new()).is_err());
+        assert!(to_zlib(&b"hello"[..], Format::Zlib, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn edit_header() {
+        let mut e = GzBuilder::new()
+            .filename("old")
+            .comment("comment")
+            .extra(&b"AB\x01\x00x"[..])
+            .mtime(42)
+            .operating_system(3)
+            .write(Vec::new(), Compression::default());
+        e.write_all(b"hello world").unwrap();
+        let gz = e.finish().unwrap();
+
+        let edited = edit_gz_header(&gz[..], Vec::new(), |header| {
+            header.filename("new").mtime(7)
+        })
+        .unwrap();
+        let mut d = GzDecoder::new(&edited[..]);
+        let mut out = String::new();
+        d.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello world");
+        let header = d.header().unwrap();
+        assert_eq!(header.filename(), Some(&b"new"[..]));
+        assert_eq!(header.comment(), Some(&b"comment"[..]));
+        assert_eq!(header.extra(), Some(&b"AB\x01\x00x"[..]));
+        assert_eq!(header.mtime(), 7);
+        assert_eq!(header.operating_system(), 3);
+    }
+}
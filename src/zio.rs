@@ -1,13 +1,20 @@
+use std::cmp;
 use std::io;
 use std::io::prelude::*;
 use std::mem;
 
-use crate::{Compress, Decompress, DecompressError, FlushCompress, FlushDecompress, Status};
+use crate::limits::Limiter;
+use crate::{
+    Compress, DecoderOptions, Decompress, DecompressError, Error, FlushCompress, FlushDecompress,
+    LimitExceeded, Limits, Status,
+};
 
 #[derive(Debug)]
 pub struct Writer<W: Write, D: Ops> {
     obj: Option<W>,
     pub data: D,
+    pub limiter: Limiter,
+    report_truncation: bool,
     buf: Vec<u8>,
 }
 
@@ -120,47 +127,108 @@ where
     R: BufRead,
     D: Ops,
 {
+    read_limited(obj, data, dst, &mut Limiter::new(Limits::new()), false)
+}
+
+// Like `read`, but fails as soon as the output goes past the limits of
+// `limiter`, which is never by more than one byte, and with `Truncated` at the
+// end of an incomplete stream if `report_truncation` is set.
+pub(crate) fn read_limited<R, D>(
+    obj: &mut R,
+    data: &mut D,
+    dst: &mut [u8],
+    limiter: &mut Limiter,
+    report_truncation: bool,
+) -> io::Result<usize>
+where
+    R: BufRead,
+    D: Ops,
+{
+    limiter
+        .check()
+        .map_err(|limit| exceeded(limit, data.total_in()))?;
+    let len = limiter.clamp(dst.len());
+    let dst = &mut dst[..len];
     loop {
-        let (read, consumed, ret, eof);
-        {
+        let (consumed, ret) = {
             let input = obj.fill_buf()?;
-            eof = input.is_empty();
-            let before_out = data.total_out();
-            let before_in = data.total_in();
-            let flush = if eof {
-                D::Flush::finish()
-            } else {
-                D::Flush::none()
-            };
-            ret = data.run(input, dst, flush);
-            read = (data.total_out() - before_out) as usize;
-            consumed = (data.total_in() - before_in) as usize;
-        }
+            read_step(input, data, dst, report_truncation)
+        };
         obj.consume(consumed);
 
-        match ret {
-            // If we haven't ready any data and we haven't hit EOF yet,
-            // then we need to keep asking for more data because if we
-            // return that 0 bytes of data have been read then it will
-            // be interpreted as EOF.
-            Ok(Status::Ok | Status::BufError) if read == 0 && !eof && !dst.is_empty() => continue,
-            Ok(Status::Ok | Status::BufError | Status::StreamEnd) => return Ok(read),
-
-            Err(..) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "corrupt deflate stream",
-                ))
-            }
+        let read = match ret {
+            Some(Ok(read)) => read,
+            _ => 0,
+        };
+        limiter
+            .account(consumed as u64, read as u64)
+            .map_err(|limit| exceeded(limit, data.total_in()))?;
+
+        if let Some(ret) = ret {
+            return ret;
         }
     }
 }
 
+// Runs `data` once over `input`, an empty slice meaning EOF, and returns how
+// much of `input` was consumed along with the result of the read, if it is
+// ready to be returned to the caller. Shared by the blocking and the
+// asynchronous readers.
+pub(crate) fn read_step<D: Ops>(
+    input: &[u8],
+    data: &mut D,
+    dst: &mut [u8],
+    report_truncation: bool,
+) -> (usize, Option<io::Result<usize>>) {
+    let eof = input.is_empty();
+    let before_out = data.total_out();
+    let before_in = data.total_in();
+    // Finishing makes some backends fail on an incomplete stream, dropping
+    // whatever they decoded last, so it is avoided when truncation is
+    // reported separately.
+    let flush = if eof && !report_truncation {
+        D::Flush::finish()
+    } else {
+        D::Flush::none()
+    };
+    let ret = data.run(input, dst, flush);
+    let read = (data.total_out() - before_out) as usize;
+    let consumed = (data.total_in() - before_in) as usize;
+
+    let ret = match ret {
+        // If we haven't ready any data and we haven't hit EOF yet,
+        // then we need to keep asking for more data because if we
+        // return that 0 bytes of data have been read then it will
+        // be interpreted as EOF.
+        Ok(Status::Ok | Status::BufError) if read == 0 && !eof && !dst.is_empty() => None,
+        // Everything that could be decoded has been returned, but the stream
+        // isn't complete.
+        Ok(Status::Ok | Status::BufError) if read == 0 && report_truncation && !dst.is_empty() => {
+            let offset = data.total_in();
+            Some(Err(Error::Truncated { offset }.into()))
+        }
+        Ok(Status::Ok | Status::BufError | Status::StreamEnd) => Some(Ok(read)),
+
+        Err(err) => Some(Err(Error::decompress(err, data.total_in()).into())),
+    };
+    (consumed, ret)
+}
+
+pub(crate) fn exceeded(limit: LimitExceeded, offset: u64) -> io::Error {
+    Error::LimitExceeded { limit, offset }.into()
+}
+
 impl<W: Write, D: Ops> Writer<W, D> {
     pub fn new(w: W, d: D) -> Writer<W, D> {
+        Writer::new_with_options(w, d, &DecoderOptions::new())
+    }
+
+    pub fn new_with_options(w: W, d: D, options: &DecoderOptions) -> Writer<W, D> {
         Writer {
             obj: Some(w),
             data: d,
+            limiter: Limiter::new(options.limits),
+            report_truncation: options.report_truncation,
             buf: Vec::with_capacity(32 * 1024),
         }
     }
@@ -169,9 +237,24 @@ impl<W: Write, D: Ops> Writer<W, D> {
         loop {
             self.dump()?;
 
+            // See `read_step` for why this doesn't always finish the codec.
+            let flush = if self.report_truncation {
+                D::Flush::none()
+            } else {
+                D::Flush::finish()
+            };
+            let before_in = self.data.total_in();
             let before = self.data.total_out();
-            self.data.run_vec(&[], &mut self.buf, D::Flush::finish())?;
+            let status = self
+                .data
+                .run_vec(&[], &mut self.buf, flush)
+                .map_err(|err| Error::decompress(err, self.data.total_in()))?;
+            self.limit(before_in, before)?;
             if before == self.data.total_out() {
+                if self.report_truncation && status != Status::StreamEnd {
+                    let offset = self.data.total_in();
+                    return Err(Error::Truncated { offset }.into());
+                }
                 return Ok(());
             }
         }
@@ -179,6 +262,7 @@ impl<W: Write, D: Ops> Writer<W, D> {
 
     pub fn replace(&mut self, w: W) -> W {
         self.buf.truncate(0);
+        self.limiter.reset();
         mem::replace(self.get_mut(), w)
     }
 
@@ -214,7 +298,9 @@ impl<W: Write, D: Ops> Writer<W, D> {
             self.dump()?;
 
             let before_in = self.data.total_in();
+            let before_out = self.data.total_out();
             let ret = self.data.run_vec(buf, &mut self.buf, D::Flush::none());
+            self.limit(before_in, before_out)?;
             let written = (self.data.total_in() - before_in) as usize;
             let is_stream_end = matches!(ret, Ok(Status::StreamEnd));
 
@@ -225,14 +311,52 @@ impl<W: Write, D: Ops> Writer<W, D> {
                 Ok(st) => match st {
                     Status::Ok | Status::BufError | Status::StreamEnd => Ok((written, st)),
                 },
-                Err(..) => Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "corrupt deflate stream",
-                )),
+                Err(err) => Err(Error::decompress(err, self.data.total_in()).into()),
             };
         }
     }
 
+    /// Flushes the codec with `flush`, writing out everything it produces
+    /// without flushing the underlying writer.
+    pub(crate) fn flush_with(&mut self, flush: D::Flush) -> io::Result<()> {
+        let before_in = self.data.total_in();
+        let before_out = self.data.total_out();
+        self.data.run_vec(&[], &mut self.buf, flush).unwrap();
+        self.limit(before_in, before_out)?;
+
+        // Unfortunately miniz doesn't actually tell us when we're done with
+        // pulling out all the data from the internal stream. To remedy this we
+        // have to continually ask the stream for more memory until it doesn't
+        // give us a chunk of memory the same size as our own internal buffer,
+        // at which point we assume it's reached the end.
+        loop {
+            self.dump()?;
+            let before_in = self.data.total_in();
+            let before = self.data.total_out();
+            self.data
+                .run_vec(&[], &mut self.buf, D::Flush::none())
+                .unwrap();
+            self.limit(before_in, before)?;
+            if before == self.data.total_out() {
+                return Ok(());
+            }
+        }
+    }
+
+    // Accounts for what the codec did since `before_in` and `before_out`,
+    // dropping any output past the limits so that it never reaches `obj`.
+    fn limit(&mut self, before_in: u64, before_out: u64) -> io::Result<()> {
+        let consumed = self.data.total_in() - before_in;
+        let produced = self.data.total_out() - before_out;
+        if let Err(limit) = self.limiter.account(consumed, produced) {
+            let excess = cmp::min(self.limiter.excess(), produced) as usize;
+            self.buf.truncate(self.buf.len() - excess);
+            self.dump()?;
+            return Err(exceeded(limit, self.data.total_in()));
+        }
+        Ok(())
+    }
+
     fn dump(&mut self) -> io::Result<()> {
         // TODO: should manage this buffer not with `drain` but probably more of
         // a deque-like strategy.
@@ -253,26 +377,7 @@ impl<W: Write, D: Ops> Write for Writer<W, D> {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.data
-            .run_vec(&[], &mut self.buf, D::Flush::sync())
-            .unwrap();
-
-        // Unfortunately miniz doesn't actually tell us when we're done with
-        // pulling out all the data from the internal stream. To remedy this we
-        // have to continually ask the stream for more memory until it doesn't
-        // give us a chunk of memory the same size as our own internal buffer,
-        // at which point we assume it's reached the end.
-        loop {
-            self.dump()?;
-            let before = self.data.total_out();
-            self.data
-                .run_vec(&[], &mut self.buf, D::Flush::none())
-                .unwrap();
-            if before == self.data.total_out() {
-                break;
-            }
-        }
-
+        self.flush_with(D::Flush::sync())?;
         self.obj.as_mut().unwrap().flush()
     }
 }
@@ -2,8 +2,10 @@ use std::io;
 use std::io::prelude::*;
 use std::mem;
 
+use crate::limits::Limiter;
+use crate::multi::MultiDecompress;
 use crate::zio;
-use crate::{Compress, Decompress};
+use crate::{Compress, DecoderOptions, Decompress, EncoderOptions, Limits, StreamRange};
 
 /// A ZLIB encoder, or compressor.
 ///
@@ -49,6 +51,16 @@ impl<R: BufRead> ZlibEncoder<R> {
         }
     }
 
+    /// Creates a new encoder like `new`, which also applies the given
+    /// `options`.
+    pub fn new_with_options(
+        r: R,
+        level: crate::Compression,
+        options: EncoderOptions,
+    ) -> ZlibEncoder<R> {
+        ZlibEncoder::new_with_compress(r, options.compress(level, true))
+    }
+
     /// Creates a new encoder with the given `compression` settings which will
     /// read uncompressed data from the given stream `r` and emit the compressed stream.
     pub fn new_with_compress(r: R, compression: Compress) -> ZlibEncoder<R> {
@@ -165,16 +177,15 @@ impl<R: BufRead + Write> Write for ZlibEncoder<R> {
 pub struct ZlibDecoder<R> {
     obj: R,
     data: Decompress,
+    limiter: Limiter,
+    report_truncation: bool,
 }
 
 impl<R: BufRead> ZlibDecoder<R> {
     /// Creates a new decoder which will decompress data read from the given
     /// stream.
     pub fn new(r: R) -> ZlibDecoder<R> {
-        ZlibDecoder {
-            obj: r,
-            data: Decompress::new(true),
-        }
+        ZlibDecoder::new_with_decompress(r, Decompress::new(true))
     }
 
     /// Creates a new decoder which will decompress data read from the given
@@ -183,12 +194,32 @@ impl<R: BufRead> ZlibDecoder<R> {
         ZlibDecoder {
             obj: r,
             data: decompression,
+            limiter: Limiter::new(Limits::new()),
+            report_truncation: false,
+        }
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, failing once any of the given `limits` is exceeded.
+    pub fn new_with_limits(r: R, limits: Limits) -> ZlibDecoder<R> {
+        ZlibDecoder::new_with_options(r, DecoderOptions::new().limits(limits))
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, as configured by `options`.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> ZlibDecoder<R> {
+        ZlibDecoder {
+            obj: r,
+            data: options.decompress(true),
+            limiter: Limiter::new(options.limits),
+            report_truncation: options.report_truncation,
         }
     }
 }
 
 pub fn reset_decoder_data<R>(zlib: &mut ZlibDecoder<R>) {
-    zlib.data = Decompress::new(true);
+    zlib.data.reset(true);
+    zlib.limiter.reset();
 }
 
 impl<R> ZlibDecoder<R> {
@@ -238,7 +269,13 @@ impl<R> ZlibDecoder<R> {
 
 impl<R: BufRead> Read for ZlibDecoder<R> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
-        zio::read(&mut self.obj, &mut self.data, into)
+        zio::read_limited(
+            &mut self.obj,
+            &mut self.data,
+            into,
+            &mut self.limiter,
+            self.report_truncation,
+        )
     }
 }
 
@@ -251,3 +288,162 @@ impl<R: BufRead + Write> Write for ZlibDecoder<R> {
         self.get_mut().flush()
     }
 }
+
+/// A decoder for a concatenation of zlib streams.
+///
+/// This structure implements a [`Read`] interface. When read from, it reads
+/// compressed data from the underlying [`BufRead`] and provides the
+/// uncompressed data of all the zlib streams it holds, one after the other,
+/// until the end of the input. [`take_streams`](Self::take_streams) tells where
+/// each of them lies in the input and in the output.
+///
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+/// [`BufRead`]: https://doc.rust-lang.org/std/io/trait.BufRead.html
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::bufread::MultiZlibDecoder;
+/// use flate2::write::ZlibEncoder;
+/// use flate2::Compression;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut bytes = Vec::new();
+/// for part in ["Hello ", "World"] {
+///     let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+///     e.write_all(part.as_bytes())?;
+///     bytes.extend(e.finish()?);
+/// }
+///
+/// let mut d = MultiZlibDecoder::new(&bytes[..]);
+/// let mut s = String::new();
+/// d.read_to_string(&mut s)?;
+/// assert_eq!(s, "Hello World");
+///
+/// let streams = d.take_streams();
+/// assert_eq!(streams.len(), 2);
+/// assert_eq!(streams[1].uncompressed(), 6..11);
+/// assert_eq!(streams[1].compressed().end, bytes.len() as u64);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MultiZlibDecoder<R> {
+    obj: R,
+    data: MultiDecompress,
+    limiter: Limiter,
+    report_truncation: bool,
+}
+
+impl<R: BufRead> MultiZlibDecoder<R> {
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream.
+    pub fn new(r: R) -> MultiZlibDecoder<R> {
+        MultiZlibDecoder::new_with_options(r, DecoderOptions::new())
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, as configured by `options`.
+    ///
+    /// The limits of `options` apply to all the streams together.
+    pub fn new_with_options(r: R, options: DecoderOptions) -> MultiZlibDecoder<R> {
+        MultiZlibDecoder {
+            obj: r,
+            data: MultiDecompress::new(options.decompress(true), true),
+            limiter: Limiter::new(options.limits),
+            report_truncation: options.report_truncation,
+        }
+    }
+
+    /// Creates a new decoder which will decompress data read from the given
+    /// stream, using the given `decompression` settings.
+    ///
+    /// `decompression` is reset for each stream after the first.
+    pub fn new_with_decompress(r: R, decompression: Decompress) -> MultiZlibDecoder<R> {
+        MultiZlibDecoder {
+            obj: r,
+            data: MultiDecompress::new(decompression, true),
+            limiter: Limiter::new(Limits::new()),
+            report_truncation: false,
+        }
+    }
+}
+
+impl<R> MultiZlibDecoder<R> {
+    /// Resets the state of this decoder entirely, swapping out the input
+    /// stream for another.
+    ///
+    /// This will reset the internal state of this decoder and replace the
+    /// input stream with the one provided, returning the previous input
+    /// stream. Future data read from this decoder will be the decompressed
+    /// version of `r`'s data.
+    pub fn reset(&mut self, r: R) -> R {
+        self.data.reset();
+        self.limiter.reset();
+        mem::replace(&mut self.obj, r)
+    }
+
+    /// Returns where the streams that were decoded to the end since the
+    /// last call lie in the input and in the output.
+    ///
+    /// The streams are kept track of until this is called.
+    pub fn take_streams(&mut self) -> Vec<StreamRange> {
+        self.data.take_streams()
+    }
+
+    /// Acquires a reference to the underlying stream
+    pub fn get_ref(&self) -> &R {
+        &self.obj
+    }
+
+    /// Acquires a mutable reference to the underlying stream
+    ///
+    /// Note that mutation of the stream may result in surprising results if
+    /// this decoder is continued to be used.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.obj
+    }
+
+    /// Consumes this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+
+    /// Returns the number of bytes that the decompressor has consumed, over
+    /// all the streams.
+    ///
+    /// Note that this will likely be smaller than what the decompressor
+    /// actually read from the underlying stream due to buffering.
+    pub fn total_in(&self) -> u64 {
+        self.data.total_in()
+    }
+
+    /// Returns the number of bytes that the decompressor has produced, over
+    /// all the streams.
+    pub fn total_out(&self) -> u64 {
+        self.data.total_out()
+    }
+}
+
+impl<R: BufRead> Read for MultiZlibDecoder<R> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        zio::read_limited(
+            &mut self.obj,
+            &mut self.data,
+            into,
+            &mut self.limiter,
+            self.report_truncation,
+        )
+    }
+}
+
+impl<R: BufRead + Write> Write for MultiZlibDecoder<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
@@ -2,6 +2,13 @@ pub mod bufread;
 pub mod read;
 pub mod write;
 
+// Returns whether `header` is a valid zlib header: deflate with a window of at
+// most 32 KiB and a valid check value.
+pub(crate) fn is_header(header: [u8; 2]) -> bool {
+    let check = u16::from_be_bytes(header) % 31;
+    header[0] & 0x0f == 8 && header[0] >> 4 <= 7 && check == 0
+}
+
 #[cfg(test)]
 mod tests {
     use std::io;
@@ -9,8 +16,8 @@ mod tests {
 
     use rand::{thread_rng, Rng};
 
-    use crate::zlib::{read, write};
-    use crate::Compression;
+    use crate::zlib::{bufread, read, write};
+    use crate::{Compression, DecoderOptions, Error};
 
     #[test]
     fn roundtrip() {
@@ -156,4 +163,155 @@ mod tests {
             v == w.finish().unwrap().finish().unwrap()
         }
     }
+
+    fn truncated_at(err: io::Error) -> u64 {
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(Error::Truncated { offset }) => *offset,
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn report_truncation() {
+        let data = crate::random_bytes().take(100_000).collect::<Vec<_>>();
+        let mut w = write::ZlibEncoder::new(Vec::new(), Compression::default());
+        w.write_all(&data).unwrap();
+        let compressed = w.finish().unwrap();
+        let truncated = &compressed[..compressed.len() / 2];
+
+        let options = DecoderOptions::new().report_truncation(true);
+        let mut r = read::ZlibDecoder::new_with_options(truncated, options);
+        let mut partial = Vec::new();
+        let err = r.read_to_end(&mut partial).unwrap_err();
+        assert_eq!(truncated_at(err), truncated.len() as u64);
+        // Stored blocks, which random data compresses to, can be decoded up
+        // to the last byte.
+        assert_eq!(partial, &data[..partial.len()]);
+        assert!(partial.len() >= truncated.len() - 100);
+
+        let mut w = write::ZlibDecoder::new_with_options(Vec::new(), options);
+        w.write_all(truncated).unwrap();
+        assert_eq!(
+            truncated_at(w.try_finish().unwrap_err()),
+            truncated.len() as u64
+        );
+        assert_eq!(w.get_ref(), &partial);
+
+        let mut r = read::ZlibDecoder::new_with_options(&compressed[..], options);
+        r.read_to_end(&mut Vec::new()).unwrap();
+        let mut w = write::ZlibDecoder::new_with_options(Vec::new(), options);
+        w.write_all(&compressed).unwrap();
+        assert_eq!(w.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn skip_checksum() {
+        let mut w = write::ZlibEncoder::new(Vec::new(), Compression::default());
+        w.write_all(b"hello world").unwrap();
+        let mut data = w.finish().unwrap();
+        let len = data.len();
+        data[len - 1] ^= 1;
+
+        let mut r = read::ZlibDecoder::new(&data[..]);
+        assert!(r.read_to_end(&mut Vec::new()).is_err());
+
+        let options = DecoderOptions::new().verify_checksums(false);
+        let mut r = read::ZlibDecoder::new_with_options(&data[..], options);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello world");
+        assert_eq!(r.total_in(), len as u64);
+
+        let mut w = write::ZlibDecoder::new_with_options(Vec::new(), options);
+        w.write_all(&data).unwrap();
+        assert_eq!(w.finish().unwrap(), b"hello world");
+    }
+
+    fn concatenation(parts: &[&[u8]]) -> (Vec<u8>, Vec<u64>) {
+        let mut data = Vec::new();
+        let mut ends = Vec::new();
+        for part in parts {
+            let mut w = write::ZlibEncoder::new(Vec::new(), Compression::default());
+            w.write_all(part).unwrap();
+            data.extend(w.finish().unwrap());
+            ends.push(data.len() as u64);
+        }
+        (data, ends)
+    }
+
+    #[test]
+    fn multi_stream() {
+        let big = crate::random_bytes().take(100_000).collect::<Vec<_>>();
+        let parts: [&[u8]; 4] = [b"hello ", b"", &big, b"world"];
+        let (data, ends) = concatenation(&parts);
+        let expected = parts.concat();
+
+        let check = |streams: Vec<crate::StreamRange>| {
+            assert_eq!(streams.len(), parts.len());
+            let (mut start_in, mut start_out) = (0, 0);
+            for ((stream, part), end) in streams.iter().zip(&parts).zip(&ends) {
+                assert_eq!(stream.compressed(), start_in..*end);
+                let end_out = start_out + part.len() as u64;
+                assert_eq!(stream.uncompressed(), start_out..end_out);
+                start_in = *end;
+                start_out = end_out;
+            }
+        };
+
+        let mut r = read::MultiZlibDecoder::new(&data[..]);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, expected);
+        assert_eq!(r.total_in(), data.len() as u64);
+        check(r.take_streams());
+        assert!(r.take_streams().is_empty());
+
+        // Trailers split across reads of the underlying reader.
+        let mut r = bufread::MultiZlibDecoder::new(io::BufReader::with_capacity(3, &data[..]));
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, expected);
+        check(r.take_streams());
+
+        let mut w = write::MultiZlibDecoder::new(Vec::new());
+        for chunk in data.chunks(7) {
+            w.write_all(chunk).unwrap();
+        }
+        w.try_finish().unwrap();
+        check(w.take_streams());
+        assert_eq!(w.finish().unwrap(), expected);
+
+        // The single-stream decoder stops after the first one.
+        let mut r = read::ZlibDecoder::new(&data[..]);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello ");
+    }
+
+    #[test]
+    fn multi_stream_truncated() {
+        let (data, ends) = concatenation(&[b"hello ", b"world"]);
+        let truncated = &data[..data.len() - 2];
+        let options = DecoderOptions::new().report_truncation(true);
+
+        let mut r = read::MultiZlibDecoder::new_with_options(truncated, options);
+        let mut out = Vec::new();
+        let err = r.read_to_end(&mut out).unwrap_err();
+        assert_eq!(truncated_at(err), truncated.len() as u64);
+        assert_eq!(out, b"hello world");
+        assert_eq!(r.take_streams().len(), 1);
+
+        let mut w = write::MultiZlibDecoder::new_with_options(Vec::new(), options);
+        w.write_all(truncated).unwrap();
+        assert_eq!(
+            truncated_at(w.try_finish().unwrap_err()),
+            truncated.len() as u64
+        );
+
+        // Whether this is an error depends on the backend, but the incomplete
+        // stream is never reported.
+        let mut r = read::MultiZlibDecoder::new(&data[..ends[0] as usize + 1]);
+        let _ = r.read_to_end(&mut Vec::new());
+        assert_eq!(r.take_streams().len(), 1);
+    }
 }
@@ -1,8 +1,9 @@
 use std::io;
 use std::io::prelude::*;
 
+use crate::multi::MultiDecompress;
 use crate::zio;
-use crate::{Compress, Decompress};
+use crate::{Compress, DecoderOptions, Decompress, EncoderOptions, Limits, StreamRange};
 
 /// A ZLIB encoder, or compressor.
 ///
@@ -44,6 +45,16 @@ impl<W: Write> ZlibEncoder<W> {
         }
     }
 
+    /// Creates a new encoder like `new`, which also applies the given
+    /// `options`.
+    pub fn new_with_options(
+        w: W,
+        level: crate::Compression,
+        options: EncoderOptions,
+    ) -> ZlibEncoder<W> {
+        ZlibEncoder::new_with_compress(w, options.compress(level, true))
+    }
+
     /// Creates a new encoder which will write compressed data to the stream
     /// `w` with the given `compression` settings.
     pub fn new_with_compress(w: W, compression: Compress) -> ZlibEncoder<W> {
@@ -226,6 +237,22 @@ impl<W: Write> ZlibDecoder<W> {
         }
     }
 
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// failing once any of the given `limits` is exceeded.
+    ///
+    /// Output beyond the limits is never written to the stream.
+    pub fn new_with_limits(w: W, limits: Limits) -> ZlibDecoder<W> {
+        ZlibDecoder::new_with_options(w, DecoderOptions::new().limits(limits))
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// as configured by `options`.
+    pub fn new_with_options(w: W, options: DecoderOptions) -> ZlibDecoder<W> {
+        ZlibDecoder {
+            inner: zio::Writer::new_with_options(w, options.decompress(true), &options),
+        }
+    }
+
     /// Creates a new decoder which will write uncompressed data to the stream `w`
     /// using the given `decompression` settings.
     ///
@@ -264,7 +291,7 @@ impl<W: Write> ZlibDecoder<W> {
     /// errors which occur will be returned from this function.
     pub fn reset(&mut self, w: W) -> io::Result<W> {
         self.inner.finish()?;
-        self.inner.data = Decompress::new(true);
+        self.inner.data.reset(true);
         Ok(self.inner.replace(w))
     }
 
@@ -338,3 +365,178 @@ impl<W: Read + Write> Read for ZlibDecoder<W> {
         self.inner.get_mut().read(buf)
     }
 }
+
+/// A decoder for a concatenation of zlib streams.
+///
+/// This structure implements a [`Write`] and will emit the decompressed data
+/// of all the zlib streams it is fed, one after the other.
+/// [`take_streams`](Self::take_streams) tells where each of them lies in the
+/// input and in the output.
+///
+/// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+///
+/// # Examples
+///
+/// ```
+/// use std::io::prelude::*;
+/// use flate2::write::ZlibEncoder;
+/// use flate2::write::MultiZlibDecoder;
+/// use flate2::Compression;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut bytes = Vec::new();
+/// for part in ["Hello ", "World"] {
+///     let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+///     e.write_all(part.as_bytes())?;
+///     bytes.extend(e.finish()?);
+/// }
+///
+/// let mut d = MultiZlibDecoder::new(Vec::new());
+/// d.write_all(&bytes)?;
+/// d.try_finish()?;
+/// assert_eq!(d.take_streams().len(), 2);
+/// assert_eq!(d.finish()?, b"Hello World");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MultiZlibDecoder<W: Write> {
+    inner: zio::Writer<W, MultiDecompress>,
+}
+
+impl<W: Write> MultiZlibDecoder<W> {
+    /// Creates a new decoder which will write uncompressed data to the stream.
+    ///
+    /// When this decoder is dropped or unwrapped the final pieces of data will
+    /// be flushed.
+    pub fn new(w: W) -> MultiZlibDecoder<W> {
+        MultiZlibDecoder::new_with_options(w, DecoderOptions::new())
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream,
+    /// as configured by `options`.
+    ///
+    /// The limits of `options` apply to all the streams together.
+    pub fn new_with_options(w: W, options: DecoderOptions) -> MultiZlibDecoder<W> {
+        MultiZlibDecoder {
+            inner: zio::Writer::new_with_options(
+                w,
+                MultiDecompress::new(options.decompress(true), true),
+                &options,
+            ),
+        }
+    }
+
+    /// Creates a new decoder which will write uncompressed data to the stream `w`
+    /// using the given `decompression` settings.
+    ///
+    /// `decompression` is reset for each stream after the first.
+    pub fn new_with_decompress(w: W, decompression: Decompress) -> MultiZlibDecoder<W> {
+        MultiZlibDecoder {
+            inner: zio::Writer::new(w, MultiDecompress::new(decompression, true)),
+        }
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Note that mutating the output/input state of the stream may corrupt this
+    /// object, so care must be taken when using this method.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    /// Resets the state of this decoder entirely, swapping out the output
+    /// stream for another.
+    ///
+    /// This will reset the internal state of this decoder and replace the
+    /// output stream with the one provided, returning the previous output
+    /// stream. Future data written to this decoder will be decompressed into
+    /// the output stream `w`.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn reset(&mut self, w: W) -> io::Result<W> {
+        self.inner.finish()?;
+        self.inner.data.reset();
+        Ok(self.inner.replace(w))
+    }
+
+    /// Returns where the streams that were decoded to the end since the
+    /// last call lie in the input and in the output.
+    ///
+    /// The streams are kept track of until this is called.
+    pub fn take_streams(&mut self) -> Vec<StreamRange> {
+        self.inner.data.take_streams()
+    }
+
+    /// Attempt to finish this output stream, writing out final chunks of data.
+    ///
+    /// Note that this function can only be used once data has finished being
+    /// written to the output stream. After this function is called then further
+    /// calls to `write` may result in a panic.
+    ///
+    /// # Panics
+    ///
+    /// Attempts to write data to this stream may result in a panic after this
+    /// function is called.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.inner.finish()
+    }
+
+    /// Consumes this decoder, flushing the output stream.
+    ///
+    /// This will flush the underlying data stream and then return the contained
+    /// writer if the flush succeeded.
+    ///
+    /// # Errors
+    ///
+    /// This function will perform I/O to complete this stream, and any I/O
+    /// errors which occur will be returned from this function.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.finish()?;
+        Ok(self.inner.take_inner())
+    }
+
+    /// Returns the number of bytes that the decompressor has consumed for
+    /// decompression, over all the streams.
+    ///
+    /// Note that this will likely be smaller than the number of bytes
+    /// successfully written to this stream due to internal buffering.
+    pub fn total_in(&self) -> u64 {
+        self.inner.data.total_in()
+    }
+
+    /// Returns the number of bytes that the decompressor has written to its
+    /// output stream, over all the streams.
+    pub fn total_out(&self) -> u64 {
+        self.inner.data.total_out()
+    }
+}
+
+impl<W: Write> Write for MultiZlibDecoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Read + Write> Read for MultiZlibDecoder<W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.get_mut().read(buf)
+    }
+}